@@ -0,0 +1,49 @@
+/* C API for minicoro-awaiters, built with the `capi` feature. */
+
+#ifndef MINICORO_AWAITERS_H
+#define MINICORO_AWAITERS_H
+
+#include <stdbool.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* Polls the future behind `data` with the task context `cx`; returns true once complete. */
+typedef bool (*mca_poll_fn)(void *data, void *cx);
+
+typedef struct mca_awaiter {
+    const void *ctx;
+    void (*block_on)(const void *ctx, mca_poll_fn poll, void *data);
+    void *(*waker_clone)(void *cx);
+    void (*waker_wake)(void *waker);
+    void (*waker_wake_by_ref)(const void *waker);
+    void (*waker_drop)(void *waker);
+} mca_awaiter;
+
+typedef struct mca_host_waker {
+    void *data;
+    void (*wake)(void *data);
+} mca_host_waker;
+
+typedef struct mca_coroutine mca_coroutine;
+
+typedef void (*mca_body_fn)(void *data, const mca_awaiter *awaiter);
+
+/* Creates a coroutine running `body(data, awaiter)`; returns NULL on failure. */
+mca_coroutine *mca_create(mca_body_fn body, void *data);
+
+/* Resumes the coroutine; returns true once its body has returned. */
+bool mca_poll(mca_coroutine *coro, const mca_host_waker *waker);
+
+/* Frees a coroutine; NULL is ignored. */
+void mca_destroy(mca_coroutine *coro);
+
+/* Blocks the running coroutine until `poll(data, cx)` returns true. */
+void mca_await(const mca_awaiter *awaiter, mca_poll_fn poll, void *data);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* MINICORO_AWAITERS_H */
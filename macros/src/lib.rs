@@ -0,0 +1,492 @@
+//! # minicoro-awaiters-macros
+//!
+//! Procedural macros for [`minicoro-awaiters`](https://crates.io/crates/minicoro-awaiters).
+//!
+//! These are re-exported from `minicoro-awaiters` behind its `macros` feature;
+//! depend on that crate rather than on this one directly.
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{quote, quote_spanned};
+use syn::ext::IdentExt;
+use syn::parse::{Parse, ParseStream};
+use syn::spanned::Spanned;
+use syn::visit::Visit;
+use syn::{FnArg, ItemFn, Path, ReturnType, Token, Type, parse_macro_input};
+
+/// Arguments accepted by `#[coroutine_fn(...)]`.
+struct Args {
+    /// Path to the `minicoro-awaiters` crate, for re-exporting users.
+    krate: Path,
+}
+
+impl Parse for Args {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let mut krate = syn::parse_quote!(::minicoro_awaiters);
+        if !input.is_empty() {
+            input.parse::<Token![crate]>()?;
+            input.parse::<Token![=]>()?;
+            krate = input.parse()?;
+        }
+        Ok(Self { krate })
+    }
+}
+
+/// Finds `impl Trait` anywhere inside a type, which cannot be named in `let` bindings.
+#[derive(Default)]
+struct ImplTraitFinder(bool);
+
+impl<'ast> Visit<'ast> for ImplTraitFinder {
+    fn visit_type_impl_trait(&mut self, _: &'ast syn::TypeImplTrait) {
+        self.0 = true;
+    }
+}
+
+fn contains_impl_trait(ty: &Type) -> bool {
+    let mut finder = ImplTraitFinder::default();
+    finder.visit_type(ty);
+    finder.0
+}
+
+/// Finds the first non-`'static` reference or lifetime inside a type, whose
+/// borrow the coroutine could outlive.
+#[derive(Default)]
+struct BorrowFinder(Option<proc_macro2::Span>);
+
+impl<'ast> Visit<'ast> for BorrowFinder {
+    fn visit_type_reference(&mut self, reference: &'ast syn::TypeReference) {
+        match &reference.lifetime {
+            Some(lifetime) if lifetime.ident == "static" => {
+                syn::visit::visit_type_reference(self, reference)
+            }
+            _ => {
+                self.0.get_or_insert(reference.span());
+            }
+        }
+    }
+
+    fn visit_lifetime(&mut self, lifetime: &'ast syn::Lifetime) {
+        if lifetime.ident != "static" {
+            self.0.get_or_insert(lifetime.span());
+        }
+    }
+}
+
+fn find_borrow(ty: &Type) -> Option<proc_macro2::Span> {
+    let mut finder = BorrowFinder::default();
+    finder.visit_type(ty);
+    finder.0
+}
+
+/// Rewrites a blocking-style function into one returning a `CoroutineFuture`.
+///
+/// The first non-receiver parameter is the awaiter. It is removed from the
+/// signature and bound to the coroutine's `CoroutineAwaiter` inside the body;
+/// the remaining parameters are moved into the coroutine, which may outlive
+/// the call, so they must be `'static`: parameters that borrow, `&self`
+/// included, are rejected.
+///
+/// ```ignore
+/// use minicoro_awaiters::coroutine_fn;
+/// use awaiter_trait::Awaiter;
+///
+/// #[coroutine_fn]
+/// fn fetch(awaiter: &impl Awaiter, id: u32) -> u32 {
+///     awaiter.r#await(Box::pin(async move { id * 2 }))
+/// }
+///
+/// async fn caller() {
+///     assert_eq!(fetch(21).await, 42);
+/// }
+/// ```
+///
+/// Use `#[coroutine_fn(crate = path)]` when `minicoro-awaiters` is re-exported
+/// under a different path.
+#[proc_macro_attribute]
+pub fn coroutine_fn(args: TokenStream, item: TokenStream) -> TokenStream {
+    let args = parse_macro_input!(args as Args);
+    let item = parse_macro_input!(item as ItemFn);
+    expand(args, item)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+fn expand(args: Args, mut item: ItemFn) -> syn::Result<TokenStream2> {
+    let krate = &args.krate;
+    if let Some(asyncness) = &item.sig.asyncness {
+        return Err(syn::Error::new(
+            asyncness.span(),
+            "`#[coroutine_fn]` functions are blocking-style and must not be `async`",
+        ));
+    }
+
+    let position = item
+        .sig
+        .inputs
+        .iter()
+        .position(|arg| matches!(arg, FnArg::Typed(_)))
+        .ok_or_else(|| {
+            syn::Error::new(
+                item.sig.inputs.span(),
+                "`#[coroutine_fn]` functions take the awaiter as their first parameter",
+            )
+        })?;
+    let inputs = core::mem::take(&mut item.sig.inputs);
+    let mut awaiter = None;
+    for (i, arg) in inputs.into_iter().enumerate() {
+        match arg {
+            FnArg::Typed(pat) if i == position => awaiter = Some(pat),
+            arg => item.sig.inputs.push(arg),
+        }
+    }
+    let awaiter = awaiter.expect("awaiter parameter was located above");
+    // The parameters move into a coroutine that may outlive the call.
+    for arg in &item.sig.inputs {
+        let ty = match arg {
+            FnArg::Receiver(receiver) => &receiver.ty,
+            FnArg::Typed(pat) => &pat.ty,
+        };
+        if let Some(span) = find_borrow(ty) {
+            return Err(syn::Error::new(
+                span,
+                "`#[coroutine_fn]` parameters other than the awaiter are moved into the \
+                 coroutine, which may outlive the call, so they must not borrow",
+            ));
+        }
+    }
+    let awaiter_pat = &awaiter.pat;
+    let binding = if contains_impl_trait(&awaiter.ty) {
+        quote!(let #awaiter_pat = &__awaiter;)
+    } else {
+        let ty = &awaiter.ty;
+        quote!(let #awaiter_pat: #ty = &__awaiter;)
+    };
+
+    let (output, closure_output) = match &item.sig.output {
+        ReturnType::Default => (quote!(()), quote!(-> ())),
+        ReturnType::Type(_, ty) if contains_impl_trait(ty) => (quote!(#ty), quote!()),
+        ReturnType::Type(_, ty) => (quote!(#ty), quote!(-> #ty)),
+    };
+    let span = item.sig.output.span();
+    item.sig.output =
+        syn::parse2(quote_spanned!(span=> -> #krate::CoroutineFuture<#output>))?;
+
+    let block = &item.block;
+    let body = quote! {
+        {
+            // Generic parameters and `Self` may still borrow; make them `'static`.
+            fn __coroutine_body<F: 'static>(body: F) -> F {
+                body
+            }
+            #krate::CoroutineFuture::new(__coroutine_body(
+                move |__awaiter: #krate::CoroutineAwaiter| #closure_output {
+                    #binding
+                    #block
+                },
+            ))
+        }
+    };
+    item.block = syn::parse2(body)?;
+    Ok(quote!(#item))
+}
+
+/// The input of `impl_blocking!`.
+struct BlockingImpl {
+    krate: Path,
+    generics: syn::Generics,
+    trait_: Path,
+    self_ty: Type,
+    awaiter: syn::Expr,
+    target: syn::Expr,
+    methods: Vec<DelegatedMethod>,
+}
+
+/// A method listed in `impl_blocking!` or `impl_async!`, delegating to
+/// `target.<delegate>`.
+struct DelegatedMethod {
+    attrs: Vec<syn::Attribute>,
+    sig: syn::Signature,
+    delegate: syn::Ident,
+}
+
+/// Parses `key = value;`, returning `None` if the input does not start with `key =`.
+fn parse_setting<T: Parse>(input: ParseStream, key: &str) -> syn::Result<Option<T>> {
+    let fork = input.fork();
+    let found = match fork.call(syn::Ident::parse_any) {
+        Ok(ident) => ident == key && fork.peek(Token![=]),
+        Err(_) => false,
+    };
+    if !found {
+        return Ok(None);
+    }
+    input.call(syn::Ident::parse_any)?;
+    input.parse::<Token![=]>()?;
+    let value = input.parse()?;
+    input.parse::<Token![;]>()?;
+    Ok(Some(value))
+}
+
+/// Parses the method list shared by `impl_blocking!` and `impl_async!`.
+fn parse_methods(input: ParseStream) -> syn::Result<Vec<DelegatedMethod>> {
+    let mut methods = Vec::new();
+    while !input.is_empty() {
+        let attrs = input.call(syn::Attribute::parse_outer)?;
+        let sig: syn::Signature = input.parse()?;
+        let delegate = if input.peek(Token![=]) {
+            input.parse::<Token![=]>()?;
+            input.parse()?
+        } else {
+            sig.ident.clone()
+        };
+        input.parse::<Token![;]>()?;
+        methods.push(DelegatedMethod {
+            attrs,
+            sig,
+            delegate,
+        });
+    }
+    Ok(methods)
+}
+
+impl Parse for BlockingImpl {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        input.parse::<Token![impl]>()?;
+        let mut generics: syn::Generics = input.parse()?;
+        let trait_ = input.parse()?;
+        input.parse::<Token![for]>()?;
+        let self_ty = input.parse()?;
+        generics.where_clause = input.parse()?;
+        let content;
+        syn::braced!(content in input);
+        let krate = parse_setting(&content, "crate")?
+            .unwrap_or_else(|| syn::parse_quote!(::minicoro_awaiters));
+        let awaiter = parse_setting(&content, "awaiter")?
+            .ok_or_else(|| content.error("expected `awaiter = <expr>;` before the methods"))?;
+        let target = parse_setting(&content, "target")?.unwrap_or_else(|| syn::parse_quote!(self));
+        let methods = parse_methods(&content)?;
+        Ok(Self {
+            krate,
+            generics,
+            trait_,
+            self_ty,
+            awaiter,
+            target,
+            methods,
+        })
+    }
+}
+
+/// Implements a blocking trait by delegating every method to the async
+/// method of the same name, awaited through an awaiter.
+///
+/// The block lists the blocking trait's method signatures, each ending in
+/// `;`, or in `= name;` to delegate to an async method with another name.
+/// `awaiter = <expr>;` says where the awaiter comes from and `target =
+/// <expr>;` (default `self`) what the async methods are called on; both are
+/// evaluated anew in each method, so they may refer to `self`. Every
+/// parameter must be a plain identifier, and is passed on unchanged.
+///
+/// ```ignore
+/// use minicoro_awaiters::impl_blocking;
+///
+/// struct BlockingStore<'a, S> {
+///     awaiter: &'a minicoro_awaiters::CoroutineAwaiter,
+///     store: S,
+/// }
+///
+/// impl_blocking! {
+///     impl<S: AsyncStore> SyncStore for BlockingStore<'_, S> {
+///         awaiter = self.awaiter;
+///         target = self.store;
+///         fn get(&self, key: &str) -> Option<Vec<u8>>;
+///         fn put(&mut self, key: String, value: Vec<u8>) = insert;
+///     }
+/// }
+/// ```
+///
+/// Start the block with `crate = path;` when `minicoro-awaiters` is
+/// re-exported under a different path.
+#[proc_macro]
+pub fn impl_blocking(item: TokenStream) -> TokenStream {
+    let item = parse_macro_input!(item as BlockingImpl);
+    expand_blocking(item)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+fn expand_blocking(item: BlockingImpl) -> syn::Result<TokenStream2> {
+    let krate = &item.krate;
+    let awaiter = &item.awaiter;
+    let target = &item.target;
+    let mut methods = Vec::new();
+    for method in &item.methods {
+        let sig = &method.sig;
+        if let Some(asyncness) = &sig.asyncness {
+            return Err(syn::Error::new(
+                asyncness.span(),
+                "`impl_blocking!` generates blocking methods, which must not be `async`",
+            ));
+        }
+        let mut args = Vec::new();
+        for arg in &sig.inputs {
+            if let FnArg::Typed(pat) = arg {
+                match &*pat.pat {
+                    syn::Pat::Ident(ident) if ident.by_ref.is_none() && ident.subpat.is_none() => {
+                        args.push(&ident.ident)
+                    }
+                    pat => {
+                        return Err(syn::Error::new(
+                            pat.span(),
+                            "`impl_blocking!` parameters must be plain identifiers",
+                        ));
+                    }
+                }
+            }
+        }
+        let attrs = &method.attrs;
+        let delegate = &method.delegate;
+        methods.push(quote! {
+            #(#attrs)*
+            #sig {
+                use #krate::__awaiter_trait::Awaiter as _;
+                (#awaiter).r#await(::core::pin::pin!((#target).#delegate(#(#args),*)))
+            }
+        });
+    }
+    let (impl_generics, _, where_clause) = item.generics.split_for_impl();
+    let trait_ = &item.trait_;
+    let self_ty = &item.self_ty;
+    Ok(quote! {
+        impl #impl_generics #trait_ for #self_ty #where_clause {
+            #(#methods)*
+        }
+    })
+}
+
+/// The input of `impl_async!`.
+struct AsyncImpl {
+    krate: Path,
+    generics: syn::Generics,
+    trait_: Path,
+    self_ty: Type,
+    target: syn::Expr,
+    pass_awaiter: bool,
+    methods: Vec<DelegatedMethod>,
+}
+
+impl Parse for AsyncImpl {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        input.parse::<Token![impl]>()?;
+        let mut generics: syn::Generics = input.parse()?;
+        let trait_ = input.parse()?;
+        input.parse::<Token![for]>()?;
+        let self_ty = input.parse()?;
+        generics.where_clause = input.parse()?;
+        let content;
+        syn::braced!(content in input);
+        let krate = parse_setting(&content, "crate")?
+            .unwrap_or_else(|| syn::parse_quote!(::minicoro_awaiters));
+        let target = parse_setting(&content, "target")?.unwrap_or_else(|| syn::parse_quote!(self));
+        let pass_awaiter = parse_setting::<syn::LitBool>(&content, "pass_awaiter")?
+            .is_some_and(|lit| lit.value);
+        let methods = parse_methods(&content)?;
+        Ok(Self {
+            krate,
+            generics,
+            trait_,
+            self_ty,
+            target,
+            pass_awaiter,
+            methods,
+        })
+    }
+}
+
+/// Implements an async trait by running every method's blocking
+/// counterpart inside a `CoroutineFuture`; the inverse of
+/// [`impl_blocking!`].
+///
+/// The block lists the async trait's method signatures as `async fn`, each
+/// ending in `;`, or in `= name;` to delegate to a blocking method with
+/// another name. `target = <expr>;` (default `self`) says what the blocking
+/// methods are called on, and `pass_awaiter = true;` passes the coroutine's
+/// `&CoroutineAwaiter` as their first argument, so they can await in turn.
+/// Every parameter must be a plain identifier, and is passed on unchanged.
+///
+/// ```ignore
+/// use minicoro_awaiters::impl_async;
+///
+/// struct AsyncStore<S>(S);
+///
+/// impl_async! {
+///     impl<S: SyncStore> AsyncStoreTrait for AsyncStore<S> {
+///         target = self.0;
+///         pass_awaiter = true;
+///         async fn get(&self, key: &str) -> Option<Vec<u8>>;
+///     }
+/// }
+/// ```
+///
+/// Start the block with `crate = path;` when `minicoro-awaiters` is
+/// re-exported under a different path.
+#[proc_macro]
+pub fn impl_async(item: TokenStream) -> TokenStream {
+    let item = parse_macro_input!(item as AsyncImpl);
+    expand_async(item)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+fn expand_async(item: AsyncImpl) -> syn::Result<TokenStream2> {
+    let krate = &item.krate;
+    let target = &item.target;
+    let mut methods = Vec::new();
+    for method in &item.methods {
+        let sig = &method.sig;
+        if sig.asyncness.is_none() {
+            return Err(syn::Error::new(
+                sig.fn_token.span(),
+                "`impl_async!` methods must be declared `async fn`",
+            ));
+        }
+        let mut args = Vec::new();
+        if item.pass_awaiter {
+            args.push(quote!(&__awaiter));
+        }
+        for arg in &sig.inputs {
+            if let FnArg::Typed(pat) = arg {
+                match &*pat.pat {
+                    syn::Pat::Ident(ident) if ident.by_ref.is_none() && ident.subpat.is_none() => {
+                        let ident = &ident.ident;
+                        args.push(quote!(#ident));
+                    }
+                    pat => {
+                        return Err(syn::Error::new(
+                            pat.span(),
+                            "`impl_async!` parameters must be plain identifiers",
+                        ));
+                    }
+                }
+            }
+        }
+        let attrs = &method.attrs;
+        let delegate = &method.delegate;
+        methods.push(quote! {
+            #(#attrs)*
+            #sig {
+                #krate::CoroutineFuture::new(move |__awaiter: #krate::CoroutineAwaiter| {
+                    (#target).#delegate(#(#args),*)
+                })
+                .await
+            }
+        });
+    }
+    let (impl_generics, _, where_clause) = item.generics.split_for_impl();
+    let trait_ = &item.trait_;
+    let self_ty = &item.self_ty;
+    Ok(quote! {
+        impl #impl_generics #trait_ for #self_ty #where_clause {
+            #(#methods)*
+        }
+    })
+}
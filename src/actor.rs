@@ -0,0 +1,222 @@
+//! Actors: a blocking-style message handler running in a coroutine.
+//!
+//! [`CoroutineActor::new`] returns the actor, a future that runs the handler
+//! for every message in its mailbox, and an [`Address`] through which any
+//! task can post messages to it. The handler receives each message together
+//! with the coroutine's [`CoroutineAwaiter`], so it can await futures in the
+//! middle of handling one; later messages queue up meanwhile. The actor
+//! finishes once every address is dropped and the mailbox is drained.
+
+use core::fmt;
+use core::future::Future;
+use core::future::poll_fn;
+use core::pin::Pin;
+use core::pin::pin;
+use core::task::Context;
+use core::task::Poll;
+
+use alloc::collections::VecDeque;
+use std::sync::Mutex;
+
+use awaiter_trait::Awaiter;
+
+use crate::CoroutineAwaiter;
+use crate::CoroutineFuture;
+use crate::sync::Arc;
+use crate::sync::AtomicBool;
+use crate::sync::AtomicUsize;
+use crate::sync::AtomicWaker;
+use crate::sync::Ordering;
+
+/// Error returned when posting to an actor that has stopped, handing the
+/// message back.
+pub struct SendError<M>(pub M);
+
+impl<M> fmt::Debug for SendError<M> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("SendError").finish_non_exhaustive()
+    }
+}
+
+impl<M> fmt::Display for SendError<M> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("the actor has stopped")
+    }
+}
+
+impl<M> core::error::Error for SendError<M> {}
+
+/// The queue between the addresses of an actor and its handler.
+struct Mailbox<M> {
+    queue: Mutex<VecDeque<M>>,
+    /// Number of live [`Address`]es.
+    senders: AtomicUsize,
+    /// The actor has been dropped or its handler returned.
+    stopped: AtomicBool,
+    /// Woken when a message arrives or the last address is dropped.
+    waker: AtomicWaker,
+}
+
+impl<M> Mailbox<M> {
+    fn pop(&self) -> Option<M> {
+        self.queue.lock().unwrap_or_else(|e| e.into_inner()).pop_front()
+    }
+
+    fn poll_recv(&self, cx: &mut Context<'_>) -> Poll<Option<M>> {
+        if let Some(message) = self.pop() {
+            return Poll::Ready(Some(message));
+        }
+        self.waker.register(cx.waker());
+        // Checked again after registering, so a racing send is not missed.
+        if let Some(message) = self.pop() {
+            return Poll::Ready(Some(message));
+        }
+        if self.senders.load(Ordering::Acquire) == 0 {
+            return Poll::Ready(None);
+        }
+        Poll::Pending
+    }
+}
+
+/// A handle for posting messages to a [`CoroutineActor`].
+pub struct Address<M> {
+    mailbox: Arc<Mailbox<M>>,
+}
+
+impl<M> Address<M> {
+    /// Queues `message` for the actor.
+    ///
+    /// Fails, returning the message, if the actor has stopped.
+    pub fn send(&self, message: M) -> Result<(), SendError<M>> {
+        if self.is_stopped() {
+            return Err(SendError(message));
+        }
+        self.mailbox
+            .queue
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push_back(message);
+        self.mailbox.waker.wake();
+        Ok(())
+    }
+
+    /// Returns `true` once the actor has been dropped or its handler has returned.
+    pub fn is_stopped(&self) -> bool {
+        self.mailbox.stopped.load(Ordering::Acquire)
+    }
+}
+
+impl<M> Clone for Address<M> {
+    fn clone(&self) -> Self {
+        self.mailbox.senders.fetch_add(1, Ordering::Relaxed);
+        Self {
+            mailbox: self.mailbox.clone(),
+        }
+    }
+}
+
+impl<M> Drop for Address<M> {
+    fn drop(&mut self) {
+        if self.mailbox.senders.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.mailbox.waker.wake();
+        }
+    }
+}
+
+impl<M> fmt::Debug for Address<M> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Address")
+            .field("stopped", &self.is_stopped())
+            .finish_non_exhaustive()
+    }
+}
+
+/// A coroutine handling messages of type `M`, returned by [`CoroutineActor::new`].
+///
+/// # Example
+///
+/// ```ignore
+/// use minicoro_awaiters::actor::CoroutineActor;
+///
+/// let (actor, address) = CoroutineActor::new(move |awaiter, request: Request| {
+///     let row = awaiter.r#await(Box::pin(db.query(request.key)));
+///     let _ = request.reply.send(row);
+/// });
+/// tokio::task::spawn_local(actor);
+/// address.send(request).unwrap();
+/// ```
+pub struct CoroutineActor<M> {
+    inner: CoroutineFuture,
+    mailbox: Arc<Mailbox<M>>,
+}
+
+impl<M> CoroutineActor<M> {
+    /// Creates an actor calling `handler` for every message posted to it.
+    #[track_caller]
+    pub fn new<H>(mut handler: H) -> (Self, Address<M>)
+    where
+        H: FnMut(&CoroutineAwaiter, M),
+    {
+        let mailbox = Arc::new(Mailbox {
+            queue: Mutex::new(VecDeque::new()),
+            senders: AtomicUsize::new(1),
+            stopped: AtomicBool::new(false),
+            waker: AtomicWaker::new(),
+        });
+        let inbox = mailbox.clone();
+        let inner = CoroutineFuture::new(move |awaiter| {
+            while let Some(message) = awaiter.r#await(pin!(poll_fn(|cx| inbox.poll_recv(cx)))) {
+                handler(&awaiter, message);
+            }
+        });
+        let address = Address {
+            mailbox: mailbox.clone(),
+        };
+        (Self { inner, mailbox }, address)
+    }
+
+    /// Returns a new address for the actor.
+    pub fn address(&self) -> Address<M> {
+        self.mailbox.senders.fetch_add(1, Ordering::Relaxed);
+        Address {
+            mailbox: self.mailbox.clone(),
+        }
+    }
+
+    /// Number of messages waiting in the mailbox.
+    pub fn pending(&self) -> usize {
+        self.mailbox
+            .queue
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .len()
+    }
+}
+
+impl<M> Future for CoroutineActor<M> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let this = self.get_mut();
+        let poll = Pin::new(&mut this.inner).poll(cx);
+        if poll.is_ready() {
+            this.mailbox.stopped.store(true, Ordering::Release);
+        }
+        poll
+    }
+}
+
+impl<M> fmt::Debug for CoroutineActor<M> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CoroutineActor")
+            .field("coroutine", &self.inner)
+            .field("pending", &self.pending())
+            .finish()
+    }
+}
+
+impl<M> Drop for CoroutineActor<M> {
+    fn drop(&mut self) {
+        self.mailbox.stopped.store(true, Ordering::Release);
+    }
+}
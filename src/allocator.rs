@@ -0,0 +1,118 @@
+//! Allocating coroutines from a caller-supplied allocator.
+//!
+//! With the `allocator-api2` feature, [`CoroutineFuture::new_in`] and
+//! [`CoroutineBuilder::build_in`] place the allocations the crate makes for
+//! a coroutine, the box holding its body closure and the slot its output is
+//! written to, in any [`allocator_api2::alloc::Allocator`], for projects that
+//! route allocations through tracking or deterministic allocators. Each
+//! allocation carries a clone of the allocator, which frees it again.
+//!
+//! Two allocations stay with the global allocator: minicoroutine allocates a
+//! coroutine's context and stack itself, and the shared state is an `Arc`,
+//! which cannot take an allocator on stable Rust.
+//!
+//! The allocator must be `'static`, since the output slot lives as long as
+//! the future, whose type does not mention the allocator. Arenas can be
+//! leaked to give out `&'static` references.
+
+use core::alloc::Layout;
+use core::ptr::NonNull;
+
+use allocator_api2::alloc::Allocator;
+
+use crate::BuildError;
+use crate::CoroutineAwaiter;
+use crate::CoroutineBuilder;
+use crate::CoroutineFuture;
+use crate::builder::Slots;
+
+/// A value stored together with the allocator that frees it.
+///
+/// `repr(C)` keeps `value` at the start, so a pointer to it is a pointer to
+/// the whole allocation.
+#[repr(C)]
+struct InAllocator<T, A> {
+    value: T,
+    alloc: A,
+}
+
+/// Allocates from `A`.
+struct AllocatorSlots<A>(A);
+
+impl<A: Allocator + Clone> Slots for AllocatorSlots<A> {
+    fn leak<T>(&self, value: T) -> Result<NonNull<T>, BuildError> {
+        let ptr = self
+            .0
+            .allocate(Layout::new::<InAllocator<T, A>>())
+            .map_err(|_| BuildError::new())?
+            .cast::<InAllocator<T, A>>();
+        let alloc = self.0.clone();
+        // SAFETY: `ptr` is valid for writes of an `InAllocator<T, A>` and suitably aligned.
+        unsafe { ptr.as_ptr().write(InAllocator { value, alloc }) };
+        Ok(ptr.cast())
+    }
+
+    unsafe fn take<T>(ptr: NonNull<T>) -> T {
+        let ptr = ptr.cast::<InAllocator<T, A>>();
+        // SAFETY: the caller guarantees `ptr` came from `leak` and is live.
+        let InAllocator { value, alloc } = unsafe { ptr.as_ptr().read() };
+        // SAFETY: allocated by a clone of `alloc` with this layout.
+        unsafe { alloc.deallocate(ptr.cast(), Layout::new::<InAllocator<T, A>>()) };
+        value
+    }
+}
+
+impl CoroutineBuilder {
+    /// Creates a coroutine running `f` with these settings, allocating its
+    /// closure and output slot from `alloc`.
+    ///
+    /// See the [module documentation](self) for what else it allocates.
+    #[track_caller]
+    pub fn build_in<T, F, A>(self, f: F, alloc: A) -> Result<CoroutineFuture<T>, BuildError>
+    where
+        F: FnOnce(CoroutineAwaiter) -> T,
+        A: Allocator + Clone + 'static,
+    {
+        self.build_in_slots(f, &AllocatorSlots(alloc))
+    }
+}
+
+impl<T> CoroutineFuture<T> {
+    /// Creates a coroutine whose closure and output slot are allocated from
+    /// `alloc`.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// use minicoro_awaiters::CoroutineFuture;
+    ///
+    /// let arena: &'static Bump = Box::leak(Box::new(Bump::new()));
+    /// let coro = CoroutineFuture::new_in(|awaiter| {
+    ///     awaiter.r#await(Box::pin(async { 42 }))
+    /// }, arena);
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if the coroutine cannot be created; use
+    /// [`try_new_in`](Self::try_new_in) to handle that case.
+    #[track_caller]
+    pub fn new_in<F, A>(f: F, alloc: A) -> Self
+    where
+        F: FnOnce(CoroutineAwaiter) -> T,
+        A: Allocator + Clone + 'static,
+    {
+        Self::try_new_in(f, alloc).unwrap()
+    }
+
+    /// Like [`new_in`](Self::new_in), but returns an error instead of
+    /// panicking if the coroutine cannot be created.
+    #[track_caller]
+    pub fn try_new_in<F, A>(f: F, alloc: A) -> Result<Self, BuildError>
+    where
+        F: FnOnce(CoroutineAwaiter) -> T,
+        A: Allocator + Clone + 'static,
+    {
+        CoroutineBuilder::new().build_in(f, alloc)
+    }
+}
@@ -0,0 +1,16 @@
+//! The coroutine implementation the crate runs on: minicoro, or with the
+//! `thread-backend` feature its emulation in [`thread_backend`](crate::thread_backend).
+
+#[cfg(not(feature = "thread-backend"))]
+pub(crate) use minicoroutine::Coroutine;
+#[cfg(not(feature = "thread-backend"))]
+pub(crate) use minicoroutine::CoroutineRef;
+#[cfg(not(feature = "thread-backend"))]
+pub(crate) use minicoroutine::GLOBAL;
+
+#[cfg(feature = "thread-backend")]
+pub(crate) use crate::thread_backend::Coroutine;
+#[cfg(feature = "thread-backend")]
+pub(crate) use crate::thread_backend::CoroutineRef;
+#[cfg(feature = "thread-backend")]
+pub(crate) use crate::thread_backend::GLOBAL;
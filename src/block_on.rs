@@ -0,0 +1,53 @@
+//! A minimal thread-parking executor.
+
+use core::future::IntoFuture;
+use core::pin::pin;
+use core::task::Context;
+use core::task::Poll;
+use core::task::Waker;
+
+use alloc::sync::Arc;
+use alloc::task::Wake;
+use std::thread::Thread;
+
+/// Wakes a parked thread.
+struct ThreadWaker(Thread);
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+/// Runs a future to completion on the current thread, parking it while the
+/// future is pending.
+///
+/// This is the simplest way to drive a [`CoroutineFuture`](crate::CoroutineFuture)
+/// from synchronous code. It must not be called from inside a coroutine, as
+/// parking would block the thread driving the outer executor.
+///
+/// # Example
+///
+/// ```ignore
+/// use minicoro_awaiters::{block_on, CoroutineFuture};
+///
+/// let value = block_on(CoroutineFuture::new(|awaiter| {
+///     awaiter.r#await(Box::pin(async { 42 }))
+/// }));
+/// assert_eq!(value, 42);
+/// ```
+pub fn block_on<F: IntoFuture>(future: F) -> F::Output {
+    let mut future = pin!(future.into_future());
+    let waker = Waker::from(Arc::new(ThreadWaker(std::thread::current())));
+    let mut cx = Context::from_waker(&waker);
+    loop {
+        match future.as_mut().poll(&mut cx) {
+            Poll::Ready(output) => return output,
+            Poll::Pending => std::thread::park(),
+        }
+    }
+}
@@ -0,0 +1,111 @@
+//! Running blocking calls from inside a coroutine without stalling its executor.
+//!
+//! Some code must call functions that block the thread, such as synchronous
+//! C libraries or file APIs. Calling them directly from a coroutine blocks
+//! every task on the executor thread as well. [`CoroutineAwaiter::block_in_place`]
+//! instead hands the call to the [`BlockingPool`] registered with
+//! [`CoroutineBuilder::blocking_pool`](crate::CoroutineBuilder::blocking_pool)
+//! and suspends the coroutine until the result comes back.
+//!
+//! [`TokioBlockingPool`] (with the `tokio` feature) uses tokio's blocking
+//! threads, and [`RayonPool`] (with the `rayon` feature) rayon's global pool.
+
+use alloc::boxed::Box;
+use core::pin::pin;
+
+use awaiter_trait::Awaiter;
+
+use crate::CoroutineAwaiter;
+use crate::spawn::handoff;
+use crate::waker::WakerSlot;
+
+/// A closure handed to a [`BlockingPool`].
+pub type BlockingJob = Box<dyn FnOnce() + Send + 'static>;
+
+/// Runs blocking closures on threads other than the coroutine's.
+pub trait BlockingPool: Send + Sync {
+    /// Runs `job` on a thread where blocking is acceptable.
+    fn run(&self, job: BlockingJob);
+}
+
+impl<W: WakerSlot> CoroutineAwaiter<W> {
+    /// Runs `f` on the coroutine's [`BlockingPool`] and suspends until it returns.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the coroutine was built without a blocking pool, or if the
+    /// pool drops the job without running it. With the `std` feature a panic
+    /// inside `f` is resumed in the coroutine.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// use minicoro_awaiters::{CoroutineFuture, blocking::TokioBlockingPool};
+    ///
+    /// let coro = CoroutineFuture::builder()
+    ///     .blocking_pool(TokioBlockingPool::current())
+    ///     .build(|awaiter| awaiter.block_in_place(|| unsafe { legacy_checksum(path) }))?;
+    /// ```
+    #[track_caller]
+    pub fn block_in_place<T, F>(&self, f: F) -> T
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let shared = self.coro.user_data();
+        let pool = shared
+            .blocking_pool()
+            .unwrap_or_else(|| panic!("{} has no blocking pool", shared.describe()));
+        let (completer, handle) = handoff();
+        #[cfg(feature = "std")]
+        let job: BlockingJob = Box::new(move || {
+            completer.complete(std::panic::catch_unwind(std::panic::AssertUnwindSafe(f)))
+        });
+        #[cfg(not(feature = "std"))]
+        let job: BlockingJob = Box::new(move || completer.complete(f()));
+        crate::events::allocated(shared, size_of_val(&*job));
+        pool.run(job);
+        let output = self
+            .r#await(pin!(handle))
+            .unwrap_or_else(|_| panic!("{} lost its blocking job", shared.describe()));
+        #[cfg(feature = "std")]
+        let output = output.unwrap_or_else(|payload| std::panic::resume_unwind(payload));
+        output
+    }
+}
+
+/// Runs blocking jobs on a tokio runtime's blocking threads.
+#[cfg(feature = "tokio")]
+#[derive(Clone, Debug)]
+pub struct TokioBlockingPool(pub tokio::runtime::Handle);
+
+#[cfg(feature = "tokio")]
+impl TokioBlockingPool {
+    /// Uses the runtime the caller is running on.
+    ///
+    /// # Panics
+    ///
+    /// Panics when called outside of a tokio runtime.
+    pub fn current() -> Self {
+        Self(tokio::runtime::Handle::current())
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl BlockingPool for TokioBlockingPool {
+    fn run(&self, job: BlockingJob) {
+        drop(self.0.spawn_blocking(job));
+    }
+}
+
+/// Runs blocking jobs on rayon's global thread pool.
+#[cfg(feature = "rayon")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RayonPool;
+
+#[cfg(feature = "rayon")]
+impl BlockingPool for RayonPool {
+    fn run(&self, job: BlockingJob) {
+        rayon::spawn(job);
+    }
+}
@@ -0,0 +1,157 @@
+//! Type-erased coroutines.
+//!
+//! [`CoroutineFuture::boxed_local`] and [`SendCoroutineFuture::boxed`] turn a
+//! coroutine into a plain boxed future, matching the `LocalBoxFuture` and
+//! `BoxFuture` aliases of `futures`, for APIs that take one. Boxing that way
+//! loses the coroutine's own methods; [`BoxCoroutine`] erases only the waker
+//! slot, so coroutines built with different [`WakerSlot`]s fit in one
+//! collection and can still be named, inspected and aborted.
+
+use core::fmt;
+use core::future::Future;
+use core::panic::Location;
+use core::pin::Pin;
+use core::task::Context;
+use core::task::Poll;
+
+use alloc::boxed::Box;
+
+use crate::CoroutineFuture;
+use crate::CoroutineStatus;
+use crate::send::SendCoroutineFuture;
+use crate::waker::WakerSlot;
+
+impl<T: 'static, W: WakerSlot + 'static> CoroutineFuture<T, W> {
+    /// Boxes the coroutine as a `Pin<Box<dyn Future>>`.
+    pub fn boxed_local(self) -> Pin<Box<dyn Future<Output = T>>> {
+        Box::pin(self)
+    }
+
+    /// Erases the coroutine's waker slot; see [`BoxCoroutine`].
+    pub fn erase(self) -> BoxCoroutine<T> {
+        BoxCoroutine(Box::new(self))
+    }
+}
+
+impl<T: Send + 'static> SendCoroutineFuture<T> {
+    /// Boxes the coroutine as a `Pin<Box<dyn Future + Send>>`, ready for
+    /// executors that store their tasks that way.
+    pub fn boxed(self) -> Pin<Box<dyn Future<Output = T> + Send>> {
+        Box::pin(self)
+    }
+}
+
+/// The parts of a [`CoroutineFuture`] that do not depend on its waker slot.
+trait Erased<T> {
+    fn poll(&mut self, cx: &mut Context<'_>) -> Poll<T>;
+    fn name(&self) -> Option<&str>;
+    fn location(&self) -> &'static Location<'static>;
+    fn id(&self) -> usize;
+    fn resume_count(&self) -> usize;
+    fn status(&self) -> CoroutineStatus;
+    fn abort(&mut self);
+}
+
+impl<T, W: WakerSlot> Erased<T> for CoroutineFuture<T, W> {
+    fn poll(&mut self, cx: &mut Context<'_>) -> Poll<T> {
+        Pin::new(self).poll(cx)
+    }
+
+    fn name(&self) -> Option<&str> {
+        CoroutineFuture::name(self)
+    }
+
+    fn location(&self) -> &'static Location<'static> {
+        CoroutineFuture::location(self)
+    }
+
+    fn id(&self) -> usize {
+        CoroutineFuture::id(self)
+    }
+
+    fn resume_count(&self) -> usize {
+        CoroutineFuture::resume_count(self)
+    }
+
+    fn status(&self) -> CoroutineStatus {
+        CoroutineFuture::status(self)
+    }
+
+    fn abort(&mut self) {
+        CoroutineFuture::abort(self)
+    }
+}
+
+/// A coroutine with output `T` and any waker slot, returned by
+/// [`CoroutineFuture::erase`].
+///
+/// # Example
+///
+/// ```ignore
+/// use minicoro_awaiters::boxed::BoxCoroutine;
+///
+/// let mut jobs: Vec<BoxCoroutine<u32>> = Vec::new();
+/// jobs.push(CoroutineFuture::new(|awaiter| count(awaiter)).erase());
+/// jobs.push(builder.build_with_slot::<CriticalSectionWaker, _, _>(|awaiter| count(awaiter))?.erase());
+/// ```
+pub struct BoxCoroutine<T = ()>(Box<dyn Erased<T>>);
+
+impl<T> BoxCoroutine<T> {
+    /// The coroutine's name, if one was given through the builder.
+    pub fn name(&self) -> Option<&str> {
+        self.0.name()
+    }
+
+    /// Where the coroutine was created.
+    pub fn location(&self) -> &'static Location<'static> {
+        self.0.location()
+    }
+
+    /// A number identifying the coroutine among all coroutines of the process.
+    pub fn id(&self) -> usize {
+        self.0.id()
+    }
+
+    /// Number of times the coroutine has been resumed.
+    pub fn resume_count(&self) -> usize {
+        self.0.resume_count()
+    }
+
+    /// The coroutine's current lifecycle state.
+    pub fn status(&self) -> CoroutineStatus {
+        self.0.status()
+    }
+
+    /// Unwinds the coroutine; see [`CoroutineFuture::abort`].
+    pub fn abort(&mut self) {
+        self.0.abort();
+    }
+}
+
+impl<T, W: WakerSlot + 'static> From<CoroutineFuture<T, W>> for BoxCoroutine<T>
+where
+    T: 'static,
+{
+    fn from(coro: CoroutineFuture<T, W>) -> Self {
+        coro.erase()
+    }
+}
+
+impl<T> Future for BoxCoroutine<T> {
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T> {
+        self.get_mut().0.poll(cx)
+    }
+}
+
+impl<T> fmt::Debug for BoxCoroutine<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BoxCoroutine")
+            .field("name", &self.name())
+            .field("location", &self.location())
+            .field("status", &self.status())
+            .field("resumes", &self.resume_count())
+            .finish_non_exhaustive()
+    }
+}
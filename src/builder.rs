@@ -0,0 +1,710 @@
+//! Configurable construction of [`CoroutineFuture`]s.
+//!
+//! Building a coroutine allocates four times: minicoroutine's context and
+//! stack (one block), the [`CoroutineShared`] state, and one box each for the
+//! body closure and its output. The body reclaims its closure on the first
+//! resume and the future frees the output slot when dropped, both through
+//! raw pointers. With the `allocator-api2` feature the closure and output
+//! boxes can come from a caller-supplied allocator (see `allocator`), but
+//! minicoroutine owns its block outright and the shared state is an `Arc`,
+//! so those two cannot. Systems that forbid the global allocator need a
+//! pool-backed `#[global_allocator]` for now.
+//!
+//! Each of those allocations reports failure as a [`BuildError`] rather than
+//! aborting, except the shared state outside `nightly` builds; see
+//! [`CoroutineFuture::try_new`].
+//!
+//! minicoro's per-coroutine push/pop storage lives in the first block, but
+//! it is not free for callers: minicoroutine passes the values of `resume`
+//! and `yield_` through it, so bytes pushed by anyone else would be read
+//! back as those. Values that cross a resume boundary go through the
+//! crate's typed channels instead:
+//! [`CoroutineConsumer`](crate::consumer::CoroutineConsumer) into the body
+//! and [`CoroutineGenerator`](crate::generator::CoroutineGenerator) out of
+//! it, whose handoff state is allocated once, with the coroutine.
+//!
+//! How minicoro switches contexts, through its assembly routines,
+//! `ucontext`, Windows fibers or Binaryen's asyncify, is fixed when its C
+//! source is compiled by `minicoro-sys`, so it cannot be a builder option.
+//! Neither `minicoro-sys` nor minicoroutine forwards the choice as a Cargo
+//! feature, and a feature of this crate cannot add defines to another
+//! crate's C build. The `cc` crate used by `minicoro-sys` honours `CFLAGS`,
+//! however, so a backend is forced with one of minicoro's `MCO_USE_ASM`,
+//! `MCO_USE_UCONTEXT`, `MCO_USE_FIBERS` or `MCO_USE_ASYNCIFY` defines:
+//!
+//! ```sh
+//! CFLAGS_aarch64_unknown_linux_musl="-DMCO_USE_UCONTEXT" cargo build --target aarch64-unknown-linux-musl
+//! ```
+
+use core::alloc::Layout;
+use core::fmt;
+use core::future::IntoFuture;
+use core::panic::Location;
+use core::ptr::NonNull;
+
+use alloc::boxed::Box;
+use alloc::string::String;
+
+use crate::ConfiguredToken;
+use crate::CoroutineAwaiter;
+use crate::CoroutineFuture;
+use crate::backend::Coroutine;
+use crate::backend::CoroutineRef;
+use crate::backend::GLOBAL;
+use crate::blocking::BlockingPool;
+use crate::cancel::Teardown;
+use crate::context::ThreadPolicy;
+use crate::coop;
+use crate::hooks::CoroutineHooks;
+use crate::panic;
+use crate::spawn::Spawner;
+use crate::stack;
+use crate::sync::Arc;
+use crate::sync::AtomicWaker;
+use crate::sync::dyn_arc;
+use crate::time::Sleeper;
+use crate::waker::CoroutineShared;
+use crate::waker::WakerSlot;
+
+/// Error returned when a coroutine cannot be created, because its stack or
+/// one of the crate's own allocations for it failed.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug, Hash)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct BuildError(());
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("failed to create the coroutine")
+    }
+}
+
+impl core::error::Error for BuildError {}
+
+impl BuildError {
+    pub(crate) const fn new() -> Self {
+        Self(())
+    }
+}
+
+/// Moves `value` to the heap like `Box::leak`, failing instead of aborting
+/// if the allocation does.
+///
+/// The result may be freed with `Box::from_raw`.
+pub(crate) fn try_leak<T>(value: T) -> Result<NonNull<T>, BuildError> {
+    let layout = Layout::new::<T>();
+    let ptr = if layout.size() == 0 {
+        NonNull::dangling()
+    } else {
+        // SAFETY: the layout has a non-zero size.
+        let ptr = unsafe { alloc::alloc::alloc(layout) }.cast::<T>();
+        NonNull::new(ptr).ok_or(BuildError(()))?
+    };
+    // SAFETY: `ptr` is valid for writes of a `T` and suitably aligned.
+    unsafe { ptr.as_ptr().write(value) };
+    Ok(ptr)
+}
+
+/// Allocates the body closure and output slot of a coroutine, which are
+/// freed through raw pointers once the builder has let go of them.
+pub(crate) trait Slots {
+    /// Moves `value` into a new allocation.
+    fn leak<T>(&self, value: T) -> Result<NonNull<T>, BuildError>;
+
+    /// Moves the value out of an allocation made by [`leak`](Self::leak) and
+    /// frees it.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must come from `leak` on this type and not have been freed.
+    unsafe fn take<T>(ptr: NonNull<T>) -> T;
+
+    /// Drops the value in an allocation made by [`leak`](Self::leak) and frees
+    /// it.
+    ///
+    /// # Safety
+    ///
+    /// As for [`take`](Self::take).
+    unsafe fn free<T>(ptr: NonNull<T>) {
+        drop(unsafe { Self::take(ptr) });
+    }
+}
+
+/// Allocates from the global allocator, through [`try_leak`].
+pub(crate) struct GlobalSlots;
+
+impl Slots for GlobalSlots {
+    fn leak<T>(&self, value: T) -> Result<NonNull<T>, BuildError> {
+        try_leak(value)
+    }
+
+    unsafe fn take<T>(ptr: NonNull<T>) -> T {
+        *unsafe { Box::from_raw(ptr.as_ptr()) }
+    }
+}
+
+/// A builder for [`CoroutineFuture`]s with non-default settings.
+///
+/// Obtained from [`CoroutineFuture::builder`].
+///
+/// # Example
+///
+/// ```ignore
+/// use minicoro_awaiters::CoroutineFuture;
+///
+/// let coro = CoroutineFuture::builder()
+///     .stack_size(64 << 10)
+///     .build(|awaiter| awaiter.r#await(Box::pin(async { 42 })))?;
+/// assert_eq!(coro.await, 42);
+/// ```
+#[derive(Clone)]
+pub struct CoroutineBuilder {
+    stack_size: Option<usize>,
+    prefault_stack: bool,
+    #[cfg(feature = "secure-stack")]
+    secure_stack: bool,
+    budget: Option<u32>,
+    resumes_per_poll: u32,
+    thread_policy: ThreadPolicy,
+    hooks: Option<Arc<dyn CoroutineHooks>>,
+    spawner: Option<Arc<dyn Spawner>>,
+    blocking_pool: Option<Arc<dyn BlockingPool>>,
+    #[cfg(feature = "std")]
+    time_slice: Option<core::time::Duration>,
+    #[cfg(feature = "std")]
+    watchdog: Option<crate::watchdog::Watchdog>,
+    #[cfg(feature = "std")]
+    panic_handler: Option<Arc<dyn crate::panic::PanicHandler>>,
+    sleeper: Option<Arc<dyn Sleeper>>,
+    detect_deadlocks: bool,
+    teardown: Teardown,
+    name: Option<Box<str>>,
+    location: Option<&'static Location<'static>>,
+}
+
+impl fmt::Debug for CoroutineBuilder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut f = f.debug_struct("CoroutineBuilder");
+        f.field("stack_size", &self.stack_size)
+            .field("prefault_stack", &self.prefault_stack);
+        #[cfg(feature = "secure-stack")]
+        f.field("secure_stack", &self.secure_stack);
+        f.field("budget", &self.budget)
+            .field("resumes_per_poll", &self.resumes_per_poll)
+            .field("thread_policy", &self.thread_policy)
+            .field("hooks", &self.hooks.is_some())
+            .field("spawner", &self.spawner.is_some())
+            .field("blocking_pool", &self.blocking_pool.is_some());
+        #[cfg(feature = "std")]
+        f.field("time_slice", &self.time_slice);
+        #[cfg(feature = "std")]
+        f.field("watchdog", &self.watchdog);
+        #[cfg(feature = "std")]
+        f.field("panic_handler", &self.panic_handler.is_some());
+        f.field("sleeper", &self.sleeper.is_some())
+            .field("detect_deadlocks", &self.detect_deadlocks)
+            .field("teardown", &self.teardown)
+            .field("name", &self.name)
+            .field("location", &self.location)
+            .finish()
+    }
+}
+
+impl Default for CoroutineBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CoroutineBuilder {
+    /// Creates a builder with the default settings.
+    pub fn new() -> Self {
+        Self {
+            stack_size: None,
+            prefault_stack: false,
+            #[cfg(feature = "secure-stack")]
+            secure_stack: false,
+            budget: Some(coop::DEFAULT_BUDGET),
+            resumes_per_poll: 1,
+            thread_policy: ThreadPolicy::Migrate,
+            hooks: None,
+            spawner: None,
+            blocking_pool: None,
+            #[cfg(feature = "std")]
+            time_slice: None,
+            #[cfg(feature = "std")]
+            watchdog: None,
+            #[cfg(feature = "std")]
+            panic_handler: None,
+            sleeper: None,
+            detect_deadlocks: cfg!(debug_assertions),
+            teardown: Teardown::default(),
+            name: None,
+            location: None,
+        }
+    }
+
+    /// Sets the size of the coroutine's stack in bytes.
+    ///
+    /// Defaults to minicoro's default stack size,
+    /// [`DEFAULT_STACK_SIZE`](stack::DEFAULT_STACK_SIZE).
+    ///
+    /// This is the only field of minicoro's `mco_desc` that minicoroutine
+    /// lets callers set. The total allocation size (`coro_size`) follows from
+    /// it, the storage used by minicoro's push/pop channel keeps minicoro's
+    /// default, and the allocator callbacks are minicoroutine's own, so the
+    /// builder cannot expose them until minicoroutine does.
+    pub fn stack_size(mut self, size: usize) -> Self {
+        self.stack_size = Some(size);
+        self
+    }
+
+    /// The size of the stacks this builder allocates.
+    pub(crate) fn stack_size_or_default(&self) -> usize {
+        self.stack_size.unwrap_or(stack::DEFAULT_STACK_SIZE)
+    }
+
+    /// Reserves a stack of `size` bytes that is committed lazily, so a
+    /// generous logical stack costs physical memory only for the pages the
+    /// coroutine actually uses.
+    ///
+    /// Sizes below [`LAZY_COMMIT_THRESHOLD`](stack::LAZY_COMMIT_THRESHOLD) are
+    /// rounded up to it. On targets where [`LAZY_COMMIT`](stack::LAZY_COMMIT)
+    /// does not hold, the rounded-up stack is committed up front, as with
+    /// [`stack_size`](Self::stack_size).
+    pub fn reserve_stack(self, size: usize) -> Self {
+        self.stack_size(size.max(stack::LAZY_COMMIT_THRESHOLD))
+    }
+
+    /// Sets whether every page of the stack, short of its lowest few KiB, is
+    /// touched when the coroutine is built, so the page faults of a fresh
+    /// stack are paid then instead of on the coroutine's first deep call.
+    ///
+    /// Building then resumes the coroutine once, before the body starts; it
+    /// does not count as a resume. Useful together with
+    /// [`CoroutinePool::prewarm`](crate::pool::CoroutinePool::prewarm) for
+    /// latency-sensitive services. Defaults to `false`.
+    pub fn prefault_stack(mut self, prefault: bool) -> Self {
+        self.prefault_stack = prefault;
+        self
+    }
+
+    /// Locks the coroutine's stack into memory while the body runs and
+    /// zeroes it once the coroutine completes or is dropped.
+    ///
+    /// For bodies handling secrets such as keys or passwords, so that what
+    /// they leave on the stack is neither swapped out nor handed out again
+    /// by the allocator. See the [`stack`] module for what is and is not
+    /// covered. Defaults to `false`.
+    #[cfg(feature = "secure-stack")]
+    pub fn secure_stack(mut self, secure: bool) -> Self {
+        self.secure_stack = secure;
+        self
+    }
+
+    /// Sets how many ready awaits the coroutine may complete per resume.
+    ///
+    /// See [`CoroutineFuture::set_budget`].
+    pub fn budget(mut self, budget: Option<u32>) -> Self {
+        self.budget = budget;
+        self
+    }
+
+    /// Sets how many times one poll of the future may resume the coroutine.
+    ///
+    /// After a resume ends with the coroutine already woken again, because
+    /// its [budget](Self::budget) ran out or an awaited future woke it right
+    /// away, the future resumes it again instead of returning `Pending` and
+    /// waiting for the executor, up to `resumes` times per poll. A poll thus
+    /// completes up to `budget * resumes` ready awaits. Defaults to 1; 0 is
+    /// treated as 1.
+    pub fn resumes_per_poll(mut self, resumes: u32) -> Self {
+        self.resumes_per_poll = resumes;
+        self
+    }
+
+    /// Sets which threads may resume the coroutine.
+    ///
+    /// See the [`context`](crate::context) module for when
+    /// [`ThreadPolicy::Pinned`] is needed.
+    pub fn thread_policy(mut self, policy: ThreadPolicy) -> Self {
+        self.thread_policy = policy;
+        self
+    }
+
+    /// Registers lifecycle hooks fired around every resume.
+    pub fn hooks(self, hooks: impl CoroutineHooks + 'static) -> Self {
+        self.shared_hooks(dyn_arc!(hooks => dyn CoroutineHooks))
+    }
+
+    /// Registers lifecycle hooks shared with other coroutines.
+    pub fn shared_hooks(mut self, hooks: Arc<dyn CoroutineHooks>) -> Self {
+        self.hooks = Some(hooks);
+        self
+    }
+
+    /// Sets the spawner used by [`CoroutineAwaiter::spawn`].
+    pub fn spawner(self, spawner: impl Spawner + 'static) -> Self {
+        self.shared_spawner(dyn_arc!(spawner => dyn Spawner))
+    }
+
+    /// Sets a spawner shared with other coroutines.
+    pub fn shared_spawner(mut self, spawner: Arc<dyn Spawner>) -> Self {
+        self.spawner = Some(spawner);
+        self
+    }
+
+    /// Sets the pool used by [`CoroutineAwaiter::block_in_place`].
+    pub fn blocking_pool(self, pool: impl BlockingPool + 'static) -> Self {
+        self.shared_blocking_pool(dyn_arc!(pool => dyn BlockingPool))
+    }
+
+    /// Sets a blocking pool shared with other coroutines.
+    pub fn shared_blocking_pool(mut self, pool: Arc<dyn BlockingPool>) -> Self {
+        self.blocking_pool = Some(pool);
+        self
+    }
+
+    /// Makes the coroutine yield back to the executor at its next ready
+    /// await, or [`checkpoint`](CoroutineAwaiter::checkpoint), once a resume
+    /// has run for `slice`.
+    ///
+    /// Enforces what the [`watchdog`](Self::watchdog) only reports, for
+    /// bodies whose awaits are often ready at once; see [`coop`]. Disabled
+    /// by default.
+    #[cfg(feature = "std")]
+    pub fn time_slice(mut self, slice: core::time::Duration) -> Self {
+        self.time_slice = Some(slice);
+        self
+    }
+
+    /// Reports resumes of the coroutine that run longer than the watchdog's
+    /// threshold without yielding; see [`watchdog`](crate::watchdog).
+    #[cfg(feature = "std")]
+    pub fn watchdog(mut self, watchdog: crate::watchdog::Watchdog) -> Self {
+        self.watchdog = Some(watchdog);
+        self
+    }
+
+    /// Decides what happens to panics of the body with `handler` instead of
+    /// the global one; see [`panic`](crate::panic).
+    #[cfg(feature = "std")]
+    pub fn on_panic(self, handler: impl crate::panic::PanicHandler + 'static) -> Self {
+        self.shared_panic_handler(dyn_arc!(handler => dyn crate::panic::PanicHandler))
+    }
+
+    /// Decides what happens to panics of the body with a handler shared with
+    /// other coroutines.
+    #[cfg(feature = "std")]
+    pub fn shared_panic_handler(mut self, handler: Arc<dyn crate::panic::PanicHandler>) -> Self {
+        self.panic_handler = Some(handler);
+        self
+    }
+
+    /// Sets the timer source used by [`CoroutineAwaiter::await_timeout`].
+    pub fn sleeper(self, sleeper: impl Sleeper + 'static) -> Self {
+        self.shared_sleeper(dyn_arc!(sleeper => dyn Sleeper))
+    }
+
+    /// Sets a timer source shared with other coroutines.
+    pub fn shared_sleeper(mut self, sleeper: Arc<dyn Sleeper>) -> Self {
+        self.sleeper = Some(sleeper);
+        self
+    }
+
+    /// Sets whether the coroutine panics when it parks on a future that can
+    /// never wake it, such as one that dropped its waker without waking it.
+    ///
+    /// The panic names the coroutine instead of leaving its task hanging
+    /// silently. Enabled by default in debug builds. Awaits issued from C
+    /// through [`CAwaiter`](crate::ffi::CAwaiter) are never checked, since
+    /// the panic could not unwind out of the C frames.
+    pub fn detect_deadlocks(mut self, detect: bool) -> Self {
+        self.detect_deadlocks = detect;
+        self
+    }
+
+    /// Sets how an abort, such as dropping the coroutine mid-await, tears
+    /// down its body; see [`Teardown`].
+    ///
+    /// Defaults to unwinding where the crate can, and to leaking the body
+    /// without the `std` feature or in `panic = "abort"` builds.
+    pub fn teardown(mut self, teardown: Teardown) -> Self {
+        self.teardown = teardown;
+        self
+    }
+
+    /// Names the coroutine.
+    ///
+    /// The name is exposed through [`CoroutineFuture::name`] and included in
+    /// diagnostics such as propagated panics and tracing spans.
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into().into_boxed_str());
+        self
+    }
+
+    /// Overrides the recorded creation site.
+    ///
+    /// By default this is the caller of [`build`](Self::build); wrappers that
+    /// create coroutines on behalf of their own callers can forward a
+    /// [`Location::caller`] captured in a `#[track_caller]` function instead.
+    pub fn location(mut self, location: &'static Location<'static>) -> Self {
+        self.location = Some(location);
+        self
+    }
+
+    /// Prepares a coroutine running `f` to be awaited directly.
+    ///
+    /// The coroutine is created when the result is awaited, which panics if
+    /// that fails; use [`build`](Self::build) to handle the error instead.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// use minicoro_awaiters::CoroutineFuture;
+    ///
+    /// let value = CoroutineFuture::builder()
+    ///     .stack_size(64 << 10)
+    ///     .run(|awaiter| awaiter.r#await(Box::pin(async { 42 })))
+    ///     .await;
+    /// ```
+    #[track_caller]
+    pub fn run<T, F: FnOnce(CoroutineAwaiter) -> T>(self, f: F) -> CoroutineRun<F> {
+        let location = self.location.unwrap_or_else(Location::caller);
+        CoroutineRun {
+            builder: self.location(location),
+            f,
+        }
+    }
+
+    /// Turns the builder into a [`ConfiguredToken`] creating every coroutine
+    /// it is asked for with these settings.
+    pub fn token(self) -> ConfiguredToken {
+        ConfiguredToken::new(self)
+    }
+
+    /// Creates a coroutine running `f` with these settings.
+    ///
+    /// See [`CoroutineFuture::new`] for how `f` is run.
+    #[track_caller]
+    pub fn build<T, F: FnOnce(CoroutineAwaiter) -> T>(
+        self,
+        f: F,
+    ) -> Result<CoroutineFuture<T>, BuildError> {
+        self.build_with_slot::<AtomicWaker, T, F>(f)
+    }
+
+    /// Creates a coroutine that stores the executor's waker in a `W` instead
+    /// of the default [`AtomicWaker`].
+    ///
+    /// See the [`waker`](crate::waker) module for when a different
+    /// [`WakerSlot`] pays off.
+    #[track_caller]
+    pub fn build_with_slot<W: WakerSlot, T, F: FnOnce(CoroutineAwaiter<W>) -> T>(
+        self,
+        f: F,
+    ) -> Result<CoroutineFuture<T, W>, BuildError> {
+        self.build_in_slots(f, &GlobalSlots)
+    }
+
+    /// Creates a coroutine whose body closure and output slot are allocated
+    /// by `slots`.
+    #[track_caller]
+    pub(crate) fn build_in_slots<W, T, F, S>(
+        self,
+        f: F,
+        slots: &S,
+    ) -> Result<CoroutineFuture<T, W>, BuildError>
+    where
+        W: WakerSlot,
+        F: FnOnce(CoroutineAwaiter<W>) -> T,
+        S: Slots,
+    {
+        let a = slots.leak(f)?.as_ptr();
+        let output = match slots.leak(None::<panic::Outcome<T>>) {
+            Ok(output) => output,
+            Err(error) => {
+                // SAFETY: nothing else has seen the closure yet.
+                unsafe { S::free(NonNull::new_unchecked(a)) };
+                return Err(error);
+            }
+        };
+        let slot = output.as_ptr();
+        let prefault = self.prefault_stack;
+        let body = move |p: CoroutineRef<(), (), (), Arc<CoroutineShared<W>>, GLOBAL>| {
+            #[cfg(feature = "sanitize")]
+            let _switch = crate::sanitize::BodySwitch::enter(p.user_data());
+            // Recorded in this small frame, before the body closure is moved
+            // onto the stack, so only minicoro's entry frames lie above it.
+            p.user_data().set_stack_base(stack::stack_pointer());
+            // SAFETY: `a` and `slot` were leaked for this body, which runs once.
+            unsafe { run_body::<W, T, F, S>(p, a, slot, prefault) }
+        };
+        let location = self.location.unwrap_or_else(Location::caller);
+        let mut shared = CoroutineShared::<W>::new(self.name, location);
+        shared.set_stack_size(self.stack_size.unwrap_or(stack::DEFAULT_STACK_SIZE));
+        #[cfg(feature = "secure-stack")]
+        shared.set_secure_stack(self.secure_stack);
+        shared.set_resumes_per_poll(self.resumes_per_poll);
+        shared.set_thread_policy(self.thread_policy);
+        shared.set_spawner(self.spawner);
+        shared.set_blocking_pool(self.blocking_pool);
+        #[cfg(feature = "std")]
+        shared.set_time_slice(self.time_slice);
+        #[cfg(feature = "std")]
+        shared.set_watchdog(self.watchdog);
+        #[cfg(feature = "std")]
+        shared.set_panic_handler(self.panic_handler);
+        shared.set_sleeper(self.sleeper);
+        shared.set_detect_deadlocks(self.detect_deadlocks);
+        shared.set_teardown(self.teardown);
+        let Ok(shared) = crate::sync::try_arc(shared) else {
+            // SAFETY: nothing else has seen either allocation yet.
+            unsafe {
+                S::free(NonNull::new_unchecked(a));
+                S::free(output);
+            }
+            return Err(BuildError(()));
+        };
+        shared.set_budget(self.budget);
+        let coro = match self.stack_size {
+            Some(size) => Coroutine::with_stack_size(body, shared, size),
+            None => Coroutine::new(body, shared),
+        };
+        if let Ok(coro) = &coro {
+            let shared = coro.user_data();
+            crate::events::created(shared);
+            crate::events::allocated(shared, size_of::<F>());
+            crate::events::allocated(shared, size_of::<Option<panic::Outcome<T>>>());
+            // The shared state, behind the two reference counts of its `Arc`.
+            let arc = size_of::<CoroutineShared<W>>() + 2 * size_of::<usize>();
+            crate::events::allocated(shared, arc);
+            let stack = self.stack_size.unwrap_or(stack::DEFAULT_STACK_SIZE);
+            crate::events::allocated(shared, stack);
+        }
+        match coro {
+            Ok(mut coro) => {
+                if prefault {
+                    // Runs the body up to its prefault yield.
+                    let _guard = panic::ResumeGuard::new();
+                    #[cfg(feature = "sanitize")]
+                    let _switch = crate::sanitize::ResumeSwitch::enter(coro.user_data());
+                    coro.resume(());
+                }
+                Ok(CoroutineFuture {
+                    coro,
+                    output,
+                    free_output: S::free::<Option<panic::Outcome<T>>>,
+                    hooks: self.hooks,
+                    done: false,
+                    #[cfg(feature = "tracing")]
+                    span: tracing::Span::none(),
+                })
+            }
+            Err(_) => {
+                // SAFETY: the body never ran, so both allocations are still
+                // owned here.
+                unsafe {
+                    S::free(NonNull::new_unchecked(a));
+                    S::free(output);
+                }
+                Err(BuildError(()))
+            }
+        }
+    }
+}
+
+/// Runs the body `a` of a coroutine built by [`CoroutineBuilder::build_in_slots`],
+/// storing its outcome in `slot`.
+///
+/// Kept out of line, so that the body closure and everything else it moves
+/// live below the stack base recorded by the caller.
+///
+/// # Safety
+///
+/// `a` must have been leaked by `S` and not freed, and `slot` must be valid
+/// for writes; the call consumes both.
+#[inline(never)]
+unsafe fn run_body<W, T, F, S>(
+    p: CoroutineRef<(), (), (), Arc<CoroutineShared<W>>, GLOBAL>,
+    a: *mut F,
+    slot: *mut Option<panic::Outcome<T>>,
+    prefault: bool,
+) where
+    W: WakerSlot,
+    F: FnOnce(CoroutineAwaiter<W>) -> T,
+    S: Slots,
+{
+    // SAFETY: guaranteed by the caller.
+    let f = unsafe { S::take(NonNull::new_unchecked(a)) };
+    if p.user_data().is_aborted() {
+        // Aborted before the first resume: drop the body unrun.
+        return;
+    }
+    #[cfg(feature = "secure-stack")]
+    if p.user_data().secure_stack() {
+        stack::lock(p.user_data());
+    }
+    if prefault {
+        // Touched during `build`; the body starts on the next resume.
+        stack::prefault(p.user_data());
+        {
+            #[cfg(feature = "sanitize")]
+            let _switch = crate::sanitize::SuspendSwitch::enter(p.user_data());
+            p.yield_(());
+        }
+        if p.user_data().is_aborted() {
+            return;
+        }
+    }
+    let outcome = panic::catch(move || f(CoroutineAwaiter { coro: p }));
+    // SAFETY: guaranteed by the caller.
+    unsafe { *slot = Some(outcome) };
+}
+
+/// A coroutine body together with its settings, returned by
+/// [`CoroutineBuilder::run`].
+///
+/// Awaiting it builds and runs the coroutine.
+pub struct CoroutineRun<F> {
+    builder: CoroutineBuilder,
+    f: F,
+}
+
+impl<F> CoroutineRun<F> {
+    /// Creates the coroutine, reporting failure instead of panicking.
+    pub fn build<T>(self) -> Result<CoroutineFuture<T>, BuildError>
+    where
+        F: FnOnce(CoroutineAwaiter) -> T,
+    {
+        self.builder.build(self.f)
+    }
+}
+
+impl<T, F: FnOnce(CoroutineAwaiter) -> T> IntoFuture for CoroutineRun<F> {
+    type Output = T;
+    type IntoFuture = CoroutineFuture<T>;
+
+    fn into_future(self) -> CoroutineFuture<T> {
+        self.build().unwrap()
+    }
+}
+
+impl<F> fmt::Debug for CoroutineRun<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CoroutineRun")
+            .field("builder", &self.builder)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Creates the span entered around every resume of a coroutine, as a child
+/// of the current span.
+#[cfg(feature = "tracing")]
+pub(crate) fn span<W: WakerSlot>(shared: &CoroutineShared<W>) -> tracing::Span {
+    tracing::debug_span!(
+        "coroutine",
+        name = shared.name().unwrap_or("<unnamed>"),
+        id = shared.id(),
+        location = %shared.location(),
+    )
+}
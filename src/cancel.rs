@@ -0,0 +1,340 @@
+//! Cooperative cancellation of coroutines.
+//!
+//! Async code requests cancellation with [`CoroutineFuture::cancel`] or a
+//! [`CancelHandle`]. Nothing is interrupted by force: the body notices at its
+//! next [`r#await_cancellable`](CoroutineAwaiter::r#await_cancellable), which
+//! then fails with [`Cancelled`], or by checking
+//! [`is_cancelled`](CoroutineAwaiter::is_cancelled) in CPU-bound loops, and
+//! winds down however it sees fit.
+//!
+//! Dropping a [`CoroutineFuture`] before it completes is the forceful
+//! counterpart: with the `std` feature the drop [aborts](CoroutineFuture::abort)
+//! the coroutine, unwinding its stack so the future it was blocked on (and
+//! everything else the body holds) is dropped right away, running its usual
+//! cancellation logic. Without `std`, or in `panic = "abort"` builds, the
+//! suspended body is abandoned instead. [`Teardown`], chosen per coroutine
+//! with [`CoroutineBuilder::teardown`](crate::CoroutineBuilder::teardown),
+//! replaces unwinding there with cooperative cancellation that lets the
+//! body return on its own.
+//!
+//! Cleanup that has to await, such as flushing a socket or releasing a
+//! distributed lock, is registered with [`CoroutineAwaiter::defer`]. The
+//! returned guard awaits its cleanup future when it is dropped as the body
+//! returns, so the cleanup completes before the outer future resolves.
+//! Nothing suspends while the body unwinds, from a panic or an abort, as that
+//! would leave the executor's thread panicking in the meantime: the cleanup
+//! is polled once then and dropped if it is not done. Teardown that should
+//! wait on the executor goes through [`CoroutineFuture::shutdown`], which
+//! winds the body down by cancellation instead of unwinding it.
+
+use core::fmt;
+use core::future::Future;
+use core::future::IntoFuture;
+use core::future::poll_fn;
+use core::pin::Pin;
+use core::pin::pin;
+use core::task::Context;
+use core::task::Poll;
+use core::task::Waker;
+
+use awaiter_trait::Awaiter;
+
+use crate::CoroutineAwaiter;
+use crate::CoroutineFuture;
+use crate::Resumed;
+use crate::sync::Arc;
+use crate::sync::AtomicWaker;
+use crate::waker::CoroutineShared;
+use crate::waker::WakerSlot;
+
+/// How an [abort](CoroutineFuture::abort) tears down a body suspended
+/// mid-await, set with [`CoroutineBuilder::teardown`](crate::CoroutineBuilder::teardown).
+///
+/// Coroutines that were never resumed are dropped unrun by every strategy.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug, Hash)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Teardown {
+    /// Unwinds the body's stack from the await it is suspended in, dropping
+    /// everything on it. The default where it is available: it needs the
+    /// `std` feature and `panic = "unwind"`, and acts as
+    /// [`Leak`](Self::Leak) elsewhere.
+    ///
+    /// Destructors that await during the unwind are resumed at most
+    /// [`UNWIND_MAX_RESUMES`] times in all; the rest of a body still
+    /// unwinding after that is leaked.
+    Unwind,
+    /// Requests [cancellation](CoroutineFuture::cancel) and resumes the body,
+    /// up to `max_resumes` times, so it can notice through
+    /// [`r#await_cancellable`](CoroutineAwaiter::r#await_cancellable) or
+    /// [`is_cancelled`](CoroutineAwaiter::is_cancelled) and return on its
+    /// own. A body still running after that is leaked as by
+    /// [`Leak`](Self::Leak).
+    ///
+    /// Works without unwinding, so it suits `panic = "abort"` builds whose
+    /// bodies are written to wind down on cancellation.
+    Cooperative {
+        /// How many resumes the body gets to return.
+        max_resumes: u32,
+    },
+    /// Abandons the body where it is suspended: nothing on its stack is
+    /// dropped, and the leak is reported through the `log` or `defmt`
+    /// backend. The default without unwinding.
+    Leak,
+}
+
+/// How many times an abort resumes a body that suspends while it is being
+/// unwound; see [`Teardown::Unwind`].
+pub const UNWIND_MAX_RESUMES: u32 = 64;
+
+impl Teardown {
+    /// The strategy actually used: [`Unwind`](Self::Unwind) falls back to
+    /// [`Leak`](Self::Leak) where the stack cannot be unwound.
+    pub(crate) fn effective(self) -> Self {
+        match self {
+            Self::Unwind if !cfg!(all(feature = "std", panic = "unwind")) => Self::Leak,
+            teardown => teardown,
+        }
+    }
+}
+
+impl Default for Teardown {
+    fn default() -> Self {
+        Self::Unwind.effective()
+    }
+}
+
+/// Error returned by awaits interrupted by cancellation.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug, Hash, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Cancelled;
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("the coroutine was cancelled")
+    }
+}
+
+impl core::error::Error for Cancelled {}
+
+/// A handle for cancelling a coroutine from anywhere, obtained from
+/// [`CoroutineFuture::cancel_handle`].
+pub struct CancelHandle<W = AtomicWaker>(Arc<CoroutineShared<W>>);
+
+impl<W> Clone for CancelHandle<W> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<W: WakerSlot> CancelHandle<W> {
+    /// Requests cancellation; see [`CoroutineFuture::cancel`].
+    pub fn cancel(&self) {
+        self.0.cancel();
+    }
+
+    /// Returns `true` once cancellation has been requested.
+    pub fn is_cancelled(&self) -> bool {
+        self.0.is_cancelled()
+    }
+}
+
+impl<W> fmt::Debug for CancelHandle<W> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("CancelHandle").field(&self.0).finish()
+    }
+}
+
+impl<T, W: WakerSlot> CoroutineFuture<T, W> {
+    /// Requests cancellation of the coroutine.
+    ///
+    /// A body parked in [`r#await_cancellable`](CoroutineAwaiter::r#await_cancellable)
+    /// is woken and the await fails with [`Cancelled`]; plain awaits are not
+    /// affected. The future still has to be polled for the body to wind down.
+    pub fn cancel(&self) {
+        self.coro.user_data().cancel();
+    }
+
+    /// Returns `true` once cancellation has been requested.
+    pub fn is_cancelled(&self) -> bool {
+        self.coro.user_data().is_cancelled()
+    }
+
+    /// Returns a handle that can cancel the coroutine after the future has
+    /// been moved into an executor.
+    pub fn cancel_handle(&self) -> CancelHandle<W> {
+        CancelHandle(self.coro.user_data().clone())
+    }
+}
+
+impl<W: WakerSlot> CoroutineAwaiter<W> {
+    /// Returns `true` once cancellation of the running coroutine has been requested.
+    ///
+    /// This is a single atomic load, cheap enough to check in tight loops.
+    pub fn is_cancelled(&self) -> bool {
+        self.coro.user_data().is_cancelled()
+    }
+
+    /// Blocks on `future` unless the coroutine is cancelled first.
+    ///
+    /// Fails with [`Cancelled`] without polling `future` if cancellation was
+    /// already requested, or at the first resume after it is requested while
+    /// `future` is pending. `future` is dropped unfinished in both cases.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// use minicoro_awaiters::CoroutineFuture;
+    ///
+    /// let coro = CoroutineFuture::new(|awaiter| loop {
+    ///     let Ok(request) = awaiter.r#await_cancellable(next_request()) else {
+    ///         break;
+    ///     };
+    ///     handle(request);
+    /// });
+    /// let handle = coro.cancel_handle();
+    /// ```
+    #[track_caller]
+    pub fn r#await_cancellable<F: IntoFuture>(&self, future: F) -> Result<F::Output, Cancelled> {
+        let shared = self.coro.user_data();
+        let mut future = pin!(future.into_future());
+        self.r#await(pin!(poll_fn(|cx| {
+            if shared.is_cancelled() {
+                return Poll::Ready(Err(Cancelled));
+            }
+            future.as_mut().poll(cx).map(Ok)
+        })))
+    }
+}
+
+impl<W: WakerSlot> CoroutineAwaiter<W> {
+    /// Registers `cleanup` to be awaited when the returned guard is dropped.
+    ///
+    /// Keep the guard alive for as long as the cleanup should be armed; drop
+    /// it early to run the cleanup then, or [`disarm`](Defer::disarm) it to
+    /// skip it. Without the `std` feature an aborted body is abandoned rather
+    /// than unwound, and its cleanups never run.
+    ///
+    /// A guard dropped while the body unwinds, from a panic or an abort,
+    /// polls its cleanup once without suspending and drops it if it is not
+    /// done; [`CoroutineFuture::shutdown`] lets cleanups that have to wait
+    /// run to completion.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// use minicoro_awaiters::CoroutineFuture;
+    ///
+    /// let coro = CoroutineFuture::new(|awaiter| {
+    ///     let lock = awaiter.r#await(Box::pin(acquire_lock()));
+    ///     let _release = awaiter.defer(lock.release());
+    ///     awaiter.r#await(Box::pin(do_work()))
+    /// });
+    /// ```
+    pub fn defer<F>(&self, cleanup: F) -> Defer<'_, F::IntoFuture, W>
+    where
+        F: IntoFuture<Output = ()>,
+    {
+        Defer {
+            awaiter: self,
+            cleanup: Some(cleanup.into_future()),
+        }
+    }
+}
+
+/// A cleanup future awaited on drop, returned by [`CoroutineAwaiter::defer`].
+pub struct Defer<'a, F: Future<Output = ()>, W: WakerSlot = AtomicWaker> {
+    awaiter: &'a CoroutineAwaiter<W>,
+    cleanup: Option<F>,
+}
+
+impl<F: Future<Output = ()>, W: WakerSlot> Defer<'_, F, W> {
+    /// Drops the guard without running its cleanup, returning the future.
+    pub fn disarm(mut self) -> F {
+        self.cleanup.take().unwrap()
+    }
+}
+
+impl<F: Future<Output = ()>, W: WakerSlot> Drop for Defer<'_, F, W> {
+    fn drop(&mut self) {
+        if let Some(cleanup) = self.cleanup.take() {
+            // Suspending mid-unwind would leave the executor's thread
+            // panicking until the coroutine is resumed again.
+            #[cfg(feature = "std")]
+            if std::thread::panicking() {
+                let _ = pin!(cleanup).poll(&mut Context::from_waker(Waker::noop()));
+                return;
+            }
+            self.awaiter.r#await(pin!(cleanup));
+        }
+    }
+}
+
+impl<F: Future<Output = ()>, W: WakerSlot> fmt::Debug for Defer<'_, F, W> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Defer")
+            .field("armed", &self.cleanup.is_some())
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T, W: WakerSlot> CoroutineFuture<T, W> {
+    /// Cancels the coroutine and drives its teardown on the executor.
+    ///
+    /// The body is [cancelled](CoroutineFuture::cancel) and then resumed
+    /// whenever it wakes, so it winds down through its
+    /// [`r#await_cancellable`](CoroutineAwaiter::r#await_cancellable) calls
+    /// and its [`Defer`] guards await their cleanups as it returns. Nothing
+    /// is unwound. Resolves to the body's output, or to `None` if it had not
+    /// started. A body that ignores cancellation keeps the shutdown pending;
+    /// dropping the `Shutdown` aborts it as dropping the coroutine would.
+    pub fn shutdown(self) -> Shutdown<T, W> {
+        Shutdown {
+            inner: self,
+            requested: false,
+        }
+    }
+}
+
+/// The teardown of a coroutine, returned by [`CoroutineFuture::shutdown`].
+pub struct Shutdown<T, W: WakerSlot = AtomicWaker> {
+    inner: CoroutineFuture<T, W>,
+    requested: bool,
+}
+
+impl<T, W: WakerSlot> Future for Shutdown<T, W> {
+    type Output = Option<T>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        let this = self.get_mut();
+        let inner = &mut this.inner;
+        if inner.done {
+            return Poll::Ready(None);
+        }
+        inner.register(cx.waker());
+        let shared = inner.coro.user_data();
+        if !this.requested {
+            this.requested = true;
+            if shared.resume_count() == 0 {
+                // Never started: there is nothing to tear down.
+                inner.abort();
+                return Poll::Ready(None);
+            }
+            // Resume right away, so the body notices the cancellation.
+            shared.cancel();
+        } else if !shared.should_resume() {
+            return Poll::Pending;
+        }
+        match inner.resume_once() {
+            Resumed::Yielded => Poll::Pending,
+            Resumed::Completed(output) => Poll::Ready(Some(output)),
+        }
+    }
+}
+
+impl<T, W: WakerSlot> fmt::Debug for Shutdown<T, W> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Shutdown")
+            .field("coroutine", &self.inner)
+            .finish()
+    }
+}
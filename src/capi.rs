@@ -0,0 +1,288 @@
+//! An exported C API for driving coroutines from non-Rust hosts.
+//!
+//! A host creates a coroutine from a C body with [`mca_create`], drives it
+//! with [`mca_poll`] from its own event loop, and frees it with
+//! [`mca_destroy`]. The body receives an [`mca_awaiter`](CAwaiter) and blocks
+//! on futures with [`mca_await`]. The declarations live in
+//! `include/minicoro_awaiters.h`.
+//!
+//! The crate builds as a static or dynamic C library with
+//! `cargo rustc --release --features capi --crate-type staticlib` (or
+//! `cdylib`). Unwinding cannot cross the C boundary, so a panicking body or
+//! awaited future aborts the process, and a coroutine destroyed before its
+//! body returns is abandoned rather than unwound.
+//!
+//! Requires the `capi` feature.
+
+use core::ffi::c_void;
+use core::future::Future;
+use core::pin::Pin;
+use core::ptr;
+use core::task::Context;
+use core::task::Poll;
+use core::task::Waker;
+
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use alloc::task::Wake;
+
+use crate::CoroutineFuture;
+use crate::cancel::Teardown;
+use crate::ffi::CAwaiter;
+use crate::ffi::PollFn;
+
+/// A coroutine body written in C.
+pub type BodyFn = unsafe extern "C" fn(data: *mut c_void, awaiter: *const CAwaiter);
+
+/// A waker supplied by the host: `wake(data)` is called whenever the
+/// coroutine should be polled again, possibly from another thread.
+#[repr(C)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct HostWaker {
+    /// Passed to `wake`.
+    pub data: *mut c_void,
+    /// Schedules the coroutine to be polled again.
+    pub wake: unsafe extern "C" fn(data: *mut c_void),
+}
+
+// SAFETY: the host promises `wake` may be called from any thread with `data`.
+unsafe impl Send for HostWaker {}
+unsafe impl Sync for HostWaker {}
+
+impl Wake for HostWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        // SAFETY: see the `Send`/`Sync` impls.
+        unsafe { (self.wake)(self.data) };
+    }
+}
+
+/// An opaque coroutine handle owned by the host.
+pub struct Coroutine {
+    future: CoroutineFuture,
+    /// The host waker of the last poll, kept to avoid a new allocation on every poll.
+    waker: Option<(HostWaker, Waker)>,
+}
+
+/// Creates a coroutine running `body(data, awaiter)`.
+///
+/// Returns null if the coroutine could not be created.
+///
+/// # Safety
+///
+/// `body` must be safe to call with `data` on the coroutine's stack during
+/// any later [`mca_poll`].
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mca_create(body: BodyFn, data: *mut c_void) -> *mut Coroutine {
+    // Unwinding the body on destruction would cross the host's C frames.
+    let builder = crate::CoroutineBuilder::new().teardown(Teardown::Leak);
+    let built = builder.build(move |awaiter| {
+        let awaiter = awaiter.as_c();
+        // SAFETY: guaranteed by the caller of `mca_create`.
+        unsafe { body(data, &awaiter) }
+    });
+    match built {
+        Ok(future) => Box::into_raw(Box::new(Coroutine {
+            future,
+            waker: None,
+        })),
+        Err(_) => ptr::null_mut(),
+    }
+}
+
+/// Resumes the coroutine, returning `true` once its body has returned.
+///
+/// While it returns `false`, `waker` is woken when the coroutine can make
+/// progress. Polling again after it returned `true` aborts the process.
+///
+/// # Safety
+///
+/// `coro` must come from [`mca_create`] and not be destroyed, and `waker`
+/// must point to a valid [`HostWaker`].
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mca_poll(coro: *mut Coroutine, waker: *const HostWaker) -> bool {
+    // SAFETY: guaranteed by the caller.
+    let (coro, host) = unsafe { (&mut *coro, *waker) };
+    if !matches!(&coro.waker, Some((last, _)) if *last == host) {
+        coro.waker = Some((host, Waker::from(Arc::new(host))));
+    }
+    let (_, waker) = coro.waker.as_ref().unwrap();
+    let mut cx = Context::from_waker(waker);
+    match Pin::new(&mut coro.future).poll(&mut cx) {
+        Poll::Ready(()) => true,
+        Poll::Pending => false,
+    }
+}
+
+/// Frees a coroutine created with [`mca_create`].
+///
+/// A body that has not returned yet is abandoned on its stack: nothing on it
+/// is dropped, and the C frames it is suspended in are never returned to.
+///
+/// # Safety
+///
+/// `coro` must come from [`mca_create`] and not be used afterwards. Null is
+/// ignored.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mca_destroy(coro: *mut Coroutine) {
+    if !coro.is_null() {
+        // SAFETY: guaranteed by the caller.
+        drop(unsafe { Box::from_raw(coro) });
+    }
+}
+
+/// Blocks the calling coroutine until `poll(data, cx)` returns `true`.
+///
+/// # Safety
+///
+/// `awaiter` must be the pointer passed to the running body, and `poll` must
+/// be safe to call with `data`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn mca_await(awaiter: *const CAwaiter, poll: PollFn, data: *mut c_void) {
+    // SAFETY: guaranteed by the caller.
+    unsafe { ((*awaiter).block_on)((*awaiter).ctx, poll, data) }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::cell::Cell;
+    use core::ffi::c_void;
+    use core::ptr;
+    use core::sync::atomic::AtomicUsize;
+    use core::sync::atomic::Ordering;
+
+    use super::HostWaker;
+    use super::mca_await;
+    use super::mca_create;
+    use super::mca_destroy;
+    use super::mca_poll;
+    use crate::ffi::CAwaiter;
+
+    /// The state a test body shares with the test, which outlives the body.
+    struct Body {
+        /// A C future, ready once set, that keeps the waker of its last poll.
+        ready: Cell<bool>,
+        awaiter: Cell<Option<CAwaiter>>,
+        waker: Cell<*mut c_void>,
+        /// Set on entry, when the await returns, and when the body's stack
+        /// is unwound.
+        started: Cell<bool>,
+        returned: Cell<bool>,
+        unwound: Cell<bool>,
+    }
+
+    impl Body {
+        fn new() -> Self {
+            Self {
+                ready: Cell::new(false),
+                awaiter: Cell::new(None),
+                waker: Cell::new(ptr::null_mut()),
+                started: Cell::new(false),
+                returned: Cell::new(false),
+                unwound: Cell::new(false),
+            }
+        }
+
+        fn as_data(&self) -> *mut c_void {
+            self as *const Self as *mut c_void
+        }
+
+        fn awaiter(&self) -> CAwaiter {
+            self.awaiter.get().unwrap()
+        }
+    }
+
+    struct SetOnDrop<'a>(&'a Cell<bool>);
+
+    impl Drop for SetOnDrop<'_> {
+        fn drop(&mut self) {
+            self.0.set(true);
+        }
+    }
+
+    unsafe extern "C" fn poll_ready(data: *mut c_void, cx: *mut c_void) -> bool {
+        // SAFETY: `data` is the test's `Body`.
+        let body = unsafe { &*(data as *const Body) };
+        if body.ready.get() {
+            return true;
+        }
+        if body.waker.get().is_null() {
+            // SAFETY: `cx` is the context of this poll.
+            body.waker.set(unsafe { (body.awaiter().waker_clone)(cx) });
+        }
+        false
+    }
+
+    unsafe extern "C" fn await_ready(data: *mut c_void, awaiter: *const CAwaiter) {
+        // SAFETY: `data` is the test's `Body`.
+        let body = unsafe { &*(data as *const Body) };
+        body.started.set(true);
+        // SAFETY: `awaiter` is the one passed to this body.
+        body.awaiter.set(Some(unsafe { *awaiter }));
+        let guard = SetOnDrop(&body.unwound);
+        // SAFETY: as above, and `poll_ready` may be called with `data`.
+        unsafe { mca_await(awaiter, poll_ready, data) };
+        body.returned.set(true);
+        core::mem::forget(guard);
+    }
+
+    unsafe extern "C" fn count_wake(data: *mut c_void) {
+        // SAFETY: `data` is the test's wake counter.
+        unsafe { (*(data as *const AtomicUsize)).fetch_add(1, Ordering::SeqCst) };
+    }
+
+    /// A body awaiting a C future runs to completion once the future wakes it.
+    #[test]
+    fn poll_to_completion() {
+        let body = Body::new();
+        let wakes = AtomicUsize::new(0);
+        let waker = HostWaker {
+            data: &wakes as *const AtomicUsize as *mut c_void,
+            wake: count_wake,
+        };
+        // SAFETY: `await_ready` may be called with a pointer to `body`.
+        let coro = unsafe { mca_create(await_ready, body.as_data()) };
+        assert!(!coro.is_null());
+        // SAFETY: `coro` is live and `waker` is valid.
+        assert!(!unsafe { mca_poll(coro, &waker) });
+        assert!(body.started.get() && !body.returned.get());
+        assert!(!body.waker.get().is_null());
+
+        body.ready.set(true);
+        // SAFETY: the waker came from `waker_clone` and is released here.
+        unsafe { (body.awaiter().waker_wake)(body.waker.get()) };
+        assert_eq!(wakes.load(Ordering::SeqCst), 1);
+        // SAFETY: as above.
+        assert!(unsafe { mca_poll(coro, &waker) });
+        assert!(body.returned.get() && !body.unwound.get());
+        // SAFETY: `coro` came from `mca_create` and is not used afterwards.
+        unsafe { mca_destroy(coro) };
+    }
+
+    /// Destroying a coroutine suspended in C abandons it instead of
+    /// unwinding through the C frames.
+    #[test]
+    fn destroy_suspended() {
+        let body = Body::new();
+        let wakes = AtomicUsize::new(0);
+        let waker = HostWaker {
+            data: &wakes as *const AtomicUsize as *mut c_void,
+            wake: count_wake,
+        };
+        // SAFETY: `await_ready` may be called with a pointer to `body`.
+        let coro = unsafe { mca_create(await_ready, body.as_data()) };
+        assert!(!coro.is_null());
+        // SAFETY: `coro` is live and `waker` is valid.
+        assert!(!unsafe { mca_poll(coro, &waker) });
+        assert!(body.started.get());
+        // SAFETY: `coro` came from `mca_create` and is not used afterwards.
+        unsafe { mca_destroy(coro) };
+        assert!(!body.returned.get() && !body.unwound.get());
+        // SAFETY: the waker came from `waker_clone` and is released here.
+        unsafe { (body.awaiter().waker_drop)(body.waker.get()) };
+        assert_eq!(wakes.load(Ordering::SeqCst), 0);
+    }
+}
@@ -0,0 +1,293 @@
+//! Push-style coroutines that consume a sequence of input values.
+//!
+//! A [`CoroutineConsumer`] runs a blocking-style body that pulls values with
+//! [`Receiver::recv`] and may await futures in between. Async code pushes
+//! values into it with [`CoroutineConsumer::send`] (or through
+//! `futures_sink::Sink` with the `futures` feature) and finally closes it to
+//! obtain the body's return value.
+
+use core::fmt;
+use core::future::Future;
+use core::future::poll_fn;
+use core::ops::Deref;
+use core::pin::Pin;
+use core::ptr::NonNull;
+use core::task::Context;
+use core::task::Poll;
+
+use alloc::boxed::Box;
+
+use crate::BuildError;
+use crate::CoroutineAwaiter;
+use crate::CoroutineBuilder;
+use crate::CoroutineFuture;
+
+/// Input handoff state shared between a [`CoroutineConsumer`] and its body.
+struct Input<T> {
+    value: Option<T>,
+    /// The sender has closed; `recv` returns `None` once `value` is drained.
+    closed: bool,
+    /// The body is suspended inside `recv`.
+    waiting: bool,
+}
+
+/// Error returned when sending to a [`CoroutineConsumer`] whose body has already returned.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug, Hash, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ConsumerFinished;
+
+impl fmt::Display for ConsumerFinished {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("the consumer coroutine has already returned")
+    }
+}
+
+impl core::error::Error for ConsumerFinished {}
+
+/// The handle a [`CoroutineConsumer`] body uses to receive values.
+///
+/// It dereferences to the coroutine's [`CoroutineAwaiter`], so the body can
+/// await futures through it as well.
+pub struct Receiver<T> {
+    awaiter: CoroutineAwaiter,
+    input: NonNull<Input<T>>,
+}
+
+impl<T> Receiver<T> {
+    /// Suspends until the next value is sent, returning `None` once the sender has closed.
+    pub fn recv(&self) -> Option<T> {
+        let input = self.input.as_ptr();
+        loop {
+            // SAFETY: the slot outlives the body, and the sender only touches it
+            // while the body is suspended.
+            unsafe {
+                if let Some(value) = (*input).value.take() {
+                    return Some(value);
+                }
+                if (*input).closed {
+                    return None;
+                }
+                (*input).waiting = true;
+            }
+            self.awaiter.suspend();
+            unsafe { (*input).waiting = false };
+        }
+    }
+}
+
+impl<T> Deref for Receiver<T> {
+    type Target = CoroutineAwaiter;
+
+    fn deref(&self) -> &CoroutineAwaiter {
+        &self.awaiter
+    }
+}
+
+/// A coroutine consuming values of type `T` and finishing with a value of type `R`.
+///
+/// # Example
+///
+/// ```ignore
+/// use minicoro_awaiters::consumer::CoroutineConsumer;
+///
+/// async fn example() {
+///     let mut sum = CoroutineConsumer::new(|rx| {
+///         let mut total = 0;
+///         while let Some(n) = rx.recv() {
+///             total += n;
+///         }
+///         total
+///     });
+///     for n in 1..=3 {
+///         sum.send(n).await.unwrap();
+///     }
+///     assert_eq!(sum.close().await, Some(6));
+/// }
+/// ```
+pub struct CoroutineConsumer<T, R = ()> {
+    inner: CoroutineFuture<R>,
+    input: NonNull<Input<T>>,
+    output: Option<R>,
+    done: bool,
+}
+
+impl<T, R> CoroutineConsumer<T, R> {
+    /// Creates a consumer running `f` inside a coroutine.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the coroutine cannot be created; use
+    /// [`with_builder`](Self::with_builder) to handle that case.
+    #[track_caller]
+    pub fn new<F: FnOnce(&Receiver<T>) -> R>(f: F) -> Self {
+        Self::with_builder(CoroutineBuilder::new(), f).unwrap()
+    }
+
+    /// Creates a consumer running `f` inside a coroutine with the settings of
+    /// `builder`.
+    #[track_caller]
+    pub fn with_builder<F: FnOnce(&Receiver<T>) -> R>(
+        builder: CoroutineBuilder,
+        f: F,
+    ) -> Result<Self, BuildError> {
+        let input = crate::builder::try_leak(Input {
+            value: None,
+            closed: false,
+            waiting: false,
+        })?;
+        match builder.build(move |awaiter| f(&Receiver { awaiter, input })) {
+            Ok(inner) => Ok(Self {
+                inner,
+                input,
+                output: None,
+                done: false,
+            }),
+            Err(error) => {
+                // SAFETY: the body never ran, so the input is still owned here.
+                unsafe { drop(Box::from_raw(input.as_ptr())) };
+                Err(error)
+            }
+        }
+    }
+
+    /// Resumes the body until it waits for a value it does not have, or returns.
+    fn drive(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        loop {
+            if self.done {
+                return Poll::Ready(());
+            }
+            // SAFETY: the body is suspended whenever this side runs.
+            let input = unsafe { &*self.input.as_ptr() };
+            if input.waiting && input.value.is_none() && !input.closed {
+                return Poll::Ready(());
+            }
+            match Pin::new(&mut self.inner).poll(cx) {
+                Poll::Ready(output) => {
+                    self.output = Some(output);
+                    self.done = true;
+                }
+                Poll::Pending => {
+                    let input = unsafe { &*self.input.as_ptr() };
+                    if !input.waiting {
+                        return Poll::Pending;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Resumes the body until it is ready to receive a value.
+    ///
+    /// Fails with [`ConsumerFinished`] if the body has returned instead.
+    pub fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), ConsumerFinished>> {
+        match self.drive(cx) {
+            Poll::Ready(()) if self.done => Poll::Ready(Err(ConsumerFinished)),
+            Poll::Ready(()) => Poll::Ready(Ok(())),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+
+    /// Hands `item` to the body; it is received on the next resume.
+    ///
+    /// Must only be called after [`poll_ready`](Self::poll_ready) has returned `Ok`.
+    pub fn start_send(&mut self, item: T) -> Result<(), ConsumerFinished> {
+        if self.done {
+            return Err(ConsumerFinished);
+        }
+        // SAFETY: the body is suspended whenever this side runs.
+        let input = unsafe { &mut *self.input.as_ptr() };
+        debug_assert!(input.value.is_none(), "`start_send` called without `poll_ready`");
+        input.value = Some(item);
+        Ok(())
+    }
+
+    /// Resumes the body until the last sent value has been received.
+    pub fn poll_flush(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), ConsumerFinished>> {
+        match self.drive(cx) {
+            Poll::Ready(()) => Poll::Ready(Ok(())),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+
+    /// Closes the input and resumes the body until it returns.
+    pub fn poll_close(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), ConsumerFinished>> {
+        // SAFETY: the body is suspended whenever this side runs.
+        unsafe { (*self.input.as_ptr()).closed = true };
+        match self.drive(cx) {
+            Poll::Ready(()) => Poll::Ready(Ok(())),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+
+    /// Sends `item` to the body once it is ready to receive it.
+    pub async fn send(&mut self, item: T) -> Result<(), ConsumerFinished> {
+        poll_fn(|cx| self.poll_ready(cx)).await?;
+        self.start_send(item)?;
+        poll_fn(|cx| self.poll_flush(cx)).await
+    }
+
+    /// Closes the input, runs the body to completion and returns its value.
+    ///
+    /// Returns `None` if the value was already taken with [`take_return`](Self::take_return).
+    pub async fn close(&mut self) -> Option<R> {
+        let _ = poll_fn(|cx| self.poll_close(cx)).await;
+        self.output.take()
+    }
+
+    /// Returns `true` once the body has returned.
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+
+    /// Takes the body's return value, if it has returned.
+    pub fn take_return(&mut self) -> Option<R> {
+        self.output.take()
+    }
+
+    /// The coroutine running the body.
+    pub(crate) fn coroutine(&self) -> &CoroutineFuture<R> {
+        &self.inner
+    }
+}
+
+impl<T, R> fmt::Debug for CoroutineConsumer<T, R> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CoroutineConsumer")
+            .field("coroutine", &self.inner)
+            .field("done", &self.done)
+            .finish_non_exhaustive()
+    }
+}
+
+// The consumer never pins its fields; values are only ever moved in and out.
+impl<T, R> Unpin for CoroutineConsumer<T, R> {}
+
+impl<T, R> Drop for CoroutineConsumer<T, R> {
+    fn drop(&mut self) {
+        // Unwind the body first, so nothing on its stack outlives the slot.
+        self.inner.abort_for_drop();
+        // SAFETY: `input was leaked from a `Box` in `new` and is only freed here.
+        drop(unsafe { Box::from_raw(self.input.as_ptr()) });
+    }
+}
+
+#[cfg(feature = "futures")]
+impl<T, R> futures_sink::Sink<T> for CoroutineConsumer<T, R> {
+    type Error = ConsumerFinished;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.get_mut().poll_ready(cx)
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: T) -> Result<(), Self::Error> {
+        self.get_mut().start_send(item)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.get_mut().poll_flush(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.get_mut().poll_close(cx)
+    }
+}
@@ -0,0 +1,176 @@
+//! Rules for resuming coroutines from multi-threaded executors.
+//!
+//! minicoro tracks the running coroutine per thread, so different coroutines
+//! can be resumed concurrently on different worker threads; the `GLOBAL`
+//! parameter of the coroutine types selects minicoroutine's process-wide
+//! configuration and does not serialize resumes. Two rules remain, and the
+//! outer futures enforce both:
+//!
+//! - A coroutine is never resumed while it is already running, whether
+//!   concurrently from another thread or reentrantly from its own body.
+//!   Handles resume through `&mut self`, so this only comes up with raw
+//!   coroutines adopted through
+//!   [`CoroutineFuture::from_coroutine`](crate::CoroutineFuture::from_coroutine);
+//!   a violation panics instead of corrupting both stacks, or is reported as
+//!   [`ResumeError::NotSuspended`](crate::error::ResumeError::NotSuspended)
+//!   by [`CoroutineFuture::try_poll`](crate::CoroutineFuture::try_poll).
+//! - Code running on a coroutine stack may cache the address of a
+//!   thread-local across a suspension point. If the coroutine is then resumed
+//!   on another thread it keeps using the first thread's thread-locals. Bodies
+//!   that use thread-locals across awaits should be built with
+//!   [`ThreadPolicy::Pinned`], which panics rather than resuming on a
+//!   different thread than the first resume.
+//!
+//! Tokio task-locals need no such care. `LocalKey::scope` sets a task-local
+//! for the duration of each poll of the future it wraps, and a coroutine is
+//! only ever resumed inside the poll of its outer future, so futures awaited
+//! in the body see the task-locals of the task polling the coroutine, on
+//! whichever thread that is. Futures handed to `spawn::TokioSpawner` and
+//! closures handed to `blocking::TokioBlockingPool` run in another task,
+//! where, as with `tokio::spawn`, values must be re-entered explicitly with
+//! `KEY.scope(value, future)` or `KEY.sync_scope(value, f)`.
+//!
+//! With the `nightly` feature the extension data an executor attaches to the
+//! `Context` it polls a [`CoroutineFuture`](crate::CoroutineFuture) with
+//! (`core::task::ContextBuilder::ext`) is forwarded to every future awaited
+//! during that poll, so task ids and budgets stored there reach them as if
+//! they were polled directly. Stable Rust has no such data to forward.
+
+#[cfg(feature = "nightly")]
+use core::any::Any;
+use core::ptr::NonNull;
+#[cfg(feature = "nightly")]
+use core::task::Context;
+#[cfg(feature = "nightly")]
+use core::task::ContextBuilder;
+#[cfg(feature = "nightly")]
+use core::task::Waker;
+
+use crate::error::ResumeError;
+use crate::sync::AtomicBool;
+#[cfg(feature = "nightly")]
+use crate::sync::AtomicPtr;
+use crate::sync::Ordering;
+use crate::waker::CoroutineShared;
+use crate::waker::WakerSlot;
+
+/// Which threads may resume a coroutine, set with
+/// [`CoroutineBuilder::thread_policy`](crate::CoroutineBuilder::thread_policy).
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug, Hash, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ThreadPolicy {
+    /// Any thread may resume the coroutine, as work-stealing executors do.
+    #[default]
+    Migrate,
+    /// Only the thread of the first resume may resume the coroutine.
+    ///
+    /// Checked with the `std` feature; without it there is no notion of
+    /// threads and this behaves like [`Migrate`](Self::Migrate).
+    Pinned,
+}
+
+/// Checks the resume rules for the coroutine of `shared` without resuming it.
+pub(crate) fn check_resume<W: WakerSlot>(shared: &CoroutineShared<W>) -> Result<(), ResumeError> {
+    if shared.running().load(Ordering::Acquire) {
+        return Err(ResumeError::NotSuspended);
+    }
+    #[cfg(feature = "std")]
+    if shared.thread_policy() == ThreadPolicy::Pinned {
+        if let Some(&owner) = shared.owner().get() {
+            if owner != std::thread::current().id() {
+                return Err(ResumeError::WrongThread);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Marks a coroutine as running for the guard's lifetime.
+///
+/// Holds a pointer rather than a reference so the coroutine can be resumed
+/// through `&mut` while the guard is alive; the guard never outlives the
+/// coroutine's user data.
+pub(crate) struct Running(NonNull<AtomicBool>);
+
+impl Running {
+    /// Checks the resume rules before resuming the coroutine of `shared`.
+    pub(crate) fn enter<W: WakerSlot>(shared: &CoroutineShared<W>) -> Self {
+        let running = shared.running();
+        if running.swap(true, Ordering::Acquire) {
+            panic!("{} resumed while already running", shared.describe());
+        }
+        #[cfg(feature = "std")]
+        if shared.thread_policy() == ThreadPolicy::Pinned {
+            let current = std::thread::current().id();
+            if *shared.owner().get_or_init(|| current) != current {
+                running.store(false, Ordering::Release);
+                panic!(
+                    "{} is pinned to another thread but was resumed on {:?}",
+                    shared.describe(),
+                    std::thread::current().name().unwrap_or("<unnamed>"),
+                );
+            }
+        }
+        Self(NonNull::from(running))
+    }
+}
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        // SAFETY: the user data outlives every resume of its coroutine.
+        unsafe { self.0.as_ref() }.store(false, Ordering::Release);
+    }
+}
+
+/// Lends the extension data of the outer `Context` to the coroutine for the
+/// guard's lifetime, restoring the previous data on drop.
+///
+/// Holds a pointer for the same reason as [`Running`].
+#[cfg(feature = "nightly")]
+pub(crate) struct ExtScope {
+    slot: NonNull<AtomicPtr<()>>,
+    previous: *mut (),
+}
+
+#[cfg(feature = "nightly")]
+impl ExtScope {
+    /// Publishes `ext`, which must outlive the guard, to the coroutine of
+    /// `shared`.
+    pub(crate) fn enter<W: WakerSlot>(
+        shared: &CoroutineShared<W>,
+        ext: &mut &mut (dyn Any + '_),
+    ) -> Self {
+        let slot = shared.ext();
+        let ext = (ext as *mut &mut (dyn Any + '_)).cast::<()>();
+        Self {
+            slot: NonNull::from(slot),
+            previous: slot.swap(ext, Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(feature = "nightly")]
+impl Drop for ExtScope {
+    fn drop(&mut self) {
+        // SAFETY: the user data outlives every resume of its coroutine.
+        unsafe { self.slot.as_ref() }.store(self.previous, Ordering::Relaxed);
+    }
+}
+
+/// Creates the `Context` awaited futures are polled with: `waker`, plus the
+/// extension data published by the running resume, if any.
+#[cfg(feature = "nightly")]
+pub(crate) fn inner_context<'a, W: WakerSlot>(
+    shared: &CoroutineShared<W>,
+    waker: &'a Waker,
+) -> Context<'a> {
+    let ext = shared.ext().load(Ordering::Relaxed).cast::<&mut (dyn Any + 'a)>();
+    if ext.is_null() {
+        return Context::from_waker(waker);
+    }
+    // SAFETY: the pointer was published by the `ExtScope` of the resume
+    // running this coroutine, so it stays valid until the coroutine next
+    // suspends; callers drop the context before that.
+    let ext = unsafe { &mut **ext };
+    ContextBuilder::from_waker(waker).ext(ext).build()
+}
@@ -0,0 +1,80 @@
+//! Cooperative scheduling for coroutines that await many ready futures.
+//!
+//! A coroutine whose awaited futures are always immediately ready never
+//! returns `Pending`, so its task would monopolize the executor thread. Each
+//! resume therefore gets a budget of ready awaits; once it is spent the
+//! coroutine wakes itself and yields back to the executor. With the `tokio`
+//! feature, tokio's own cooperative budget is consulted as well.
+//!
+//! A budget of awaits says nothing about the time spent between them. With
+//! the `std` feature, [`CoroutineBuilder::time_slice`](crate::CoroutineBuilder::time_slice)
+//! also bounds the wall time of a resume: once it is spent, the next ready
+//! await yields as if the budget were, and so does
+//! [`CoroutineAwaiter::checkpoint`], which long computations can call as a
+//! safepoint between awaits. Code that neither awaits nor reaches a
+//! checkpoint still runs on; the [`watchdog`](crate::watchdog) reports it.
+
+use core::task::Context;
+use core::task::Poll;
+
+use crate::CoroutineAwaiter;
+use crate::waker::CoroutineShared;
+use crate::waker::WakerSlot;
+
+/// The default number of ready awaits a coroutine may complete per resume.
+pub const DEFAULT_BUDGET: u32 = 128;
+
+/// Spends one unit of budget for a ready await.
+///
+/// Returns `Poll::Pending` after waking `cx` when the coroutine should yield
+/// back to the executor before continuing.
+pub(crate) fn poll_proceed<W: WakerSlot>(
+    shared: &CoroutineShared<W>,
+    cx: &mut Context<'_>,
+) -> Poll<()> {
+    #[cfg(feature = "tokio")]
+    match tokio::task::coop::poll_proceed(cx) {
+        Poll::Ready(restore) => restore.made_progress(),
+        // Tokio has already arranged for the task to be polled again.
+        Poll::Pending => return Poll::Pending,
+    }
+    if shared.consume_budget() && !shared.slice_expired() {
+        Poll::Ready(())
+    } else {
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    }
+}
+
+impl<W: WakerSlot> CoroutineAwaiter<W> {
+    /// Yields back to the executor if the current resume has run past the
+    /// coroutine's time slice, and returns at once otherwise.
+    ///
+    /// A safepoint for computations that go a long time between awaits. The
+    /// check is a clock read, and nothing without a time slice.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// use std::time::Duration;
+    /// use minicoro_awaiters::CoroutineFuture;
+    ///
+    /// let coro = CoroutineFuture::builder()
+    ///     .time_slice(Duration::from_millis(2))
+    ///     .build(move |awaiter| {
+    ///         for row in &mut image.rows {
+    ///             blur(row);
+    ///             awaiter.checkpoint();
+    ///         }
+    ///     })?;
+    /// ```
+    #[track_caller]
+    pub fn checkpoint(&self) {
+        let shared = self.coro.user_data();
+        if shared.slice_expired() {
+            // Resumed again on the outer future's next poll.
+            shared.wake();
+            self.suspend();
+        }
+    }
+}
@@ -0,0 +1,72 @@
+//! Sections of a coroutine body that must not suspend.
+//!
+//! A body that suspends while holding a lock the executor does not know
+//! about, such as a `std::sync::Mutex` guard, leaves the lock held while
+//! other tasks run on the same thread; the next one to take it deadlocks the
+//! thread. [`CoroutineAwaiter::no_yield`] marks such a section: in debug
+//! builds, any await inside it that would suspend the coroutine panics,
+//! naming where the section was entered, so the bug shows up in tests rather
+//! than as a hang. Awaits of futures that are already ready do not suspend
+//! and are allowed. Release builds skip the check.
+
+use core::fmt;
+use core::marker::PhantomData;
+use core::panic::Location;
+
+use crate::CoroutineAwaiter;
+use crate::waker::WakerSlot;
+
+impl<W: WakerSlot> CoroutineAwaiter<W> {
+    /// Enters a section in which the coroutine must not suspend, lasting
+    /// until the returned guard is dropped.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// use minicoro_awaiters::CoroutineFuture;
+    ///
+    /// let coro = CoroutineFuture::new(move |awaiter| {
+    ///     let _section = awaiter.no_yield();
+    ///     let mut table = table.lock().unwrap();
+    ///     table.insert(key, value);
+    ///     // An `awaiter.r#await(..)` here panics in debug builds.
+    /// });
+    /// ```
+    #[track_caller]
+    pub fn no_yield(&self) -> NoYieldGuard<'_, W> {
+        let previous = if cfg!(debug_assertions) {
+            self.coro.user_data().swap_no_yield(Some(Location::caller()))
+        } else {
+            None
+        };
+        NoYieldGuard {
+            awaiter: self,
+            previous,
+            _not_send: PhantomData,
+        }
+    }
+}
+
+/// A section in which the coroutine must not suspend, returned by
+/// [`CoroutineAwaiter::no_yield`].
+pub struct NoYieldGuard<'a, W: WakerSlot> {
+    awaiter: &'a CoroutineAwaiter<W>,
+    /// The enclosing section, restored on drop.
+    previous: Option<&'static Location<'static>>,
+    /// Sections are entered and left on the coroutine's own stack.
+    _not_send: PhantomData<*const ()>,
+}
+
+impl<W: WakerSlot> Drop for NoYieldGuard<'_, W> {
+    fn drop(&mut self) {
+        if cfg!(debug_assertions) {
+            self.awaiter.coro.user_data().swap_no_yield(self.previous);
+        }
+    }
+}
+
+impl<W: WakerSlot> fmt::Debug for NoYieldGuard<'_, W> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NoYieldGuard").finish_non_exhaustive()
+    }
+}
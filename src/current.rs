@@ -0,0 +1,103 @@
+//! Reaching the running coroutine's awaiter without passing it around.
+//!
+//! Library code deep in a coroutine's call stack can await through
+//! [`await_in_coroutine`] or [`with_current_awaiter`] instead of taking an
+//! awaiter parameter. The body opts in by running that code inside
+//! [`CoroutineAwaiter::enter`], which makes its awaiter current until the
+//! closure returns. The current awaiter follows the coroutine: it is set
+//! again whenever the coroutine resumes and restored to the caller's when it
+//! suspends, so coroutines interleaved on one thread never see each other's.
+//!
+//! Requires the `std` feature.
+
+use core::cell::Cell;
+use core::future::IntoFuture;
+use core::pin::pin;
+use core::ptr;
+
+use awaiter_trait::Awaiter;
+
+use crate::CoroutineAwaiter;
+use crate::waker::CoroutineShared;
+use crate::waker::WakerSlot;
+
+std::thread_local! {
+    /// The entered awaiter of the coroutine running on this thread, if any.
+    static CURRENT: Cell<*const ()> = const { Cell::new(ptr::null()) };
+}
+
+impl CoroutineAwaiter {
+    /// Runs `f` with this awaiter as the current one.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// use minicoro_awaiters::{CoroutineFuture, current::await_in_coroutine};
+    ///
+    /// fn load_config() -> Config {
+    ///     await_in_coroutine(fetch_config())
+    /// }
+    ///
+    /// let coro = CoroutineFuture::new(|awaiter| awaiter.enter(load_config));
+    /// ```
+    pub fn enter<R>(&self, f: impl FnOnce() -> R) -> R {
+        let shared = self.coro.user_data();
+        let this = self as *const Self as *const ();
+        let _restore = Entered {
+            shared,
+            previous: shared.swap_entered(this),
+            outer: CURRENT.replace(this),
+        };
+        f()
+    }
+}
+
+/// Restores the previously entered awaiter when leaving [`CoroutineAwaiter::enter`].
+struct Entered<'a> {
+    shared: &'a CoroutineShared,
+    previous: *const (),
+    outer: *const (),
+}
+
+impl Drop for Entered<'_> {
+    fn drop(&mut self) {
+        self.shared.swap_entered(self.previous);
+        CURRENT.set(self.outer);
+    }
+}
+
+/// Makes a coroutine's entered awaiter current while it is resumed.
+pub(crate) struct ResumeScope(*const ());
+
+impl ResumeScope {
+    pub(crate) fn enter<W: WakerSlot>(shared: &CoroutineShared<W>) -> Self {
+        Self(CURRENT.replace(shared.entered()))
+    }
+}
+
+impl Drop for ResumeScope {
+    fn drop(&mut self) {
+        CURRENT.set(self.0);
+    }
+}
+
+/// Calls `f` with the current awaiter, or returns `None` outside of
+/// [`CoroutineAwaiter::enter`].
+pub fn with_current_awaiter<R>(f: impl FnOnce(&CoroutineAwaiter) -> R) -> Option<R> {
+    let current = CURRENT.get();
+    // SAFETY: a non-null pointer was set by `enter`, whose awaiter stays
+    // borrowed until it resets the pointer, and only while its coroutine runs.
+    unsafe { current.cast::<CoroutineAwaiter>().as_ref() }.map(f)
+}
+
+/// Blocks the running coroutine on `future` through the current awaiter.
+///
+/// # Panics
+///
+/// Panics when called outside of [`CoroutineAwaiter::enter`].
+#[track_caller]
+pub fn await_in_coroutine<F: IntoFuture>(future: F) -> F::Output {
+    let mut future = pin!(future.into_future());
+    with_current_awaiter(|awaiter| awaiter.r#await(future.as_mut()))
+        .expect("`await_in_coroutine` called outside of `CoroutineAwaiter::enter`")
+}
@@ -0,0 +1,171 @@
+//! A process-wide registry of live coroutines, for debugging hangs.
+//!
+//! With the `diagnostics` feature, every coroutine registers itself when it
+//! is created and leaves the registry once its last handle is dropped.
+//! [`dump`] takes a snapshot of all of them, their ids, names, lifecycle
+//! states, resume counts and creation and await sites, and the snapshot's
+//! `Display` prints one line per coroutine, ready for a log or a debug
+//! endpoint:
+//!
+//! ```ignore
+//! // On SIGUSR1, or from an admin route:
+//! eprintln!("{}", minicoro_awaiters::diagnostics::dump());
+//! ```
+//!
+//! Registration takes a global lock, once at creation and once at drop, so
+//! the feature is meant for debugging builds and services that can afford it.
+
+use core::fmt;
+use core::panic::Location;
+
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use std::sync::Mutex;
+
+use crate::observe::LifecycleState;
+use crate::waker::CoroutineShared;
+use crate::waker::WakerSlot;
+
+/// A registered coroutine: its shared state and how to describe it.
+struct Entry {
+    shared: *const (),
+    snapshot: unsafe fn(*const ()) -> CoroutineInfo,
+}
+
+// SAFETY: the pointer is only dereferenced, by `snapshot`, under the registry
+// lock, which the coroutine's shared state takes before it is freed. The
+// state itself is `Send + Sync`.
+unsafe impl Send for Entry {}
+
+static REGISTRY: Mutex<BTreeMap<usize, Entry>> = Mutex::new(BTreeMap::new());
+
+fn registry() -> std::sync::MutexGuard<'static, BTreeMap<usize, Entry>> {
+    REGISTRY.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Adds a coroutine whose shared state has reached its final address.
+pub(crate) fn register<W: WakerSlot>(shared: &CoroutineShared<W>) {
+    /// Describes the coroutine behind `shared`.
+    ///
+    /// # Safety
+    ///
+    /// `shared` must point to a live `CoroutineShared<W>`.
+    unsafe fn snapshot<W: WakerSlot>(shared: *const ()) -> CoroutineInfo {
+        // SAFETY: guaranteed by the caller.
+        let shared = unsafe { &*shared.cast::<CoroutineShared<W>>() };
+        CoroutineInfo {
+            id: shared.id(),
+            name: shared.name().map(Into::into),
+            state: LifecycleState::of(shared, shared.phase()),
+            resume_count: shared.resume_count(),
+            location: shared.location(),
+            await_site: shared.await_site(),
+        }
+    }
+    let entry = Entry {
+        shared: shared as *const CoroutineShared<W> as *const (),
+        snapshot: snapshot::<W>,
+    };
+    registry().insert(shared.id(), entry);
+}
+
+/// Removes a coroutine whose shared state is being freed.
+pub(crate) fn unregister(id: usize) {
+    registry().remove(&id);
+}
+
+/// Takes a snapshot of every live coroutine, ordered by id.
+pub fn dump() -> Snapshot {
+    let registry = registry();
+    let coroutines = registry
+        .values()
+        // SAFETY: registered states stay alive while the lock is held.
+        .map(|entry| unsafe { (entry.snapshot)(entry.shared) })
+        .collect();
+    Snapshot { coroutines }
+}
+
+/// The state of one coroutine at the time of a [`dump`].
+#[derive(Clone, PartialEq, Eq, Debug, Hash)]
+pub struct CoroutineInfo {
+    id: usize,
+    name: Option<Box<str>>,
+    state: LifecycleState,
+    resume_count: usize,
+    location: &'static Location<'static>,
+    await_site: Option<&'static Location<'static>>,
+}
+
+impl CoroutineInfo {
+    /// The coroutine's id, unique within the process.
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    /// The coroutine's name, if one was given to the builder.
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// What the coroutine was doing.
+    pub fn state(&self) -> LifecycleState {
+        self.state
+    }
+
+    /// Number of times the coroutine had been resumed.
+    pub fn resume_count(&self) -> usize {
+        self.resume_count
+    }
+
+    /// Where the coroutine was created.
+    pub fn location(&self) -> &'static Location<'static> {
+        self.location
+    }
+
+    /// The `r#await` call the coroutine was inside of, if any.
+    pub fn await_site(&self) -> Option<&'static Location<'static>> {
+        self.await_site
+    }
+}
+
+impl fmt::Display for CoroutineInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{}", self.id)?;
+        if let Some(name) = &self.name {
+            write!(f, " `{name}`")?;
+        }
+        write!(
+            f,
+            " created at {}: {:?} after {} resumes",
+            self.location, self.state, self.resume_count
+        )?;
+        if let Some(site) = self.await_site {
+            write!(f, ", inside the await at {site}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Every live coroutine at one point in time, returned by [`dump`].
+#[derive(Clone, PartialEq, Eq, Debug, Hash)]
+pub struct Snapshot {
+    coroutines: Vec<CoroutineInfo>,
+}
+
+impl Snapshot {
+    /// The coroutines, ordered by id.
+    pub fn coroutines(&self) -> &[CoroutineInfo] {
+        &self.coroutines
+    }
+}
+
+impl fmt::Display for Snapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{} live coroutines", self.coroutines.len())?;
+        for coroutine in &self.coroutines {
+            writeln!(f, "  {coroutine}")?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,106 @@
+//! Choosing between this crate and another awaiter at runtime.
+//!
+//! Library code written against [`awaiter_trait`] can run on a native async
+//! context where one exists and fall back to the minicoro bridge where it
+//! does not. [`EitherCoroutine`] and [`EitherAwaiter`] hold one of two
+//! implementations, decided when they are created, and implement the same
+//! trait by delegating to it, so callers stay generic over a single type.
+
+use core::fmt;
+use core::future::Future;
+use core::pin::Pin;
+
+use awaiter_trait::Awaiter;
+use awaiter_trait::Coroutine;
+
+use crate::CoroutineAwaiter;
+use crate::CoroutineToken;
+
+/// One of two [`Coroutine`] implementations, by default falling back to
+/// [`CoroutineToken`].
+///
+/// # Example
+///
+/// ```ignore
+/// use awaiter_trait::Coroutine;
+/// use minicoro_awaiters::either::EitherCoroutine;
+///
+/// let coroutine = EitherCoroutine::pick(NativeContext::current());
+/// let value = coroutine.exec(|awaiter| awaiter.r#await(Box::pin(async { 42 }))).await;
+/// ```
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug, Hash)]
+pub enum EitherCoroutine<A, B = CoroutineToken> {
+    /// The preferred implementation.
+    Native(A),
+    /// The implementation used when the preferred one is unavailable.
+    Bridge(B),
+}
+
+impl<A, B: Default> EitherCoroutine<A, B> {
+    /// Uses `native` if there is one and a default `B` otherwise.
+    pub fn pick(native: Option<A>) -> Self {
+        match native {
+            Some(native) => Self::Native(native),
+            None => Self::Bridge(B::default()),
+        }
+    }
+}
+
+impl<A, B> EitherCoroutine<A, B> {
+    /// Returns `true` if the preferred implementation is in use.
+    pub fn is_native(&self) -> bool {
+        matches!(self, Self::Native(_))
+    }
+}
+
+impl<A: Coroutine, B: Coroutine> Coroutine for EitherCoroutine<A, B> {
+    fn exec<T>(
+        &self,
+        f: impl FnOnce(&(dyn awaiter_trait::r#dyn::DynAwaiter + '_)) -> T,
+    ) -> impl Future<Output = T> {
+        async move {
+            match self {
+                Self::Native(native) => native.exec(f).await,
+                Self::Bridge(bridge) => bridge.exec(f).await,
+            }
+        }
+    }
+}
+
+awaiter_trait::autoimpl!(<A: Coroutine, B: Coroutine> EitherCoroutine<A, B> as Coroutine);
+
+/// One of two [`Awaiter`] implementations, by default falling back to
+/// [`CoroutineAwaiter`].
+pub enum EitherAwaiter<A, B = CoroutineAwaiter> {
+    /// The preferred implementation.
+    Native(A),
+    /// The implementation used when the preferred one is unavailable.
+    Bridge(B),
+}
+
+impl<A, B> EitherAwaiter<A, B> {
+    /// Returns `true` if the preferred implementation is in use.
+    pub fn is_native(&self) -> bool {
+        matches!(self, Self::Native(_))
+    }
+}
+
+impl<A: Awaiter, B: Awaiter> Awaiter for EitherAwaiter<A, B> {
+    fn r#await<T>(&self, f: Pin<&mut (dyn Future<Output = T> + '_)>) -> T {
+        match self {
+            Self::Native(native) => native.r#await(f),
+            Self::Bridge(bridge) => bridge.r#await(f),
+        }
+    }
+}
+
+impl<A: fmt::Debug, B: fmt::Debug> fmt::Debug for EitherAwaiter<A, B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Native(native) => f.debug_tuple("Native").field(native).finish(),
+            Self::Bridge(bridge) => f.debug_tuple("Bridge").field(bridge).finish(),
+        }
+    }
+}
+
+awaiter_trait::autoimpl!(<A: Awaiter, B: Awaiter> EitherAwaiter<A, B> as Awaiter);
@@ -0,0 +1,325 @@
+//! One error type for every way a coroutine can fail.
+//!
+//! Each fallible API returns the narrowest error it can produce, such as
+//! [`BuildError`] or [`Elapsed`], so callers match only on what can
+//! happen. [`CoroutineError`] unites them: every such error converts into
+//! it with `?`, and [`CoroutineFuture::catch_errors`] reports the failures
+//! that otherwise panic out of a coroutine, a panic of its body or a stack
+//! overflow, as values of it too.
+//!
+//! minicoroutine's resume hides minicoro's result codes: it returns whether
+//! the coroutine yielded or finished, and treats any failure as finishing.
+//! The crate checks the conditions minicoro would reject on its own side
+//! instead, before and after every resume, and
+//! [`CoroutineFuture::try_poll`] reports them as a [`ResumeError`] rather
+//! than panicking, so a coroutine in a state it should never be in is
+//! detected rather than mistaken for one that completed.
+
+use core::fmt;
+use core::future::Future;
+use core::pin::Pin;
+use core::task::Context;
+use core::task::Poll;
+
+#[cfg(feature = "std")]
+use alloc::boxed::Box;
+#[cfg(feature = "std")]
+use core::any::Any;
+
+use crate::BuildError;
+use crate::CoroutineFuture;
+use crate::Resumed;
+use crate::cancel::Cancelled;
+use crate::consumer::ConsumerFinished;
+use crate::panic;
+#[cfg(feature = "std")]
+use crate::panic::PanicAction;
+use crate::spawn::JoinError;
+use crate::stack::StackOverflow;
+use crate::sync::AtomicWaker;
+use crate::time::Elapsed;
+use crate::waker::WakerSlot;
+
+/// A coroutine that could not be resumed, returned by
+/// [`CoroutineFuture::try_poll`].
+///
+/// Each variant names the minicoro result or status it corresponds to.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug, Hash)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
+pub enum ResumeError {
+    /// The coroutine has already completed or was aborted (`MCO_DEAD`).
+    Dead,
+    /// The coroutine is already running, as when it is resumed from its own
+    /// body (`MCO_NOT_SUSPENDED`).
+    NotSuspended,
+    /// The coroutine is [pinned](crate::context::ThreadPolicy::Pinned) to
+    /// another thread (`MCO_INVALID_OPERATION`).
+    WrongThread,
+    /// minicoro reported the coroutine finished, but its body never stored
+    /// an output, so the coroutine died some other way (`MCO_GENERIC_ERROR`).
+    NoOutput,
+}
+
+impl fmt::Display for ResumeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Dead => "the coroutine has already finished",
+            Self::NotSuspended => "the coroutine is already running",
+            Self::WrongThread => "the coroutine is pinned to another thread",
+            Self::NoOutput => "the coroutine finished without an output",
+        })
+    }
+}
+
+impl core::error::Error for ResumeError {}
+
+/// Any failure of a coroutine or of an operation on one.
+#[non_exhaustive]
+pub enum CoroutineError {
+    /// The coroutine could not be created.
+    Build(BuildError),
+    /// The coroutine ran out of stack.
+    StackOverflow(StackOverflow),
+    /// The coroutine was aborted before it completed.
+    Aborted,
+    /// An await was interrupted by cancellation.
+    Cancelled(Cancelled),
+    /// An await did not complete within its timeout.
+    Elapsed(Elapsed),
+    /// A spawned task was dropped before completing.
+    Join(JoinError),
+    /// A consumer coroutine had already returned.
+    ConsumerFinished(ConsumerFinished),
+    /// The coroutine could not be resumed.
+    Resume(ResumeError),
+    /// The coroutine's body panicked, with this payload.
+    ///
+    /// With the `backtrace` feature the payload is a
+    /// [`CoroutinePanic`](crate::panic::CoroutinePanic).
+    #[cfg(feature = "std")]
+    Panicked(Box<dyn Any + Send>),
+}
+
+impl CoroutineError {
+    /// The panic message, if the body panicked with a string.
+    #[cfg(feature = "std")]
+    pub fn panic_message(&self) -> Option<&str> {
+        let Self::Panicked(payload) = self else {
+            return None;
+        };
+        #[cfg(feature = "backtrace")]
+        if let Some(panic) = payload.downcast_ref::<panic::CoroutinePanic>() {
+            return panic.message();
+        }
+        payload
+            .downcast_ref::<&'static str>()
+            .copied()
+            .or_else(|| payload.downcast_ref::<alloc::string::String>().map(|s| s.as_str()))
+    }
+
+    /// Resumes the panic of a body that panicked, and returns every other
+    /// error unchanged.
+    #[cfg(feature = "std")]
+    pub fn resume_panic(self) -> Self {
+        match self {
+            Self::Panicked(payload) => std::panic::resume_unwind(payload),
+            error => error,
+        }
+    }
+}
+
+impl fmt::Debug for CoroutineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Build(error) => f.debug_tuple("Build").field(error).finish(),
+            Self::StackOverflow(error) => f.debug_tuple("StackOverflow").field(error).finish(),
+            Self::Aborted => f.write_str("Aborted"),
+            Self::Cancelled(error) => f.debug_tuple("Cancelled").field(error).finish(),
+            Self::Elapsed(error) => f.debug_tuple("Elapsed").field(error).finish(),
+            Self::Join(error) => f.debug_tuple("Join").field(error).finish(),
+            Self::ConsumerFinished(error) => {
+                f.debug_tuple("ConsumerFinished").field(error).finish()
+            }
+            Self::Resume(error) => f.debug_tuple("Resume").field(error).finish(),
+            #[cfg(feature = "std")]
+            Self::Panicked(_) => f
+                .debug_struct("Panicked")
+                .field("message", &self.panic_message())
+                .finish_non_exhaustive(),
+        }
+    }
+}
+
+impl fmt::Display for CoroutineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Build(error) => error.fmt(f),
+            Self::StackOverflow(error) => error.fmt(f),
+            Self::Aborted => f.write_str("the coroutine was aborted"),
+            Self::Cancelled(error) => error.fmt(f),
+            Self::Elapsed(error) => error.fmt(f),
+            Self::Join(error) => error.fmt(f),
+            Self::ConsumerFinished(error) => error.fmt(f),
+            Self::Resume(error) => error.fmt(f),
+            #[cfg(feature = "std")]
+            Self::Panicked(_) => match self.panic_message() {
+                Some(message) => write!(f, "the coroutine panicked: {message}"),
+                None => f.write_str("the coroutine panicked"),
+            },
+        }
+    }
+}
+
+impl core::error::Error for CoroutineError {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        match self {
+            Self::Build(error) => Some(error),
+            Self::StackOverflow(error) => Some(error),
+            Self::Cancelled(error) => Some(error),
+            Self::Elapsed(error) => Some(error),
+            Self::Join(error) => Some(error),
+            Self::ConsumerFinished(error) => Some(error),
+            Self::Resume(error) => Some(error),
+            #[cfg(feature = "backtrace")]
+            Self::Panicked(payload) => payload
+                .downcast_ref::<panic::CoroutinePanic>()
+                .map(|panic| panic as &(dyn core::error::Error + 'static)),
+            _ => None,
+        }
+    }
+}
+
+macro_rules! from_errors {
+    ($($variant:ident($error:ty)),* $(,)?) => {$(
+        impl From<$error> for CoroutineError {
+            fn from(error: $error) -> Self {
+                Self::$variant(error)
+            }
+        }
+    )*};
+}
+
+from_errors! {
+    Build(BuildError),
+    StackOverflow(StackOverflow),
+    Cancelled(Cancelled),
+    Elapsed(Elapsed),
+    Join(JoinError),
+    ConsumerFinished(ConsumerFinished),
+    Resume(ResumeError),
+}
+
+impl<T, W: WakerSlot> CoroutineFuture<T, W> {
+    /// Polls the coroutine like [`Future::poll`], but reports a coroutine
+    /// that cannot be resumed as a [`ResumeError`] instead of panicking.
+    ///
+    /// Resumes at most once per call. Panics of the body and stack overflows
+    /// still propagate; [`catch_errors`](Self::catch_errors) reports those,
+    /// and resume errors, as values.
+    pub fn try_poll(&mut self, cx: &mut Context<'_>) -> Poll<Result<T, ResumeError>> {
+        if self.done {
+            return Poll::Ready(Err(ResumeError::Dead));
+        }
+        self.register(cx.waker());
+        if !self.coro.user_data().should_resume() {
+            return Poll::Pending;
+        }
+        crate::context::check_resume(self.coro.user_data())?;
+        match self.try_resume_raw()? {
+            Resumed::Yielded => Poll::Pending,
+            Resumed::Completed(outcome) => Poll::Ready(Ok(self.finish_or_panic(outcome))),
+        }
+    }
+
+    /// Resolves to `Err` instead of panicking if the body panics, the
+    /// coroutine overflows its stack or it was aborted before being awaited.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// use minicoro_awaiters::{CoroutineFuture, error::CoroutineError};
+    ///
+    /// match CoroutineFuture::new(|awaiter| parse(&awaiter, input)).catch_errors().await {
+    ///     Ok(ast) => compile(ast),
+    ///     Err(error @ CoroutineError::Panicked(_)) => report_bug(error),
+    ///     Err(error) => return Err(error.into()),
+    /// }
+    /// ```
+    pub fn catch_errors(self) -> CatchErrors<T, W> {
+        CatchErrors { inner: self }
+    }
+
+    /// Turns the outcome of the finished body into its output or the error
+    /// it failed with.
+    fn finish_caught(&self, outcome: panic::Outcome<T>) -> Result<T, CoroutineError> {
+        let shared = self.coro.user_data();
+        if shared.has_overflowed() {
+            return Err(CoroutineError::StackOverflow(StackOverflow));
+        }
+        match outcome {
+            Ok(output) => Ok(output),
+            #[cfg(feature = "std")]
+            Err(payload) if panic::is_abort(&*payload) => Err(CoroutineError::Aborted),
+            #[cfg(feature = "std")]
+            Err(payload) => {
+                let origin = Some(shared.origin());
+                match panic::decide(shared.panic_handler(), &*payload, shared.origin()) {
+                    Some(PanicAction::Propagate) => {
+                        std::panic::resume_unwind(panic::attach_origin(payload, origin))
+                    }
+                    Some(PanicAction::Error) | None => {
+                        Err(CoroutineError::Panicked(panic::attach_origin(payload, origin)))
+                    }
+                    Some(PanicAction::Swallow) => Err(CoroutineError::Aborted),
+                }
+            }
+        }
+    }
+}
+
+/// A coroutine reporting every failure as a [`CoroutineError`], returned by
+/// [`CoroutineFuture::catch_errors`].
+pub struct CatchErrors<T, W: WakerSlot = AtomicWaker> {
+    inner: CoroutineFuture<T, W>,
+}
+
+impl<T, W: WakerSlot> CatchErrors<T, W> {
+    /// The wrapped coroutine.
+    pub fn get_ref(&self) -> &CoroutineFuture<T, W> {
+        &self.inner
+    }
+
+    pub(crate) fn get_mut(&mut self) -> &mut CoroutineFuture<T, W> {
+        &mut self.inner
+    }
+}
+
+impl<T, W: WakerSlot> fmt::Debug for CatchErrors<T, W> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CatchErrors")
+            .field("coroutine", &self.inner)
+            .finish()
+    }
+}
+
+impl<T, W: WakerSlot> Future for CatchErrors<T, W> {
+    type Output = Result<T, CoroutineError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let inner = &mut self.get_mut().inner;
+        if inner.done {
+            // Completed futures have resolved already, so this one was aborted.
+            return Poll::Ready(Err(CoroutineError::Aborted));
+        }
+        inner.register(cx.waker());
+        if !inner.coro.user_data().should_resume() {
+            return Poll::Pending;
+        }
+        crate::context::check_resume(inner.coro.user_data())?;
+        match inner.try_resume_raw()? {
+            Resumed::Yielded => Poll::Pending,
+            Resumed::Completed(outcome) => Poll::Ready(inner.finish_caught(outcome)),
+        }
+    }
+}
@@ -0,0 +1,117 @@
+//! Lifecycle events reported to logging backends and observers.
+//!
+//! With the `log` or `defmt` feature, every coroutine reports its creation,
+//! each resume and suspension, and its completion as `debug` records naming
+//! the coroutine and its [id](crate::CoroutineFuture::id). Without a backend
+//! enabled only the phase read by [observers](crate::observe) is recorded.
+//!
+//! [`created`] also registers the coroutine for the `diagnostics` feature,
+//! and [`allocated`] feeds the counters of the `alloc-stats` feature.
+
+use crate::observe;
+use crate::waker::CoroutineShared;
+use crate::waker::WakerSlot;
+
+/// The coroutine was created.
+#[cfg_attr(
+    not(any(feature = "log", feature = "defmt", feature = "diagnostics")),
+    allow(unused_variables)
+)]
+#[inline]
+pub(crate) fn created<W: WakerSlot>(shared: &CoroutineShared<W>) {
+    #[cfg(feature = "diagnostics")]
+    crate::diagnostics::register(shared);
+    #[cfg(feature = "log")]
+    log::debug!("{} (#{}): created", shared.describe(), shared.id());
+    #[cfg(feature = "defmt")]
+    defmt::debug!(
+        "coroutine {=str} (#{=usize}): created",
+        shared.name().unwrap_or("<unnamed>"),
+        shared.id()
+    );
+}
+
+/// The coroutine is about to be resumed.
+#[inline]
+pub(crate) fn resumed<W: WakerSlot>(shared: &CoroutineShared<W>) {
+    shared.set_phase(observe::PHASE_RUNNING);
+    #[cfg(feature = "log")]
+    log::debug!("{} (#{}): resumed", shared.describe(), shared.id());
+    #[cfg(feature = "defmt")]
+    defmt::debug!(
+        "coroutine {=str} (#{=usize}): resumed",
+        shared.name().unwrap_or("<unnamed>"),
+        shared.id()
+    );
+}
+
+/// The coroutine suspended without finishing.
+#[inline]
+pub(crate) fn yielded<W: WakerSlot>(shared: &CoroutineShared<W>) {
+    shared.set_phase(observe::PHASE_YIELDED);
+    #[cfg(feature = "log")]
+    log::debug!("{} (#{}): yielded", shared.describe(), shared.id());
+    #[cfg(feature = "defmt")]
+    defmt::debug!(
+        "coroutine {=str} (#{=usize}): yielded",
+        shared.name().unwrap_or("<unnamed>"),
+        shared.id()
+    );
+}
+
+/// The coroutine body returned, panicked or was aborted.
+#[inline]
+pub(crate) fn completed<W: WakerSlot>(shared: &CoroutineShared<W>) {
+    shared.set_phase(observe::PHASE_COMPLETED);
+    #[cfg(feature = "log")]
+    log::debug!("{} (#{}): completed", shared.describe(), shared.id());
+    #[cfg(feature = "defmt")]
+    defmt::debug!(
+        "coroutine {=str} (#{=usize}): completed",
+        shared.name().unwrap_or("<unnamed>"),
+        shared.id()
+    );
+}
+
+/// An abort gave up on the suspended body, leaving what is on its stack
+/// undropped.
+#[cfg_attr(not(any(feature = "log", feature = "defmt")), allow(unused_variables))]
+#[inline]
+pub(crate) fn leaked<W: WakerSlot>(shared: &CoroutineShared<W>) {
+    #[cfg(feature = "log")]
+    log::warn!(
+        "{} (#{}): aborted without unwinding, leaking its stack frames",
+        shared.describe(),
+        shared.id()
+    );
+    #[cfg(feature = "defmt")]
+    defmt::warn!(
+        "coroutine {=str} (#{=usize}): aborted without unwinding, leaking its stack frames",
+        shared.name().unwrap_or("<unnamed>"),
+        shared.id()
+    );
+}
+
+/// The stack of a coroutine built with a secure stack could not be locked
+/// into memory.
+#[cfg(feature = "secure-stack")]
+#[cfg_attr(not(any(feature = "log", feature = "defmt")), allow(unused_variables))]
+#[inline]
+pub(crate) fn stack_lock_failed<W: WakerSlot>(shared: &CoroutineShared<W>) {
+    #[cfg(feature = "log")]
+    log::warn!("{} (#{}): could not lock its stack into memory", shared.describe(), shared.id());
+    #[cfg(feature = "defmt")]
+    defmt::warn!(
+        "coroutine {=str} (#{=usize}): could not lock its stack into memory",
+        shared.name().unwrap_or("<unnamed>"),
+        shared.id()
+    );
+}
+
+/// The crate allocated `bytes` on behalf of the coroutine.
+#[cfg_attr(not(feature = "alloc-stats"), allow(unused_variables))]
+#[inline]
+pub(crate) fn allocated<W: WakerSlot>(shared: &CoroutineShared<W>, bytes: usize) {
+    #[cfg(feature = "alloc-stats")]
+    shared.record_alloc(bytes);
+}
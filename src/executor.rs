@@ -0,0 +1,281 @@
+//! A minimal single-threaded executor for targets without a runtime.
+//!
+//! On bare metal the crate already provides green threads: every
+//! [`CoroutineFuture`] is a thread of blocking-style code with a stack of
+//! its own. What is left is something to drive them, and [`Executor`] is
+//! that: a run queue of tasks, coroutines or plain futures, polled
+//! cooperatively on the calling thread. It needs `alloc` and nothing else.
+//!
+//! Every task gets a waker of its own, which marks the task as ready in the
+//! executor's queue, so a round only polls the tasks woken since the last
+//! one. Wakers are `Send + Sync` and may be woken from other threads and
+//! from interrupt handlers; the tasks themselves never leave the executor's
+//! thread and need not be `Send`.
+//!
+//! [`Executor::run`] finishes once every task has. Between rounds with no
+//! ready task it calls the `idle` closure it was given, typically an
+//! instruction such as `wfi` that sleeps until the next interrupt. The
+//! waker passed to [`Executor::with_waker`] is woken along with every task,
+//! for hosts whose idle state needs an explicit signal to end, such as a
+//! parked thread.
+//!
+//! Tasks spawn further tasks through an [`ExecutorSpawner`], a
+//! [`LocalSpawner`] for [`spawn_local`](crate::spawn::spawn_local); what is
+//! spawned through it joins the queue at the start of the next round.
+
+use core::cell::RefCell;
+use core::fmt;
+use core::future::Future;
+use core::pin::Pin;
+use core::task::Context;
+use core::task::Poll;
+use core::task::Waker;
+
+use alloc::boxed::Box;
+use alloc::rc::Rc;
+use alloc::vec::Vec;
+
+use crate::CoroutineAwaiter;
+use crate::CoroutineFuture;
+use crate::reactor::Reactor;
+use crate::spawn::JoinHandle;
+use crate::spawn::LocalBoxFuture;
+use crate::spawn::LocalSpawner;
+use crate::spawn::handoff;
+
+/// Tasks per segment of the run queue, which grows a segment at a time.
+const SEGMENT: usize = usize::BITS as usize;
+
+/// Drives tasks on the current thread until they are all done.
+///
+/// # Example
+///
+/// ```ignore
+/// use minicoro_awaiters::executor::Executor;
+///
+/// let mut executor = Executor::new();
+/// executor.spawn_coroutine(|awaiter| loop {
+///     let reading = awaiter.wait(sensor.read());
+///     awaiter.wait(uplink.send(reading));
+/// });
+/// executor.spawn(blink(led));
+/// executor.run(cortex_m::asm::wfi);
+/// ```
+pub struct Executor {
+    /// One slot per task index, `None` once the task has finished.
+    tasks: Vec<Option<LocalBoxFuture>>,
+    /// The ready queue, one reactor per `SEGMENT` slots.
+    segments: Vec<Reactor>,
+    /// Indices of empty slots.
+    free: Vec<usize>,
+    /// Futures spawned through an `ExecutorSpawner` since the last round.
+    injected: Rc<RefCell<Vec<LocalBoxFuture>>>,
+    /// Woken whenever a task is.
+    waker: Waker,
+    /// Number of unfinished tasks.
+    live: usize,
+}
+
+impl Executor {
+    /// Creates an executor without tasks.
+    pub fn new() -> Self {
+        Self::with_waker(Waker::noop().clone())
+    }
+
+    /// Creates an executor that wakes `waker` whenever one of its tasks is
+    /// woken.
+    pub fn with_waker(waker: Waker) -> Self {
+        Self {
+            tasks: Vec::new(),
+            segments: Vec::new(),
+            free: Vec::new(),
+            injected: Rc::new(RefCell::new(Vec::new())),
+            waker,
+            live: 0,
+        }
+    }
+
+    /// Number of tasks that have not finished yet, not counting those
+    /// spawned through an [`ExecutorSpawner`] since the last round.
+    pub fn len(&self) -> usize {
+        self.live
+    }
+
+    /// Returns `true` if every task has finished.
+    pub fn is_empty(&self) -> bool {
+        self.live == 0 && self.injected.borrow().is_empty()
+    }
+
+    /// Adds `future` as a task, first polled by the next round, and returns a
+    /// handle to its output.
+    pub fn spawn<F: Future + 'static>(&mut self, future: F) -> JoinHandle<F::Output> {
+        let (completer, handle) = handoff();
+        self.insert(Box::pin(async move { completer.complete(future.await) }));
+        handle
+    }
+
+    /// Adds a coroutine running `f` as a task.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the coroutine cannot be created; spawn a coroutine from
+    /// [`CoroutineFuture::try_new`] with [`spawn`](Self::spawn) to handle
+    /// that case.
+    #[track_caller]
+    pub fn spawn_coroutine<T: 'static>(
+        &mut self,
+        f: impl FnOnce(CoroutineAwaiter) -> T + 'static,
+    ) -> JoinHandle<T> {
+        self.spawn(CoroutineFuture::new(f))
+    }
+
+    /// Returns a spawner that running tasks can add tasks to the executor
+    /// through.
+    pub fn spawner(&self) -> ExecutorSpawner {
+        ExecutorSpawner {
+            injected: self.injected.clone(),
+        }
+    }
+
+    fn insert(&mut self, task: LocalBoxFuture) {
+        let index = match self.free.pop() {
+            Some(index) => {
+                self.segments[index / SEGMENT].mark(index % SEGMENT);
+                index
+            }
+            None => {
+                // A new segment starts out with every slot ready.
+                let start = self.tasks.len();
+                self.segments.push(Reactor::new(SEGMENT));
+                self.tasks.resize_with(start + SEGMENT, || None);
+                self.free.extend((start + 1..start + SEGMENT).rev());
+                start
+            }
+        };
+        self.tasks[index] = Some(task);
+        self.live += 1;
+    }
+
+    /// Returns `true` if a task is ready to be polled.
+    pub fn is_ready(&self) -> bool {
+        !self.injected.borrow().is_empty() || self.segments.iter().any(Reactor::is_ready)
+    }
+
+    /// Polls every ready task, round after round, until none is ready, and
+    /// returns the number of tasks left.
+    ///
+    /// # Panics
+    ///
+    /// Propagates the panic of a task. Wakeups of the tasks the round had
+    /// not polled yet may be lost with it, so the executor should not be run
+    /// again afterwards.
+    pub fn run_until_stalled(&mut self) -> usize {
+        while self.is_ready() {
+            let injected = core::mem::take(&mut *self.injected.borrow_mut());
+            for task in injected {
+                self.insert(task);
+            }
+            let cx = Context::from_waker(&self.waker);
+            for (segment, reactor) in self.segments.iter().enumerate() {
+                let tasks = &mut self.tasks[segment * SEGMENT..][..SEGMENT];
+                reactor.poll_woken(&cx, |index, cx| {
+                    let task = tasks[index].as_mut()?;
+                    if task.as_mut().poll(cx).is_ready() {
+                        tasks[index] = None;
+                        self.free.push(segment * SEGMENT + index);
+                        self.live -= 1;
+                    }
+                    None::<()>
+                });
+            }
+        }
+        self.live
+    }
+
+    /// Runs the tasks until they have all finished, calling `idle` whenever
+    /// none of them is ready.
+    ///
+    /// `idle` should wait for whatever can wake a task, such as an interrupt;
+    /// returning at once is correct as well, but spins.
+    pub fn run(&mut self, mut idle: impl FnMut()) {
+        while self.run_until_stalled() != 0 {
+            // A task may have been woken since the round ended.
+            if !self.is_ready() {
+                idle();
+            }
+        }
+    }
+
+    /// Runs the tasks, `future` among them, until `future` finishes, and
+    /// returns its output.
+    ///
+    /// The other tasks are left in the executor, unfinished. `idle` is
+    /// called as for [`run`](Self::run).
+    pub fn block_on<F: Future + 'static>(
+        &mut self,
+        future: F,
+        mut idle: impl FnMut(),
+    ) -> F::Output {
+        let mut handle = self.spawn(future);
+        loop {
+            self.run_until_stalled();
+            if handle.is_finished() {
+                break;
+            }
+            if !self.is_ready() {
+                idle();
+            }
+        }
+        match Pin::new(&mut handle).poll(&mut Context::from_waker(Waker::noop())) {
+            Poll::Ready(Ok(output)) => output,
+            _ => unreachable!("the task finished without an output"),
+        }
+    }
+}
+
+impl Default for Executor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for Executor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Executor")
+            .field("tasks", &self.live)
+            .field("ready", &self.is_ready())
+            .finish_non_exhaustive()
+    }
+}
+
+/// Adds tasks to an [`Executor`] from inside its running tasks, returned by
+/// [`Executor::spawner`].
+///
+/// # Example
+///
+/// ```ignore
+/// use minicoro_awaiters::spawn::spawn_local;
+///
+/// let spawner = executor.spawner();
+/// executor.spawn_coroutine(move |awaiter| {
+///     for connection in listener.incoming(&awaiter) {
+///         spawn_local(&spawner, serve(connection));
+///     }
+/// });
+/// ```
+#[derive(Clone)]
+pub struct ExecutorSpawner {
+    injected: Rc<RefCell<Vec<LocalBoxFuture>>>,
+}
+
+impl LocalSpawner for ExecutorSpawner {
+    fn spawn_local(&self, future: LocalBoxFuture) {
+        self.injected.borrow_mut().push(future);
+    }
+}
+
+impl fmt::Debug for ExecutorSpawner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ExecutorSpawner").finish_non_exhaustive()
+    }
+}
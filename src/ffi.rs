@@ -0,0 +1,226 @@
+//! A C ABI for awaiting futures from C code running inside a coroutine.
+//!
+//! Coroutine bodies that call into C can hand that code a [`CAwaiter`]: a
+//! `#[repr(C)]` table of function pointers plus an opaque context. C code
+//! blocks on a future by passing a poll callback to
+//! [`block_on`](CAwaiter::block_on), which suspends the coroutine exactly as
+//! [`CoroutineAwaiter::r#await`](awaiter_trait::Awaiter::r#await) does.
+//!
+//! Poll callbacks receive an opaque pointer to the task context. C futures use
+//! the waker functions in the table to obtain a waker from it; Rust futures
+//! are exposed to C through [`ForeignFuture`], which forwards the context to
+//! [`Future::poll`] unchanged.
+//!
+//! ```c
+//! typedef struct {
+//!     const void *ctx;
+//!     void (*block_on)(const void *ctx, bool (*poll)(void *data, void *cx), void *data);
+//!     void *(*waker_clone)(void *cx);
+//!     void (*waker_wake)(void *waker);
+//!     void (*waker_wake_by_ref)(const void *waker);
+//!     void (*waker_drop)(void *waker);
+//! } mca_awaiter;
+//! ```
+//!
+//! Unwinding cannot cross these functions: a panic in an awaited future
+//! aborts the process, so Rust futures handed to C code should not panic.
+//! For the same reason an abort must not unwind a body blocked in C, and
+//! [`as_c`](CoroutineAwaiter::as_c) is only available to coroutines built
+//! with [`Teardown::Leak`] or [`Teardown::Cooperative`], and
+//! [`detect_deadlocks`](crate::CoroutineBuilder::detect_deadlocks) does not
+//! check awaits issued from C.
+
+use core::ffi::c_void;
+use core::fmt;
+use core::future::Future;
+use core::future::poll_fn;
+use core::pin::Pin;
+use core::pin::pin;
+use core::task::Context;
+use core::task::Poll;
+use core::task::Waker;
+
+use alloc::boxed::Box;
+
+use awaiter_trait::Awaiter;
+
+use crate::CoroutineAwaiter;
+use crate::cancel::Teardown;
+use crate::waker::WakerSlot;
+
+/// A poll callback: polls the future behind `data` with the task context `cx`
+/// and returns `true` once it has completed.
+pub type PollFn = unsafe extern "C" fn(data: *mut c_void, cx: *mut c_void) -> bool;
+
+/// A C-compatible view of a [`CoroutineAwaiter`].
+///
+/// Obtained from [`CoroutineAwaiter::as_c`]; it borrows the awaiter and must
+/// only be used on the coroutine's own stack while the body is running.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct CAwaiter {
+    /// The awaiter, passed back as the first argument of `block_on`.
+    pub ctx: *const c_void,
+    /// Polls `poll(data, cx)` until it returns `true`, suspending the coroutine
+    /// while it is pending.
+    pub block_on: unsafe extern "C" fn(ctx: *const c_void, poll: PollFn, data: *mut c_void),
+    /// Returns an owned waker for the task context `cx`.
+    pub waker_clone: unsafe extern "C" fn(cx: *mut c_void) -> *mut c_void,
+    /// Wakes and releases a waker returned by `waker_clone`.
+    pub waker_wake: unsafe extern "C" fn(waker: *mut c_void),
+    /// Wakes a waker returned by `waker_clone` without releasing it.
+    pub waker_wake_by_ref: unsafe extern "C" fn(waker: *const c_void),
+    /// Releases a waker returned by `waker_clone` without waking it.
+    pub waker_drop: unsafe extern "C" fn(waker: *mut c_void),
+}
+
+impl fmt::Debug for CAwaiter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CAwaiter").field("ctx", &self.ctx).finish_non_exhaustive()
+    }
+}
+
+impl<W: WakerSlot> CoroutineAwaiter<W> {
+    /// Returns a C-compatible function table for awaiting through this awaiter.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the coroutine tears down by [unwinding](Teardown::Unwind),
+    /// the default with the `std` feature: aborting it while C code is
+    /// blocked in [`block_on`](CAwaiter::block_on) would unwind through the
+    /// C frames, which aborts the process.
+    #[track_caller]
+    pub fn as_c(&self) -> CAwaiter {
+        let shared = self.coro.user_data();
+        assert!(
+            shared.teardown() != Teardown::Unwind,
+            "{} hands its awaiter to C, so it must be built with `Teardown::Leak` or \
+             `Teardown::Cooperative`",
+            shared.describe()
+        );
+        CAwaiter {
+            ctx: self as *const Self as *const c_void,
+            block_on: block_on::<W>,
+            waker_clone,
+            waker_wake,
+            waker_wake_by_ref,
+            waker_drop,
+        }
+    }
+}
+
+unsafe extern "C" fn block_on<W: WakerSlot>(ctx: *const c_void, poll: PollFn, data: *mut c_void) {
+    // SAFETY: `ctx` was produced by `as_c` on an awaiter that is still alive.
+    let awaiter = unsafe { &*(ctx as *const CoroutineAwaiter<W>) };
+    let shared = awaiter.coro.user_data();
+    shared.enter_c_await();
+    awaiter.r#await(pin!(poll_fn(|cx| {
+        // SAFETY: the caller guarantees `poll` may be called with `data`.
+        if unsafe { poll(data, cx as *mut Context<'_> as *mut c_void) } {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    })));
+    shared.exit_c_await();
+}
+
+unsafe extern "C" fn waker_clone(cx: *mut c_void) -> *mut c_void {
+    // SAFETY: `cx` is the context passed to a poll callback.
+    let cx = unsafe { &*(cx as *const Context<'_>) };
+    Box::into_raw(Box::new(cx.waker().clone())) as *mut c_void
+}
+
+unsafe extern "C" fn waker_wake(waker: *mut c_void) {
+    // SAFETY: `waker` was returned by `waker_clone` and is released here.
+    unsafe { Box::from_raw(waker as *mut Waker) }.wake();
+}
+
+unsafe extern "C" fn waker_wake_by_ref(waker: *const c_void) {
+    // SAFETY: `waker` was returned by `waker_clone` and not released yet.
+    unsafe { (*(waker as *const Waker)).wake_by_ref() };
+}
+
+unsafe extern "C" fn waker_drop(waker: *mut c_void) {
+    // SAFETY: `waker` was returned by `waker_clone` and is released here.
+    drop(unsafe { Box::from_raw(waker as *mut Waker) });
+}
+
+/// A Rust future prepared to be awaited from C through [`CAwaiter::block_on`].
+///
+/// # Example
+///
+/// ```ignore
+/// use core::ffi::c_void;
+/// use core::pin::pin;
+/// use minicoro_awaiters::CoroutineBuilder;
+/// use minicoro_awaiters::cancel::Teardown;
+/// use minicoro_awaiters::ffi::{CAwaiter, ForeignFuture, PollFn};
+///
+/// unsafe extern "C" {
+///     fn c_protocol_step(awaiter: *const CAwaiter, poll: PollFn, data: *mut c_void);
+/// }
+///
+/// let builder = CoroutineBuilder::new().teardown(Teardown::Leak);
+/// let coro = builder.build(|awaiter| {
+///     let c = awaiter.as_c();
+///     let mut fut = pin!(ForeignFuture::new(async { 42 }));
+///     let (poll, data) = fut.as_mut().as_raw();
+///     unsafe { c_protocol_step(&c, poll, data) };
+///     fut.take_output()
+/// })?;
+/// ```
+pub struct ForeignFuture<F: Future> {
+    future: F,
+    output: Option<F::Output>,
+}
+
+impl<F: Future> ForeignFuture<F> {
+    /// Wraps `future`.
+    pub fn new(future: F) -> Self {
+        Self {
+            future,
+            output: None,
+        }
+    }
+
+    /// Returns the poll callback and data pointer to hand to C code.
+    ///
+    /// The pointer stays valid for as long as the pinned wrapper is alive.
+    pub fn as_raw(self: Pin<&mut Self>) -> (PollFn, *mut c_void) {
+        // SAFETY: the wrapper is pinned, and the callback only pins `future` in place.
+        let this = unsafe { self.get_unchecked_mut() };
+        (poll_foreign::<F>, this as *mut Self as *mut c_void)
+    }
+
+    /// Takes the future's output once a poll callback has reported completion.
+    pub fn take_output(self: Pin<&mut Self>) -> Option<F::Output> {
+        // SAFETY: `output` is not structurally pinned.
+        unsafe { self.get_unchecked_mut() }.output.take()
+    }
+}
+
+impl<F: Future> fmt::Debug for ForeignFuture<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ForeignFuture")
+            .field("done", &self.output.is_some())
+            .finish_non_exhaustive()
+    }
+}
+
+unsafe extern "C" fn poll_foreign<F: Future>(data: *mut c_void, cx: *mut c_void) -> bool {
+    // SAFETY: `data` came from `as_raw` on a pinned `ForeignFuture<F>`, and `cx`
+    // is the context passed to a poll callback.
+    let this = unsafe { &mut *(data as *mut ForeignFuture<F>) };
+    let cx = unsafe { &mut *(cx as *mut Context<'_>) };
+    if this.output.is_some() {
+        return true;
+    }
+    match unsafe { Pin::new_unchecked(&mut this.future) }.poll(cx) {
+        Poll::Ready(output) => {
+            this.output = Some(output);
+            true
+        }
+        Poll::Pending => false,
+    }
+}
@@ -0,0 +1,390 @@
+//! Generators: coroutines that yield a sequence of items and then return a value.
+//!
+//! A [`CoroutineGenerator`] runs a blocking-style body that can both await
+//! futures and hand items to its consumer through a [`Yielder`]. From async
+//! code it is consumed item by item with [`CoroutineGenerator::poll_next`]
+//! (or as a `futures_core::Stream` with the `futures` feature), after which
+//! the body's return value is available from [`CoroutineGenerator::take_return`].
+//!
+//! By default every item is a round trip through the executor: the body
+//! suspends as soon as it yields. [`CoroutineGenerator::with_capacity`] lets
+//! the body run ahead and buffer up to that many items before it suspends,
+//! which the consumer then takes without resuming it.
+//!
+//! [`coro_yield!`](crate::coro_yield) is shorthand for yielding from the
+//! body, one item or a whole iterator at a time.
+//!
+//! [`CoroutineTryStream`] is the variant for fallible producers: its body
+//! returns a `Result` and can use `?`, and the consumer sees the items as
+//! `Ok` values followed, if the body failed, by its error, after which the
+//! stream ends.
+
+use core::fmt;
+use core::future::Future;
+use core::ops::Deref;
+use core::pin::Pin;
+use core::ptr::NonNull;
+use core::task::Context;
+use core::task::Poll;
+
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+
+use crate::BuildError;
+use crate::CoroutineAwaiter;
+use crate::CoroutineFuture;
+
+/// Items yielded by the body and not yet taken by the consumer.
+struct Buffer<Y> {
+    items: VecDeque<Y>,
+    capacity: usize,
+}
+
+/// The handle a [`CoroutineGenerator`] body uses to yield items.
+///
+/// It dereferences to the coroutine's [`CoroutineAwaiter`], so the body can
+/// await futures through it as well.
+pub struct Yielder<Y> {
+    awaiter: CoroutineAwaiter,
+    buffer: NonNull<Buffer<Y>>,
+}
+
+impl<Y> Yielder<Y> {
+    /// Hands `item` to the consumer, suspending until the consumer has taken
+    /// every buffered item if the buffer is full.
+    pub fn yield_item(&self, item: Y) {
+        // SAFETY: the buffer outlives the body, and the consumer only touches
+        // it while the body is suspended.
+        let buffer = unsafe { &mut *self.buffer.as_ptr() };
+        buffer.items.push_back(item);
+        if buffer.items.len() >= buffer.capacity {
+            self.awaiter.suspend();
+        }
+    }
+
+    /// Yields every item of `items` in order.
+    pub fn yield_all(&self, items: impl IntoIterator<Item = Y>) {
+        for item in items {
+            self.yield_item(item);
+        }
+    }
+}
+
+/// Yields from a [`CoroutineGenerator`] body.
+///
+/// `coro_yield!(y, value)` yields one item through the yielder `y`, and
+/// `coro_yield!(y, for items)` yields every item of an iterator.
+///
+/// # Example
+///
+/// ```ignore
+/// use minicoro_awaiters::{coro_yield, generator::CoroutineGenerator};
+///
+/// let squares = CoroutineGenerator::new(|y| {
+///     coro_yield!(y, 0);
+///     coro_yield!(y, for (1..10).map(|n| n * n));
+/// });
+/// ```
+#[macro_export]
+macro_rules! coro_yield {
+    ($yielder:expr, for $items:expr $(,)?) => {
+        $crate::generator::Yielder::yield_all(&$yielder, $items)
+    };
+    ($yielder:expr, $item:expr $(,)?) => {
+        $crate::generator::Yielder::yield_item(&$yielder, $item)
+    };
+}
+
+impl<Y> Deref for Yielder<Y> {
+    type Target = CoroutineAwaiter;
+
+    fn deref(&self) -> &CoroutineAwaiter {
+        &self.awaiter
+    }
+}
+
+/// A coroutine yielding items of type `Y` and finishing with a value of type `R`.
+///
+/// # Example
+///
+/// ```ignore
+/// use minicoro_awaiters::generator::CoroutineGenerator;
+/// use futures::StreamExt;
+///
+/// async fn example() {
+///     let mut tokens = CoroutineGenerator::new(|y| {
+///         for word in ["a", "b", "c"] {
+///             y.yield_item(word);
+///         }
+///         3usize
+///     });
+///     while let Some(token) = tokens.next().await {
+///         println!("token: {token}");
+///     }
+///     assert_eq!(tokens.take_return(), Some(3));
+/// }
+/// ```
+pub struct CoroutineGenerator<Y, R = ()> {
+    inner: CoroutineFuture<R>,
+    buffer: NonNull<Buffer<Y>>,
+    output: Option<R>,
+    done: bool,
+}
+
+impl<Y, R> CoroutineGenerator<Y, R> {
+    /// Creates a generator running `f` inside a coroutine.
+    #[track_caller]
+    pub fn new<F: FnOnce(&Yielder<Y>) -> R>(f: F) -> Self {
+        Self::with_capacity(1, f)
+    }
+
+    /// Creates a generator whose body may yield up to `capacity` items ahead
+    /// of its consumer before suspending; 0 is treated as 1.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the coroutine cannot be created; use
+    /// [`try_with_capacity`](Self::try_with_capacity) to handle that case.
+    #[track_caller]
+    pub fn with_capacity<F: FnOnce(&Yielder<Y>) -> R>(capacity: usize, f: F) -> Self {
+        Self::try_with_capacity(capacity, f).unwrap()
+    }
+
+    /// Like [`with_capacity`](Self::with_capacity), but returns an error if
+    /// the coroutine, its item buffer or any other allocation fails.
+    #[track_caller]
+    pub fn try_with_capacity<F: FnOnce(&Yielder<Y>) -> R>(
+        capacity: usize,
+        f: F,
+    ) -> Result<Self, BuildError> {
+        let capacity = capacity.max(1);
+        let mut items = VecDeque::new();
+        items
+            .try_reserve_exact(capacity)
+            .map_err(|_| BuildError::new())?;
+        let buffer = crate::builder::try_leak(Buffer { items, capacity })?;
+        match CoroutineFuture::try_new(move |awaiter| f(&Yielder { awaiter, buffer })) {
+            Ok(inner) => Ok(Self {
+                inner,
+                buffer,
+                output: None,
+                done: false,
+            }),
+            Err(error) => {
+                // SAFETY: the body was never created, so nothing else holds the buffer.
+                drop(unsafe { Box::from_raw(buffer.as_ptr()) });
+                Err(error)
+            }
+        }
+    }
+
+    /// Number of yielded items waiting to be taken.
+    pub fn buffered(&self) -> usize {
+        // SAFETY: the body is suspended whenever the consumer runs.
+        unsafe { (*self.buffer.as_ptr()).items.len() }
+    }
+
+    /// Resumes the generator until it yields its next item or finishes.
+    ///
+    /// Returns `Poll::Ready(None)` once the body has returned; its value can
+    /// then be obtained with [`take_return`](Self::take_return).
+    pub fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Y>> {
+        let this = self.get_mut();
+        // SAFETY: the body is suspended or finished whenever this side runs.
+        let buffer = this.buffer.as_ptr();
+        if let Some(item) = unsafe { (*buffer).items.pop_front() } {
+            return Poll::Ready(Some(item));
+        }
+        if this.done {
+            return Poll::Ready(None);
+        }
+        if let Poll::Ready(output) = Pin::new(&mut this.inner).poll(cx) {
+            this.output = Some(output);
+            this.done = true;
+        }
+        match unsafe { (*buffer).items.pop_front() } {
+            Some(item) => Poll::Ready(Some(item)),
+            None if this.done => Poll::Ready(None),
+            None => Poll::Pending,
+        }
+    }
+
+    /// Returns `true` once the body has returned.
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+
+    /// Takes the body's return value, if the generator has been exhausted.
+    pub fn take_return(&mut self) -> Option<R> {
+        self.output.take()
+    }
+}
+
+impl<Y, R> fmt::Debug for CoroutineGenerator<Y, R> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CoroutineGenerator")
+            .field("coroutine", &self.inner)
+            .field("done", &self.done)
+            .finish_non_exhaustive()
+    }
+}
+
+// The generator never pins its fields; the yielded items and return value are
+// only ever moved out.
+impl<Y, R> Unpin for CoroutineGenerator<Y, R> {}
+
+impl<Y, R> Drop for CoroutineGenerator<Y, R> {
+    fn drop(&mut self) {
+        // Unwind the body first, so nothing on its stack outlives the slot.
+        self.inner.abort_for_drop();
+        // SAFETY: `buffer` was leaked from a `Box` in `with_capacity` and is
+        // only freed here.
+        drop(unsafe { Box::from_raw(self.buffer.as_ptr()) });
+    }
+}
+
+#[cfg(feature = "futures")]
+impl<Y, R> futures_core::Stream for CoroutineGenerator<Y, R> {
+    type Item = Y;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Y>> {
+        CoroutineGenerator::poll_next(self, cx)
+    }
+}
+
+#[cfg(feature = "nightly")]
+impl<Y, R> core::async_iter::AsyncIterator for CoroutineGenerator<Y, R> {
+    type Item = Y;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Y>> {
+        CoroutineGenerator::poll_next(self, cx)
+    }
+}
+
+#[cfg(feature = "futures")]
+impl<Y, R> futures_core::FusedStream for CoroutineGenerator<Y, R> {
+    fn is_terminated(&self) -> bool {
+        self.done && self.buffered() == 0
+    }
+}
+
+/// A generator for fallible producers, yielding `Ok` items until its body
+/// returns, and the body's error, if it fails, as the last item.
+///
+/// The body yields plain items and can use `?`, so it reads like a fallible
+/// function. With the `futures` feature the generator is a
+/// `futures_core::Stream` of `Result<T, E>`, and so a `TryStream`, that ends
+/// right after the first `Err`.
+///
+/// # Example
+///
+/// ```ignore
+/// use minicoro_awaiters::generator::CoroutineTryStream;
+/// use futures::TryStreamExt;
+///
+/// async fn example() -> Result<(), io::Error> {
+///     let mut rows = CoroutineTryStream::new(|y| {
+///         let reader = y.r#await(Box::pin(open(path)))?;
+///         while let Some(line) = y.r#await(Box::pin(reader.next_line()))? {
+///             y.yield_item(parse(&line));
+///         }
+///         Ok(())
+///     });
+///     while let Some(row) = rows.try_next().await? {
+///         insert(row);
+///     }
+///     Ok(())
+/// }
+/// ```
+pub struct CoroutineTryStream<T, E> {
+    inner: CoroutineGenerator<T, Result<(), E>>,
+}
+
+impl<T, E> CoroutineTryStream<T, E> {
+    /// Creates a stream running `f` inside a coroutine.
+    #[track_caller]
+    pub fn new<F: FnOnce(&Yielder<T>) -> Result<(), E>>(f: F) -> Self {
+        Self::with_capacity(1, f)
+    }
+
+    /// Creates a stream whose body may yield up to `capacity` items ahead of
+    /// its consumer before suspending; 0 is treated as 1.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the coroutine cannot be created; use
+    /// [`try_with_capacity`](Self::try_with_capacity) to handle that case.
+    #[track_caller]
+    pub fn with_capacity<F: FnOnce(&Yielder<T>) -> Result<(), E>>(capacity: usize, f: F) -> Self {
+        Self::try_with_capacity(capacity, f).unwrap()
+    }
+
+    /// Like [`with_capacity`](Self::with_capacity), but returns an error if
+    /// the coroutine, its item buffer or any other allocation fails.
+    #[track_caller]
+    pub fn try_with_capacity<F: FnOnce(&Yielder<T>) -> Result<(), E>>(
+        capacity: usize,
+        f: F,
+    ) -> Result<Self, BuildError> {
+        let inner = CoroutineGenerator::try_with_capacity(capacity, f)?;
+        Ok(Self { inner })
+    }
+
+    /// Number of yielded items waiting to be taken.
+    pub fn buffered(&self) -> usize {
+        self.inner.buffered()
+    }
+
+    /// Resumes the body until it yields its next item or finishes.
+    ///
+    /// Items buffered before the body failed are all returned before its
+    /// error, and `Poll::Ready(None)` follows the error.
+    pub fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<T, E>>> {
+        let inner = &mut self.get_mut().inner;
+        match Pin::new(&mut *inner).poll_next(cx) {
+            Poll::Ready(Some(item)) => Poll::Ready(Some(Ok(item))),
+            Poll::Ready(None) => Poll::Ready(inner.take_return().and_then(Result::err).map(Err)),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+
+    /// Returns `true` once the stream has ended, after its error if there
+    /// was one.
+    pub fn is_terminated(&self) -> bool {
+        self.inner.done && self.inner.buffered() == 0 && self.inner.output.is_none()
+    }
+}
+
+impl<T, E> fmt::Debug for CoroutineTryStream<T, E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CoroutineTryStream")
+            .field("coroutine", &self.inner.inner)
+            .field("done", &self.inner.done)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(feature = "futures")]
+impl<T, E> futures_core::Stream for CoroutineTryStream<T, E> {
+    type Item = Result<T, E>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<T, E>>> {
+        CoroutineTryStream::poll_next(self, cx)
+    }
+}
+
+#[cfg(feature = "nightly")]
+impl<T, E> core::async_iter::AsyncIterator for CoroutineTryStream<T, E> {
+    type Item = Result<T, E>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<T, E>>> {
+        CoroutineTryStream::poll_next(self, cx)
+    }
+}
+
+#[cfg(feature = "futures")]
+impl<T, E> futures_core::FusedStream for CoroutineTryStream<T, E> {
+    fn is_terminated(&self) -> bool {
+        CoroutineTryStream::is_terminated(self)
+    }
+}
@@ -0,0 +1,168 @@
+//! Lifecycle hooks fired around coroutine resumes.
+//!
+//! Register a [`CoroutineHooks`] implementation with
+//! [`CoroutineBuilder::hooks`](crate::CoroutineBuilder::hooks) to integrate
+//! profilers or to install thread-local context for the coroutine body. All
+//! callbacks run on the thread polling the outer future; since the coroutine
+//! runs on that same thread, thread-locals set in
+//! [`on_resume`](CoroutineHooks::on_resume) are visible inside the body until
+//! the matching [`on_yield`](CoroutineHooks::on_yield).
+//!
+//! [`CoroutineFuture::on_complete`] registers callbacks for the typed
+//! outcome of one coroutine instead, run exactly once however the coroutine
+//! ends.
+
+use core::fmt;
+use core::future::Future;
+use core::pin::Pin;
+use core::task::Context;
+use core::task::Poll;
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+
+use crate::CoroutineFuture;
+use crate::error::CatchErrors;
+use crate::error::CoroutineError;
+use crate::sync::AtomicWaker;
+use crate::waker::WakerSlot;
+
+/// Callbacks fired around each resume of a coroutine.
+///
+/// Every method has an empty default, so implementations only override what
+/// they need.
+///
+/// # Example
+///
+/// ```ignore
+/// use minicoro_awaiters::{CoroutineFuture, hooks::CoroutineHooks};
+///
+/// struct Trace;
+///
+/// impl CoroutineHooks for Trace {
+///     fn on_resume(&self) {
+///         println!("resuming");
+///     }
+///     fn on_complete(&self) {
+///         println!("done");
+///     }
+/// }
+///
+/// let coro = CoroutineFuture::builder().hooks(Trace).build(|awaiter| {
+///     awaiter.r#await(Box::pin(async { 1 }))
+/// })?;
+/// ```
+pub trait CoroutineHooks {
+    /// Called right before the coroutine is resumed.
+    fn on_resume(&self) {}
+
+    /// Called right after the coroutine suspends without finishing.
+    fn on_yield(&self) {}
+
+    /// Called once, right after the coroutine body returns or panics.
+    fn on_complete(&self) {}
+}
+
+/// A callback passed to [`CoroutineFuture::on_complete`].
+type Callback<T> = Box<dyn FnOnce(Result<&T, &CoroutineError>)>;
+
+impl<T, W: WakerSlot> CoroutineFuture<T, W> {
+    /// Calls `f` exactly once when the coroutine finishes, with its output or
+    /// the error it failed with.
+    ///
+    /// Unlike [`CoroutineHooks::on_complete`], the callback sees the typed
+    /// outcome. It runs whether the returned future is awaited or, say,
+    /// spawned and detached; if the future is dropped before the coroutine
+    /// finishes, the coroutine is aborted and `f` sees
+    /// [`CoroutineError::Aborted`]. Failures still panic out of the future
+    /// once the callbacks have run, as with a plain `.await`.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// use minicoro_awaiters::CoroutineFuture;
+    ///
+    /// let coro = CoroutineFuture::new(|awaiter| handle(&awaiter, request))
+    ///     .on_complete(|result| metrics.record(result.is_ok()));
+    /// tokio::task::spawn_local(coro);
+    /// ```
+    pub fn on_complete(
+        self,
+        f: impl FnOnce(Result<&T, &CoroutineError>) + 'static,
+    ) -> OnComplete<T, W> {
+        OnComplete {
+            inner: self.catch_errors(),
+            callbacks: Vec::new(),
+        }
+        .on_complete(f)
+    }
+}
+
+/// A coroutine with completion callbacks, returned by
+/// [`CoroutineFuture::on_complete`].
+pub struct OnComplete<T, W: WakerSlot = AtomicWaker> {
+    inner: CatchErrors<T, W>,
+    /// Emptied once the callbacks have run.
+    callbacks: Vec<Callback<T>>,
+}
+
+impl<T, W: WakerSlot> OnComplete<T, W> {
+    /// Adds another callback, run after those registered before it.
+    pub fn on_complete(mut self, f: impl FnOnce(Result<&T, &CoroutineError>) + 'static) -> Self {
+        self.callbacks.push(Box::new(f));
+        self
+    }
+
+    /// The wrapped coroutine.
+    pub fn get_ref(&self) -> &CoroutineFuture<T, W> {
+        self.inner.get_ref()
+    }
+
+    fn notify(&mut self, result: Result<&T, &CoroutineError>) {
+        for callback in core::mem::take(&mut self.callbacks) {
+            callback(result);
+        }
+    }
+}
+
+impl<T, W: WakerSlot> Future for OnComplete<T, W> {
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T> {
+        let this = self.get_mut();
+        let Poll::Ready(result) = Pin::new(&mut this.inner).poll(cx) else {
+            return Poll::Pending;
+        };
+        this.notify(result.as_ref());
+        match result {
+            Ok(output) => Poll::Ready(output),
+            Err(error) => {
+                #[cfg(feature = "std")]
+                let error = error.resume_panic();
+                panic!("{}: {error}", this.get_ref().coro.user_data().describe())
+            }
+        }
+    }
+}
+
+impl<T, W: WakerSlot> fmt::Debug for OnComplete<T, W> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OnComplete")
+            .field("coroutine", self.get_ref())
+            .field("callbacks", &self.callbacks.len())
+            .finish()
+    }
+}
+
+// The callbacks are only ever moved out, and the coroutine is `Unpin`.
+impl<T, W: WakerSlot> Unpin for OnComplete<T, W> {}
+
+impl<T, W: WakerSlot> Drop for OnComplete<T, W> {
+    fn drop(&mut self) {
+        if !self.callbacks.is_empty() {
+            // Unwind the body before reporting that it was aborted.
+            self.inner.get_mut().abort_for_drop();
+            self.notify(Err(&CoroutineError::Aborted));
+        }
+    }
+}
@@ -0,0 +1,751 @@
+//! Bridges between `futures_io` streams and `std::io` inside coroutines.
+//!
+//! [`SyncIo`] goes from async to blocking: it wraps an `AsyncRead` /
+//! `AsyncWrite` object and implements `std::io::Read` / `std::io::Write` by
+//! awaiting it through an awaiter, so synchronous code running in a
+//! coroutine can use async streams. [`CoroutineAwaiter::blocking_io`] is a
+//! shorthand for it. Buffering follows the wrapped stream: an
+//! `AsyncBufRead` becomes a `std::io::BufRead` and an `AsyncSeek` a
+//! `std::io::Seek`, so wrapping a buffered async reader is the way to get a
+//! buffered blocking one without copying through a second buffer.
+//!
+//! [`CoroutineIo`] goes the other way: it runs a blocking-style protocol
+//! handler that calls [`IoHandle::read`] and [`IoHandle::write`] (or uses the
+//! handle through `std::io::Read` / `std::io::Write`). Bytes written from
+//! async code become the handler's input, and bytes the handler writes are
+//! what async code reads. [`CoroutineIo::from_reader`] and
+//! [`CoroutineIo::from_writer`] build such a handler around a blocking
+//! `std::io` object, running each of its calls through
+//! [`block_in_place`](crate::CoroutineAwaiter::block_in_place) so legacy
+//! streams can be used from async code without stalling the executor.
+//!
+//! [`CoroutineAwaiter::copy`], [`CoroutineAwaiter::read_to_end`] and
+//! [`CoroutineAwaiter::read_exact_timeout`] are the usual building blocks on
+//! top of async streams, written for coroutines: they suspend while waiting
+//! and stop with an `Interrupted` error once the coroutine is cancelled.
+//!
+//! Errors keep their `std::io` meaning in both directions: `io::Error`s from
+//! either side pass through unchanged, a handler that has returned reads as
+//! end-of-stream and rejects writes with `BrokenPipe`, and the crate's
+//! [`Cancelled`] and [`Elapsed`] convert into `Interrupted` and `TimedOut`
+//! errors, so `?` works on them inside I/O code.
+//!
+//! Requires the `std` feature.
+
+use core::fmt;
+use core::future::Future;
+use core::future::poll_fn;
+use core::ops::Deref;
+use core::pin::Pin;
+use core::pin::pin;
+use core::ptr::NonNull;
+use core::task::Context;
+use core::task::Poll;
+use core::task::Waker;
+use core::time::Duration;
+
+use alloc::boxed::Box;
+use alloc::vec;
+use alloc::vec::Vec;
+use std::io;
+use std::io::Write as _;
+
+use awaiter_trait::Awaiter;
+use futures_io::AsyncBufRead;
+use futures_io::AsyncRead;
+use futures_io::AsyncSeek;
+use futures_io::AsyncWrite;
+
+use crate::BuildError;
+use crate::CoroutineAwaiter;
+use crate::CoroutineBuilder;
+use crate::CoroutineFuture;
+use crate::cancel::Cancelled;
+use crate::time::Elapsed;
+use crate::waker::WakerSlot;
+
+/// Adapts an async stream into a blocking `std::io` one by awaiting through an awaiter.
+///
+/// # Example
+///
+/// ```ignore
+/// use minicoro_awaiters::{io::SyncIo, CoroutineFuture};
+/// use std::io::Read;
+///
+/// async fn example(socket: impl futures_io::AsyncRead + Unpin) {
+///     CoroutineFuture::new(move |awaiter| {
+///         let mut socket = SyncIo::new(&awaiter, socket);
+///         let mut header = [0; 4];
+///         socket.read_exact(&mut header).unwrap();
+///     })
+///     .await;
+/// }
+/// ```
+pub struct SyncIo<'a, A: ?Sized, T> {
+    awaiter: &'a A,
+    io: T,
+}
+
+impl<'a, A: Awaiter + ?Sized, T> SyncIo<'a, A, T> {
+    /// Wraps `io`, awaiting its operations through `awaiter`.
+    pub fn new(awaiter: &'a A, io: T) -> Self {
+        Self { awaiter, io }
+    }
+
+    /// Returns a reference to the wrapped stream.
+    pub fn get_ref(&self) -> &T {
+        &self.io
+    }
+
+    /// Returns a mutable reference to the wrapped stream.
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.io
+    }
+
+    /// Unwraps the adapter, returning the stream.
+    pub fn into_inner(self) -> T {
+        self.io
+    }
+}
+
+impl<A: Awaiter + ?Sized, T: AsyncRead + Unpin> io::Read for SyncIo<'_, A, T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let io = &mut self.io;
+        self.awaiter
+            .r#await(pin!(poll_fn(|cx| Pin::new(&mut *io).poll_read(cx, buf))))
+    }
+}
+
+impl<A: Awaiter + ?Sized, T: AsyncWrite + Unpin> io::Write for SyncIo<'_, A, T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let io = &mut self.io;
+        self.awaiter
+            .r#await(pin!(poll_fn(|cx| Pin::new(&mut *io).poll_write(cx, buf))))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let io = &mut self.io;
+        self.awaiter
+            .r#await(pin!(poll_fn(|cx| Pin::new(&mut *io).poll_flush(cx))))
+    }
+}
+
+impl<A: Awaiter + ?Sized, T: AsyncBufRead + Unpin> io::BufRead for SyncIo<'_, A, T> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        let io = &mut self.io;
+        let (data, len) = self.awaiter.r#await(pin!(poll_fn(|cx| {
+            Pin::new(&mut *io)
+                .poll_fill_buf(cx)
+                .map(|buf| buf.map(|buf| (buf.as_ptr(), buf.len())))
+        })))?;
+        // SAFETY: the buffer belongs to the stream, which stays mutably
+        // borrowed through `self` for the lifetime of the returned slice.
+        Ok(unsafe { core::slice::from_raw_parts(data, len) })
+    }
+
+    fn consume(&mut self, amt: usize) {
+        Pin::new(&mut self.io).consume(amt);
+    }
+}
+
+impl<A: Awaiter + ?Sized, T: AsyncSeek + Unpin> io::Seek for SyncIo<'_, A, T> {
+    fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+        let io = &mut self.io;
+        self.awaiter
+            .r#await(pin!(poll_fn(|cx| Pin::new(&mut *io).poll_seek(cx, pos))))
+    }
+}
+
+/// What the handler is suspended on, if not on an awaited future.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Blocked {
+    None,
+    Read,
+    Write,
+}
+
+/// Buffer handoff state shared between a [`CoroutineIo`] and its handler.
+///
+/// The raw buffers are only valid for the duration of the resume issued by
+/// the `poll_read` / `poll_write` call that installed them.
+struct Pipe {
+    read_dst: Option<(NonNull<u8>, usize)>,
+    read_done: Option<usize>,
+    write_src: Option<(NonNull<u8>, usize)>,
+    write_done: Option<usize>,
+    write_closed: bool,
+    blocked: Blocked,
+    read_waker: Option<Waker>,
+    write_waker: Option<Waker>,
+}
+
+impl Pipe {
+    fn wake_reader(&mut self) {
+        if let Some(waker) = self.read_waker.take() {
+            waker.wake();
+        }
+    }
+
+    fn wake_writer(&mut self) {
+        if let Some(waker) = self.write_waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+/// The handle a [`CoroutineIo`] handler uses to exchange bytes with async code.
+///
+/// It dereferences to the coroutine's [`CoroutineAwaiter`], so the handler can
+/// await futures through it as well.
+pub struct IoHandle {
+    awaiter: CoroutineAwaiter,
+    pipe: NonNull<Pipe>,
+}
+
+impl IoHandle {
+    /// Blocks until async code writes bytes, copying as many as fit into `buf`.
+    ///
+    /// Returns `Ok(0)` once the async side has closed its write half.
+    pub fn read(&self, buf: &mut [u8]) -> io::Result<usize> {
+        let pipe = self.pipe.as_ptr();
+        loop {
+            // SAFETY: the pipe outlives the handler, and the async side only
+            // touches it while the handler is suspended.
+            unsafe {
+                if let Some((src, len)) = (*pipe).write_src.take() {
+                    let n = len.min(buf.len());
+                    core::ptr::copy_nonoverlapping(src.as_ptr(), buf.as_mut_ptr(), n);
+                    (*pipe).write_done = Some(n);
+                    return Ok(n);
+                }
+                if (*pipe).write_closed {
+                    return Ok(0);
+                }
+                (*pipe).blocked = Blocked::Read;
+                (*pipe).wake_writer();
+            }
+            self.awaiter.suspend();
+            unsafe { (*pipe).blocked = Blocked::None };
+        }
+    }
+
+    /// Blocks until async code reads, handing it as many bytes of `buf` as it asks for.
+    pub fn write(&self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let pipe = self.pipe.as_ptr();
+        loop {
+            // SAFETY: as in `read`.
+            unsafe {
+                if let Some((dst, len)) = (*pipe).read_dst.take() {
+                    let n = len.min(buf.len());
+                    core::ptr::copy_nonoverlapping(buf.as_ptr(), dst.as_ptr(), n);
+                    (*pipe).read_done = Some(n);
+                    return Ok(n);
+                }
+                (*pipe).blocked = Blocked::Write;
+                (*pipe).wake_reader();
+            }
+            self.awaiter.suspend();
+            unsafe { (*pipe).blocked = Blocked::None };
+        }
+    }
+}
+
+impl Deref for IoHandle {
+    type Target = CoroutineAwaiter;
+
+    fn deref(&self) -> &CoroutineAwaiter {
+        &self.awaiter
+    }
+}
+
+impl io::Read for &IoHandle {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        IoHandle::read(self, buf)
+    }
+}
+
+impl io::Write for &IoHandle {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        IoHandle::write(self, buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A coroutine-backed byte stream implementing `AsyncRead` and `AsyncWrite`.
+///
+/// Closing the stream ends the handler's input and resolves once the handler
+/// has returned, so a handler that is still writing by then needs its output
+/// read in the meantime.
+///
+/// # Example
+///
+/// ```ignore
+/// use minicoro_awaiters::io::CoroutineIo;
+/// use std::io::{BufRead, BufReader, Write};
+///
+/// // A legacy line-echo handler serving an async connection.
+/// let stream = CoroutineIo::new(|mut io| {
+///     let mut lines = BufReader::new(io);
+///     let mut line = String::new();
+///     while lines.read_line(&mut line).unwrap() > 0 {
+///         io.write_all(line.as_bytes()).unwrap();
+///         line.clear();
+///     }
+/// });
+/// ```
+pub struct CoroutineIo<R = ()> {
+    inner: CoroutineFuture<R>,
+    pipe: NonNull<Pipe>,
+    output: Option<R>,
+    done: bool,
+    /// What `poll_close` reports once the handler has returned.
+    close_result: fn(&Option<R>) -> io::Result<()>,
+}
+
+impl<R> CoroutineIo<R> {
+    /// Creates a byte stream served by `f` running inside a coroutine.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the coroutine cannot be created; use
+    /// [`with_builder`](Self::with_builder) to handle that case.
+    #[track_caller]
+    pub fn new<F: FnOnce(&IoHandle) -> R>(f: F) -> Self {
+        Self::with_builder(CoroutineBuilder::new(), f).unwrap()
+    }
+
+    /// Creates a byte stream served by `f` running inside a coroutine built
+    /// with the settings of `builder`.
+    #[track_caller]
+    pub fn with_builder<F: FnOnce(&IoHandle) -> R>(
+        builder: CoroutineBuilder,
+        f: F,
+    ) -> Result<Self, BuildError> {
+        let pipe = crate::builder::try_leak(Pipe {
+            read_dst: None,
+            read_done: None,
+            write_src: None,
+            write_done: None,
+            write_closed: false,
+            blocked: Blocked::None,
+            read_waker: None,
+            write_waker: None,
+        })?;
+        let inner = match builder.build(move |awaiter| f(&IoHandle { awaiter, pipe })) {
+            Ok(inner) => inner,
+            Err(error) => {
+                // SAFETY: the body was never created, so nothing else holds the pipe.
+                drop(unsafe { Box::from_raw(pipe.as_ptr()) });
+                return Err(error);
+            }
+        };
+        Ok(Self {
+            inner,
+            pipe,
+            output: None,
+            done: false,
+            close_result: |_| Ok(()),
+        })
+    }
+
+    /// Resumes the handler once, recording its return value if it finishes.
+    fn resume(&mut self, cx: &mut Context<'_>) {
+        if let Poll::Ready(output) = Pin::new(&mut self.inner).poll(cx) {
+            self.output = Some(output);
+            self.done = true;
+            // SAFETY: the handler has returned, so the pipe is exclusively ours.
+            let pipe = unsafe { &mut *self.pipe.as_ptr() };
+            pipe.wake_reader();
+            pipe.wake_writer();
+        }
+    }
+
+    /// Returns `true` once the handler has returned.
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+
+    /// Takes the handler's return value, if it has returned.
+    pub fn take_return(&mut self) -> Option<R> {
+        self.output.take()
+    }
+}
+
+impl<T: Send + 'static> CoroutineIo<io::Result<T>> {
+    /// Exposes a blocking reader as an `AsyncRead` stream.
+    ///
+    /// The coroutine reads up to `capacity` bytes at a time on the blocking
+    /// pool of `builder` and hands them to async readers. The stream ends when
+    /// `reader` reaches end-of-file or fails; [`take_return`](Self::take_return)
+    /// then yields the reader back, or the error. Writes to the stream fail
+    /// with `BrokenPipe` once it has ended and are otherwise never consumed.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// use minicoro_awaiters::{CoroutineFuture, blocking::TokioBlockingPool, io::CoroutineIo};
+    ///
+    /// let builder = CoroutineFuture::builder().blocking_pool(TokioBlockingPool::current());
+    /// let file = CoroutineIo::from_reader(builder, std::fs::File::open(path)?, 64 << 10)?;
+    /// futures::io::copy(file, &mut socket).await?;
+    /// ```
+    #[track_caller]
+    pub fn from_reader(
+        builder: CoroutineBuilder,
+        reader: T,
+        capacity: usize,
+    ) -> Result<Self, BuildError>
+    where
+        T: io::Read,
+    {
+        Self::with_builder(builder, move |handle| {
+            let mut reader = reader;
+            let mut buf = vec![0; capacity.max(1)];
+            loop {
+                let (back, filled, read) = handle.block_in_place(move || {
+                    let read = reader.read(&mut buf);
+                    (reader, buf, read)
+                });
+                (reader, buf) = (back, filled);
+                let n = read?;
+                if n == 0 {
+                    return Ok(reader);
+                }
+                let mut out = handle;
+                out.write_all(&buf[..n])?;
+            }
+        })
+    }
+
+    /// Exposes a blocking writer as an `AsyncWrite` stream.
+    ///
+    /// Bytes written from async code are collected up to `capacity` at a time
+    /// and written to `writer` on the blocking pool of `builder`. Closing the
+    /// stream resolves once the coroutine has flushed `writer` and returned,
+    /// failing with the first error if there was one;
+    /// [`take_return`](Self::take_return) then yields the writer back, or the
+    /// error, and writes fail with `BrokenPipe`.
+    #[track_caller]
+    pub fn from_writer(
+        builder: CoroutineBuilder,
+        writer: T,
+        capacity: usize,
+    ) -> Result<Self, BuildError>
+    where
+        T: io::Write,
+    {
+        let stream = Self::with_builder(builder, move |handle| {
+            let mut writer = writer;
+            let mut buf = vec![0; capacity.max(1)];
+            loop {
+                let n = handle.read(&mut buf)?;
+                let (back, drained, written) = handle.block_in_place(move || {
+                    let written = if n == 0 { writer.flush() } else { writer.write_all(&buf[..n]) };
+                    (writer, buf, written)
+                });
+                (writer, buf) = (back, drained);
+                written?;
+                if n == 0 {
+                    return Ok(writer);
+                }
+            }
+        });
+        stream.map(|mut stream| {
+            stream.close_result = handler_error::<T>;
+            stream
+        })
+    }
+}
+
+/// Reports the error a handler returning `io::Result<T>` failed with, rebuilt
+/// so [`CoroutineIo::take_return`] still yields the original.
+fn handler_error<T>(output: &Option<io::Result<T>>) -> io::Result<()> {
+    match output {
+        Some(Err(error)) => Err(io::Error::new(error.kind(), error.to_string())),
+        _ => Ok(()),
+    }
+}
+
+impl<R> fmt::Debug for CoroutineIo<R> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CoroutineIo")
+            .field("coroutine", &self.inner)
+            .field("done", &self.done)
+            .finish_non_exhaustive()
+    }
+}
+
+// The stream never pins its fields.
+impl<R> Unpin for CoroutineIo<R> {}
+
+impl<R> Drop for CoroutineIo<R> {
+    fn drop(&mut self) {
+        // Unwind the body first, so nothing on its stack outlives the slot.
+        self.inner.abort_for_drop();
+        // SAFETY: `pipe was leaked from a `Box` in `new` and is only freed here.
+        drop(unsafe { Box::from_raw(self.pipe.as_ptr()) });
+    }
+}
+
+impl<R> AsyncRead for CoroutineIo<R> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        if this.done || buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+        let pipe = this.pipe.as_ptr();
+        // SAFETY: the handler is suspended whenever this side runs, and `buf`
+        // outlives the resume that may write into it.
+        unsafe {
+            (*pipe).read_dst = NonNull::new(buf.as_mut_ptr()).map(|p| (p, buf.len()));
+        }
+        this.resume(cx);
+        let pipe = unsafe { &mut *pipe };
+        pipe.read_dst = None;
+        if let Some(n) = pipe.read_done.take() {
+            return Poll::Ready(Ok(n));
+        }
+        if this.done {
+            return Poll::Ready(Ok(0));
+        }
+        if pipe.blocked == Blocked::Read {
+            pipe.read_waker = Some(cx.waker().clone());
+        }
+        Poll::Pending
+    }
+}
+
+impl<R> AsyncWrite for CoroutineIo<R> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        if this.done {
+            return Poll::Ready(Err(io::ErrorKind::BrokenPipe.into()));
+        }
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+        let pipe = this.pipe.as_ptr();
+        // SAFETY: as in `poll_read`.
+        unsafe {
+            (*pipe).write_src = NonNull::new(buf.as_ptr() as *mut u8).map(|p| (p, buf.len()));
+        }
+        this.resume(cx);
+        let pipe = unsafe { &mut *pipe };
+        pipe.write_src = None;
+        if let Some(n) = pipe.write_done.take() {
+            return Poll::Ready(Ok(n));
+        }
+        if this.done {
+            return Poll::Ready(Err(io::ErrorKind::BrokenPipe.into()));
+        }
+        if pipe.blocked == Blocked::Write {
+            pipe.write_waker = Some(cx.waker().clone());
+        }
+        Poll::Pending
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        // Writes complete only once the handler has copied the bytes.
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if !this.done {
+            // SAFETY: the handler is suspended whenever this side runs.
+            let pipe = this.pipe.as_ptr();
+            unsafe { (*pipe).write_closed = true };
+            // Let the handler observe end-of-input and finish what it does
+            // after it, such as flushing.
+            this.resume(cx);
+            if !this.done {
+                let pipe = unsafe { &mut *pipe };
+                if pipe.blocked == Blocked::Write {
+                    pipe.write_waker = Some(cx.waker().clone());
+                }
+                return Poll::Pending;
+            }
+        }
+        Poll::Ready((this.close_result)(&this.output))
+    }
+}
+
+impl From<Cancelled> for io::Error {
+    fn from(cancelled: Cancelled) -> Self {
+        io::Error::new(io::ErrorKind::Interrupted, cancelled)
+    }
+}
+
+impl From<Elapsed> for io::Error {
+    fn from(elapsed: Elapsed) -> Self {
+        io::Error::new(io::ErrorKind::TimedOut, elapsed)
+    }
+}
+
+/// Size of the chunks [`CoroutineAwaiter::copy`] and
+/// [`CoroutineAwaiter::read_to_end`] move data in.
+const CHUNK: usize = 8 * 1024;
+
+impl<W: WakerSlot> CoroutineAwaiter<W> {
+    /// Wraps an async stream for blocking use inside the coroutine; see [`SyncIo`].
+    pub fn blocking_io<T>(&self, io: T) -> SyncIo<'_, Self, T> {
+        SyncIo::new(self, io)
+    }
+
+    /// Blocks on one I/O operation, failing with `Interrupted` if the
+    /// coroutine is cancelled first.
+    fn await_io<T>(
+        &self,
+        poll: impl FnMut(&mut Context<'_>) -> Poll<io::Result<T>>,
+    ) -> io::Result<T> {
+        self.r#await_cancellable(poll_fn(poll))?
+    }
+
+    /// Returns `true` for an `Interrupted` error that did not come from
+    /// cancelling the coroutine, which `std::io` convention retries.
+    fn is_retryable(&self, error: &io::Error) -> bool {
+        error.kind() == io::ErrorKind::Interrupted && !self.is_cancelled()
+    }
+
+    /// Copies everything `reader` produces into `writer`, then flushes it,
+    /// returning the number of bytes copied; the async counterpart of
+    /// `std::io::copy`.
+    ///
+    /// The coroutine suspends whenever either side is not ready. Once the
+    /// coroutine is [cancelled](crate::cancel), the copy stops at its next
+    /// wait and fails with an `Interrupted` error.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// use minicoro_awaiters::CoroutineFuture;
+    ///
+    /// let coro = CoroutineFuture::new(move |awaiter| {
+    ///     let sent = awaiter.copy(&mut file, &mut socket)?;
+    ///     log::info!("sent {sent} bytes");
+    ///     io::Result::Ok(())
+    /// });
+    /// ```
+    pub fn copy<R, T>(&self, reader: &mut R, writer: &mut T) -> io::Result<u64>
+    where
+        R: AsyncRead + Unpin + ?Sized,
+        T: AsyncWrite + Unpin + ?Sized,
+    {
+        let mut buf = vec![0; CHUNK];
+        let mut copied = 0;
+        loop {
+            let len = match self.await_io(|cx| Pin::new(&mut *reader).poll_read(cx, &mut buf)) {
+                Ok(0) => break,
+                Ok(len) => len,
+                Err(error) if self.is_retryable(&error) => continue,
+                Err(error) => return Err(error),
+            };
+            let mut written = 0;
+            while written < len {
+                let chunk = &buf[written..len];
+                match self.await_io(|cx| Pin::new(&mut *writer).poll_write(cx, chunk)) {
+                    Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                    Ok(n) => written += n,
+                    Err(error) if self.is_retryable(&error) => {}
+                    Err(error) => return Err(error),
+                }
+            }
+            copied += len as u64;
+        }
+        self.await_io(|cx| Pin::new(&mut *writer).poll_flush(cx))?;
+        Ok(copied)
+    }
+
+    /// Reads until end-of-stream, appending to `buf`, and returns the number
+    /// of bytes read; the async counterpart of `std::io::Read::read_to_end`.
+    ///
+    /// Cancellation is respected as by [`copy`](Self::copy); the bytes read
+    /// before it stay in `buf`.
+    pub fn read_to_end<R>(&self, reader: &mut R, buf: &mut Vec<u8>) -> io::Result<usize>
+    where
+        R: AsyncRead + Unpin + ?Sized,
+    {
+        let start = buf.len();
+        loop {
+            let filled = buf.len();
+            buf.resize(filled + CHUNK, 0);
+            let read = self.await_io(|cx| Pin::new(&mut *reader).poll_read(cx, &mut buf[filled..]));
+            buf.truncate(filled + read.as_ref().map_or(0, |&len| len));
+            match read {
+                Ok(0) => return Ok(buf.len() - start),
+                Ok(_) => {}
+                Err(error) if self.is_retryable(&error) => {}
+                Err(error) => return Err(error),
+            }
+        }
+    }
+
+    /// Fills `buf` from `reader` within `timeout`, the async counterpart of
+    /// `std::io::Read::read_exact` with a deadline for the whole buffer.
+    ///
+    /// Fails with `TimedOut` if the buffer is not full in time,
+    /// `UnexpectedEof` if the stream ends first, and `Interrupted` once the
+    /// coroutine is cancelled. The contents of `buf` are unspecified after
+    /// an error.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the coroutine was built without a
+    /// [sleeper](crate::CoroutineBuilder::sleeper).
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// use core::time::Duration;
+    /// use minicoro_awaiters::{CoroutineFuture, time::TokioSleeper};
+    ///
+    /// let coro = CoroutineFuture::builder()
+    ///     .sleeper(TokioSleeper)
+    ///     .build(move |awaiter| {
+    ///         let mut header = [0; 16];
+    ///         awaiter.read_exact_timeout(&mut socket, &mut header, Duration::from_secs(5))?;
+    ///         io::Result::Ok(Header::parse(&header))
+    ///     })?;
+    /// ```
+    #[track_caller]
+    pub fn read_exact_timeout<R>(
+        &self,
+        reader: &mut R,
+        buf: &mut [u8],
+        timeout: Duration,
+    ) -> io::Result<()>
+    where
+        R: AsyncRead + Unpin + ?Sized,
+    {
+        let shared = self.coro.user_data();
+        let mut filled = 0;
+        let read = poll_fn(|cx| {
+            if shared.is_cancelled() {
+                return Poll::Ready(Err(Cancelled.into()));
+            }
+            while filled < buf.len() {
+                match Pin::new(&mut *reader).poll_read(cx, &mut buf[filled..]) {
+                    Poll::Ready(Ok(0)) => {
+                        return Poll::Ready(Err(io::ErrorKind::UnexpectedEof.into()));
+                    }
+                    Poll::Ready(Ok(len)) => filled += len,
+                    Poll::Ready(Err(error)) if error.kind() == io::ErrorKind::Interrupted => {}
+                    Poll::Ready(Err(error)) => return Poll::Ready(Err(error)),
+                    Poll::Pending => return Poll::Pending,
+                }
+            }
+            Poll::Ready(Ok(()))
+        });
+        self.await_timeout(read, timeout)?
+    }
+}
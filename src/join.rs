@@ -0,0 +1,475 @@
+//! Awaiting several futures at once from inside a coroutine.
+//!
+//! [`coro_join!`](crate::coro_join) and [`coro_select!`](crate::coro_select)
+//! give blocking-style code the ergonomics of `join!` and `select!`. Both
+//! wrap their futures in [`MaybeDone`] and block on them through a single
+//! await, so the branches make progress concurrently. Each branch gets a
+//! waker of its own, and a resume polls only the branches that were woken.
+//! [`poll_all`] and [`poll_any`] are the underlying building blocks and work
+//! with any [`Awaiter`].
+//!
+//! For a variable number of futures, [`CoroutineAwaiter::buffer_unordered`]
+//! mirrors `StreamExt::buffer_unordered`: it keeps up to a fixed number of
+//! futures from an iterator in flight and hands back their outputs, as a
+//! blocking [`Iterator`], in the order they complete, in the same way.
+//!
+//! On the async side, [`CoroutineFuture::join`] and [`CoroutineFuture::zip`]
+//! pair a coroutine with another one, or with any future, in a single
+//! [`Join`] future that completes once both have.
+
+use core::fmt;
+use core::future::Future;
+use core::future::IntoFuture;
+use core::future::poll_fn;
+use core::mem;
+use core::pin::Pin;
+use core::pin::pin;
+use core::task::Context;
+use core::task::Poll;
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+
+use awaiter_trait::Awaiter;
+
+use crate::CoroutineAwaiter;
+use crate::CoroutineFuture;
+use crate::reactor::Reactor;
+use crate::waker::WakerSlot;
+
+enum State<F: Future> {
+    Pending(F),
+    Done(F::Output),
+    Taken,
+}
+
+/// A future that keeps its output once complete, until it is taken.
+pub struct MaybeDone<F: Future>(State<F>);
+
+impl<F: Future> MaybeDone<F> {
+    /// Wraps `future`.
+    pub fn new(future: F) -> Self {
+        Self(State::Pending(future))
+    }
+
+    /// Takes the output, if the future has completed and it was not taken yet.
+    pub fn take_output(self: Pin<&mut Self>) -> Option<F::Output> {
+        // SAFETY: the future is never moved; it has already been dropped in
+        // place when the state is `Done`.
+        let this = unsafe { self.get_unchecked_mut() };
+        match this.0 {
+            State::Done(_) => match mem::replace(&mut this.0, State::Taken) {
+                State::Done(output) => Some(output),
+                _ => unreachable!(),
+            },
+            _ => None,
+        }
+    }
+}
+
+impl<F: Future> fmt::Debug for MaybeDone<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = match self.0 {
+            State::Pending(_) => "Pending",
+            State::Done(_) => "Done",
+            State::Taken => "Taken",
+        };
+        f.debug_tuple("MaybeDone").field(&format_args!("{state}")).finish()
+    }
+}
+
+/// An output-erased view of a [`MaybeDone`].
+pub trait PollDone {
+    /// Polls the wrapped future, returning `true` once it has completed.
+    fn poll_done(self: Pin<&mut Self>, cx: &mut Context<'_>) -> bool;
+}
+
+impl<F: Future> PollDone for MaybeDone<F> {
+    fn poll_done(self: Pin<&mut Self>, cx: &mut Context<'_>) -> bool {
+        // SAFETY: the future is pinned in place and only dropped there.
+        let this = unsafe { self.get_unchecked_mut() };
+        match &mut this.0 {
+            State::Pending(future) => match unsafe { Pin::new_unchecked(future) }.poll(cx) {
+                Poll::Ready(output) => {
+                    this.0 = State::Done(output);
+                    true
+                }
+                Poll::Pending => false,
+            },
+            _ => true,
+        }
+    }
+}
+
+/// Blocks until every future in `futures` has completed.
+///
+/// Each future is polled with a waker of its own, so a resume only polls
+/// the futures that were woken since their last poll.
+pub fn poll_all<A: Awaiter + ?Sized>(awaiter: &A, futures: &mut [Pin<&mut dyn PollDone>]) {
+    let reactor = Reactor::new(futures.len());
+    let mut done = alloc::vec![false; futures.len()];
+    let mut pending = futures.len();
+    awaiter.r#await(pin!(poll_fn(|cx| {
+        reactor.poll_woken(cx, |index, cx| {
+            if !done[index] && futures[index].as_mut().poll_done(cx) {
+                done[index] = true;
+                pending -= 1;
+            }
+            None::<()>
+        });
+        if pending == 0 { Poll::Ready(()) } else { Poll::Pending }
+    })))
+}
+
+/// Blocks until one future in `futures` has completed, returning its index.
+///
+/// Like [`poll_all`], only the futures woken since their last poll are
+/// polled again. They are polled in order, so earlier ones win when several
+/// are ready.
+pub fn poll_any<A: Awaiter + ?Sized>(
+    awaiter: &A,
+    futures: &mut [Pin<&mut dyn PollDone>],
+) -> usize {
+    let reactor = Reactor::new(futures.len());
+    awaiter.r#await(pin!(poll_fn(|cx| {
+        match reactor.poll_woken(cx, |index, cx| {
+            futures[index].as_mut().poll_done(cx).then_some(index)
+        }) {
+            Some(index) => Poll::Ready(index),
+            None => Poll::Pending,
+        }
+    })))
+}
+
+/// Futures from an iterator run concurrently, yielding their outputs as
+/// they complete; returned by [`CoroutineAwaiter::buffer_unordered`].
+pub struct BufferUnordered<'a, A: ?Sized, I: Iterator>
+where
+    I::Item: IntoFuture,
+{
+    awaiter: &'a A,
+    futures: I,
+    /// The running futures, each in the slot its reactor waker refers to.
+    slots: Vec<Option<Pin<Box<<I::Item as IntoFuture>::IntoFuture>>>>,
+    /// Created on the first call to `next`, with one child per slot.
+    reactor: Option<Reactor>,
+    running: usize,
+    limit: usize,
+    /// `futures` has returned `None`.
+    exhausted: bool,
+}
+
+impl<'a, A: Awaiter + ?Sized, I: Iterator> BufferUnordered<'a, A, I>
+where
+    I::Item: IntoFuture,
+{
+    /// Runs the futures of `futures`, up to `limit` at a time, blocking on
+    /// them through `awaiter`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `limit` is zero.
+    pub fn new(awaiter: &'a A, futures: I, limit: usize) -> Self {
+        assert!(limit > 0, "`buffer_unordered` needs a limit of at least one");
+        Self {
+            awaiter,
+            futures,
+            slots: Vec::new(),
+            reactor: None,
+            running: 0,
+            limit,
+            exhausted: false,
+        }
+    }
+
+    /// Number of futures started and not finished yet.
+    pub fn in_flight(&self) -> usize {
+        self.running
+    }
+
+    /// Starts futures from the iterator in every free slot.
+    fn fill(&mut self) {
+        let Some(reactor) = &self.reactor else {
+            // The first fill decides how many slots there are: at most
+            // `limit`, and no more than the iterator had futures for.
+            while self.slots.len() < self.limit {
+                let Some(future) = self.futures.next() else {
+                    self.exhausted = true;
+                    break;
+                };
+                self.slots.push(Some(Box::pin(future.into_future())));
+            }
+            self.running = self.slots.len();
+            self.reactor = Some(Reactor::new(self.slots.len()));
+            return;
+        };
+        if self.exhausted {
+            return;
+        }
+        for (index, slot) in self.slots.iter_mut().enumerate() {
+            if slot.is_some() {
+                continue;
+            }
+            let Some(future) = self.futures.next() else {
+                self.exhausted = true;
+                return;
+            };
+            *slot = Some(Box::pin(future.into_future()));
+            self.running += 1;
+            reactor.mark(index);
+        }
+    }
+}
+
+impl<A: Awaiter + ?Sized, I: Iterator> Iterator for BufferUnordered<'_, A, I>
+where
+    I::Item: IntoFuture,
+{
+    type Item = <I::Item as IntoFuture>::Output;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.fill();
+        if self.running == 0 {
+            return None;
+        }
+        let reactor = self.reactor.as_ref()?;
+        let slots = &mut self.slots;
+        let (index, output) = self.awaiter.r#await(pin!(poll_fn(|cx| {
+            let ready = reactor.poll_woken(cx, |index, cx| {
+                // A freed slot can still be woken by the future that ran in it.
+                match slots[index].as_mut()?.as_mut().poll(cx) {
+                    Poll::Ready(output) => Some((index, output)),
+                    Poll::Pending => None,
+                }
+            });
+            match ready {
+                Some(ready) => Poll::Ready(ready),
+                None => Poll::Pending,
+            }
+        })));
+        self.slots[index] = None;
+        self.running -= 1;
+        Some(output)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        if self.exhausted {
+            return (self.running, Some(self.running));
+        }
+        let (lower, upper) = self.futures.size_hint();
+        (
+            lower.saturating_add(self.running),
+            upper.and_then(|upper| upper.checked_add(self.running)),
+        )
+    }
+}
+
+impl<A: ?Sized, I: Iterator> fmt::Debug for BufferUnordered<'_, A, I>
+where
+    I::Item: IntoFuture,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BufferUnordered")
+            .field("in_flight", &self.running)
+            .field("limit", &self.limit)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<W: WakerSlot> CoroutineAwaiter<W> {
+    /// Runs the futures of `futures` concurrently, keeping up to `limit` of
+    /// them in flight, and iterates over their outputs as they complete.
+    ///
+    /// Each call to `next` blocks the coroutine until one of the running
+    /// futures finishes, then starts the next one from `futures`. Futures
+    /// still running when the iterator is dropped are dropped unfinished.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `limit` is zero.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// use minicoro_awaiters::CoroutineFuture;
+    ///
+    /// let coro = CoroutineFuture::new(move |awaiter| {
+    ///     for page in awaiter.buffer_unordered(urls.iter().map(|url| fetch(url)), 8) {
+    ///         index(page);
+    ///     }
+    /// });
+    /// ```
+    pub fn buffer_unordered<I>(
+        &self,
+        futures: I,
+        limit: usize,
+    ) -> BufferUnordered<'_, Self, I::IntoIter>
+    where
+        I: IntoIterator,
+        I::Item: IntoFuture,
+    {
+        BufferUnordered::new(self, futures.into_iter(), limit)
+    }
+}
+
+impl<T, W: WakerSlot> CoroutineFuture<T, W> {
+    /// Drives this coroutine and `other` in one future, resolving to both
+    /// outputs once both have finished.
+    ///
+    /// Each poll resumes whichever of the two has been woken, alternating
+    /// which goes first so neither can starve the other.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// use minicoro_awaiters::CoroutineFuture;
+    ///
+    /// let producer = CoroutineFuture::new(move |awaiter| produce(awaiter, tx));
+    /// let consumer = CoroutineFuture::new(move |awaiter| consume(awaiter, rx));
+    /// let ((), total) = producer.join(consumer).await;
+    /// ```
+    pub fn join<U>(self, other: CoroutineFuture<U, W>) -> Join<Self, CoroutineFuture<U, W>> {
+        Join::new(self, other)
+    }
+
+    /// Like [`join`](Self::join), but pairs the coroutine with any future,
+    /// such as an `async` block or a [`catch_overflow`](Self::catch_overflow)
+    /// wrapper around another coroutine.
+    pub fn zip<F: IntoFuture>(self, other: F) -> Join<Self, F::IntoFuture> {
+        Join::new(self, other.into_future())
+    }
+}
+
+/// Two futures driven together, returned by [`CoroutineFuture::join`] and
+/// [`CoroutineFuture::zip`].
+///
+/// Both futures are pinned structurally: they are polled in place and never
+/// moved once the `Join` is pinned, so it is `Unpin` exactly when both are.
+pub struct Join<A: Future, B: Future> {
+    a: MaybeDone<A>,
+    b: MaybeDone<B>,
+    /// Whether `b` is polled first on the next poll.
+    b_first: bool,
+}
+
+impl<A: Future, B: Future> Join<A, B> {
+    fn new(a: A, b: B) -> Self {
+        Self {
+            a: MaybeDone::new(a),
+            b: MaybeDone::new(b),
+            b_first: false,
+        }
+    }
+}
+
+impl<A: Future, B: Future> fmt::Debug for Join<A, B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Join")
+            .field("a", &self.a)
+            .field("b", &self.b)
+            .finish()
+    }
+}
+
+impl<A: Future, B: Future> Future for Join<A, B> {
+    type Output = (A::Output, B::Output);
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // SAFETY: `a` and `b` are structurally pinned and never moved.
+        let this = unsafe { self.get_unchecked_mut() };
+        let mut a = unsafe { Pin::new_unchecked(&mut this.a) };
+        let mut b = unsafe { Pin::new_unchecked(&mut this.b) };
+        let done = if mem::replace(&mut this.b_first, !this.b_first) {
+            b.as_mut().poll_done(cx) & a.as_mut().poll_done(cx)
+        } else {
+            a.as_mut().poll_done(cx) & b.as_mut().poll_done(cx)
+        };
+        if !done {
+            return Poll::Pending;
+        }
+        match (a.take_output(), b.take_output()) {
+            (Some(a), Some(b)) => Poll::Ready((a, b)),
+            _ => panic!("`Join` polled after completion"),
+        }
+    }
+}
+
+/// Blocks on several futures concurrently and returns a tuple of their outputs.
+///
+/// # Example
+///
+/// ```ignore
+/// use minicoro_awaiters::{CoroutineFuture, coro_join};
+///
+/// let coro = CoroutineFuture::new(|awaiter| {
+///     let (a, b) = coro_join!(awaiter, async { 1 }, async { "two" });
+///     assert_eq!((a, b), (1, "two"));
+/// });
+/// ```
+#[macro_export]
+macro_rules! coro_join {
+    ($awaiter:expr, $($future:expr),+ $(,)?) => {
+        $crate::coro_join!(@munch $awaiter; []; $($future,)+)
+    };
+    (@munch $awaiter:expr; [$($slot:ident)*]; $future:expr, $($rest:expr,)*) => {{
+        let mut __slot = ::core::pin::pin!($crate::join::MaybeDone::new(
+            ::core::future::IntoFuture::into_future($future),
+        ));
+        $crate::coro_join!(@munch $awaiter; [$($slot)* __slot]; $($rest,)*)
+    }};
+    (@munch $awaiter:expr; [$($slot:ident)*];) => {{
+        $crate::join::poll_all(
+            &$awaiter,
+            &mut [$($slot.as_mut() as ::core::pin::Pin<&mut dyn $crate::join::PollDone>),*],
+        );
+        ($($slot.as_mut().take_output().unwrap(),)*)
+    }};
+}
+
+/// Blocks until the first of several futures completes and runs its branch.
+///
+/// Each branch is `pattern = future => expression`; the pattern must be
+/// irrefutable. The other futures are dropped unfinished. Branches are
+/// polled in order, so earlier ones win when several are ready.
+///
+/// # Example
+///
+/// ```ignore
+/// use minicoro_awaiters::{CoroutineFuture, coro_select};
+///
+/// let coro = CoroutineFuture::new(|awaiter| {
+///     coro_select!(awaiter, {
+///         line = read_line() => println!("read {line}"),
+///         () = sleep(Duration::from_secs(1)) => println!("timed out"),
+///     })
+/// });
+/// ```
+#[macro_export]
+macro_rules! coro_select {
+    ($awaiter:expr, { $($branches:tt)+ }) => {
+        $crate::coro_select!(@munch $awaiter; []; $($branches)+)
+    };
+    (@munch $awaiter:expr; [$($arms:tt)*]; $pat:pat = $future:expr => $body:expr) => {
+        $crate::coro_select!(@munch $awaiter; [$($arms)*]; $pat = $future => $body,)
+    };
+    (@munch $awaiter:expr; [$($arms:tt)*]; $pat:pat = $future:expr => $body:expr, $($rest:tt)*) => {{
+        let mut __slot = ::core::pin::pin!($crate::join::MaybeDone::new(
+            ::core::future::IntoFuture::into_future($future),
+        ));
+        $crate::coro_select!(@munch $awaiter; [$($arms)* (__slot, $pat, $body)]; $($rest)*)
+    }};
+    (@munch $awaiter:expr; [$(($slot:ident, $pat:pat, $body:expr))*];) => {{
+        $crate::join::poll_any(
+            &$awaiter,
+            &mut [$($slot.as_mut() as ::core::pin::Pin<&mut dyn $crate::join::PollDone>),*],
+        );
+        $(
+            if let ::core::option::Option::Some(__output) = $slot.as_mut().take_output() {
+                let $pat = __output;
+                $body
+            } else
+        )* {
+            ::core::unreachable!()
+        }
+    }};
+}
@@ -14,6 +14,9 @@
 //! - [`CoroutineFuture`]: A future wrapper around a coroutine that can be awaited from async code
 //! - [`CoroutineToken`]: A token type implementing `awaiter_trait::Coroutine` for ergonomic usage
 //!
+//! [`ConfiguredToken`] is a token that applies the settings of a
+//! [`CoroutineBuilder`] to every coroutine it creates.
+//!
 //! For backwards compatibility, the old single-letter type aliases [`R`], [`C`], and [`Token`]
 //! are still available.
 //!
@@ -41,26 +44,226 @@
 //! - `no_std` compatible (requires `alloc`)
 //! - Seamless integration with Rust's async/await
 //! - Works with the `awaiter-trait` ecosystem
+//!
+//! ## Cargo Features
+//!
+//! - `macros`: re-exports the [`coroutine_fn`] attribute macro and the
+//!   [`impl_blocking!`] and [`impl_async!`] macros
+//! - `allocator-api2`: allocates coroutine closures and output slots from a
+//!   caller-supplied `allocator_api2` allocator (see `allocator`)
+//! - `alloc-stats`: counts the allocations made on behalf of each coroutine
+//!   (see `memory`)
+//! - `futures`: implements `futures_core::Stream` for [`generator::CoroutineGenerator`],
+//!   [`generator::CoroutineTryStream`] and [`set::CoroutineSet`] (and
+//!   `progress::Progress` with `std`), and `futures_sink::Sink` for
+//!   [`consumer::CoroutineConsumer`], and adds the `stream` helpers for
+//!   consuming streams inside coroutines
+//! - `std`: catches panics inside coroutines and resumes them on the async side
+//!   (see `panic`), and adds [`block_on`], the default executor of
+//!   [`blocking_main!`], the `io` adapters between
+//!   `futures_io` and `std::io`, `actor`, `current`, `progress`, `shared`, `wait` and `watchdog`
+//! - `diagnostics`: registers every live coroutine in a global registry that
+//!   `diagnostics::dump` takes snapshots of
+//! - `decommit`: lets `pool::CoroutinePool` release the resident stack memory
+//!   of workers that sit idle
+//! - `executor`: adds `executor`, a minimal single-threaded executor driving
+//!   coroutines and plain futures without a runtime, for `no_std` targets
+//! - `secure-stack`: adds [`CoroutineBuilder::secure_stack`], locking a
+//!   coroutine's stack into memory while it runs and zeroing it afterwards
+//! - `backtrace`: attaches the coroutine-side backtrace to propagated panics
+//! - `capi`: exports a C API for driving coroutines from non-Rust hosts (see `capi`)
+//! - `critical-section`: adds [`waker::CriticalSectionWaker`] for waking from
+//!   interrupt handlers
+//! - `portable-atomic`: builds on targets without native compare-and-swap,
+//!   such as thumbv6m, through `portable-atomic`; signatures taking an `Arc`
+//!   then take `portable_atomic_util::Arc`
+//! - `tracing`: enters a per-coroutine `tracing` span around every resume,
+//!   created as a child of the span current when the coroutine is first
+//!   polled, so events from its body attach to the request polling it
+//! - `tracy`, `puffin`: open a Tracy zone or puffin scope, named after the
+//!   coroutine, around every resume, covering the stack switch as well as
+//!   the body
+//! - `log`: emits `debug` records when coroutines are created, resumed,
+//!   suspended and completed
+//! - `defmt`: emits the same records through `defmt`, and implements
+//!   `defmt::Format` for the crate's status and error types
+//! - `tokio`: consults tokio's cooperative budget on every ready await (see `coop`)
+//!   and adds `spawn::TokioSpawner`, `time::TokioSleeper` and
+//!   `blocking::TokioBlockingPool`
+//! - `smol`: adds `spawn::SmolSpawner`
+//! - `rayon`: adds `blocking::RayonPool`
+//! - `wasm-bindgen`: awaits JavaScript promises inside coroutines and drives
+//!   coroutines with `wasm_bindgen_futures::spawn_local` (see `wasm`)
+//! - `thread-backend`: runs every coroutine on an OS thread instead of a
+//!   minicoro stack, so code using coroutines can be tested under Miri (see
+//!   `thread_backend`)
+//! - `test-util`: adds a mock awaiter with virtual time for unit tests (see
+//!   `test_util`)
+//! - `sanitize`: announces coroutine stack switches to AddressSanitizer and
+//!   ThreadSanitizer through their fiber APIs; needs a nightly compiler
+//! - `nightly`: interop with the unstable `core::ops::Coroutine` trait (see `nightly`),
+//!   implements `core::async_iter::AsyncIterator` for
+//!   [`generator::CoroutineGenerator`], and makes the allocation of each
+//!   coroutine's shared state fallible (see [`CoroutineFuture::try_new`]) and
+//!   forwards `Context` extension data to awaited futures (see `context`)
 
 #![no_std]
+#![cfg_attr(feature = "nightly", feature(coroutine_trait, async_iterator, allocator_api, context_ext))]
+#![cfg_attr(feature = "sanitize", feature(cfg_sanitize))]
 extern crate alloc;
+#[cfg(any(feature = "std", loom))]
+extern crate std;
+use core::fmt;
 use core::future::Future;
-use core::mem::MaybeUninit;
+use core::panic::Location;
+use core::ptr::NonNull;
 use core::task::Context;
 use core::task::Poll;
+use core::task::Waker;
 
 use alloc::boxed::Box;
-use atomic_waker::AtomicWaker;
-use minicoroutine::Coroutine;
-use minicoroutine::CoroutineRef;
-use minicoroutine::GLOBAL;
+use alloc::vec::Vec;
+
+use backend::Coroutine;
+use backend::CoroutineRef;
+use backend::GLOBAL;
+use cancel::Teardown;
+use error::ResumeError;
+use hooks::CoroutineHooks;
+use sync::Arc;
+use sync::AtomicUsize;
+use sync::AtomicWaker;
+use sync::Ordering;
+use waker::CoroutineShared;
+use waker::WakerSlot;
+
+#[cfg(feature = "macros")]
+pub use minicoro_awaiters_macros::coroutine_fn;
+#[cfg(feature = "macros")]
+pub use minicoro_awaiters_macros::impl_async;
+#[cfg(feature = "macros")]
+pub use minicoro_awaiters_macros::impl_blocking;
+/// Paths used by the code the macros generate.
+#[cfg(feature = "macros")]
+#[doc(hidden)]
+pub use awaiter_trait as __awaiter_trait;
+
+mod backend;
+mod builder;
+pub use builder::BuildError;
+pub use builder::CoroutineBuilder;
+pub use builder::CoroutineRun;
+
+#[cfg(feature = "std")]
+mod block_on;
+#[cfg(feature = "std")]
+pub use block_on::block_on;
+
+#[cfg(feature = "std")]
+pub mod actor;
+#[cfg(feature = "allocator-api2")]
+pub mod allocator;
+pub mod blocking;
+pub mod boxed;
+pub mod cancel;
+#[cfg(feature = "capi")]
+pub mod capi;
+pub mod consumer;
+pub mod context;
+pub mod coop;
+pub mod critical;
+#[cfg(feature = "diagnostics")]
+pub mod diagnostics;
+#[cfg(feature = "std")]
+pub mod current;
+pub mod either;
+pub mod error;
+mod events;
+#[cfg(feature = "executor")]
+pub mod executor;
+pub mod ffi;
+pub mod generator;
+pub mod hooks;
+pub mod join;
+pub mod local;
+pub mod map;
+#[cfg(feature = "alloc-stats")]
+pub mod memory;
+#[cfg(feature = "std")]
+pub mod io;
+#[cfg(feature = "nightly")]
+pub mod nightly;
+pub mod observe;
+pub mod panic;
+pub mod pool;
+#[cfg(feature = "std")]
+pub mod progress;
+#[cfg(any(feature = "tracy", feature = "puffin"))]
+mod profiler;
+mod reactor;
+#[cfg(feature = "sanitize")]
+mod sanitize;
+pub mod scope;
+pub mod send;
+#[cfg(feature = "std")]
+pub mod shared;
+pub mod set;
+pub mod spawn;
+pub mod stack;
+#[cfg(feature = "futures")]
+pub mod stream;
+mod sync;
+#[cfg(feature = "test-util")]
+pub mod test_util;
+#[cfg(feature = "thread-backend")]
+pub mod thread_backend;
+pub mod time;
+#[cfg(feature = "std")]
+pub mod wait;
+pub mod waker;
+#[cfg(feature = "wasm-bindgen")]
+pub mod wasm;
+#[cfg(feature = "std")]
+pub mod watchdog;
+pub mod worker;
+
+/// The lifecycle state of a coroutine, as observed from its handles.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug, Hash)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum CoroutineStatus {
+    /// The coroutine has not been resumed yet.
+    Created,
+    /// The coroutine is executing; only observable from inside it.
+    Running,
+    /// The coroutine yielded and resumes on the next poll.
+    Suspended,
+    /// The coroutine is waiting for an awaited future to wake it.
+    Parked,
+    /// The coroutine body has returned.
+    Completed,
+}
 
 /// An awaiter that allows awaiting futures from within a minicoro coroutine.
 ///
 /// This struct implements [`awaiter_trait::Awaiter`], enabling futures to be
 /// awaited in a blocking fashion within a coroutine. When a future is polled
 /// and returns `Pending`, the coroutine yields and will be resumed when the
-/// future's waker is invoked.
+/// future's waker is invoked. Futures are polled with a waker backed by the
+/// coroutine's [`CoroutineShared`](waker::CoroutineShared) slot, so awaiting
+/// does not clone the executor's waker.
+///
+/// Awaited futures need not be `Send`: the coroutine never leaves the thread
+/// of its [`CoroutineFuture`], which is `!Send`. Coroutines that must move
+/// between threads use [`send::SendAwaiter`] instead.
+///
+/// # Sharing
+///
+/// The awaiter is owned by the coroutine body and cannot be cloned. To hand
+/// it to helpers, pass `&CoroutineAwaiter`, or take an [`AwaiterRef`] from
+/// [`handle`](Self::handle), a `Copy` handle that can be stored in several
+/// structs at once. Every copy refers to the same coroutine, and awaiting
+/// through any of them suspends that coroutine; awaits only work from the
+/// coroutine's own stack, and one attempted from anywhere else panics.
 ///
 /// # Example
 ///
@@ -73,33 +276,293 @@ use minicoroutine::GLOBAL;
 ///     println!("Got: {}", value);
 /// });
 /// ```
-pub struct CoroutineAwaiter {
+pub struct CoroutineAwaiter<W = AtomicWaker> {
     /// The underlying coroutine reference used for yielding and accessing user data.
-    pub coro: CoroutineRef<(), (), (), AtomicWaker, GLOBAL>,
+    pub(crate) coro: CoroutineRef<(), (), (), Arc<CoroutineShared<W>>, GLOBAL>,
 }
 
 /// Type alias for backwards compatibility.
 #[deprecated(since = "0.2.0", note = "Use `CoroutineAwaiter` instead")]
 pub type R = CoroutineAwaiter;
 
-impl awaiter_trait::Awaiter for CoroutineAwaiter {
+impl<W: WakerSlot> awaiter_trait::Awaiter for CoroutineAwaiter<W> {
+    #[track_caller]
     fn r#await<T>(&self, mut f: core::pin::Pin<&mut (dyn Future<Output = T> + '_)>) -> T {
+        // The outer future registers the executor's waker before every resume,
+        // so the slot-backed waker is always ready to forward wakeups.
+        let shared = self.coro.user_data();
+        // Left in place if polling panics, so the panic names the await site.
+        shared.set_await_site(Some(Location::caller()));
+        let waker = waker::borrow(shared);
         loop {
-            let t = loop {
-                match self.coro.user_data().take() {
-                    Some(a) => break a,
-                    None => self.coro.yield_(()),
-                }
+            shared.clear_woken();
+            // Rebuilt for every poll and dropped before suspending, since the
+            // extension data belongs to the outer poll that resumed us.
+            #[cfg(feature = "nightly")]
+            let mut cx = context::inner_context(shared, &waker);
+            #[cfg(not(feature = "nightly"))]
+            let mut cx = Context::from_waker(&waker);
+            let poll = match f.as_mut().poll(&mut cx) {
+                Poll::Ready(a) => Poll::Ready((a, coop::poll_proceed(shared, &mut cx))),
+                Poll::Pending => Poll::Pending,
             };
-            match f.as_mut().poll(&mut Context::from_waker(&t)) {
-                Poll::Ready(a) => return a,
-                Poll::Pending => self.coro.yield_(()),
+            drop(cx);
+            match poll {
+                Poll::Ready((a, proceed)) => {
+                    if proceed.is_pending() {
+                        // Budget spent: the waker has been woken, so the
+                        // outer future resumes us on its next poll.
+                        self.suspend();
+                    }
+                    shared.set_await_site(None);
+                    return a;
+                }
+                Poll::Pending => {
+                    waker::assert_wakeable(shared);
+                    shared.set_parked(true);
+                    self.suspend();
+                    shared.set_parked(false);
+                }
+            }
+        }
+    }
+}
+
+impl<W: WakerSlot> CoroutineAwaiter<W> {
+    /// Yields to the outer future, unwinding instead of returning if the
+    /// coroutine is aborted while suspended.
+    #[track_caller]
+    pub(crate) fn suspend(&self) {
+        if cfg!(debug_assertions) {
+            let shared = self.coro.user_data();
+            if let Some(guard) = shared.no_yield() {
+                panic!(
+                    "{} suspended inside the no-yield section entered at {guard}",
+                    shared.describe()
+                );
             }
         }
+        self.assert_on_own_stack();
+        self.check_stack();
+        #[cfg(feature = "decommit")]
+        self.coro.user_data().set_suspended_sp(stack::stack_pointer());
+        {
+            #[cfg(feature = "sanitize")]
+            let _switch = sanitize::SuspendSwitch::enter(self.coro.user_data());
+            self.coro.yield_(());
+        }
+        let shared = self.coro.user_data();
+        panic::unwind_if_aborted(shared.is_aborted() && shared.teardown() == Teardown::Unwind);
+    }
+
+    /// Wraps the reference a body created directly with `minicoroutine`
+    /// receives, for a coroutine adopted with
+    /// [`CoroutineFuture::from_coroutine`].
+    pub fn from_coro_ref(coro: CoroutineRef<(), (), (), Arc<CoroutineShared<W>>, GLOBAL>) -> Self {
+        Self { coro }
+    }
+
+    /// The underlying minicoro coroutine reference.
+    ///
+    /// Reading its status and its [`CoroutineShared`] user data, for example
+    /// to [`wake`](CoroutineShared::wake) the coroutine, is supported.
+    /// Yielding through it directly bypasses the awaiter's bookkeeping: the
+    /// coroutine is not resumed again until something wakes it, and an abort
+    /// during such a yield is not noticed until the next await.
+    pub fn coro_ref(&self) -> &CoroutineRef<(), (), (), Arc<CoroutineShared<W>>, GLOBAL> {
+        &self.coro
+    }
+
+    /// The name of the running coroutine, if one was given through the builder.
+    pub fn name(&self) -> Option<&str> {
+        self.coro.user_data().name()
+    }
+
+    /// Where the running coroutine was created.
+    pub fn location(&self) -> &'static Location<'static> {
+        self.coro.user_data().location()
+    }
+
+    /// Blocks the coroutine on `future`, pinning it on the coroutine's stack.
+    ///
+    /// Shorthand for `r#await(pin!(future.into_future()))`, for the common
+    /// awaits of a value such as a `oneshot::Receiver` or a request builder.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// use minicoro_awaiters::CoroutineFuture;
+    ///
+    /// let coro = CoroutineFuture::new(move |awaiter| {
+    ///     let reply = awaiter.wait(reply_rx).expect("sender dropped");
+    ///     reply.len()
+    /// });
+    /// ```
+    #[track_caller]
+    pub fn wait<F: core::future::IntoFuture>(&self, future: F) -> F::Output {
+        let future = core::pin::pin!(future.into_future());
+        awaiter_trait::Awaiter::r#await(self, future)
+    }
+
+    /// Calls the async closure `f` and blocks the coroutine on its future,
+    /// which is pinned on the coroutine's stack.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// use minicoro_awaiters::CoroutineFuture;
+    ///
+    /// let coro = CoroutineFuture::new(move |awaiter| {
+    ///     let user = awaiter.call(async || client.get_user(id).await);
+    ///     user.name
+    /// });
+    /// ```
+    #[track_caller]
+    pub fn call<T>(&self, f: impl AsyncFnOnce() -> T) -> T {
+        self.wait(f())
+    }
+
+    /// Like [`call`](Self::call), passing `arg` to `f`; use a tuple for
+    /// several arguments.
+    #[track_caller]
+    pub fn call_with<A, T>(&self, f: impl AsyncFnOnce(A) -> T, arg: A) -> T {
+        self.wait(f(arg))
+    }
+
+    /// Like [`call_with`](Self::call_with), but borrows `f`, so a closure
+    /// that mutates its captures can be called again afterwards.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// use minicoro_awaiters::CoroutineFuture;
+    ///
+    /// let coro = CoroutineFuture::new(move |awaiter| {
+    ///     let mut sent = 0;
+    ///     let mut send = async |line: String| {
+    ///         sink.send(line).await;
+    ///         sent += 1;
+    ///     };
+    ///     for line in lines {
+    ///         awaiter.call_mut(&mut send, line);
+    ///     }
+    /// });
+    /// ```
+    #[track_caller]
+    pub fn call_mut<A, T, F: AsyncFnMut(A) -> T + ?Sized>(&self, f: &mut F, arg: A) -> T {
+        self.wait(f(arg))
+    }
+
+    /// Polls `future` once without suspending, returning `None` if it is
+    /// still pending.
+    ///
+    /// The future is polled with the coroutine's waker, so it can be kept
+    /// and awaited afterwards without losing a wakeup; a future dropped after
+    /// this returns `None` may still wake the coroutine once, spuriously.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// use core::pin::pin;
+    /// use minicoro_awaiters::CoroutineFuture;
+    ///
+    /// let coro = CoroutineFuture::new(move |awaiter| {
+    ///     let mut lookup = pin!(cache.get(key));
+    ///     match awaiter.try_await(lookup.as_mut()) {
+    ///         Some(value) => value,
+    ///         None => awaiter.r#await(Box::pin(fetch(key))),
+    ///     }
+    /// });
+    /// ```
+    pub fn try_await<F: Future + ?Sized>(
+        &self,
+        future: core::pin::Pin<&mut F>,
+    ) -> Option<F::Output> {
+        let shared = self.coro.user_data();
+        let waker = waker::borrow(shared);
+        #[cfg(feature = "nightly")]
+        let mut cx = context::inner_context(shared, &waker);
+        #[cfg(not(feature = "nightly"))]
+        let mut cx = Context::from_waker(&waker);
+        match future.poll(&mut cx) {
+            Poll::Ready(output) => Some(output),
+            Poll::Pending => None,
+        }
+    }
+
+    /// Returns a `Copy` handle to the awaiter for passing down to helpers.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// use minicoro_awaiters::{AwaiterRef, CoroutineFuture};
+    ///
+    /// struct Client<'a> {
+    ///     awaiter: AwaiterRef<'a>,
+    /// }
+    ///
+    /// let coro = CoroutineFuture::new(|awaiter| {
+    ///     let users = Client { awaiter: awaiter.handle() };
+    ///     let orders = Client { awaiter: awaiter.handle() };
+    ///     merge(users.fetch(), orders.fetch())
+    /// });
+    /// ```
+    pub fn handle(&self) -> AwaiterRef<'_, W> {
+        AwaiterRef(self)
+    }
+}
+
+/// A `Copy` handle to a [`CoroutineAwaiter`], returned by
+/// [`CoroutineAwaiter::handle`].
+///
+/// It dereferences to the awaiter and implements [`awaiter_trait::Awaiter`]
+/// by delegating to it.
+pub struct AwaiterRef<'a, W = AtomicWaker>(&'a CoroutineAwaiter<W>);
+
+impl<W> Clone for AwaiterRef<'_, W> {
+    fn clone(&self) -> Self {
+        *self
     }
 }
 
-awaiter_trait::autoimpl!(<> CoroutineAwaiter as Awaiter);
+impl<W> Copy for AwaiterRef<'_, W> {}
+
+impl<W> core::ops::Deref for AwaiterRef<'_, W> {
+    type Target = CoroutineAwaiter<W>;
+
+    fn deref(&self) -> &CoroutineAwaiter<W> {
+        self.0
+    }
+}
+
+impl<W: WakerSlot> awaiter_trait::Awaiter for AwaiterRef<'_, W> {
+    #[track_caller]
+    fn r#await<T>(&self, f: core::pin::Pin<&mut (dyn Future<Output = T> + '_)>) -> T {
+        awaiter_trait::Awaiter::r#await(self.0, f)
+    }
+}
+
+impl<W: WakerSlot> fmt::Debug for AwaiterRef<'_, W> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("AwaiterRef").field(self.0).finish()
+    }
+}
+
+awaiter_trait::autoimpl!(<'a, W: WakerSlot> AwaiterRef<'a, W> as Awaiter);
+
+impl<W: WakerSlot> fmt::Debug for CoroutineAwaiter<W> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let shared = self.coro.user_data();
+        f.debug_struct("CoroutineAwaiter")
+            .field("name", &shared.name())
+            .field("location", &shared.location())
+            .field("status", &CoroutineStatus::Running)
+            .field("resumes", &shared.resume_count())
+            .finish_non_exhaustive()
+    }
+}
+
+awaiter_trait::autoimpl!(<W: WakerSlot> CoroutineAwaiter<W> as Awaiter);
 
 /// A future wrapper around a minicoro coroutine.
 ///
@@ -112,6 +575,22 @@ awaiter_trait::autoimpl!(<> CoroutineAwaiter as Awaiter);
 /// Use [`CoroutineFuture::new`] to create a new coroutine with a closure that receives
 /// a [`CoroutineAwaiter`] for awaiting futures inside the coroutine.
 ///
+/// # Pinning
+///
+/// `CoroutineFuture` is [`Unpin`] for every `T`: the coroutine's stack, the
+/// futures it awaits and the slot its output is written to all live on the
+/// heap, and the future itself only holds pointers to them. Moving it between
+/// polls never moves anything the coroutine refers to, so wrappers can poll
+/// it through [`Pin::new`](core::pin::Pin::new) without projecting pins.
+///
+/// It also makes `&mut CoroutineFuture` a future, through core's impl for
+/// mutable references to `Unpin` futures, so a coroutine kept in a struct
+/// can be awaited by reference, for example in one arm of a `select!`,
+/// without being consumed. With the `futures` feature it implements
+/// `FusedFuture`, which `futures::select!` requires, and reports itself
+/// terminated once its output has been taken; it must not be polled after
+/// that.
+///
 /// # Example
 ///
 /// ```ignore
@@ -126,20 +605,32 @@ awaiter_trait::autoimpl!(<> CoroutineAwaiter as Awaiter);
 ///     coro.await; // Run the coroutine to completion
 /// }
 /// ```
-pub struct CoroutineFuture {
+pub struct CoroutineFuture<T = (), W: WakerSlot = AtomicWaker> {
     /// The underlying minicoro coroutine.
-    pub coro: Coroutine<(), (), (), AtomicWaker, GLOBAL>,
+    pub(crate) coro: Coroutine<(), (), (), Arc<CoroutineShared<W>>, GLOBAL>,
+    /// Heap slot the coroutine body writes its return value (or panic) into.
+    output: NonNull<Option<panic::Outcome<T>>>,
+    /// Frees `output`, with the allocator it came from.
+    free_output: unsafe fn(NonNull<Option<panic::Outcome<T>>>),
+    /// Lifecycle hooks registered through the builder.
+    hooks: Option<Arc<dyn CoroutineHooks>>,
+    /// The body has returned.
+    done: bool,
+    /// The span entered around every resume; none until the first resume.
+    #[cfg(feature = "tracing")]
+    span: tracing::Span,
 }
 
 /// Type alias for backwards compatibility.
 #[deprecated(since = "0.2.0", note = "Use `CoroutineFuture` instead")]
 pub type C = CoroutineFuture;
 
-impl CoroutineFuture {
+impl<T> CoroutineFuture<T> {
     /// Creates a new coroutine that can await futures.
     ///
     /// The provided closure receives a [`CoroutineAwaiter`] that can be used to
-    /// await futures from within the coroutine.
+    /// await futures from within the coroutine. Its return value becomes the
+    /// output of the future.
     ///
     /// # Arguments
     ///
@@ -156,30 +647,501 @@ impl CoroutineFuture {
     ///     println!("Got: {}", value);
     /// });
     /// ```
-    pub fn new<T: FnOnce(CoroutineAwaiter)>(a: T) -> Self {
-        // let a = MaybeUninit::new(a);
-        let a = Box::leak(Box::new(a)) as *mut _ as *mut ();
+    ///
+    /// # Panics
+    ///
+    /// Panics if the coroutine cannot be created; use [`CoroutineFuture::try_new`]
+    /// to handle that case.
+    #[track_caller]
+    pub fn new<F: FnOnce(CoroutineAwaiter) -> T>(a: F) -> Self {
+        Self::try_new(a).unwrap()
+    }
+
+    /// Creates a new coroutine future, returning an error instead of
+    /// panicking or aborting if it cannot be allocated.
+    ///
+    /// Every allocation made for the coroutine is fallible: the boxes holding
+    /// the closure and its output, minicoro's context and stack, and, with
+    /// the `nightly` feature and without `portable-atomic`, the shared state.
+    /// Stable Rust has no fallible `Arc` constructor, so other builds still
+    /// abort if that last allocation fails. The same holds for
+    /// [`CoroutineBuilder::build`].
+    #[track_caller]
+    pub fn try_new<F: FnOnce(CoroutineAwaiter) -> T>(a: F) -> Result<Self, BuildError> {
+        CoroutineBuilder::new().build(a)
+    }
+}
+
+impl<T, W: WakerSlot> CoroutineFuture<T, W> {
+    /// The coroutine's name, if one was given through the builder.
+    pub fn name(&self) -> Option<&str> {
+        self.coro.user_data().name()
+    }
+
+    /// Where the coroutine was created.
+    pub fn location(&self) -> &'static Location<'static> {
+        self.coro.user_data().location()
+    }
+
+    /// A number identifying the coroutine among all coroutines of the process.
+    pub fn id(&self) -> usize {
+        self.coro.user_data().id()
+    }
+
+    /// The underlying minicoro coroutine.
+    ///
+    /// Reading its status and its [`CoroutineShared`] user data is
+    /// supported. It is only handed out by shared reference: resuming it
+    /// anywhere but through this future would skip the waker registration,
+    /// hooks and output handling the future relies on.
+    pub fn as_raw(&self) -> &Coroutine<(), (), (), Arc<CoroutineShared<W>>, GLOBAL> {
+        &self.coro
+    }
+
+    /// The `r#await` call the coroutine is suspended in, if any.
+    pub fn await_site(&self) -> Option<&'static Location<'static>> {
+        self.coro.user_data().await_site()
+    }
+
+    /// Number of times the coroutine has been resumed.
+    pub fn resume_count(&self) -> usize {
+        self.coro.user_data().resume_count()
+    }
+
+    /// The coroutine's current lifecycle state.
+    pub fn status(&self) -> CoroutineStatus {
+        let shared = self.coro.user_data();
+        if self.done {
+            CoroutineStatus::Completed
+        } else if shared.resume_count() == 0 {
+            CoroutineStatus::Created
+        } else if shared.is_parked() {
+            CoroutineStatus::Parked
+        } else {
+            CoroutineStatus::Suspended
+        }
+    }
+}
+
+impl<T, W: WakerSlot> fmt::Debug for CoroutineFuture<T, W> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CoroutineFuture")
+            .field("name", &self.name())
+            .field("location", &self.location())
+            .field("status", &self.status())
+            .field("resumes", &self.resume_count())
+            .finish_non_exhaustive()
+    }
+}
+
+impl CoroutineFuture {
+    /// Returns a builder for coroutines with non-default settings.
+    pub fn builder() -> CoroutineBuilder {
+        CoroutineBuilder::new()
+    }
+
+    /// Adopts a coroutine created directly with `minicoroutine`.
+    ///
+    /// The coroutine's user data must be a fresh `Arc<CoroutineShared>`
+    /// (for example `Arc::default()`) that is not shared with any other
+    /// coroutine. To await futures, the body wraps the [`CoroutineRef`] it
+    /// receives with [`CoroutineAwaiter::from_coro_ref`]. Yields that
+    /// do not go through an awaiter suspend the future until it is next polled,
+    /// so such a body must arrange to be woken itself, e.g. through
+    /// [`CoroutineShared::wake`].
+    ///
+    /// The adopted coroutine must not have been resumed yet, and is never
+    /// resumed by anything but the returned future. Unlike coroutines from
+    /// [`CoroutineBuilder`], an adopted body that is [aborted](Self::abort)
+    /// (or dropped) before it starts still runs up to its first suspension,
+    /// and is then torn down as if it had been aborted there.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// use minicoro_awaiters::{CoroutineAwaiter, CoroutineFuture};
+    /// use minicoroutine::Coroutine;
+    ///
+    /// let coro = Coroutine::new(
+    ///     |p| {
+    ///         let awaiter = CoroutineAwaiter::from_coro_ref(p);
+    ///         awaiter.r#await(Box::pin(async { 42 }));
+    ///     },
+    ///     Default::default(),
+    /// )
+    /// .unwrap();
+    /// CoroutineFuture::from_coroutine(coro).await;
+    /// ```
+    pub fn from_coroutine(coro: Coroutine<(), (), (), Arc<CoroutineShared>, GLOBAL>) -> Self {
+        // The body writes no output, so completion yields the unit value.
+        let output = NonNull::from(Box::leak(Box::new(Some(Ok(())))));
+        events::created(coro.user_data());
         Self {
-            coro: Coroutine::new(
-                move |p| unsafe { *Box::from_raw(a as *mut T) }(CoroutineAwaiter { coro: p }),
-                Default::default(),
-            )
-            .unwrap(),
+            #[cfg(feature = "tracing")]
+            span: tracing::Span::none(),
+            coro,
+            output,
+            free_output: <builder::GlobalSlots as builder::Slots>::free,
+            hooks: None,
+            done: false,
+        }
+    }
+}
+
+impl<T, W: WakerSlot> CoroutineFuture<T, W> {
+    /// Sets how many ready awaits the coroutine may complete per resume
+    /// before yielding back to the executor.
+    ///
+    /// Defaults to [`coop::DEFAULT_BUDGET`]; `None` disables the budget.
+    pub fn set_budget(&mut self, budget: Option<u32>) {
+        self.coro.user_data().set_budget(budget);
+    }
+}
+
+// The future never pins its fields; everything the coroutine refers to is
+// heap-allocated and outlives every move of the handle.
+impl<T, W: WakerSlot> Unpin for CoroutineFuture<T, W> {}
+
+impl<T, W: WakerSlot> Drop for CoroutineFuture<T, W> {
+    fn drop(&mut self) {
+        self.abort_for_drop();
+        // Wipes what an abort skipped while panicking left on the stack.
+        #[cfg(feature = "secure-stack")]
+        stack::wipe(self.coro.user_data());
+        // SAFETY: `output` was allocated for `free_output` in `build` and is
+        // only freed here.
+        unsafe { (self.free_output)(self.output) };
+    }
+}
+
+/// The result of a single resume step, see [`CoroutineFuture::resume_with_waker`].
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug, Hash)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Resumed<T> {
+    /// The coroutine suspended again without finishing.
+    Yielded,
+    /// The coroutine body returned this value.
+    Completed(T),
+}
+
+impl<T, W: WakerSlot> CoroutineFuture<T, W> {
+    /// Registers `waker` and resumes the coroutine exactly once.
+    ///
+    /// This is the step [`Future::poll`] is built on, for schedulers that
+    /// drive coroutines themselves. Unlike `poll`, it resumes even when
+    /// [`needs_resume`](Self::needs_resume) is `false`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the coroutine has already completed.
+    pub fn resume_with_waker(&mut self, waker: &Waker) -> Resumed<T> {
+        self.register(waker);
+        self.resume_once()
+    }
+
+    /// Returns `true` unless the coroutine is parked on an awaited future that
+    /// has not woken since it was last resumed.
+    pub fn needs_resume(&self) -> bool {
+        !self.done && self.coro.user_data().should_resume()
+    }
+
+    /// Resumes the coroutine once if it is ready, without an executor.
+    ///
+    /// For frame loops and other schedulers that are not async: call `step`
+    /// once per tick, and the coroutine advances whenever a future it awaits
+    /// has woken it, as reported by [`needs_resume`](Self::needs_resume). No
+    /// waker needs to be supplied; wakeups are recorded on the coroutine
+    /// itself. Loops that sleep between ticks can instead pass a waker that
+    /// wakes them to [`resume_with_waker`](Self::resume_with_waker).
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// use minicoro_awaiters::{CoroutineFuture, Resumed};
+    ///
+    /// let mut script = CoroutineFuture::new(|awaiter| run_cutscene(awaiter));
+    /// loop {
+    ///     if let Resumed::Completed(()) = script.step() {
+    ///         break;
+    ///     }
+    ///     render_frame();
+    /// }
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if the coroutine has already completed.
+    pub fn step(&mut self) -> Resumed<T> {
+        if !self.done && !self.coro.user_data().should_resume() {
+            return Resumed::Yielded;
+        }
+        self.resume_with_waker(Waker::noop())
+    }
+
+    /// Resumes the coroutine exactly once outside any async context and
+    /// returns its output if that finished it.
+    ///
+    /// For draining coroutines opportunistically from synchronous code, such
+    /// as a shutdown path. The waker registered by the last poll, if any, is
+    /// kept, so a coroutine that is still pending afterwards can be handed
+    /// back to its executor; without one, futures awaited during the probe
+    /// are polled with a no-op waker.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the coroutine has already completed.
+    pub fn poll_probe(&mut self) -> Poll<T> {
+        match self.resume_once() {
+            Resumed::Yielded => Poll::Pending,
+            Resumed::Completed(output) => Poll::Ready(output),
+        }
+    }
+
+    /// Unwinds the coroutine's stack, running the destructors of everything it
+    /// holds, and discards its output.
+    ///
+    /// The body observes the abort as an unwind out of its current suspension
+    /// point; a body that has not started yet never runs. Without the `std`
+    /// feature or in `panic = "abort"` builds there is no unwinding, and a
+    /// suspended body is abandoned on its stack instead, unless the builder
+    /// chose another [`Teardown`](cancel::Teardown). Polling the future
+    /// afterwards panics.
+    pub fn abort(&mut self) {
+        if self.done {
+            return;
+        }
+        self.done = true;
+        let shared = self.coro.user_data();
+        shared.abort();
+        // A body that never ran gets one extra resume: a built body returns
+        // at once, and an adopted one runs up to its first suspension, where
+        // the strategy takes over.
+        let fresh = u32::from(shared.resume_count() == 0);
+        let limit = match shared.teardown() {
+            Teardown::Unwind => cancel::UNWIND_MAX_RESUMES,
+            Teardown::Cooperative { max_resumes } => {
+                shared.cancel();
+                max_resumes
+            }
+            Teardown::Leak => 0,
+        }
+        .saturating_add(fresh);
+        let mut finished = false;
+        if limit != 0 {
+            #[cfg(feature = "tracing")]
+            self.open_span();
+            let mut resumes = 0;
+            while resumes < limit {
+                resumes += 1;
+                let resumed = {
+                    let _running = context::Running::enter(self.coro.user_data());
+                    let _guard = panic::ResumeGuard::new();
+                    #[cfg(feature = "tracing")]
+                    let _span = self.span.enter();
+                    #[cfg(feature = "tracy")]
+                    let _zone = profiler::tracy_zone(self.coro.user_data());
+                    #[cfg(feature = "puffin")]
+                    puffin::profile_scope!("coroutine", profiler::name(self.coro.user_data()));
+                    #[cfg(feature = "sanitize")]
+                    let _switch = sanitize::ResumeSwitch::enter(self.coro.user_data());
+                    self.coro.resume(())
+                };
+                if resumed.is_none() {
+                    finished = true;
+                    break;
+                }
+            }
+        }
+        if !finished {
+            events::leaked(self.coro.user_data());
+        }
+        #[cfg(feature = "secure-stack")]
+        stack::wipe(self.coro.user_data());
+        if let Some(hooks) = &self.hooks {
+            hooks.on_complete();
+        }
+        events::completed(self.coro.user_data());
+        // SAFETY: the coroutine has finished, so nothing else accesses the slot.
+        drop(unsafe { (*self.output.as_ptr()).take() });
+    }
+
+    /// Aborts the coroutine when its owner is dropped mid-await, so the future
+    /// it is blocked on is dropped in place rather than frozen on its stack.
+    ///
+    /// Skipped while the thread is already panicking, where a second unwind
+    /// would abort the process; the coroutine is abandoned instead.
+    pub(crate) fn abort_for_drop(&mut self) {
+        #[cfg(feature = "std")]
+        if std::thread::panicking() {
+            return;
+        }
+        self.abort();
+    }
+
+    /// Stores `waker` to be woken by awaited futures.
+    ///
+    /// Registered on every poll: the slot already skips replacing a waker
+    /// that [`will_wake`](Waker::will_wake) the same task, and a wake may
+    /// have consumed the stored one since the last poll.
+    pub(crate) fn register(&mut self, waker: &Waker) {
+        self.coro.user_data().register(waker);
+    }
+
+    fn resume_once(&mut self) -> Resumed<T> {
+        match self.resume_raw() {
+            Resumed::Yielded => Resumed::Yielded,
+            Resumed::Completed(outcome) => Resumed::Completed(self.finish_or_panic(outcome)),
+        }
+    }
+
+    pub(crate) fn resume_checked(&mut self) -> Resumed<Result<T, stack::StackOverflow>> {
+        match self.resume_raw() {
+            Resumed::Yielded => Resumed::Yielded,
+            Resumed::Completed(outcome) => Resumed::Completed(self.finish(outcome)),
+        }
+    }
+
+    /// Turns the outcome of the finished body into its output, resuming its
+    /// panic on the caller's stack.
+    pub(crate) fn finish(&self, outcome: panic::Outcome<T>) -> Result<T, stack::StackOverflow> {
+        let shared = self.coro.user_data();
+        if shared.has_overflowed() {
+            // The outcome is the unwind started by the failed stack check.
+            return Err(stack::StackOverflow);
+        }
+        #[cfg(feature = "std")]
+        if let Err(payload) = &outcome {
+            if !panic::is_abort(&**payload) {
+                // Nothing to resolve to but the panic; the handler can still log it.
+                panic::decide(shared.panic_handler(), &**payload, shared.origin());
+            }
+        }
+        Ok(panic::unwrap(outcome, Some(shared.origin())))
+    }
+
+    /// Like [`finish`](Self::finish), but panics on a stack overflow.
+    pub(crate) fn finish_or_panic(&self, outcome: panic::Outcome<T>) -> T {
+        match self.finish(outcome) {
+            Ok(output) => output,
+            Err(stack::StackOverflow) => {
+                panic!("{} overflowed its stack", self.coro.user_data().describe())
+            }
+        }
+    }
+
+    /// Creates the coroutine's span on its first resume, as a child of the
+    /// span current in the outer future's poll.
+    #[cfg(feature = "tracing")]
+    fn open_span(&mut self) {
+        if self.span.is_none() {
+            self.span = builder::span(self.coro.user_data());
+        }
+    }
+
+    /// Resumes the coroutine once, returning the body's outcome as caught.
+    pub(crate) fn resume_raw(&mut self) -> Resumed<panic::Outcome<T>> {
+        match self.try_resume_raw() {
+            Ok(resumed) => resumed,
+            Err(_) => panic!("{} polled after completion", self.coro.user_data().describe()),
+        }
+    }
+
+    /// Like [`resume_raw`](Self::resume_raw), but reports a coroutine that
+    /// finished without an outcome as an error.
+    pub(crate) fn try_resume_raw(&mut self) -> Result<Resumed<panic::Outcome<T>>, ResumeError> {
+        #[cfg(feature = "tracing")]
+        self.open_span();
+        let shared = self.coro.user_data();
+        shared.reset_budget();
+        shared.count_resume();
+        if let Some(hooks) = &self.hooks {
+            hooks.on_resume();
+        }
+        events::resumed(shared);
+        #[cfg(feature = "std")]
+        let started = shared.watchdog().map(|_| std::time::Instant::now());
+        let resumed = {
+            let _running = context::Running::enter(self.coro.user_data());
+            let _guard = panic::ResumeGuard::new();
+            #[cfg(feature = "std")]
+            let _current = current::ResumeScope::enter(self.coro.user_data());
+            #[cfg(feature = "tracing")]
+            let _span = self.span.enter();
+            #[cfg(feature = "tracy")]
+            let _zone = profiler::tracy_zone(self.coro.user_data());
+            #[cfg(feature = "puffin")]
+            puffin::profile_scope!("coroutine", profiler::name(self.coro.user_data()));
+            #[cfg(feature = "sanitize")]
+            let _switch = sanitize::ResumeSwitch::enter(self.coro.user_data());
+            self.coro.resume(())
+        };
+        #[cfg(feature = "std")]
+        if let Some(started) = started {
+            let shared = self.coro.user_data();
+            if let Some(watchdog) = shared.watchdog() {
+                watchdog.check(shared, started.elapsed());
+            }
+        }
+        match resumed {
+            Some(_) => {
+                if let Some(hooks) = &self.hooks {
+                    hooks.on_yield();
+                }
+                events::yielded(self.coro.user_data());
+                Ok(Resumed::Yielded)
+            }
+            None => {
+                self.done = true;
+                #[cfg(feature = "secure-stack")]
+                stack::wipe(self.coro.user_data());
+                if let Some(hooks) = &self.hooks {
+                    hooks.on_complete();
+                }
+                events::completed(self.coro.user_data());
+                // SAFETY: the coroutine has finished, so nothing else accesses the slot.
+                match unsafe { (*self.output.as_ptr()).take() } {
+                    Some(outcome) => Ok(Resumed::Completed(outcome)),
+                    None => Err(ResumeError::NoOutput),
+                }
+            }
         }
     }
 }
 
-impl Future for CoroutineFuture {
-    type Output = ();
+impl<T, W: WakerSlot> Future for CoroutineFuture<T, W> {
+    type Output = T;
 
-    fn poll(mut self: core::pin::Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        self.coro.user_data().register(&cx.waker());
-        match self.coro.resume(()) {
-            Some(_) => Poll::Pending,
-            None => Poll::Ready(()),
+    fn poll(self: core::pin::Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        this.register(cx.waker());
+        if !this.coro.user_data().should_resume() {
+            // Polled for an unrelated reason; the awaited future is still pending.
+            return Poll::Pending;
+        }
+        #[cfg(feature = "nightly")]
+        let mut ext = cx.ext();
+        #[cfg(feature = "nightly")]
+        let _ext = context::ExtScope::enter(this.coro.user_data(), &mut ext);
+        let mut resumes = this.coro.user_data().resumes_per_poll();
+        loop {
+            if let Resumed::Completed(output) = this.resume_once() {
+                return Poll::Ready(output);
+            }
+            resumes -= 1;
+            if resumes == 0 || !this.coro.user_data().is_woken() {
+                return Poll::Pending;
+            }
         }
     }
 }
+
+#[cfg(feature = "futures")]
+impl<T, W: WakerSlot> futures_core::FusedFuture for CoroutineFuture<T, W> {
+    fn is_terminated(&self) -> bool {
+        self.done
+    }
+}
+
 /// A token type for creating coroutines through the `awaiter_trait::Coroutine` interface.
 ///
 /// This zero-sized type implements [`awaiter_trait::Coroutine`], providing an ergonomic
@@ -198,6 +1160,34 @@ impl Future for CoroutineFuture {
 ///     assert_eq!(result, 42);
 /// }
 /// ```
+///
+/// # Nesting
+///
+/// `exec` may be called from inside a running coroutine and the returned
+/// future awaited through that coroutine's awaiter. The inner coroutine runs
+/// on a stack of its own, resumed from the outer one's, and wakes propagate
+/// from its awaited futures through both slots to the executor.
+///
+/// Each body must await through the awaiter it was given. Awaiting through
+/// the outer awaiter from inside the inner body panics, because suspending
+/// would switch away from the wrong stack. If the outer coroutine is
+/// aborted while the inner one is suspended, the inner one is abandoned
+/// rather than unwound, since the outer stack is already unwinding.
+///
+/// ```ignore
+/// let outer = CoroutineToken.exec(|outer| {
+///     let inner = CoroutineToken.exec(|inner| inner.r#await(Box::pin(async { 1 })));
+///     outer.r#await(Box::pin(inner)) + 1
+/// });
+/// assert_eq!(outer.await, 2);
+/// ```
+///
+/// # Trait objects
+///
+/// `Coroutine::exec` is generic, so `dyn Coroutine` does not exist. The
+/// token also implements the object-safe counterparts in
+/// `awaiter_trait::r#dyn`, through `awaiter_trait::autoimpl!`, so plugin
+/// boundaries can take it as a trait object of those instead.
 #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug, Hash, Default)]
 pub struct CoroutineToken;
 
@@ -210,20 +1200,212 @@ impl awaiter_trait::Coroutine for CoroutineToken {
         &self,
         f: impl FnOnce(&(dyn awaiter_trait::r#dyn::DynAwaiter + '_)) -> T,
     ) -> impl Future<Output = T> {
-        async move {
-            let mut c = MaybeUninit::uninit();
-            match &mut c {
-                c => {
-                    CoroutineFuture::new(move |a| {
-                        let v = f(&a);
-                        c.write(v);
-                    })
-                    .await
-                }
-            };
-            unsafe { c.assume_init() }
-        }
+        // The result travels through the coroutine's own output slot, which
+        // the future owns and frees even if it is dropped mid-await.
+        CoroutineFuture::new(move |a| f(&a))
     }
 }
 
 awaiter_trait::autoimpl!(<> CoroutineToken as Coroutine);
+
+impl CoroutineToken {
+    /// Runs every closure in `fs` in turn on a single coroutine, collecting
+    /// their results.
+    ///
+    /// Compared to one [`exec`](awaiter_trait::Coroutine::exec) per closure,
+    /// this creates one stack and one future for the whole batch. The
+    /// closures run sequentially, so one blocked on an await holds up the
+    /// rest, and a panic in one of them fails the whole batch.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// use minicoro_awaiters::CoroutineToken;
+    ///
+    /// let replies = CoroutineToken
+    ///     .exec_many(requests.into_iter().map(|request| {
+    ///         move |awaiter: &dyn DynAwaiter| send(awaiter, request)
+    ///     }))
+    ///     .await;
+    /// ```
+    pub fn exec_many<T, F>(&self, fs: impl IntoIterator<Item = F>) -> impl Future<Output = Vec<T>>
+    where
+        F: FnOnce(&(dyn awaiter_trait::r#dyn::DynAwaiter + '_)) -> T,
+    {
+        let fs = fs.into_iter();
+        awaiter_trait::Coroutine::exec(self, move |awaiter| fs.map(|f| f(awaiter)).collect())
+    }
+}
+
+/// A [`CoroutineToken`] that builds every coroutine from a
+/// [`CoroutineBuilder`] template.
+///
+/// Code written against `awaiter_trait::Coroutine` only ever calls `exec`,
+/// so the token is the one place settings such as the stack size or the
+/// hooks can be chosen for it. Each `exec` builds its coroutine from a clone
+/// of the template; with a [name prefix](Self::name_prefix), the coroutines
+/// are also named after it and numbered in creation order, the numbering
+/// shared between clones of the token.
+///
+/// # Example
+///
+/// ```ignore
+/// use minicoro_awaiters::{ConfiguredToken, CoroutineFuture};
+/// use awaiter_trait::Coroutine;
+///
+/// let token = CoroutineFuture::builder()
+///     .stack_size(256 << 10)
+///     .hooks(Metrics::default())
+///     .token()
+///     .name_prefix("plugin");
+/// // Runs as "plugin-0" on a 256 KiB stack.
+/// let result = token.exec(|awaiter| plugin.run(awaiter)).await;
+/// ```
+#[derive(Clone)]
+pub struct ConfiguredToken {
+    builder: CoroutineBuilder,
+    name_prefix: Option<Box<str>>,
+    /// Number of coroutines named so far, by this token and its clones.
+    named: Arc<AtomicUsize>,
+}
+
+impl ConfiguredToken {
+    /// Creates a token building its coroutines from `builder`.
+    pub fn new(builder: CoroutineBuilder) -> Self {
+        Self {
+            builder,
+            name_prefix: None,
+            named: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Names each coroutine `"{prefix}-{n}"`, replacing the name of the
+    /// template, where `n` counts the coroutines created so far.
+    pub fn name_prefix(mut self, prefix: impl Into<alloc::string::String>) -> Self {
+        self.name_prefix = Some(prefix.into().into_boxed_str());
+        self
+    }
+
+    /// The template coroutines are built from.
+    pub fn builder(&self) -> &CoroutineBuilder {
+        &self.builder
+    }
+
+    /// Returns a builder for the next coroutine, named after the prefix.
+    fn next_builder(&self) -> CoroutineBuilder {
+        let builder = self.builder.clone();
+        match &self.name_prefix {
+            Some(prefix) => {
+                let n = self.named.fetch_add(1, Ordering::Relaxed);
+                builder.name(alloc::format!("{prefix}-{n}"))
+            }
+            None => builder,
+        }
+    }
+
+    /// Like [`exec`](awaiter_trait::Coroutine::exec), but returns an error
+    /// instead of panicking if the coroutine cannot be created.
+    #[track_caller]
+    pub fn try_exec<T>(
+        &self,
+        f: impl FnOnce(&(dyn awaiter_trait::r#dyn::DynAwaiter + '_)) -> T,
+    ) -> Result<CoroutineFuture<T>, BuildError> {
+        self.next_builder().build(move |a| f(&a))
+    }
+
+    /// Runs every closure in `fs` in turn on a single coroutine, collecting
+    /// their results, as [`CoroutineToken::exec_many`] does.
+    pub fn exec_many<T, F>(&self, fs: impl IntoIterator<Item = F>) -> impl Future<Output = Vec<T>>
+    where
+        F: FnOnce(&(dyn awaiter_trait::r#dyn::DynAwaiter + '_)) -> T,
+    {
+        let fs = fs.into_iter();
+        awaiter_trait::Coroutine::exec(self, move |awaiter| fs.map(|f| f(awaiter)).collect())
+    }
+}
+
+impl awaiter_trait::Coroutine for ConfiguredToken {
+    /// # Panics
+    ///
+    /// Panics if the coroutine cannot be created; see
+    /// [`try_exec`](ConfiguredToken::try_exec).
+    fn exec<T>(
+        &self,
+        f: impl FnOnce(&(dyn awaiter_trait::r#dyn::DynAwaiter + '_)) -> T,
+    ) -> impl Future<Output = T> {
+        self.try_exec(f).unwrap()
+    }
+}
+
+awaiter_trait::autoimpl!(<> ConfiguredToken as Coroutine);
+
+impl From<CoroutineBuilder> for ConfiguredToken {
+    fn from(builder: CoroutineBuilder) -> Self {
+        Self::new(builder)
+    }
+}
+
+impl fmt::Debug for ConfiguredToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ConfiguredToken")
+            .field("builder", &self.builder)
+            .field("name_prefix", &self.name_prefix)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Turns a blocking-style `main`, taking an awaiter, into the program's
+/// entry point.
+///
+/// The body runs inside a [`CoroutineFuture`], so it can call async
+/// libraries through the awaiter while the rest of a synchronous binary is
+/// ported over. By default the coroutine is driven by [`block_on`], which
+/// needs the `std` feature. A `with` clause hands it to another executor
+/// instead: its expression is called with the coroutine and must return
+/// its output.
+///
+/// # Example
+///
+/// ```ignore
+/// minicoro_awaiters::blocking_main! {
+///     fn main(awaiter) -> std::io::Result<()> {
+///         let config = awaiter.r#await(Box::pin(load_config()))?;
+///         legacy::run(&awaiter, config)
+///     }
+/// }
+/// ```
+///
+/// With a tokio runtime driving the coroutine instead:
+///
+/// ```ignore
+/// minicoro_awaiters::blocking_main! {
+///     with |coro| tokio::runtime::Runtime::new().unwrap().block_on(coro);
+///     fn main(awaiter) {
+///         legacy::run(&awaiter);
+///     }
+/// }
+/// ```
+#[macro_export]
+macro_rules! blocking_main {
+    (
+        $(#[$attr:meta])*
+        fn main($awaiter:ident $(: $ty:ty)?) $(-> $ret:ty)? $body:block
+    ) => {
+        $crate::blocking_main! {
+            with $crate::block_on;
+            $(#[$attr])*
+            fn main($awaiter $(: $ty)?) $(-> $ret)? $body
+        }
+    };
+    (
+        with $executor:expr;
+        $(#[$attr:meta])*
+        fn main($awaiter:ident $(: $ty:ty)?) $(-> $ret:ty)? $body:block
+    ) => {
+        $(#[$attr])*
+        fn main() $(-> $ret)? {
+            let coro = $crate::CoroutineFuture::new(move |$awaiter $(: $ty)?| $(-> $ret)? $body);
+            ($executor)(coro)
+        }
+    };
+}
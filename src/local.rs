@@ -0,0 +1,296 @@
+//! Single-threaded coroutines without atomics.
+//!
+//! [`LocalCoroutineFuture`] is a `!Send` counterpart of
+//! [`CoroutineFuture`](crate::CoroutineFuture) for thread-per-core runtimes.
+//! It keeps the executor's waker in a plain `Cell<Option<Waker>>` and polls
+//! awaited futures with that waker directly, so neither resuming nor awaiting
+//! touches an atomic.
+//!
+//! The price is the features built on the shared waker slot: every poll of
+//! the outer future resumes the coroutine, and there are no names, hooks,
+//! budgets or cancellation.
+//!
+//! # Thread-per-core runtimes
+//!
+//! glommio and monoio run `!Send` tasks pinned to one thread, and their
+//! wakers are only meant to be woken from that thread (monoio accepts
+//! foreign wakeups only with its `sync` feature). Both suit
+//! [`LocalCoroutineFuture`]: the body runs on the task's own thread, the
+//! waker is kept in a `Cell` rather than an atomic slot, and awaited futures
+//! are polled with the runtime's waker itself, never with one of the
+//! crate's, so they never see a waker that could travel to another thread.
+//! Bodies that start background work spawn through the runtime's local spawn
+//! function with [`spawn::spawn_local`](crate::spawn::spawn_local).
+//!
+//! ```ignore
+//! use minicoro_awaiters::local::LocalCoroutineFuture;
+//!
+//! glommio::LocalExecutor::default().run(async {
+//!     let coro = LocalCoroutineFuture::new(|awaiter| {
+//!         awaiter.r#await(Box::pin(glommio::timer::sleep(Duration::from_millis(10))));
+//!     });
+//!     coro.await;
+//! });
+//! ```
+//!
+//! [`CoroutineFuture`](crate::CoroutineFuture) works on these runtimes too,
+//! as the waker it hands to awaited futures wakes the runtime's waker on
+//! whichever thread it is woken, under the same rule; it is the choice when
+//! the body needs a builder setting.
+
+use core::cell::Cell;
+use core::fmt;
+use core::future::Future;
+use core::marker::PhantomData;
+use core::pin::Pin;
+use core::ptr::NonNull;
+use core::task::Context;
+use core::task::Poll;
+use core::task::Waker;
+
+use alloc::boxed::Box;
+use alloc::rc::Rc;
+
+use crate::BuildError;
+use crate::backend::Coroutine;
+use crate::backend::CoroutineRef;
+use crate::backend::GLOBAL;
+use crate::builder::try_leak;
+use crate::panic;
+
+/// State shared between a [`LocalCoroutineFuture`] and its body.
+#[derive(Default)]
+struct LocalShared {
+    /// The executor's waker, lent to the body while it runs.
+    waker: Cell<Option<Waker>>,
+    /// The outer future is unwinding the coroutine.
+    aborted: Cell<bool>,
+}
+
+/// The awaiter passed to the body of a [`LocalCoroutineFuture`].
+///
+/// Implements [`awaiter_trait::Awaiter`] like
+/// [`CoroutineAwaiter`](crate::CoroutineAwaiter), polling awaited futures
+/// with the executor's own waker.
+pub struct LocalCoroutineAwaiter {
+    coro: CoroutineRef<(), (), (), Rc<LocalShared>, GLOBAL>,
+}
+
+impl LocalCoroutineAwaiter {
+    fn suspend(&self) {
+        self.coro.yield_(());
+        panic::unwind_if_aborted(self.coro.user_data().aborted.get());
+    }
+}
+
+impl awaiter_trait::Awaiter for LocalCoroutineAwaiter {
+    fn r#await<T>(&self, mut f: Pin<&mut (dyn Future<Output = T> + '_)>) -> T {
+        let shared = self.coro.user_data();
+        loop {
+            let waker = shared
+                .waker
+                .take()
+                .expect("`LocalCoroutineFuture` resumed without a waker");
+            let poll = f.as_mut().poll(&mut Context::from_waker(&waker));
+            shared.waker.set(Some(waker));
+            match poll {
+                Poll::Ready(value) => return value,
+                Poll::Pending => self.suspend(),
+            }
+        }
+    }
+}
+
+impl fmt::Debug for LocalCoroutineAwaiter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LocalCoroutineAwaiter").finish_non_exhaustive()
+    }
+}
+
+awaiter_trait::autoimpl!(<> LocalCoroutineAwaiter as Awaiter);
+
+/// A `!Send` future running a coroutine without atomics.
+///
+/// # Example
+///
+/// ```ignore
+/// use minicoro_awaiters::local::LocalCoroutineFuture;
+///
+/// let coro = LocalCoroutineFuture::new(|awaiter| {
+///     awaiter.r#await(Box::pin(async { 42 }))
+/// });
+/// assert_eq!(coro.await, 42);
+/// ```
+pub struct LocalCoroutineFuture<T = ()> {
+    coro: Coroutine<(), (), (), Rc<LocalShared>, GLOBAL>,
+    output: NonNull<Option<panic::Outcome<T>>>,
+    started: bool,
+    done: bool,
+    _not_send: PhantomData<*const ()>,
+}
+
+impl<T> LocalCoroutineFuture<T> {
+    /// Creates a coroutine running `f` with minicoro's default stack size.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the coroutine cannot be created; use
+    /// [`try_new`](Self::try_new) to handle that case.
+    pub fn new<F: FnOnce(LocalCoroutineAwaiter) -> T>(f: F) -> Self {
+        Self::try_new(f).unwrap()
+    }
+
+    /// Creates a coroutine running `f` on a stack of `size` bytes.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the coroutine cannot be created; use
+    /// [`try_with_stack_size`](Self::try_with_stack_size) to handle that case.
+    pub fn with_stack_size<F: FnOnce(LocalCoroutineAwaiter) -> T>(f: F, size: usize) -> Self {
+        Self::try_with_stack_size(f, size).unwrap()
+    }
+
+    /// Like [`new`](Self::new), but returns an error if the coroutine or any
+    /// of its allocations fails, with the same exception as
+    /// [`CoroutineFuture::try_new`](crate::CoroutineFuture::try_new) for the
+    /// shared state.
+    pub fn try_new<F: FnOnce(LocalCoroutineAwaiter) -> T>(f: F) -> Result<Self, BuildError> {
+        Self::build(f, None)
+    }
+
+    /// Like [`with_stack_size`](Self::with_stack_size), but returns an error
+    /// if the coroutine or any of its allocations fails.
+    pub fn try_with_stack_size<F: FnOnce(LocalCoroutineAwaiter) -> T>(
+        f: F,
+        size: usize,
+    ) -> Result<Self, BuildError> {
+        Self::build(f, Some(size))
+    }
+
+    fn build<F: FnOnce(LocalCoroutineAwaiter) -> T>(
+        f: F,
+        stack_size: Option<usize>,
+    ) -> Result<Self, BuildError> {
+        let a = try_leak(f)?.as_ptr();
+        let output = match try_leak(None::<panic::Outcome<T>>) {
+            Ok(output) => output,
+            Err(error) => {
+                // SAFETY: nothing else has seen the closure yet.
+                drop(unsafe { Box::from_raw(a) });
+                return Err(error);
+            }
+        };
+        let slot = output.as_ptr();
+        let body = move |p: CoroutineRef<(), (), (), Rc<LocalShared>, GLOBAL>| unsafe {
+            let f = *Box::from_raw(a);
+            if p.user_data().aborted.get() {
+                return;
+            }
+            *slot = Some(panic::catch(move || f(LocalCoroutineAwaiter { coro: p })));
+        };
+        #[cfg(feature = "nightly")]
+        let shared = Rc::try_new(LocalShared::default());
+        #[cfg(not(feature = "nightly"))]
+        let shared = Ok::<_, ()>(Rc::new(LocalShared::default()));
+        let Ok(shared) = shared else {
+            // SAFETY: nothing else has seen either box yet.
+            unsafe {
+                drop(Box::from_raw(a));
+                drop(Box::from_raw(output.as_ptr()));
+            }
+            return Err(BuildError::new());
+        };
+        let coro = match stack_size {
+            Some(size) => Coroutine::with_stack_size(body, shared, size),
+            None => Coroutine::new(body, shared),
+        };
+        match coro {
+            Ok(coro) => Ok(Self {
+                coro,
+                output,
+                started: false,
+                done: false,
+                _not_send: PhantomData,
+            }),
+            Err(_) => {
+                // SAFETY: the body never ran, so both boxes are still owned here.
+                unsafe {
+                    drop(Box::from_raw(a));
+                    drop(Box::from_raw(output.as_ptr()));
+                }
+                Err(BuildError::new())
+            }
+        }
+    }
+
+    /// Returns `true` once the body has returned.
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+
+    fn resume(&mut self) -> bool {
+        let _guard = panic::ResumeGuard::new();
+        self.coro.resume(()).is_some()
+    }
+}
+
+impl<T> Future for LocalCoroutineFuture<T> {
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T> {
+        let this = self.get_mut();
+        assert!(!this.done, "`LocalCoroutineFuture` polled after completion");
+        this.started = true;
+        let shared = this.coro.user_data();
+        let waker = match shared.waker.take() {
+            Some(waker) if waker.will_wake(cx.waker()) => waker,
+            _ => cx.waker().clone(),
+        };
+        shared.waker.set(Some(waker));
+        if this.resume() {
+            return Poll::Pending;
+        }
+        this.done = true;
+        // SAFETY: the coroutine has finished, so nothing else accesses the slot.
+        let outcome = unsafe { (*this.output.as_ptr()).take() }
+            .expect("`LocalCoroutineFuture` completed without an output");
+        Poll::Ready(panic::unwrap(outcome, None))
+    }
+}
+
+impl<T> fmt::Debug for LocalCoroutineFuture<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LocalCoroutineFuture")
+            .field("started", &self.started)
+            .field("done", &self.done)
+            .finish_non_exhaustive()
+    }
+}
+
+// The future never pins its fields; the coroutine lives on its own stack.
+impl<T> Unpin for LocalCoroutineFuture<T> {}
+
+#[cfg(feature = "futures")]
+impl<T> futures_core::FusedFuture for LocalCoroutineFuture<T> {
+    fn is_terminated(&self) -> bool {
+        self.done
+    }
+}
+
+impl<T> Drop for LocalCoroutineFuture<T> {
+    fn drop(&mut self) {
+        // Unwind a suspended body, as `CoroutineFuture` does. Without `std`,
+        // or with `panic = "abort"`, only a body that never started can be
+        // finished this way.
+        #[cfg(all(feature = "std", panic = "unwind"))]
+        let unwind = !std::thread::panicking();
+        #[cfg(not(all(feature = "std", panic = "unwind")))]
+        let unwind = !self.started;
+        if !self.done && unwind {
+            self.coro.user_data().aborted.set(true);
+            while self.resume() {}
+        }
+        // SAFETY: `output` was leaked from a `Box` in `build` and is only freed here.
+        drop(unsafe { Box::from_raw(self.output.as_ptr()) });
+    }
+}
@@ -0,0 +1,236 @@
+//! Transforming a coroutine's output without another async block.
+//!
+//! Wrapping a [`CoroutineFuture`] in `async move { f(coro.await) }` costs a
+//! second state machine and hides the coroutine behind an unnameable type.
+//! [`CoroutineFuture::map_output`], [`CoroutineFuture::inspect`] and
+//! [`CoroutineFuture::and_then`] apply the function when the coroutine
+//! completes and return nameable futures that still expose the coroutine
+//! through `get_ref`.
+//!
+//! A coroutine whose body returns `Result<T, E>` is already a
+//! `futures_core::TryFuture`, through that trait's blanket implementation, so
+//! `TryFutureExt` combinators and `?` in the awaiting `async fn` work on it
+//! directly. [`CoroutineFuture::map_err`] and [`CoroutineFuture::err_into`]
+//! adapt its error type, as when a body using `?` on I/O errors feeds a
+//! caller with an error type of its own.
+
+use core::fmt;
+use core::future::Future;
+use core::pin::Pin;
+use core::task::Context;
+use core::task::Poll;
+
+use crate::CoroutineFuture;
+use crate::sync::AtomicWaker;
+use crate::waker::WakerSlot;
+
+impl<T, W: WakerSlot> CoroutineFuture<T, W> {
+    /// Resolves to `f` applied to the coroutine's output.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// use minicoro_awaiters::CoroutineFuture;
+    ///
+    /// let len = CoroutineFuture::new(|awaiter| read_body(awaiter)).map_output(|body| body.len());
+    /// ```
+    pub fn map_output<U, F: FnOnce(T) -> U>(self, f: F) -> MapOutput<T, F, W> {
+        MapOutput {
+            inner: self,
+            f: Some(f),
+        }
+    }
+
+    /// Calls `f` with a reference to the coroutine's output before resolving to it.
+    pub fn inspect<F: FnOnce(&T)>(self, f: F) -> Inspect<T, F, W> {
+        Inspect {
+            inner: self,
+            f: Some(f),
+        }
+    }
+}
+
+impl<U, E, W: WakerSlot> CoroutineFuture<Result<U, E>, W> {
+    /// Resolves to `f` applied to the coroutine's `Ok` output, passing errors
+    /// through unchanged.
+    pub fn and_then<V, F: FnOnce(U) -> Result<V, E>>(self, f: F) -> AndThen<U, E, F, W> {
+        AndThen {
+            inner: self,
+            f: Some(f),
+        }
+    }
+
+    /// Resolves to the coroutine's output with `f` applied to its `Err`
+    /// value, passing successes through unchanged.
+    pub fn map_err<E2, F: FnOnce(E) -> E2>(self, f: F) -> MapErr<U, E, F, W> {
+        MapErr {
+            inner: self,
+            f: Some(f),
+        }
+    }
+
+    /// Resolves to the coroutine's output with its error converted by
+    /// [`From`].
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// use minicoro_awaiters::CoroutineFuture;
+    ///
+    /// async fn load(path: &str) -> Result<Config, AppError> {
+    ///     let text = CoroutineFuture::new(|awaiter| read_file(awaiter, path))
+    ///         .err_into::<AppError>()
+    ///         .await?;
+    ///     Ok(parse(&text)?)
+    /// }
+    /// ```
+    pub fn err_into<E2: From<E>>(self) -> MapErr<U, E, fn(E) -> E2, W> {
+        self.map_err(E2::from as fn(E) -> E2)
+    }
+}
+
+/// A coroutine with a function applied to its output, returned by
+/// [`CoroutineFuture::map_output`].
+pub struct MapOutput<T, F, W: WakerSlot = AtomicWaker> {
+    inner: CoroutineFuture<T, W>,
+    f: Option<F>,
+}
+
+/// A coroutine whose output is passed to a function first, returned by
+/// [`CoroutineFuture::inspect`].
+pub struct Inspect<T, F, W: WakerSlot = AtomicWaker> {
+    inner: CoroutineFuture<T, W>,
+    f: Option<F>,
+}
+
+/// A fallible coroutine with a function applied to its `Ok` output, returned
+/// by [`CoroutineFuture::and_then`].
+pub struct AndThen<U, E, F, W: WakerSlot = AtomicWaker> {
+    inner: CoroutineFuture<Result<U, E>, W>,
+    f: Option<F>,
+}
+
+/// A fallible coroutine with a function applied to its `Err` output,
+/// returned by [`CoroutineFuture::map_err`] and [`CoroutineFuture::err_into`].
+pub struct MapErr<U, E, F, W: WakerSlot = AtomicWaker> {
+    inner: CoroutineFuture<Result<U, E>, W>,
+    f: Option<F>,
+}
+
+/// Polls `inner` and applies the stored function to its output.
+fn poll_map<T, W: WakerSlot, F, U>(
+    inner: &mut CoroutineFuture<T, W>,
+    f: &mut Option<F>,
+    cx: &mut Context<'_>,
+    apply: impl FnOnce(F, T) -> U,
+) -> Poll<U> {
+    Pin::new(inner).poll(cx).map(|output| {
+        let f = f.take().expect("polled after completion");
+        apply(f, output)
+    })
+}
+
+impl<T, U, F: FnOnce(T) -> U, W: WakerSlot> Future for MapOutput<T, F, W> {
+    type Output = U;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<U> {
+        let this = self.get_mut();
+        poll_map(&mut this.inner, &mut this.f, cx, |f, output| f(output))
+    }
+}
+
+impl<T, F: FnOnce(&T), W: WakerSlot> Future for Inspect<T, F, W> {
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T> {
+        let this = self.get_mut();
+        poll_map(&mut this.inner, &mut this.f, cx, |f, output| {
+            f(&output);
+            output
+        })
+    }
+}
+
+impl<U, V, E, F: FnOnce(U) -> Result<V, E>, W: WakerSlot> Future for AndThen<U, E, F, W> {
+    type Output = Result<V, E>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<V, E>> {
+        let this = self.get_mut();
+        poll_map(&mut this.inner, &mut this.f, cx, |f, output| output.and_then(f))
+    }
+}
+
+impl<U, E, E2, F: FnOnce(E) -> E2, W: WakerSlot> Future for MapErr<U, E, F, W> {
+    type Output = Result<U, E2>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<U, E2>> {
+        let this = self.get_mut();
+        poll_map(&mut this.inner, &mut this.f, cx, |f, output| output.map_err(f))
+    }
+}
+
+// The wrappers never pin their fields; the function is only ever moved out.
+impl<T, F, W: WakerSlot> Unpin for MapOutput<T, F, W> {}
+impl<T, F, W: WakerSlot> Unpin for Inspect<T, F, W> {}
+impl<U, E, F, W: WakerSlot> Unpin for AndThen<U, E, F, W> {}
+impl<U, E, F, W: WakerSlot> Unpin for MapErr<U, E, F, W> {}
+
+impl<T, F, W: WakerSlot> MapOutput<T, F, W> {
+    /// The wrapped coroutine.
+    pub fn get_ref(&self) -> &CoroutineFuture<T, W> {
+        &self.inner
+    }
+}
+
+impl<T, F, W: WakerSlot> fmt::Debug for MapOutput<T, F, W> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MapOutput")
+            .field("coroutine", &self.inner)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T, F, W: WakerSlot> Inspect<T, F, W> {
+    /// The wrapped coroutine.
+    pub fn get_ref(&self) -> &CoroutineFuture<T, W> {
+        &self.inner
+    }
+}
+
+impl<T, F, W: WakerSlot> fmt::Debug for Inspect<T, F, W> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Inspect")
+            .field("coroutine", &self.inner)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<U, E, F, W: WakerSlot> AndThen<U, E, F, W> {
+    /// The wrapped coroutine.
+    pub fn get_ref(&self) -> &CoroutineFuture<Result<U, E>, W> {
+        &self.inner
+    }
+}
+
+impl<U, E, F, W: WakerSlot> fmt::Debug for AndThen<U, E, F, W> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AndThen")
+            .field("coroutine", &self.inner)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<U, E, F, W: WakerSlot> MapErr<U, E, F, W> {
+    /// The wrapped coroutine.
+    pub fn get_ref(&self) -> &CoroutineFuture<Result<U, E>, W> {
+        &self.inner
+    }
+}
+
+impl<U, E, F, W: WakerSlot> fmt::Debug for MapErr<U, E, F, W> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MapErr")
+            .field("coroutine", &self.inner)
+            .finish_non_exhaustive()
+    }
+}
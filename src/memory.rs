@@ -0,0 +1,65 @@
+//! Attributing memory to coroutines.
+//!
+//! With the `alloc-stats` feature every coroutine counts the allocations the
+//! crate makes on its behalf: at build time its closure box, output slot,
+//! shared state and stack, and later the futures and jobs it hands to
+//! [`CoroutineAwaiter::spawn`](crate::CoroutineAwaiter::spawn) and
+//! `block_in_place` and the timers behind `await_timeout`. The counts are
+//! cumulative and read with [`CoroutineFuture::alloc_stats`] or
+//! [`CoroutineAwaiter::alloc_stats`], so growth can be traced to the
+//! coroutines, by name, that keep allocating.
+//!
+//! Allocations made by the body itself, or by the futures it awaits, go
+//! through the global allocator unobserved; attribute those with a tracking
+//! `#[global_allocator]` keyed on [`CoroutineFuture::id`].
+
+use core::fmt;
+
+use crate::CoroutineAwaiter;
+use crate::CoroutineFuture;
+use crate::waker::WakerSlot;
+
+/// Allocations made on behalf of a coroutine, returned by
+/// [`CoroutineFuture::alloc_stats`].
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug, Hash, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct AllocStats {
+    allocations: usize,
+    bytes: usize,
+}
+
+impl AllocStats {
+    pub(crate) fn new(allocations: usize, bytes: usize) -> Self {
+        Self { allocations, bytes }
+    }
+
+    /// Number of allocations made so far.
+    pub fn allocations(&self) -> usize {
+        self.allocations
+    }
+
+    /// Total size of those allocations, in bytes.
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+}
+
+impl fmt::Display for AllocStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} allocations, {} bytes", self.allocations, self.bytes)
+    }
+}
+
+impl<T, W: WakerSlot> CoroutineFuture<T, W> {
+    /// The allocations the crate has made on behalf of this coroutine.
+    pub fn alloc_stats(&self) -> AllocStats {
+        self.coro.user_data().alloc_stats()
+    }
+}
+
+impl<W: WakerSlot> CoroutineAwaiter<W> {
+    /// The allocations the crate has made on behalf of the running coroutine.
+    pub fn alloc_stats(&self) -> AllocStats {
+        self.coro.user_data().alloc_stats()
+    }
+}
@@ -0,0 +1,162 @@
+//! Interop with the unstable [`core::ops::Coroutine`] trait.
+//!
+//! [`StackfulCoroutine`] runs a blocking-style closure on a minicoro stack and
+//! exposes it as a language coroutine, so code written against
+//! [`core::ops::Coroutine`] can drive it. [`StackfulCoroutine::from_coroutine`]
+//! goes the other way and moves an existing language coroutine onto a
+//! stackful stack.
+//!
+//! Requires the `nightly` feature.
+
+use core::ops::Coroutine as LangCoroutine;
+use core::ops::CoroutineState;
+use core::pin::Pin;
+use core::pin::pin;
+use core::ptr::NonNull;
+
+use alloc::boxed::Box;
+
+use crate::backend::Coroutine;
+use crate::backend::CoroutineRef;
+use crate::backend::GLOBAL;
+use crate::panic;
+use crate::panic::Outcome;
+
+/// Values exchanged between a [`StackfulCoroutine`] and its body on each resume.
+struct Channel<I, Y> {
+    input: Option<I>,
+    yielded: Option<Y>,
+}
+
+/// The handle a [`StackfulCoroutine`] body uses to yield values.
+pub struct Yielder<I, Y> {
+    coro: CoroutineRef<(), (), (), (), GLOBAL>,
+    channel: NonNull<Channel<I, Y>>,
+}
+
+impl<I, Y> Yielder<I, Y> {
+    /// Suspends the coroutine, handing `value` to the resumer, and returns the
+    /// argument of the next [`resume`](LangCoroutine::resume).
+    pub fn yield_(&self, value: Y) -> I {
+        // SAFETY: the channel outlives the coroutine body, and the resumer only
+        // touches it while the body is suspended.
+        unsafe { (*self.channel.as_ptr()).yielded = Some(value) };
+        self.coro.yield_(());
+        unsafe { (*self.channel.as_ptr()).input.take() }
+            .expect("`StackfulCoroutine` resumed without an input")
+    }
+}
+
+/// A minicoro-backed coroutine implementing [`core::ops::Coroutine`].
+///
+/// # Example
+///
+/// ```ignore
+/// #![feature(coroutine_trait)]
+/// use core::ops::{Coroutine, CoroutineState};
+/// use core::pin::Pin;
+/// use minicoro_awaiters::nightly::StackfulCoroutine;
+///
+/// let mut coro = StackfulCoroutine::new(|y, first: u32| {
+///     let second = y.yield_(first + 1);
+///     second * 2
+/// });
+/// assert_eq!(Pin::new(&mut coro).resume(1), CoroutineState::Yielded(2));
+/// assert_eq!(Pin::new(&mut coro).resume(5), CoroutineState::Complete(10));
+/// ```
+pub struct StackfulCoroutine<I, Y, R> {
+    coro: Coroutine<(), (), (), (), GLOBAL>,
+    channel: NonNull<Channel<I, Y>>,
+    output: NonNull<Option<Outcome<R>>>,
+}
+
+impl<I, Y, R> StackfulCoroutine<I, Y, R> {
+    /// Creates a coroutine running `f` on its own stack.
+    ///
+    /// `f` receives a [`Yielder`] and the argument of the first resume.
+    pub fn new<F: FnOnce(&Yielder<I, Y>, I) -> R>(f: F) -> Self {
+        let f = Box::leak(Box::new(f)) as *mut _ as *mut ();
+        let channel = NonNull::from(Box::leak(Box::new(Channel {
+            input: None,
+            yielded: None,
+        })));
+        let output = NonNull::from(Box::leak(Box::new(None::<Outcome<R>>)));
+        let slot = output.as_ptr();
+        Self {
+            coro: Coroutine::new(
+                move |p| unsafe {
+                    let yielder = Yielder { coro: p, channel };
+                    let input = (*channel.as_ptr())
+                        .input
+                        .take()
+                        .expect("`StackfulCoroutine` resumed without an input");
+                    let f = *Box::from_raw(f as *mut F);
+                    *slot = Some(panic::catch(move || f(&yielder, input)));
+                },
+                (),
+            )
+            .unwrap(),
+            channel,
+            output,
+        }
+    }
+
+    /// Runs an existing language coroutine on a stackful stack.
+    ///
+    /// Every yield of `coroutine` becomes a yield of the returned coroutine, so
+    /// the two behave identically from the resumer's point of view.
+    pub fn from_coroutine<C>(coroutine: C) -> Self
+    where
+        C: LangCoroutine<I, Yield = Y, Return = R>,
+    {
+        Self::new(move |yielder, mut input| {
+            let mut coroutine = pin!(coroutine);
+            loop {
+                match coroutine.as_mut().resume(input) {
+                    CoroutineState::Yielded(value) => input = yielder.yield_(value),
+                    CoroutineState::Complete(value) => return value,
+                }
+            }
+        })
+    }
+}
+
+impl<I, Y, R> core::fmt::Debug for StackfulCoroutine<I, Y, R> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("StackfulCoroutine").finish_non_exhaustive()
+    }
+}
+
+impl<I, Y, R> Drop for StackfulCoroutine<I, Y, R> {
+    fn drop(&mut self) {
+        // SAFETY: both slots were leaked from boxes in `new` and are only freed here.
+        drop(unsafe { Box::from_raw(self.channel.as_ptr()) });
+        drop(unsafe { Box::from_raw(self.output.as_ptr()) });
+    }
+}
+
+impl<I, Y, R> LangCoroutine<I> for StackfulCoroutine<I, Y, R> {
+    type Yield = Y;
+    type Return = R;
+
+    fn resume(self: Pin<&mut Self>, arg: I) -> CoroutineState<Y, R> {
+        let this = self.get_mut();
+        // SAFETY: the body is suspended, so the resumer has exclusive access to the slots.
+        unsafe { (*this.channel.as_ptr()).input = Some(arg) };
+        let resumed = {
+            let _guard = panic::ResumeGuard::new();
+            this.coro.resume(())
+        };
+        match resumed {
+            Some(_) => CoroutineState::Yielded(
+                unsafe { (*this.channel.as_ptr()).yielded.take() }
+                    .expect("`StackfulCoroutine` suspended without yielding a value"),
+            ),
+            None => CoroutineState::Complete(panic::unwrap(
+                unsafe { (*this.output.as_ptr()).take() }
+                    .expect("`StackfulCoroutine` resumed after completion"),
+                None,
+            )),
+        }
+    }
+}
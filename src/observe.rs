@@ -0,0 +1,170 @@
+//! Watching the lifecycle of a coroutine from another task.
+//!
+//! A [`CoroutineObserver`], from [`CoroutineFuture::observer`], follows a
+//! coroutine after the future itself has been handed to an executor. It
+//! reports the coroutine's [`LifecycleState`] on demand through
+//! [`state`](CoroutineObserver::state), and waits for it to change through
+//! [`poll_state`](CoroutineObserver::poll_state), or as a `Stream` with the
+//! `futures` feature, so a supervisor can keep a live view of many
+//! coroutines.
+//!
+//! An observer samples the state and does not queue events: a state that is
+//! entered and left between two polls of the observer is not seen. Every
+//! coroutine has a single slot for the observer's waker, so all observers of
+//! one coroutine should be polled from the same task.
+
+use core::fmt;
+use core::future::Future;
+use core::future::poll_fn;
+use core::panic::Location;
+use core::task::Context;
+use core::task::Poll;
+
+use crate::CoroutineFuture;
+use crate::sync::Arc;
+use crate::sync::AtomicWaker;
+use crate::waker::CoroutineShared;
+use crate::waker::WakerSlot;
+
+pub(crate) const PHASE_CREATED: usize = 0;
+pub(crate) const PHASE_RUNNING: usize = 1;
+pub(crate) const PHASE_YIELDED: usize = 2;
+pub(crate) const PHASE_COMPLETED: usize = 3;
+
+/// What a coroutine is doing, as seen by a [`CoroutineObserver`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Hash)]
+pub enum LifecycleState {
+    /// The coroutine has not been resumed yet.
+    Created,
+    /// The coroutine is being resumed.
+    Running,
+    /// The coroutine is suspended on a pending future, awaited at this site.
+    Awaiting(&'static Location<'static>),
+    /// The coroutine is suspended without waiting on anything, such as after
+    /// running out of budget, and resumes on the next poll.
+    Yielded,
+    /// The body has returned, panicked or been aborted.
+    Completed,
+}
+
+impl LifecycleState {
+    pub(crate) fn of<W: WakerSlot>(shared: &CoroutineShared<W>, phase: usize) -> Self {
+        match phase & 3 {
+            PHASE_CREATED => Self::Created,
+            PHASE_RUNNING => Self::Running,
+            PHASE_COMPLETED => Self::Completed,
+            _ => match shared.await_site() {
+                Some(site) if shared.is_parked() => Self::Awaiting(site),
+                _ => Self::Yielded,
+            },
+        }
+    }
+}
+
+impl<T, W: WakerSlot> CoroutineFuture<T, W> {
+    /// Returns a handle reporting the coroutine's lifecycle after the future
+    /// has been moved into an executor.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// use minicoro_awaiters::CoroutineFuture;
+    ///
+    /// let coro = CoroutineFuture::builder().name("ingest").build(ingest)?;
+    /// let mut observer = coro.observer();
+    /// tokio::spawn(coro);
+    /// while let Some(state) = observer.next_state().await {
+    ///     dashboard.update(observer.id(), state);
+    /// }
+    /// ```
+    pub fn observer(&self) -> CoroutineObserver<W> {
+        CoroutineObserver {
+            shared: self.coro.user_data().clone(),
+            last: None,
+        }
+    }
+}
+
+/// A handle following the lifecycle of a coroutine, obtained from
+/// [`CoroutineFuture::observer`].
+pub struct CoroutineObserver<W = AtomicWaker> {
+    shared: Arc<CoroutineShared<W>>,
+    /// The phase last reported by `poll_state`.
+    last: Option<usize>,
+}
+
+impl<W: WakerSlot> CoroutineObserver<W> {
+    /// The coroutine's current state.
+    pub fn state(&self) -> LifecycleState {
+        LifecycleState::of(&self.shared, self.shared.phase())
+    }
+
+    /// Waits for the state to change since the last call, returning the new
+    /// state, or `Poll::Ready(None)` once `Completed` has been reported.
+    ///
+    /// The first call reports the current state right away.
+    pub fn poll_state(&mut self, cx: &mut Context<'_>) -> Poll<Option<LifecycleState>> {
+        if self.last.is_some_and(|last| last & 3 == PHASE_COMPLETED) {
+            return Poll::Ready(None);
+        }
+        self.shared.observe(cx.waker());
+        // Read after registering, so an event racing the registration is not missed.
+        let phase = self.shared.phase();
+        if self.last == Some(phase) {
+            return Poll::Pending;
+        }
+        self.last = Some(phase);
+        Poll::Ready(Some(LifecycleState::of(&self.shared, phase)))
+    }
+
+    /// Waits for the next state change; see [`poll_state`](Self::poll_state).
+    pub fn next_state(&mut self) -> impl Future<Output = Option<LifecycleState>> + '_ {
+        poll_fn(|cx| self.poll_state(cx))
+    }
+
+    /// The coroutine's name, if one was given to the builder.
+    pub fn name(&self) -> Option<&str> {
+        self.shared.name()
+    }
+
+    /// The coroutine's id, unique within the process.
+    pub fn id(&self) -> usize {
+        self.shared.id()
+    }
+
+    /// Number of times the coroutine has been resumed.
+    pub fn resume_count(&self) -> usize {
+        self.shared.resume_count()
+    }
+}
+
+impl<W> Clone for CoroutineObserver<W> {
+    fn clone(&self) -> Self {
+        Self {
+            shared: self.shared.clone(),
+            last: None,
+        }
+    }
+}
+
+impl<W: WakerSlot> fmt::Debug for CoroutineObserver<W> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CoroutineObserver")
+            .field("id", &self.id())
+            .field("name", &self.name())
+            .field("state", &self.state())
+            .finish()
+    }
+}
+
+#[cfg(feature = "futures")]
+impl<W: WakerSlot> futures_core::Stream for CoroutineObserver<W> {
+    type Item = LifecycleState;
+
+    fn poll_next(
+        self: core::pin::Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<LifecycleState>> {
+        self.get_mut().poll_state(cx)
+    }
+}
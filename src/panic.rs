@@ -0,0 +1,402 @@
+//! Propagating panics out of coroutines.
+//!
+//! Unwinding must never cross the stack switch back into the outer future.
+//! With the `std` feature, every coroutine body runs under
+//! [`catch_unwind`](std::panic::catch_unwind) and a caught panic is resumed
+//! on the async side once the outer future observes completion. Without
+//! `std` there is no unwinding support to hook into, and a panicking body
+//! should be built with `panic = "abort"`.
+//!
+//! With the `backtrace` feature, the backtrace at the panic site inside the
+//! coroutine is captured before the coroutine stack unwinds, and the
+//! propagated payload is a [`CoroutinePanic`] carrying it.
+//!
+//! What happens to a caught panic is up to its [`PanicHandler`], registered
+//! per coroutine with
+//! [`CoroutineBuilder::on_panic`](crate::CoroutineBuilder::on_panic) or for
+//! every coroutine without one with [`set_handler`]. The handler sees the
+//! payload and the coroutine it came from, and returns a [`PanicAction`]:
+//! propagate the panic, report it as an error, or swallow it. Without a
+//! handler, panics propagate out of a plain `.await` and are reported as
+//! errors by [`CoroutineFuture::catch_errors`](crate::CoroutineFuture::catch_errors).
+
+#[cfg(feature = "std")]
+use alloc::boxed::Box;
+#[cfg(feature = "std")]
+use core::any::Any;
+
+/// The result of running a coroutine body: its value or a caught panic.
+#[cfg(feature = "std")]
+pub(crate) type Outcome<T> = std::thread::Result<T>;
+
+/// The result of running a coroutine body.
+#[cfg(not(feature = "std"))]
+pub(crate) type Outcome<T> = Result<T, core::convert::Infallible>;
+
+/// Runs a coroutine body, catching any panic it raises.
+#[cfg(feature = "std")]
+pub(crate) fn catch<T>(f: impl FnOnce() -> T) -> Outcome<T> {
+    #[cfg(feature = "backtrace")]
+    backtrace::install_hook();
+    std::panic::catch_unwind(std::panic::AssertUnwindSafe(f)).map_err(|payload| {
+        #[cfg(feature = "backtrace")]
+        let payload: Box<dyn Any + Send> = Box::new(CoroutinePanic {
+            payload,
+            backtrace: backtrace::take(),
+            name: None,
+            location: None,
+            await_site: None,
+        });
+        payload
+    })
+}
+
+/// Runs a coroutine body.
+#[cfg(not(feature = "std"))]
+pub(crate) fn catch<T>(f: impl FnOnce() -> T) -> Outcome<T> {
+    Ok(f())
+}
+
+/// The name, creation site and await site of a coroutine, for diagnostics.
+pub(crate) type Origin<'a> = (
+    Option<&'a str>,
+    &'static core::panic::Location<'static>,
+    Option<&'static core::panic::Location<'static>>,
+);
+
+/// Returns the body's value, resuming its panic on the caller's stack.
+///
+/// `origin` identifies the coroutine in the propagated [`CoroutinePanic`].
+#[cfg_attr(not(feature = "std"), allow(unused_variables))]
+pub(crate) fn unwrap<T>(outcome: Outcome<T>, origin: Option<Origin<'_>>) -> T {
+    match outcome {
+        Ok(value) => value,
+        #[cfg(feature = "std")]
+        Err(payload) => std::panic::resume_unwind(attach_origin(payload, origin)),
+    }
+}
+
+/// Fills in the coroutine's identity on a payload about to leave it.
+#[cfg(feature = "std")]
+#[cfg_attr(not(feature = "backtrace"), allow(unused_mut, unused_variables))]
+pub(crate) fn attach_origin(
+    mut payload: Box<dyn Any + Send>,
+    origin: Option<Origin<'_>>,
+) -> Box<dyn Any + Send> {
+    #[cfg(feature = "backtrace")]
+    if let (Some(panic), Some((name, location, await_site))) =
+        (payload.downcast_mut::<CoroutinePanic>(), origin)
+    {
+        panic.name = name.map(Into::into);
+        panic.location = Some(location);
+        panic.await_site = await_site;
+    }
+    payload
+}
+
+/// The payload of the unwind started by [`CoroutineFuture::abort`](crate::CoroutineFuture::abort).
+#[cfg(feature = "std")]
+pub(crate) struct Aborted;
+
+#[cfg(feature = "std")]
+pub(crate) fn is_abort(payload: &(dyn Any + Send)) -> bool {
+    #[cfg(feature = "backtrace")]
+    if let Some(panic) = payload.downcast_ref::<CoroutinePanic>() {
+        return panic.payload.is::<Aborted>();
+    }
+    payload.is::<Aborted>()
+}
+
+/// Called after every suspension with whether the outer future is aborting
+/// the coroutine: unwinds the coroutine's stack if it is.
+///
+/// Awaits in destructors that run during that unwind return normally.
+#[cfg_attr(not(feature = "std"), allow(unused_variables))]
+pub(crate) fn unwind_if_aborted(aborted: bool) {
+    #[cfg(feature = "std")]
+    if aborted && !std::thread::panicking() {
+        std::panic::resume_unwind(Box::new(Aborted));
+    }
+}
+
+/// What to do with a panic caught in a coroutine, as decided by its
+/// [`PanicHandler`].
+#[cfg(feature = "std")]
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug, Hash)]
+pub enum PanicAction {
+    /// Resume the panic on the thread polling the coroutine, even from
+    /// [`catch_errors`](crate::CoroutineFuture::catch_errors).
+    Propagate,
+    /// Report the panic as
+    /// [`CoroutineError::Panicked`](crate::error::CoroutineError::Panicked)
+    /// from [`catch_errors`](crate::CoroutineFuture::catch_errors).
+    Error,
+    /// Drop the payload: [`catch_errors`](crate::CoroutineFuture::catch_errors)
+    /// resolves to
+    /// [`CoroutineError::Aborted`](crate::error::CoroutineError::Aborted), as
+    /// if the coroutine had been aborted.
+    Swallow,
+}
+
+/// The coroutine a panic handled by a [`PanicHandler`] came from.
+#[cfg(feature = "std")]
+#[derive(Clone, Copy, Debug)]
+pub struct PanicInfo<'a> {
+    name: Option<&'a str>,
+    location: &'static core::panic::Location<'static>,
+    await_site: Option<&'static core::panic::Location<'static>>,
+}
+
+#[cfg(feature = "std")]
+impl PanicInfo<'_> {
+    /// The name of the coroutine that panicked, if it was given one.
+    pub fn name(&self) -> Option<&str> {
+        self.name
+    }
+
+    /// Where the coroutine that panicked was created.
+    pub fn location(&self) -> &'static core::panic::Location<'static> {
+        self.location
+    }
+
+    /// The `r#await` call the coroutine panicked inside of, if it panicked
+    /// while polling an awaited future.
+    pub fn await_site(&self) -> Option<&'static core::panic::Location<'static>> {
+        self.await_site
+    }
+}
+
+/// Decides what happens to panics caught in coroutines.
+///
+/// Implemented for closures taking the payload and a [`PanicInfo`]. Handlers
+/// run on the thread polling the coroutine, once its stack has unwound.
+///
+/// A plain `.await` of a coroutine has no error to resolve to, so it resumes
+/// the panic whatever the handler returns; the handler still runs first, so
+/// it can log the panic. Await
+/// [`catch_errors`](crate::CoroutineFuture::catch_errors) for the other
+/// actions to take effect.
+///
+/// # Example
+///
+/// ```ignore
+/// use minicoro_awaiters::CoroutineFuture;
+/// use minicoro_awaiters::panic::PanicAction;
+///
+/// let coro = CoroutineFuture::builder()
+///     .on_panic(|payload, info| {
+///         log::error!("{:?} panicked at {}", info.name(), info.location());
+///         PanicAction::Swallow
+///     })
+///     .build(|awaiter| render(&awaiter, frame))?
+///     .catch_errors();
+/// ```
+#[cfg(feature = "std")]
+pub trait PanicHandler: Send + Sync {
+    /// Called with the payload of a panic caught in the coroutine.
+    fn on_panic(&self, payload: &(dyn Any + Send), info: &PanicInfo<'_>) -> PanicAction;
+}
+
+#[cfg(feature = "std")]
+impl<F> PanicHandler for F
+where
+    F: Fn(&(dyn Any + Send), &PanicInfo<'_>) -> PanicAction + Send + Sync,
+{
+    fn on_panic(&self, payload: &(dyn Any + Send), info: &PanicInfo<'_>) -> PanicAction {
+        self(payload, info)
+    }
+}
+
+/// The handler of coroutines without one of their own.
+#[cfg(feature = "std")]
+static HANDLER: std::sync::RwLock<Option<crate::sync::Arc<dyn PanicHandler>>> =
+    std::sync::RwLock::new(None);
+
+/// Registers the handler of every coroutine built without
+/// [`on_panic`](crate::CoroutineBuilder::on_panic), replacing any previous one.
+#[cfg(feature = "std")]
+pub fn set_handler(handler: impl PanicHandler + 'static) {
+    let handler = crate::sync::dyn_arc!(handler => dyn PanicHandler);
+    *HANDLER.write().unwrap_or_else(|e| e.into_inner()) = Some(handler);
+}
+
+/// Unregisters the handler set with [`set_handler`].
+#[cfg(feature = "std")]
+pub fn clear_handler() {
+    *HANDLER.write().unwrap_or_else(|e| e.into_inner()) = None;
+}
+
+/// Asks the coroutine's handler, or the global one, what to do with
+/// `payload`; `None` if neither is registered.
+#[cfg(feature = "std")]
+pub(crate) fn decide(
+    handler: Option<&dyn PanicHandler>,
+    payload: &(dyn Any + Send),
+    origin: Origin<'_>,
+) -> Option<PanicAction> {
+    #[cfg(feature = "backtrace")]
+    let payload = match payload.downcast_ref::<CoroutinePanic>() {
+        Some(panic) => panic.payload(),
+        None => payload,
+    };
+    let (name, location, await_site) = origin;
+    let info = PanicInfo {
+        name,
+        location,
+        await_site,
+    };
+    if let Some(handler) = handler {
+        return Some(handler.on_panic(payload, &info));
+    }
+    let global = HANDLER.read().unwrap_or_else(|e| e.into_inner()).clone();
+    Some(global?.on_panic(payload, &info))
+}
+
+/// Marks the current thread as running a coroutine for the guard's lifetime.
+///
+/// Held by the outer future around every resume, so the panic hook knows
+/// which panics happen on a coroutine stack.
+pub(crate) struct ResumeGuard(());
+
+impl ResumeGuard {
+    pub(crate) fn new() -> Self {
+        #[cfg(feature = "backtrace")]
+        backtrace::DEPTH.with(|depth| depth.set(depth.get() + 1));
+        Self(())
+    }
+}
+
+impl Drop for ResumeGuard {
+    fn drop(&mut self) {
+        #[cfg(feature = "backtrace")]
+        backtrace::DEPTH.with(|depth| depth.set(depth.get() - 1));
+    }
+}
+
+/// A panic propagated out of a coroutine, with the backtrace of its panic site.
+///
+/// With the `backtrace` feature this is the payload passed to
+/// [`resume_unwind`](std::panic::resume_unwind); downcast to it to recover the
+/// original payload and the coroutine-side backtrace.
+#[cfg(feature = "backtrace")]
+pub struct CoroutinePanic {
+    payload: Box<dyn Any + Send>,
+    backtrace: Option<std::backtrace::Backtrace>,
+    name: Option<Box<str>>,
+    location: Option<&'static core::panic::Location<'static>>,
+    await_site: Option<&'static core::panic::Location<'static>>,
+}
+
+#[cfg(feature = "backtrace")]
+impl CoroutinePanic {
+    /// The payload the coroutine panicked with.
+    pub fn payload(&self) -> &(dyn Any + Send) {
+        &*self.payload
+    }
+
+    /// Consumes the panic, returning the original payload.
+    pub fn into_payload(self) -> Box<dyn Any + Send> {
+        self.payload
+    }
+
+    /// The backtrace captured at the panic site inside the coroutine.
+    pub fn backtrace(&self) -> Option<&std::backtrace::Backtrace> {
+        self.backtrace.as_ref()
+    }
+
+    /// The name of the coroutine that panicked, if it was given one.
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// Where the coroutine that panicked was created.
+    pub fn location(&self) -> Option<&'static core::panic::Location<'static>> {
+        self.location
+    }
+
+    /// The `r#await` call the coroutine panicked inside of, if it panicked
+    /// while polling an awaited future.
+    pub fn await_site(&self) -> Option<&'static core::panic::Location<'static>> {
+        self.await_site
+    }
+
+    /// The panic message, if the payload is a string.
+    pub fn message(&self) -> Option<&str> {
+        self.payload
+            .downcast_ref::<&'static str>()
+            .copied()
+            .or_else(|| self.payload.downcast_ref::<alloc::string::String>().map(|s| s.as_str()))
+    }
+}
+
+#[cfg(feature = "backtrace")]
+impl core::fmt::Debug for CoroutinePanic {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("CoroutinePanic")
+            .field("message", &self.message())
+            .field("name", &self.name)
+            .field("location", &self.location)
+            .field("await_site", &self.await_site)
+            .field("backtrace", &self.backtrace)
+            .finish()
+    }
+}
+
+#[cfg(feature = "backtrace")]
+impl core::fmt::Display for CoroutinePanic {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match &self.name {
+            Some(name) => write!(f, "coroutine `{name}`")?,
+            None => f.write_str("coroutine")?,
+        }
+        if let Some(location) = self.location {
+            write!(f, " created at {location}")?;
+        }
+        if let Some(site) = self.await_site {
+            write!(f, ", awaiting at {site},")?;
+        }
+        f.write_str(" panicked")?;
+        if let Some(message) = self.message() {
+            write!(f, ": {message}")?;
+        }
+        if let Some(backtrace) = &self.backtrace {
+            write!(f, "\n{backtrace}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "backtrace")]
+impl core::error::Error for CoroutinePanic {}
+
+#[cfg(feature = "backtrace")]
+mod backtrace {
+    use core::cell::Cell;
+    use std::backtrace::Backtrace;
+    use std::sync::Once;
+
+    std::thread_local! {
+        /// Number of coroutine resumes active on this thread.
+        pub(super) static DEPTH: Cell<usize> = const { Cell::new(0) };
+        static CAPTURED: Cell<Option<Backtrace>> = const { Cell::new(None) };
+    }
+
+    /// Chains a panic hook that captures backtraces of panics on coroutine stacks.
+    pub(super) fn install_hook() {
+        static ONCE: Once = Once::new();
+        ONCE.call_once(|| {
+            let previous = std::panic::take_hook();
+            std::panic::set_hook(alloc::boxed::Box::new(move |info| {
+                if DEPTH.with(Cell::get) > 0 {
+                    CAPTURED.with(|captured| captured.set(Some(Backtrace::force_capture())));
+                }
+                previous(info);
+            }));
+        });
+    }
+
+    /// Takes the backtrace captured for the most recent coroutine panic.
+    pub(super) fn take() -> Option<Backtrace> {
+        CAPTURED.with(Cell::take)
+    }
+}
@@ -0,0 +1,303 @@
+//! Reusing coroutines, and their stacks, across short jobs.
+//!
+//! Building a coroutine allocates its stack, and the first deep call on a
+//! fresh stack page faults. A [`CoroutinePool`] keeps idle
+//! [`CoroutineWorker`]s around so a job only pays for that once per worker,
+//! and [`CoroutinePool::prewarm`] pays it at startup: the workers it creates
+//! are built with
+//! [`prefault_stack`](crate::CoroutineBuilder::prefault_stack), touching every
+//! stack page before the first job arrives.
+//!
+//! Jobs run on any idle worker, or on a new one if none is idle. A worker
+//! whose job panics, or whose job is dropped while suspended, is discarded
+//! rather than returned to the pool.
+//!
+//! With [`CoroutinePool::learn_stack_sizes`], the pool measures the
+//! [high-water mark](crate::CoroutineFuture::stack_high_water) of every job
+//! and remembers the deepest one per job type. Later jobs of that type run on
+//! a stack of that peak plus the [`RED_ZONE`] and a chosen margin, rather
+//! than the builder's size, and [`CoroutinePool::stack_sizes`] and
+//! [`CoroutinePool::seed_stack_size`] carry the measurements over to the
+//! next run of the program. Job types are told apart by their type name, so
+//! closures defined in the same function share one entry, which then holds
+//! the largest of their peaks. The high-water mark is sampled at stack
+//! checks only, so the margin has to cover frames deeper than the deepest
+//! suspension point.
+//!
+//! Idle workers keep the stack pages their jobs touched resident, so a burst
+//! of deep jobs leaves the process's memory use at its peak. With the
+//! `decommit` feature, [`CoroutinePool::decommit_after`] hands the unused
+//! part of every stack that has sat idle for a while back to the kernel
+//! (with `madvise(MADV_DONTNEED)` on unix targets; elsewhere it does
+//! nothing). The pages are committed again, zeroed, when a later job reaches
+//! them, so prewarmed stacks lose their head start once decommitted.
+
+use core::cell::RefCell;
+use core::fmt;
+
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec::Vec;
+#[cfg(feature = "decommit")]
+use std::time::Duration;
+#[cfg(feature = "decommit")]
+use std::time::Instant;
+
+use crate::BuildError;
+use crate::CoroutineAwaiter;
+use crate::CoroutineBuilder;
+use crate::stack::RED_ZONE;
+use crate::worker::CoroutineWorker;
+
+/// The granularity of learned stack sizes.
+const PAGE: usize = 4 * 1024;
+
+/// A pool of idle coroutines for running jobs.
+///
+/// # Example
+///
+/// ```ignore
+/// use minicoro_awaiters::CoroutineBuilder;
+/// use minicoro_awaiters::pool::CoroutinePool;
+///
+/// let pool = CoroutinePool::new(CoroutineBuilder::new().stack_size(256 << 10));
+/// pool.prewarm(16)?;
+/// let body = pool.run(|awaiter| awaiter.r#await(Box::pin(fetch(url)))).await;
+/// ```
+pub struct CoroutinePool {
+    builder: CoroutineBuilder,
+    idle: RefCell<Vec<Idle>>,
+    /// Set by [`CoroutinePool::learn_stack_sizes`].
+    profile: Option<StackProfile>,
+    /// How long a worker sits idle before its stack is decommitted.
+    #[cfg(feature = "decommit")]
+    decommit_after: Option<Duration>,
+}
+
+/// A worker waiting in the pool.
+struct Idle {
+    worker: CoroutineWorker,
+    /// When the worker was returned to the pool.
+    #[cfg(feature = "decommit")]
+    since: Instant,
+    /// The worker's stack has been decommitted since it was returned.
+    #[cfg(feature = "decommit")]
+    decommitted: bool,
+}
+
+impl Idle {
+    fn new(worker: CoroutineWorker) -> Self {
+        Self {
+            worker,
+            #[cfg(feature = "decommit")]
+            since: Instant::now(),
+            #[cfg(feature = "decommit")]
+            decommitted: false,
+        }
+    }
+}
+
+/// The peak stack use of each job type seen by a pool.
+struct StackProfile {
+    /// Bytes allocated beyond a job type's peak and the red zone.
+    margin: usize,
+    peaks: RefCell<BTreeMap<Box<str>, usize>>,
+}
+
+impl StackProfile {
+    fn record(&self, job: &str, peak: usize) {
+        let mut peaks = self.peaks.borrow_mut();
+        match peaks.get_mut(job) {
+            Some(known) => *known = (*known).max(peak),
+            None => {
+                peaks.insert(job.into(), peak);
+            }
+        }
+    }
+
+    /// The stack size for jobs of type `job`, if it has been measured.
+    fn stack_size(&self, job: &str) -> Option<usize> {
+        let peak = *self.peaks.borrow().get(job)?;
+        Some((peak + RED_ZONE + self.margin).next_multiple_of(PAGE))
+    }
+}
+
+impl CoroutinePool {
+    /// Creates an empty pool whose coroutines are built with `builder`.
+    pub fn new(builder: CoroutineBuilder) -> Self {
+        Self {
+            builder,
+            idle: RefCell::new(Vec::new()),
+            profile: None,
+            #[cfg(feature = "decommit")]
+            decommit_after: None,
+        }
+    }
+
+    /// Learns how much stack each type of job uses and runs later jobs of
+    /// that type on stacks of their peak plus [`RED_ZONE`] plus `margin`
+    /// bytes, rounded up to a page.
+    ///
+    /// Job types not measured yet run on stacks of the builder's size.
+    pub fn learn_stack_sizes(mut self, margin: usize) -> Self {
+        self.profile = Some(StackProfile {
+            margin,
+            peaks: RefCell::new(BTreeMap::new()),
+        });
+        self
+    }
+
+    /// The peak stack use measured for each job type, by type name, if
+    /// [`learn_stack_sizes`](Self::learn_stack_sizes) is enabled.
+    ///
+    /// Persist these and pass them to [`seed_stack_size`](Self::seed_stack_size)
+    /// at startup to skip relearning them. Type names are only stable across
+    /// builds of the same code.
+    pub fn stack_sizes(&self) -> Vec<(String, usize)> {
+        let Some(profile) = &self.profile else {
+            return Vec::new();
+        };
+        profile
+            .peaks
+            .borrow()
+            .iter()
+            .map(|(job, &peak)| (String::from(&**job), peak))
+            .collect()
+    }
+
+    /// Records a peak stack use of `peak` bytes for the job type named `job`,
+    /// as returned by [`stack_sizes`](Self::stack_sizes).
+    ///
+    /// Peaks only ever grow: a smaller value than the one already known is
+    /// ignored. Does nothing unless
+    /// [`learn_stack_sizes`](Self::learn_stack_sizes) is enabled.
+    pub fn seed_stack_size(&self, job: &str, peak: usize) {
+        if let Some(profile) = &self.profile {
+            profile.record(job, peak);
+        }
+    }
+
+    /// Decommits the stacks of workers that have been idle for at least
+    /// `idle`, whenever a job is returned to the pool.
+    ///
+    /// Call [`decommit_idle`](Self::decommit_idle) periodically as well if the
+    /// pool can go quiet for long stretches.
+    #[cfg(feature = "decommit")]
+    pub fn decommit_after(mut self, idle: Duration) -> Self {
+        self.decommit_after = Some(idle);
+        self
+    }
+
+    /// Releases the unused stack memory of idle workers and returns the
+    /// number of bytes released.
+    ///
+    /// Only workers idle for the duration given to
+    /// [`decommit_after`](Self::decommit_after) are affected, or every idle
+    /// worker if none was given. Each stack is decommitted at most once per
+    /// idle period.
+    #[cfg(feature = "decommit")]
+    pub fn decommit_idle(&self) -> usize {
+        let now = Instant::now();
+        let mut released = 0;
+        for idle in self.idle.borrow_mut().iter_mut() {
+            let due = self
+                .decommit_after
+                .is_none_or(|after| now.duration_since(idle.since) >= after);
+            if due && !idle.decommitted {
+                released += crate::stack::decommit(idle.worker.coroutine().coro.user_data());
+                idle.decommitted = true;
+            }
+        }
+        released
+    }
+
+    /// Adds `n` idle coroutines to the pool, touching every page of their
+    /// stacks now.
+    ///
+    /// Stops at the first coroutine that cannot be created; those built
+    /// before it stay in the pool.
+    #[track_caller]
+    pub fn prewarm(&self, n: usize) -> Result<(), BuildError> {
+        let builder = self.builder.clone().prefault_stack(true);
+        for _ in 0..n {
+            let worker = CoroutineWorker::with_builder(builder.clone(), ())?;
+            self.idle.borrow_mut().push(Idle::new(worker));
+        }
+        Ok(())
+    }
+
+    /// Number of idle coroutines in the pool.
+    pub fn idle(&self) -> usize {
+        self.idle.borrow().len()
+    }
+
+    /// Runs `job` on an idle coroutine, building a new one if there is none,
+    /// and returns its result.
+    ///
+    /// # Panics
+    ///
+    /// Resumes the panic of `job`, and panics if a new coroutine cannot be
+    /// created.
+    pub async fn run<T: 'static, F>(&self, job: F) -> T
+    where
+        F: FnOnce(&CoroutineAwaiter) -> T + 'static,
+    {
+        let name = core::any::type_name::<F>();
+        let size = self.profile.as_ref().map(|profile| {
+            profile
+                .stack_size(name)
+                .unwrap_or_else(|| self.builder.stack_size_or_default())
+        });
+        let idle = self.take_idle(size);
+        let mut worker = match (idle, size) {
+            (Some(worker), _) => worker,
+            (None, Some(size)) => {
+                CoroutineWorker::with_builder(self.builder.clone().stack_size(size), ()).unwrap()
+            }
+            (None, None) => CoroutineWorker::with_builder(self.builder.clone(), ()).unwrap(),
+        };
+        if self.profile.is_some() {
+            worker.coroutine().coro.user_data().reset_stack_high_water();
+        }
+        let output = worker.run(move |awaiter, _| job(awaiter)).await;
+        if let Some(profile) = &self.profile {
+            if let Some(peak) = worker.coroutine().stack_high_water() {
+                profile.record(name, peak);
+            }
+        }
+        self.idle.borrow_mut().push(Idle::new(worker));
+        #[cfg(feature = "decommit")]
+        if self.decommit_after.is_some() {
+            self.decommit_idle();
+        }
+        output
+    }
+
+    /// Takes the idle worker with the smallest stack of at least `size`
+    /// bytes, or the most recently used one if `size` is `None`.
+    fn take_idle(&self, size: Option<usize>) -> Option<CoroutineWorker> {
+        let mut idle = self.idle.borrow_mut();
+        let Some(size) = size else {
+            return idle.pop().map(|idle| idle.worker);
+        };
+        let (index, _) = idle
+            .iter()
+            .enumerate()
+            .filter_map(|(index, idle)| {
+                let stack = idle.worker.coroutine().coro.user_data().stack_size()?;
+                (stack >= size).then_some((index, stack))
+            })
+            .min_by_key(|&(_, stack)| stack)?;
+        Some(idle.remove(index).worker)
+    }
+}
+
+impl fmt::Debug for CoroutinePool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CoroutinePool")
+            .field("builder", &self.builder)
+            .field("idle", &self.idle())
+            .finish()
+    }
+}
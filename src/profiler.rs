@@ -0,0 +1,31 @@
+//! Frame-profiler scopes around coroutine resumes.
+//!
+//! Game engines and other frame-based programs are profiled with tools like
+//! Tracy and puffin rather than `tracing` subscribers. With the `tracy`
+//! feature every resume of a [`CoroutineFuture`](crate::CoroutineFuture)
+//! opens a Tracy zone, and with the `puffin` feature a puffin scope, named
+//! after the coroutine, or `coroutine` if it has none. The scope covers the
+//! switch onto the coroutine's stack and back, so both the time spent in the
+//! body and the cost of switching show up in the frame timeline. Tracy zones
+//! are attributed to where the coroutine was created.
+//!
+//! Nothing is recorded while no Tracy client is running, or while puffin's
+//! scopes are off (see `puffin::set_scopes_on`).
+
+use crate::waker::CoroutineShared;
+use crate::waker::WakerSlot;
+
+/// The name coroutine scopes are shown under.
+pub(crate) fn name<W: WakerSlot>(shared: &CoroutineShared<W>) -> &str {
+    shared.name().unwrap_or("coroutine")
+}
+
+/// Opens a Tracy zone for a resume of the coroutine, if a client is running.
+#[cfg(feature = "tracy")]
+pub(crate) fn tracy_zone<W: WakerSlot>(
+    shared: &CoroutineShared<W>,
+) -> Option<tracy_client::Span> {
+    let client = tracy_client::Client::running()?;
+    let location = shared.location();
+    Some(client.span_alloc(Some(name(shared)), "resume", location.file(), location.line(), 0))
+}
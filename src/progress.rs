@@ -0,0 +1,332 @@
+//! Reporting progress out of a running coroutine.
+//!
+//! A [`ProgressFuture`] runs a body that receives a [`Reporter`] in place of
+//! the plain awaiter. [`Reporter::report`] records the latest progress value
+//! without suspending, and any number of [`Progress`] handles, obtained from
+//! [`ProgressFuture::progress`], read it from other tasks: borrowed in place,
+//! awaited with [`Progress::changed`], or, with the `futures` feature,
+//! consumed as a `futures_core::Stream`.
+//!
+//! Like a watch channel, handles see the latest value only; values reported
+//! in quick succession between two reads are skipped. Once the coroutine has
+//! finished or was dropped, waiting for a change returns `false`.
+
+use core::fmt;
+use core::future::Future;
+use core::future::poll_fn;
+use core::ops::Deref;
+use core::pin::Pin;
+use core::task::Context;
+use core::task::Poll;
+use core::task::Waker;
+
+use alloc::vec::Vec;
+use std::sync::Mutex;
+use std::sync::MutexGuard;
+
+use crate::BuildError;
+use crate::CoroutineAwaiter;
+use crate::CoroutineBuilder;
+use crate::CoroutineFuture;
+use crate::sync::Arc;
+
+/// The latest progress value, shared between the body and the handles.
+struct State<P> {
+    value: Option<P>,
+    /// Number of values reported so far.
+    version: usize,
+    /// The coroutine has finished or was dropped.
+    finished: bool,
+    /// Handles waiting for the next change.
+    wakers: Vec<Waker>,
+}
+
+struct Channel<P> {
+    state: Mutex<State<P>>,
+}
+
+impl<P> Channel<P> {
+    fn lock(&self) -> MutexGuard<'_, State<P>> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Applies `f` to the state and wakes every waiting handle.
+    fn publish(&self, f: impl FnOnce(&mut State<P>)) {
+        let wakers = {
+            let mut state = self.lock();
+            f(&mut state);
+            core::mem::take(&mut state.wakers)
+        };
+        for waker in wakers {
+            waker.wake();
+        }
+    }
+}
+
+/// The handle a [`ProgressFuture`] body reports progress through.
+///
+/// It dereferences to the coroutine's [`CoroutineAwaiter`], so the body can
+/// await futures through it as well.
+pub struct Reporter<P> {
+    awaiter: CoroutineAwaiter,
+    channel: Arc<Channel<P>>,
+}
+
+impl<P> Reporter<P> {
+    /// Replaces the progress value seen by every [`Progress`] handle.
+    ///
+    /// Does not suspend the coroutine.
+    pub fn report(&self, value: P) {
+        self.channel.publish(|state| {
+            state.value = Some(value);
+            state.version += 1;
+        });
+    }
+}
+
+impl<P> Deref for Reporter<P> {
+    type Target = CoroutineAwaiter;
+
+    fn deref(&self) -> &CoroutineAwaiter {
+        &self.awaiter
+    }
+}
+
+impl<P> fmt::Debug for Reporter<P> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Reporter")
+            .field("awaiter", &self.awaiter)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A coroutine reporting progress values of type `P` while it runs.
+///
+/// # Example
+///
+/// ```ignore
+/// use minicoro_awaiters::progress::ProgressFuture;
+///
+/// let migration = ProgressFuture::new(move |reporter| {
+///     for (done, table) in tables.iter().enumerate() {
+///         reporter.r#await(Box::pin(migrate(table)));
+///         reporter.report((done + 1, tables.len()));
+///     }
+/// });
+/// let mut progress = migration.progress();
+/// spawn(async move {
+///     while progress.changed().await {
+///         draw_bar(*progress.borrow_and_update());
+///     }
+/// });
+/// migration.await;
+/// ```
+pub struct ProgressFuture<T, P> {
+    inner: CoroutineFuture<T>,
+    channel: Arc<Channel<P>>,
+}
+
+impl<T, P> ProgressFuture<T, P> {
+    /// Creates a coroutine running `f`, which reports progress through the
+    /// [`Reporter`] it is given.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the coroutine cannot be created; use
+    /// [`with_builder`](Self::with_builder) to handle that case.
+    #[track_caller]
+    pub fn new<F: FnOnce(&Reporter<P>) -> T>(f: F) -> Self {
+        Self::with_builder(CoroutineBuilder::new(), f).unwrap()
+    }
+
+    /// Creates a coroutine running `f` with the settings of `builder`.
+    #[track_caller]
+    pub fn with_builder<F: FnOnce(&Reporter<P>) -> T>(
+        builder: CoroutineBuilder,
+        f: F,
+    ) -> Result<Self, BuildError> {
+        let channel = Arc::new(Channel {
+            state: Mutex::new(State {
+                value: None,
+                version: 0,
+                finished: false,
+                wakers: Vec::new(),
+            }),
+        });
+        let reporter = channel.clone();
+        let inner = builder.build(move |awaiter| {
+            f(&Reporter {
+                awaiter,
+                channel: reporter,
+            })
+        })?;
+        Ok(Self { inner, channel })
+    }
+
+    /// Returns a new handle for reading the coroutine's progress.
+    ///
+    /// The handle's first [`changed`](Progress::changed) completes for the
+    /// latest value reported, if any, rather than for the next one.
+    pub fn progress(&self) -> Progress<P> {
+        Progress {
+            channel: self.channel.clone(),
+            seen: 0,
+        }
+    }
+
+    /// The wrapped coroutine.
+    pub fn get_ref(&self) -> &CoroutineFuture<T> {
+        &self.inner
+    }
+}
+
+impl<T, P> Future for ProgressFuture<T, P> {
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T> {
+        let this = self.get_mut();
+        let poll = Pin::new(&mut this.inner).poll(cx);
+        if poll.is_ready() {
+            this.channel.publish(|state| state.finished = true);
+        }
+        poll
+    }
+}
+
+impl<T, P> fmt::Debug for ProgressFuture<T, P> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ProgressFuture")
+            .field("coroutine", &self.inner)
+            .finish_non_exhaustive()
+    }
+}
+
+// The future never pins its fields.
+impl<T, P> Unpin for ProgressFuture<T, P> {}
+
+impl<T, P> Drop for ProgressFuture<T, P> {
+    fn drop(&mut self) {
+        // Unwind the body first, so it cannot report after the handles are told it finished.
+        self.inner.abort_for_drop();
+        self.channel.publish(|state| state.finished = true);
+    }
+}
+
+/// A handle reading the progress of a [`ProgressFuture`].
+///
+/// Each handle tracks which value it has seen, so clones wait for changes
+/// independently.
+pub struct Progress<P> {
+    channel: Arc<Channel<P>>,
+    /// The version of the last value marked as seen.
+    seen: usize,
+}
+
+impl<P> Progress<P> {
+    /// Borrows the latest value, `None` before the first report, without
+    /// marking it as seen.
+    ///
+    /// The body cannot report while the borrow is held, so keep it short.
+    pub fn borrow(&self) -> ProgressRef<'_, P> {
+        ProgressRef {
+            state: self.channel.lock(),
+        }
+    }
+
+    /// Like [`borrow`](Self::borrow), but marks the value as seen.
+    pub fn borrow_and_update(&mut self) -> ProgressRef<'_, P> {
+        let state = self.channel.lock();
+        self.seen = state.version;
+        ProgressRef { state }
+    }
+
+    /// Returns `true` if a value has been reported since the last one marked
+    /// as seen.
+    pub fn has_changed(&self) -> bool {
+        self.channel.lock().version != self.seen
+    }
+
+    /// Returns `true` once the coroutine has finished or was dropped.
+    pub fn is_finished(&self) -> bool {
+        self.channel.lock().finished
+    }
+
+    /// Waits until a value the handle has not seen is reported and marks it
+    /// as seen.
+    ///
+    /// Returns `false` instead once the coroutine has finished without
+    /// reporting anything new.
+    pub async fn changed(&mut self) -> bool {
+        poll_fn(|cx| self.poll_changed(cx)).await
+    }
+
+    /// Polls for a value the handle has not seen; see [`changed`](Self::changed).
+    pub fn poll_changed(&mut self, cx: &mut Context<'_>) -> Poll<bool> {
+        let mut state = self.channel.lock();
+        if state.version != self.seen {
+            self.seen = state.version;
+            return Poll::Ready(true);
+        }
+        if state.finished {
+            return Poll::Ready(false);
+        }
+        if !state.wakers.iter().any(|waker| waker.will_wake(cx.waker())) {
+            state.wakers.push(cx.waker().clone());
+        }
+        Poll::Pending
+    }
+}
+
+impl<P> Clone for Progress<P> {
+    fn clone(&self) -> Self {
+        Self {
+            channel: self.channel.clone(),
+            seen: self.seen,
+        }
+    }
+}
+
+impl<P: fmt::Debug> fmt::Debug for Progress<P> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = self.channel.lock();
+        f.debug_struct("Progress")
+            .field("value", &state.value)
+            .field("finished", &state.finished)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Yields the latest value every time it changes, ending once the coroutine
+/// has finished.
+#[cfg(feature = "futures")]
+impl<P: Clone> futures_core::Stream for Progress<P> {
+    type Item = P;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<P>> {
+        let this = self.get_mut();
+        match this.poll_changed(cx) {
+            Poll::Ready(true) => Poll::Ready(this.channel.lock().value.clone()),
+            Poll::Ready(false) => Poll::Ready(None),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+/// A borrow of the latest progress value, returned by [`Progress::borrow`].
+pub struct ProgressRef<'a, P> {
+    state: MutexGuard<'a, State<P>>,
+}
+
+impl<P> Deref for ProgressRef<'_, P> {
+    type Target = Option<P>;
+
+    fn deref(&self) -> &Option<P> {
+        &self.state.value
+    }
+}
+
+impl<P: fmt::Debug> fmt::Debug for ProgressRef<'_, P> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.state.value.fmt(f)
+    }
+}
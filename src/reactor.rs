@@ -0,0 +1,144 @@
+//! Per-future wakers for awaiting several futures at once.
+//!
+//! Awaiting many futures through a single await would otherwise poll every
+//! one of them whenever any of them wakes the coroutine. A [`Reactor`] gives
+//! each child future a waker of its own, which records the child as ready
+//! before waking the future polling them all, so each poll only touches the
+//! children woken since they were last polled. The [`executor`](crate::executor)
+//! uses reactors as its ready queue.
+
+use core::task::Context;
+use core::task::RawWaker;
+use core::task::RawWakerVTable;
+use core::task::Waker;
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+
+use crate::sync::Arc;
+use crate::sync::AtomicUsize;
+use crate::sync::AtomicWaker;
+use crate::sync::Ordering;
+
+const BITS: usize = usize::BITS as usize;
+
+/// The state shared between a reactor and its child wakers.
+struct Ready {
+    /// One bit per child, set once its waker is woken.
+    bits: Box<[AtomicUsize]>,
+    /// The waker of the task polling the children.
+    parent: AtomicWaker,
+}
+
+impl Ready {
+    fn mark(&self, index: usize) {
+        self.bits[index / BITS].fetch_or(1 << (index % BITS), Ordering::Release);
+    }
+}
+
+/// The data behind a child waker.
+struct Child {
+    ready: Arc<Ready>,
+    index: usize,
+}
+
+impl Child {
+    fn wake(&self) {
+        self.ready.mark(self.index);
+        self.ready.parent.wake();
+    }
+}
+
+const VTABLE: RawWakerVTable = RawWakerVTable::new(clone_waker, wake, wake_by_ref, drop_waker);
+
+unsafe fn clone_waker(data: *const ()) -> RawWaker {
+    // SAFETY: `data` came from `Arc::into_raw` on an `Arc<Child>` kept alive by the waker.
+    unsafe { Arc::increment_strong_count(data as *const Child) };
+    RawWaker::new(data, &VTABLE)
+}
+
+unsafe fn wake(data: *const ()) {
+    // SAFETY: the waker owns one strong count, which is released here.
+    let child = unsafe { Arc::from_raw(data as *const Child) };
+    child.wake();
+}
+
+unsafe fn wake_by_ref(data: *const ()) {
+    // SAFETY: the waker keeps the allocation alive.
+    unsafe { (*(data as *const Child)).wake() };
+}
+
+unsafe fn drop_waker(data: *const ()) {
+    // SAFETY: the waker owns one strong count, which is released here.
+    unsafe { Arc::decrement_strong_count(data as *const Child) };
+}
+
+/// Multiplexes the wakeups of a fixed number of child futures.
+pub(crate) struct Reactor {
+    ready: Arc<Ready>,
+    /// One waker per child, built once and reused for every poll.
+    wakers: Vec<Waker>,
+}
+
+impl Reactor {
+    /// Creates a reactor for `len` children, every one of them ready to be
+    /// polled for the first time.
+    pub(crate) fn new(len: usize) -> Self {
+        let ready = Arc::new(Ready {
+            bits: (0..len.div_ceil(BITS)).map(|_| AtomicUsize::new(0)).collect(),
+            parent: AtomicWaker::new(),
+        });
+        let wakers = (0..len)
+            .map(|index| {
+                ready.mark(index);
+                let child = Arc::new(Child {
+                    ready: ready.clone(),
+                    index,
+                });
+                let raw = RawWaker::new(Arc::into_raw(child) as *const (), &VTABLE);
+                // SAFETY: the vtable upholds the `RawWaker` contract for `Child`.
+                unsafe { Waker::from_raw(raw) }
+            })
+            .collect();
+        Self { ready, wakers }
+    }
+
+    /// Marks the child at `index` as ready, as for a future newly put in its
+    /// place.
+    pub(crate) fn mark(&self, index: usize) {
+        self.ready.mark(index);
+    }
+
+    /// Returns `true` if a child has been woken since it was last polled.
+    pub(crate) fn is_ready(&self) -> bool {
+        self.ready.bits.iter().any(|word| word.load(Ordering::Relaxed) != 0)
+    }
+
+    /// Calls `poll` with the index and a context for every child woken
+    /// since it was last polled, in index order, stopping at the first call
+    /// that returns `Some`.
+    ///
+    /// The children left unpolled by stopping early stay ready for the next
+    /// call. `cx` is woken whenever a child is.
+    pub(crate) fn poll_woken<R>(
+        &self,
+        cx: &Context<'_>,
+        mut poll: impl FnMut(usize, &mut Context<'_>) -> Option<R>,
+    ) -> Option<R> {
+        // Registered before taking the bits, so a wake racing the poll is not lost.
+        self.ready.parent.register(cx.waker());
+        for (word_index, word) in self.ready.bits.iter().enumerate() {
+            let mut bits = word.swap(0, Ordering::Acquire);
+            while bits != 0 {
+                let index = word_index * BITS + bits.trailing_zeros() as usize;
+                bits &= bits - 1;
+                let mut child_cx = Context::from_waker(&self.wakers[index]);
+                if let Some(output) = poll(index, &mut child_cx) {
+                    word.fetch_or(bits, Ordering::Relaxed);
+                    return Some(output);
+                }
+            }
+        }
+        None
+    }
+}
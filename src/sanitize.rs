@@ -0,0 +1,222 @@
+//! Telling sanitizers about coroutine stack switches.
+//!
+//! AddressSanitizer and ThreadSanitizer follow the stack each thread runs
+//! on. A switch to a coroutine stack they are not told about makes them
+//! report false positives and print broken stack traces. With the
+//! `sanitize` feature, which needs a nightly compiler, the crate announces
+//! every switch into and out of the coroutines built by
+//! [`CoroutineBuilder`](crate::CoroutineBuilder) through the fiber API of
+//! the sanitizer the build uses, `-Zsanitizer=address` or
+//! `-Zsanitizer=thread`. Without either, the annotations compile away.
+//!
+//! The bounds of a coroutine's stack are only known once its body has
+//! started, so the very first switch into it is announced to AddressSanitizer
+//! without them. Coroutines from
+//! [`CoroutineFuture::from_coroutine`](crate::CoroutineFuture::from_coroutine)
+//! and [`LocalCoroutineFuture`](crate::local::LocalCoroutineFuture) run bodies
+//! the crate does not control and are not annotated.
+//!
+//! Valgrind is not covered by the feature: registering a stack with it
+//! takes a client request, a magic instruction sequence specific to every
+//! architecture, rather than a function call. minicoro issues those itself
+//! when its C source is compiled with `MCO_USE_VALGRIND` defined, which
+//! `CFLAGS="-DMCO_USE_VALGRIND"` does (see the `builder` module on passing
+//! defines to minicoro).
+
+#![cfg_attr(
+    not(any(sanitize = "address", sanitize = "thread")),
+    allow(unused_variables, dead_code)
+)]
+
+use core::cell::Cell;
+use core::ffi::c_void;
+use core::ptr;
+
+use crate::waker::CoroutineShared;
+use crate::waker::WakerSlot;
+
+#[cfg(sanitize = "address")]
+unsafe extern "C" {
+    fn __sanitizer_start_switch_fiber(
+        fake_stack_save: *mut *mut c_void,
+        bottom: *const c_void,
+        size: usize,
+    );
+    fn __sanitizer_finish_switch_fiber(
+        fake_stack_save: *mut c_void,
+        bottom_old: *mut *const c_void,
+        size_old: *mut usize,
+    );
+}
+
+#[cfg(sanitize = "thread")]
+unsafe extern "C" {
+    fn __tsan_get_current_fiber() -> *mut c_void;
+    fn __tsan_create_fiber(flags: u32) -> *mut c_void;
+    fn __tsan_destroy_fiber(fiber: *mut c_void);
+    fn __tsan_switch_to_fiber(fiber: *mut c_void, flags: u32);
+}
+
+/// The sanitizer state of one coroutine.
+pub(crate) struct Fiber {
+    /// The fake stack of the thread resuming the coroutine, while it runs.
+    outer_fake_stack: Cell<*mut c_void>,
+    /// The fake stack of the coroutine, while it is suspended.
+    inner_fake_stack: Cell<*mut c_void>,
+    /// The stack of the thread resuming the coroutine.
+    caller_bottom: Cell<*const c_void>,
+    caller_size: Cell<usize>,
+    /// ThreadSanitizer's fiber for the coroutine; null until the first resume.
+    fiber: Cell<*mut c_void>,
+    /// ThreadSanitizer's fiber of the thread resuming the coroutine.
+    caller: Cell<*mut c_void>,
+}
+
+// SAFETY: only the thread currently resuming the coroutine touches the cells,
+// and resumes never overlap.
+unsafe impl Send for Fiber {}
+unsafe impl Sync for Fiber {}
+
+impl Fiber {
+    pub(crate) const fn new() -> Self {
+        Self {
+            outer_fake_stack: Cell::new(ptr::null_mut()),
+            inner_fake_stack: Cell::new(ptr::null_mut()),
+            caller_bottom: Cell::new(ptr::null()),
+            caller_size: Cell::new(0),
+            fiber: Cell::new(ptr::null_mut()),
+            caller: Cell::new(ptr::null_mut()),
+        }
+    }
+
+    /// Announces that the coroutine's stack runs from here on.
+    fn entered(&self) {
+        // SAFETY: paired with the `__sanitizer_start_switch_fiber` of the
+        // switch that got here.
+        #[cfg(sanitize = "address")]
+        unsafe {
+            __sanitizer_finish_switch_fiber(
+                self.inner_fake_stack.get(),
+                self.caller_bottom.as_ptr(),
+                self.caller_size.as_ptr(),
+            );
+        }
+    }
+
+    /// Announces a switch back to the thread resuming the coroutine; a
+    /// `finishing` coroutine never runs again.
+    fn leaving(&self, finishing: bool) {
+        // SAFETY: the caller's stack was recorded when the coroutine entered.
+        #[cfg(sanitize = "address")]
+        unsafe {
+            let save = if finishing {
+                ptr::null_mut()
+            } else {
+                self.inner_fake_stack.as_ptr()
+            };
+            __sanitizer_start_switch_fiber(save, self.caller_bottom.get(), self.caller_size.get());
+        }
+        // SAFETY: the caller's fiber was recorded by the resume.
+        #[cfg(sanitize = "thread")]
+        unsafe {
+            __tsan_switch_to_fiber(self.caller.get(), 0);
+        }
+    }
+}
+
+impl Drop for Fiber {
+    fn drop(&mut self) {
+        // SAFETY: the coroutine no longer runs, so its fiber is not current.
+        #[cfg(sanitize = "thread")]
+        if !self.fiber.get().is_null() {
+            unsafe { __tsan_destroy_fiber(self.fiber.get()) };
+        }
+    }
+}
+
+/// Announces a resume of the coroutine when created and its return to the
+/// outer future when dropped.
+///
+/// Holds a pointer rather than a borrow, so the coroutine can be resumed
+/// while the guard lives.
+pub(crate) struct ResumeSwitch(*const Fiber);
+
+impl ResumeSwitch {
+    pub(crate) fn enter<W: WakerSlot>(shared: &CoroutineShared<W>) -> Self {
+        let fiber = shared.fiber();
+        // SAFETY: the bounds are those of the coroutine's stack, or null
+        // before they are known.
+        #[cfg(sanitize = "address")]
+        unsafe {
+            let (bottom, size) = match shared.stack_bottom() {
+                Some((bottom, size)) => (bottom as *const c_void, size),
+                None => (ptr::null(), 0),
+            };
+            __sanitizer_start_switch_fiber(fiber.outer_fake_stack.as_ptr(), bottom, size);
+        }
+        // SAFETY: the fiber is created once and destroyed with the coroutine.
+        #[cfg(sanitize = "thread")]
+        unsafe {
+            fiber.caller.set(__tsan_get_current_fiber());
+            if fiber.fiber.get().is_null() {
+                fiber.fiber.set(__tsan_create_fiber(0));
+            }
+            __tsan_switch_to_fiber(fiber.fiber.get(), 0);
+        }
+        Self(fiber)
+    }
+}
+
+impl Drop for ResumeSwitch {
+    fn drop(&mut self) {
+        // SAFETY: the shared state outlives every resume of its coroutine.
+        let fiber = unsafe { &*self.0 };
+        // SAFETY: paired with the start of the coroutine's switch back here.
+        #[cfg(sanitize = "address")]
+        unsafe {
+            __sanitizer_finish_switch_fiber(
+                fiber.outer_fake_stack.get(),
+                ptr::null_mut(),
+                ptr::null_mut(),
+            );
+        }
+    }
+}
+
+/// Announces a suspension of the coroutine when created and its next resume
+/// when dropped.
+pub(crate) struct SuspendSwitch(*const Fiber);
+
+impl SuspendSwitch {
+    pub(crate) fn enter<W: WakerSlot>(shared: &CoroutineShared<W>) -> Self {
+        let fiber = shared.fiber();
+        fiber.leaving(false);
+        Self(fiber)
+    }
+}
+
+impl Drop for SuspendSwitch {
+    fn drop(&mut self) {
+        // SAFETY: the shared state outlives the coroutine's body.
+        unsafe { &*self.0 }.entered();
+    }
+}
+
+/// Announces the start of the coroutine's body when created and its end,
+/// the final switch out, when dropped.
+pub(crate) struct BodySwitch(*const Fiber);
+
+impl BodySwitch {
+    pub(crate) fn enter<W: WakerSlot>(shared: &CoroutineShared<W>) -> Self {
+        let fiber = shared.fiber();
+        fiber.entered();
+        Self(fiber)
+    }
+}
+
+impl Drop for BodySwitch {
+    fn drop(&mut self) {
+        // SAFETY: the shared state outlives the coroutine's body.
+        unsafe { &*self.0 }.leaving(true);
+    }
+}
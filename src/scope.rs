@@ -0,0 +1,181 @@
+//! Structured concurrency for coroutines started from async code.
+//!
+//! [`scope`] hands a [`CoroutineScope`] to a setup closure, which starts any
+//! number of coroutines on it, and then resolves only once every one of
+//! them has finished. The first body to return `Err` cancels the others,
+//! aborting each where it is suspended, and the scope resolves to that
+//! error. A panicking body cancels them the same way before its panic
+//! resumes out of the scope, and dropping the scope future cancels whatever
+//! still runs, so no coroutine ever outlives its scope.
+//!
+//! [`CoroutineSet`](crate::set::CoroutineSet) is the unstructured
+//! counterpart, handing back every output and leaving failures to the
+//! caller.
+
+use core::fmt;
+use core::future::Future;
+use core::future::poll_fn;
+use core::task::Context;
+use core::task::Poll;
+
+use alloc::vec::Vec;
+
+use crate::BuildError;
+use crate::CoroutineAwaiter;
+use crate::CoroutineBuilder;
+use crate::CoroutineFuture;
+use crate::Resumed;
+
+/// Runs `f` to start coroutines on a fresh [`CoroutineScope`], then waits
+/// for all of them.
+///
+/// Resolves to the value of `f` once every coroutine has returned `Ok`, or
+/// to the first error after cancelling the rest.
+///
+/// # Panics
+///
+/// Resumes the panic of a coroutine whose body panicked, after cancelling
+/// the rest.
+///
+/// # Example
+///
+/// ```ignore
+/// use minicoro_awaiters::scope::scope;
+///
+/// scope(|s| {
+///     for shard in shards {
+///         s.spawn_coroutine(move |awaiter| shard.migrate(&awaiter));
+///     }
+/// })
+/// .await?;
+/// ```
+pub async fn scope<T, E>(f: impl FnOnce(&mut CoroutineScope<E>) -> T) -> Result<T, E> {
+    let mut scope = CoroutineScope::new();
+    let value = f(&mut scope);
+    scope.join().await?;
+    Ok(value)
+}
+
+/// A group of coroutines that are joined or cancelled together.
+///
+/// Dropping the scope aborts every coroutine still in it.
+pub struct CoroutineScope<E> {
+    members: Vec<CoroutineFuture<Result<(), E>>>,
+    builder: CoroutineBuilder,
+}
+
+impl<E> CoroutineScope<E> {
+    /// Creates an empty scope building coroutines with default settings.
+    pub fn new() -> Self {
+        Self::with_builder(CoroutineBuilder::new())
+    }
+
+    /// Creates an empty scope building coroutines with the settings of
+    /// `builder`.
+    pub fn with_builder(builder: CoroutineBuilder) -> Self {
+        Self {
+            members: Vec::new(),
+            builder,
+        }
+    }
+
+    /// Starts a coroutine running `body` in the scope. It is first resumed
+    /// by the next poll of the scope.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the coroutine cannot be created; use
+    /// [`try_spawn_coroutine`](Self::try_spawn_coroutine) to handle that case.
+    #[track_caller]
+    pub fn spawn_coroutine(&mut self, body: impl FnOnce(CoroutineAwaiter) -> Result<(), E>) {
+        self.try_spawn_coroutine(body).unwrap()
+    }
+
+    /// Like [`spawn_coroutine`](Self::spawn_coroutine), but returns an error
+    /// if the coroutine cannot be created.
+    #[track_caller]
+    pub fn try_spawn_coroutine(
+        &mut self,
+        body: impl FnOnce(CoroutineAwaiter) -> Result<(), E>,
+    ) -> Result<(), BuildError> {
+        let coroutine = self.builder.clone().build(body)?;
+        self.members.push(coroutine);
+        Ok(())
+    }
+
+    /// Number of coroutines that have not finished yet.
+    pub fn len(&self) -> usize {
+        self.members.len()
+    }
+
+    /// Returns `true` if no coroutines are left.
+    pub fn is_empty(&self) -> bool {
+        self.members.is_empty()
+    }
+
+    /// Aborts every coroutine in the scope and empties it.
+    ///
+    /// See [`CoroutineFuture::abort`].
+    pub fn cancel(&mut self) {
+        for mut member in self.members.drain(..) {
+            member.abort();
+        }
+    }
+
+    /// Resumes every woken coroutine, returning `Poll::Ready(Ok(()))` once
+    /// all have returned `Ok`, or the first error after cancelling the rest.
+    ///
+    /// # Panics
+    ///
+    /// Resumes the panic of a coroutine whose body panicked, after
+    /// cancelling the rest.
+    pub fn poll_join(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), E>> {
+        let mut index = 0;
+        while index < self.members.len() {
+            let member = &mut self.members[index];
+            member.register(cx.waker());
+            if !member.coro.user_data().should_resume() {
+                index += 1;
+                continue;
+            }
+            match member.resume_raw() {
+                Resumed::Yielded => index += 1,
+                Resumed::Completed(outcome) => {
+                    // The last member moves into `index`, which is visited next.
+                    let member = self.members.swap_remove(index);
+                    if !matches!(outcome, Ok(Ok(()))) {
+                        self.cancel();
+                    }
+                    if let Err(error) = member.finish_or_panic(outcome) {
+                        return Poll::Ready(Err(error));
+                    }
+                }
+            }
+        }
+        if self.members.is_empty() {
+            Poll::Ready(Ok(()))
+        } else {
+            Poll::Pending
+        }
+    }
+
+    /// Waits for every coroutine in the scope; see [`poll_join`](Self::poll_join).
+    pub fn join(mut self) -> impl Future<Output = Result<(), E>> {
+        poll_fn(move |cx| self.poll_join(cx))
+    }
+}
+
+impl<E> Default for CoroutineScope<E> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<E> fmt::Debug for CoroutineScope<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CoroutineScope")
+            .field("len", &self.len())
+            .field("builder", &self.builder)
+            .finish()
+    }
+}
@@ -0,0 +1,172 @@
+//! Coroutines that may move between threads.
+//!
+//! A [`CoroutineFuture`] is `!Send`. Its stack can hold anything the body
+//! keeps alive across an await, and the compiler cannot see those values, so
+//! [`CoroutineAwaiter`] accepts every future, including `!Send` ones holding
+//! an `Rc` or a `RefCell` borrow. That is the local case, and it needs no
+//! extra care.
+//!
+//! For multi-threaded executors, [`CoroutineBuilder::build_send`] creates a
+//! [`SendCoroutineFuture`] instead. Everything the type system can check is
+//! required to be `Send`: the body, its output and every future awaited
+//! through the [`SendAwaiter`] it receives. The one thing it cannot check,
+//! locals held across awaits on the coroutine stack, is the caller's promise,
+//! which is why the constructor is `unsafe`. [`CoroutineToken::exec_send`]
+//! is the same for callers going through the token.
+
+use core::fmt;
+use core::future::Future;
+use core::future::IntoFuture;
+use core::pin::Pin;
+use core::pin::pin;
+use core::task::Context;
+use core::task::Poll;
+
+use awaiter_trait::Awaiter;
+
+use crate::BuildError;
+use crate::CoroutineAwaiter;
+use crate::CoroutineBuilder;
+use crate::CoroutineFuture;
+use crate::CoroutineToken;
+
+/// The awaiter passed to the body of a [`SendCoroutineFuture`].
+///
+/// Unlike [`CoroutineAwaiter`] it does not implement
+/// [`awaiter_trait::Awaiter`], whose signature cannot require `Send`, and
+/// only awaits `Send` futures.
+pub struct SendAwaiter(CoroutineAwaiter);
+
+impl SendAwaiter {
+    /// Blocks the coroutine until `future` completes; see
+    /// [`Awaiter::r#await`](awaiter_trait::Awaiter::r#await).
+    #[track_caller]
+    pub fn r#await<F>(&self, future: F) -> F::Output
+    where
+        F: IntoFuture,
+        F::IntoFuture: Send,
+    {
+        self.0.r#await(pin!(future.into_future()))
+    }
+
+    /// Returns `true` once cancellation of the running coroutine has been requested.
+    pub fn is_cancelled(&self) -> bool {
+        self.0.is_cancelled()
+    }
+
+    /// Fails the coroutine if it is running out of stack; see
+    /// [`CoroutineAwaiter::check_stack`].
+    pub fn check_stack(&self) {
+        self.0.check_stack();
+    }
+}
+
+impl fmt::Debug for SendAwaiter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("SendAwaiter").field(&self.0).finish()
+    }
+}
+
+/// A coroutine that may be resumed on any thread, created by
+/// [`CoroutineBuilder::build_send`].
+pub struct SendCoroutineFuture<T = ()>(CoroutineFuture<T>);
+
+// SAFETY: the body, its output and every awaited future are `Send`, and the
+// caller of `build_send` promised the same for locals kept across awaits.
+unsafe impl<T: Send> Send for SendCoroutineFuture<T> {}
+
+impl<T> SendCoroutineFuture<T> {
+    /// The wrapped coroutine.
+    pub fn get_ref(&self) -> &CoroutineFuture<T> {
+        &self.0
+    }
+
+    /// Unwraps the coroutine, giving up `Send`.
+    pub fn into_inner(self) -> CoroutineFuture<T> {
+        self.0
+    }
+}
+
+impl<T> Future for SendCoroutineFuture<T> {
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T> {
+        Pin::new(&mut self.get_mut().0).poll(cx)
+    }
+}
+
+impl<T> fmt::Debug for SendCoroutineFuture<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("SendCoroutineFuture").field(&self.0).finish()
+    }
+}
+
+impl CoroutineBuilder {
+    /// Creates a coroutine that may be moved to and resumed on other threads.
+    ///
+    /// # Safety
+    ///
+    /// The body must not keep a `!Send` value alive across an await. It may
+    /// use one between awaits, for example an `Rc` that is created and
+    /// dropped without suspending in between.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// use minicoro_awaiters::CoroutineFuture;
+    ///
+    /// // SAFETY: the body holds nothing but `Send` values across awaits.
+    /// let coro = unsafe {
+    ///     CoroutineFuture::builder().build_send(|awaiter| {
+    ///         awaiter.r#await(async { 42 })
+    ///     })?
+    /// };
+    /// tokio::spawn(coro);
+    /// ```
+    #[track_caller]
+    pub unsafe fn build_send<T, F>(self, f: F) -> Result<SendCoroutineFuture<T>, BuildError>
+    where
+        T: Send,
+        F: FnOnce(SendAwaiter) -> T + Send,
+    {
+        self.build(move |awaiter| f(SendAwaiter(awaiter)))
+            .map(SendCoroutineFuture)
+    }
+}
+
+impl CoroutineToken {
+    /// Runs `f` on a new coroutine, like
+    /// [`exec`](awaiter_trait::Coroutine::exec), returning a future that may
+    /// be moved to other threads, such as with `tokio::spawn`.
+    ///
+    /// `f` receives a [`SendAwaiter`] rather than a `&dyn DynAwaiter`, which
+    /// would accept `!Send` futures.
+    ///
+    /// # Safety
+    ///
+    /// As for [`CoroutineBuilder::build_send`]: `f` must not keep a `!Send`
+    /// value alive across an await.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the coroutine cannot be created.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// use minicoro_awaiters::CoroutineToken;
+    ///
+    /// // SAFETY: the body holds nothing but `Send` values across awaits.
+    /// let task = unsafe { CoroutineToken.exec_send(|awaiter| awaiter.r#await(fetch(url))) };
+    /// tokio::spawn(task);
+    /// ```
+    #[track_caller]
+    pub unsafe fn exec_send<T, F>(&self, f: F) -> SendCoroutineFuture<T>
+    where
+        T: Send,
+        F: FnOnce(SendAwaiter) -> T + Send,
+    {
+        // SAFETY: forwarded to the caller.
+        unsafe { CoroutineBuilder::new().build_send(f) }.unwrap()
+    }
+}
@@ -0,0 +1,362 @@
+//! Running many coroutines as one collection.
+//!
+//! A [`CoroutineSet`] owns any number of [`CoroutineFuture`]s, resumes
+//! those that were woken whenever it is polled, and yields their outputs in
+//! completion order through [`CoroutineSet::join_next`]. Panics propagate
+//! out of `join_next` just as they would out of awaiting the coroutine
+//! itself, and dropping the set aborts every coroutine still in it.
+//!
+//! Which members a poll resumes, and how often, is decided by the set's
+//! [`Schedule`]. The default [`RoundRobin`] rotates where each poll starts
+//! and resumes every woken member once, so a coroutine that keeps waking
+//! itself cannot starve the others.
+//!
+//! Every member owns a whole stack for as long as it lives: a suspended
+//! coroutine has live frames on its stack, so it cannot hand the stack back
+//! and take another one later. For very large numbers of mostly idle jobs,
+//! [`BoundedSet`] bounds the memory instead, creating a coroutine (and its
+//! stack) only once a slot frees up and queueing the bodies submitted
+//! beyond that.
+
+use core::fmt;
+use core::future::Future;
+use core::future::poll_fn;
+use core::panic::Location;
+use core::task::Context;
+use core::task::Poll;
+
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+
+use crate::BuildError;
+use crate::CoroutineAwaiter;
+use crate::CoroutineBuilder;
+use crate::CoroutineFuture;
+use crate::Resumed;
+use crate::sync::AtomicWaker;
+use crate::waker::WakerSlot;
+
+/// Decides the order and number of resumes within one poll of a [`CoroutineSet`].
+pub trait Schedule {
+    /// Returns the index of the member the next poll starts with, given
+    /// `len` members; later members follow in order, wrapping around.
+    fn start(&mut self, len: usize) -> usize;
+
+    /// How many times a woken member may be resumed per poll of the set.
+    ///
+    /// A member stops early once it parks on a pending future or completes.
+    fn resumes_per_poll(&self) -> u32 {
+        1
+    }
+}
+
+/// Rotates the starting member on every poll.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug, Hash)]
+pub struct RoundRobin {
+    next: usize,
+    resumes_per_poll: u32,
+}
+
+impl RoundRobin {
+    /// Resumes every woken member at most `resumes_per_poll` times per poll.
+    pub fn new(resumes_per_poll: u32) -> Self {
+        Self {
+            next: 0,
+            resumes_per_poll,
+        }
+    }
+}
+
+impl Default for RoundRobin {
+    fn default() -> Self {
+        Self::new(1)
+    }
+}
+
+impl Schedule for RoundRobin {
+    fn start(&mut self, len: usize) -> usize {
+        let start = self.next % len;
+        self.next = start + 1;
+        start
+    }
+
+    fn resumes_per_poll(&self) -> u32 {
+        self.resumes_per_poll
+    }
+}
+
+/// Always starts with the oldest member, favouring coroutines added first.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug, Hash, Default)]
+pub struct InsertionOrder;
+
+impl Schedule for InsertionOrder {
+    fn start(&mut self, _len: usize) -> usize {
+        0
+    }
+}
+
+/// A collection of coroutines driven together, yielding outputs as they complete.
+///
+/// # Example
+///
+/// ```ignore
+/// use minicoro_awaiters::{CoroutineFuture, set::CoroutineSet};
+///
+/// let mut set = CoroutineSet::new();
+/// for url in urls {
+///     set.push(CoroutineFuture::new(move |awaiter| fetch(&awaiter, url)));
+/// }
+/// while let Some(page) = set.join_next().await {
+///     handle(page);
+/// }
+/// ```
+pub struct CoroutineSet<T, W: WakerSlot = AtomicWaker, S: Schedule = RoundRobin> {
+    members: Vec<CoroutineFuture<T, W>>,
+    schedule: S,
+}
+
+impl<T, W: WakerSlot> CoroutineSet<T, W> {
+    /// Creates an empty set with the [`RoundRobin`] schedule.
+    pub fn new() -> Self {
+        Self::with_schedule(RoundRobin::default())
+    }
+}
+
+impl<T, W: WakerSlot, S: Schedule> CoroutineSet<T, W, S> {
+    /// Creates an empty set resuming its members according to `schedule`.
+    pub fn with_schedule(schedule: S) -> Self {
+        Self {
+            members: Vec::new(),
+            schedule,
+        }
+    }
+
+    /// Adds `coroutine` to the set. It is first resumed by the next poll.
+    pub fn push(&mut self, coroutine: CoroutineFuture<T, W>) {
+        self.members.push(coroutine);
+    }
+
+    /// Number of coroutines that have not completed yet.
+    pub fn len(&self) -> usize {
+        self.members.len()
+    }
+
+    /// Returns `true` if no coroutines are left.
+    pub fn is_empty(&self) -> bool {
+        self.members.is_empty()
+    }
+
+    /// Resumes every woken coroutine until one completes, returning its output.
+    ///
+    /// Returns `Poll::Ready(None)` once the set is empty.
+    ///
+    /// # Panics
+    ///
+    /// Resumes the panic of a coroutine whose body panicked; the coroutine is
+    /// removed from the set first.
+    pub fn poll_join_next(&mut self, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        if self.members.is_empty() {
+            return Poll::Ready(None);
+        }
+        let len = self.members.len();
+        let start = self.schedule.start(len) % len;
+        let budget = self.schedule.resumes_per_poll().max(1);
+        for offset in 0..len {
+            let index = (start + offset) % len;
+            let member = &mut self.members[index];
+            member.register(cx.waker());
+            for _ in 0..budget {
+                if !member.coro.user_data().should_resume() {
+                    break;
+                }
+                if let Resumed::Completed(outcome) = member.resume_raw() {
+                    let member = self.members.swap_remove(index);
+                    return Poll::Ready(Some(member.finish_or_panic(outcome)));
+                }
+            }
+        }
+        Poll::Pending
+    }
+
+    /// Waits for the next coroutine to complete and returns its output, or
+    /// `None` once the set is empty.
+    pub fn join_next(&mut self) -> impl Future<Output = Option<T>> + '_ {
+        poll_fn(|cx| self.poll_join_next(cx))
+    }
+
+    /// Aborts every coroutine in the set and empties it.
+    ///
+    /// See [`CoroutineFuture::abort`].
+    pub fn abort_all(&mut self) {
+        for mut member in self.members.drain(..) {
+            member.abort();
+        }
+    }
+}
+
+impl<T, W: WakerSlot, S: Schedule + Default> Default for CoroutineSet<T, W, S> {
+    fn default() -> Self {
+        Self::with_schedule(S::default())
+    }
+}
+
+impl<T, W: WakerSlot, S: Schedule + fmt::Debug> fmt::Debug for CoroutineSet<T, W, S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CoroutineSet")
+            .field("len", &self.len())
+            .field("schedule", &self.schedule)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T, W: WakerSlot, S: Schedule> Extend<CoroutineFuture<T, W>> for CoroutineSet<T, W, S> {
+    fn extend<I: IntoIterator<Item = CoroutineFuture<T, W>>>(&mut self, iter: I) {
+        self.members.extend(iter);
+    }
+}
+
+impl<T, W: WakerSlot, S: Schedule + Default> FromIterator<CoroutineFuture<T, W>>
+    for CoroutineSet<T, W, S>
+{
+    fn from_iter<I: IntoIterator<Item = CoroutineFuture<T, W>>>(iter: I) -> Self {
+        Self {
+            members: iter.into_iter().collect(),
+            schedule: S::default(),
+        }
+    }
+}
+
+#[cfg(feature = "futures")]
+impl<T, W: WakerSlot, S: Schedule + Unpin> futures_core::Stream for CoroutineSet<T, W, S> {
+    type Item = T;
+
+    fn poll_next(self: core::pin::Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        self.get_mut().poll_join_next(cx)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.len(), Some(self.len()))
+    }
+}
+
+/// A body queued in a [`BoundedSet`], with the site that submitted it.
+type Queued<T> = (Box<dyn FnOnce(CoroutineAwaiter) -> T>, &'static Location<'static>);
+
+/// A [`CoroutineSet`] with at most a fixed number of live coroutines.
+///
+/// Bodies pushed beyond the limit wait in a queue without a stack and are
+/// built, with the set's [`CoroutineBuilder`], as running members complete.
+/// The recorded creation site of each coroutine is the caller of
+/// [`push`](Self::push).
+///
+/// # Example
+///
+/// ```ignore
+/// use minicoro_awaiters::{CoroutineFuture, set::BoundedSet};
+///
+/// // At most 1024 stacks of 16 KiB, however many connections are queued.
+/// let mut set = BoundedSet::new(CoroutineFuture::builder().stack_size(16 << 10), 1024);
+/// for connection in connections {
+///     set.push(move |awaiter| serve(&awaiter, connection));
+/// }
+/// while let Some(result) = set.join_next().await {
+///     result?;
+/// }
+/// ```
+pub struct BoundedSet<T, S: Schedule = RoundRobin> {
+    running: CoroutineSet<T, AtomicWaker, S>,
+    queued: VecDeque<Queued<T>>,
+    builder: CoroutineBuilder,
+    limit: usize,
+}
+
+impl<T> BoundedSet<T> {
+    /// Creates an empty set building at most `limit` coroutines at a time
+    /// with `builder`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `limit` is zero.
+    pub fn new(builder: CoroutineBuilder, limit: usize) -> Self {
+        Self::with_schedule(builder, limit, RoundRobin::default())
+    }
+}
+
+impl<T, S: Schedule> BoundedSet<T, S> {
+    /// Like [`new`](BoundedSet::new), resuming members according to `schedule`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `limit` is zero.
+    pub fn with_schedule(builder: CoroutineBuilder, limit: usize, schedule: S) -> Self {
+        assert!(limit > 0, "a `BoundedSet` needs room for at least one coroutine");
+        Self {
+            running: CoroutineSet::with_schedule(schedule),
+            queued: VecDeque::new(),
+            builder,
+            limit,
+        }
+    }
+
+    /// Queues a coroutine running `body`.
+    #[track_caller]
+    pub fn push(&mut self, body: impl FnOnce(CoroutineAwaiter) -> T + 'static) {
+        self.queued.push_back((Box::new(body), Location::caller()));
+    }
+
+    /// Number of live coroutines.
+    pub fn running(&self) -> usize {
+        self.running.len()
+    }
+
+    /// Number of bodies waiting for a free slot.
+    pub fn queued(&self) -> usize {
+        self.queued.len()
+    }
+
+    /// Returns `true` if nothing is running or queued.
+    pub fn is_empty(&self) -> bool {
+        self.running.is_empty() && self.queued.is_empty()
+    }
+
+    /// Admits queued bodies while there is room, then drives the live
+    /// coroutines like [`CoroutineSet::poll_join_next`].
+    ///
+    /// Yields `Err` for a body whose coroutine could not be built; the body
+    /// is dropped unrun.
+    pub fn poll_join_next(&mut self, cx: &mut Context<'_>) -> Poll<Option<Result<T, BuildError>>> {
+        while self.running.len() < self.limit {
+            let Some((body, location)) = self.queued.pop_front() else {
+                break;
+            };
+            match self.builder.clone().location(location).build(body) {
+                Ok(coroutine) => self.running.push(coroutine),
+                Err(error) => return Poll::Ready(Some(Err(error))),
+            }
+        }
+        self.running.poll_join_next(cx).map(|output| output.map(Ok))
+    }
+
+    /// Waits for the next coroutine to complete; see
+    /// [`poll_join_next`](Self::poll_join_next).
+    pub fn join_next(&mut self) -> impl Future<Output = Option<Result<T, BuildError>>> + '_ {
+        poll_fn(|cx| self.poll_join_next(cx))
+    }
+
+    /// Aborts every live coroutine and drops the queued bodies.
+    pub fn abort_all(&mut self) {
+        self.running.abort_all();
+        self.queued.clear();
+    }
+}
+
+impl<T, S: Schedule + fmt::Debug> fmt::Debug for BoundedSet<T, S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BoundedSet")
+            .field("running", &self.running)
+            .field("queued", &self.queued.len())
+            .field("limit", &self.limit)
+            .finish_non_exhaustive()
+    }
+}
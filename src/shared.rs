@@ -0,0 +1,180 @@
+//! Awaiting one coroutine from several tasks.
+//!
+//! [`CoroutineFuture::shared`] turns a coroutine into a [`SharedCoroutine`]
+//! handle that can be cloned and awaited any number of times. Whichever
+//! handle is polled drives the coroutine, every waiting handle is woken when
+//! it makes progress, and once it finishes each handle resolves to a clone
+//! of its output.
+//!
+//! Failures follow the crate's usual rules, once: the first handle to see a
+//! panic of the body resumes it, with its original payload, and every other
+//! handle panics with its message, as it does for stack overflows and
+//! aborts. The coroutine is aborted when the last handle is dropped before
+//! it finishes.
+
+use core::cell::RefCell;
+use core::fmt;
+use core::future::Future;
+use core::pin::Pin;
+use core::task::Context;
+use core::task::Poll;
+use core::task::Waker;
+
+use alloc::rc::Rc;
+use alloc::string::String;
+use alloc::string::ToString;
+use alloc::sync::Arc;
+use alloc::task::Wake;
+use alloc::vec::Vec;
+use std::sync::Mutex;
+
+use crate::CoroutineFuture;
+use crate::error::CatchErrors;
+use crate::error::CoroutineError;
+use crate::sync::AtomicWaker;
+use crate::waker::WakerSlot;
+
+/// Wakes every handle waiting on the coroutine.
+struct Fanout {
+    wakers: Mutex<Vec<Waker>>,
+}
+
+impl Fanout {
+    fn register(&self, waker: &Waker) {
+        let mut wakers = self.wakers.lock().unwrap_or_else(|e| e.into_inner());
+        if !wakers.iter().any(|known| known.will_wake(waker)) {
+            wakers.push(waker.clone());
+        }
+    }
+}
+
+impl Wake for Fanout {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        let wakers = core::mem::take(&mut *self.wakers.lock().unwrap_or_else(|e| e.into_inner()));
+        for waker in wakers {
+            waker.wake();
+        }
+    }
+}
+
+enum State<T, W: WakerSlot> {
+    Running(CatchErrors<T, W>),
+    Finished(T),
+    Failed {
+        /// Taken by the first handle to see the failure.
+        error: Option<CoroutineError>,
+        message: String,
+    },
+}
+
+struct Inner<T, W: WakerSlot> {
+    state: RefCell<State<T, W>>,
+    fanout: Arc<Fanout>,
+    /// `fanout` as a waker, handed to the coroutine on every poll.
+    waker: Waker,
+}
+
+impl<T, W: WakerSlot> CoroutineFuture<T, W> {
+    /// Lets several tasks await the coroutine, each receiving a clone of its
+    /// output.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// use minicoro_awaiters::CoroutineFuture;
+    ///
+    /// let config = CoroutineFuture::new(|awaiter| load_config(&awaiter)).shared();
+    /// let (a, b) = futures::join!(config.clone(), config);
+    /// ```
+    pub fn shared(self) -> SharedCoroutine<T, W>
+    where
+        T: Clone,
+    {
+        let fanout = Arc::new(Fanout {
+            wakers: Mutex::new(Vec::new()),
+        });
+        SharedCoroutine {
+            inner: Rc::new(Inner {
+                state: RefCell::new(State::Running(self.catch_errors())),
+                waker: Waker::from(fanout.clone()),
+                fanout,
+            }),
+        }
+    }
+}
+
+/// A cloneable handle to a coroutine awaited by several tasks, returned by
+/// [`CoroutineFuture::shared`].
+pub struct SharedCoroutine<T, W: WakerSlot = AtomicWaker> {
+    inner: Rc<Inner<T, W>>,
+}
+
+impl<T, W: WakerSlot> SharedCoroutine<T, W> {
+    /// Returns `true` once the coroutine has finished, successfully or not.
+    pub fn is_finished(&self) -> bool {
+        !matches!(*self.inner.state.borrow(), State::Running(_))
+    }
+
+    /// Number of handles to the coroutine.
+    pub fn handles(&self) -> usize {
+        Rc::strong_count(&self.inner)
+    }
+}
+
+impl<T: Clone, W: WakerSlot> Future for SharedCoroutine<T, W> {
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T> {
+        let inner = &*self.inner;
+        let mut state = inner.state.borrow_mut();
+        if let State::Running(coroutine) = &mut *state {
+            inner.fanout.register(cx.waker());
+            let mut shared_cx = Context::from_waker(&inner.waker);
+            *state = match Pin::new(coroutine).poll(&mut shared_cx) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(Ok(output)) => State::Finished(output),
+                Poll::Ready(Err(error)) => State::Failed {
+                    message: error.to_string(),
+                    error: Some(error),
+                },
+            };
+            // Every other handle is waiting on the fanout.
+            inner.waker.wake_by_ref();
+        }
+        let failure = match &mut *state {
+            State::Running(_) => unreachable!("the coroutine finished above"),
+            State::Finished(output) => return Poll::Ready(output.clone()),
+            State::Failed { error, message } => (error.take(), message.clone()),
+        };
+        // Released before unwinding, so the other handles can still look.
+        drop(state);
+        match failure {
+            (Some(error), _) => {
+                let error = error.resume_panic();
+                panic!("{error}")
+            }
+            (None, message) => panic!("the shared coroutine failed: {message}"),
+        }
+    }
+}
+
+impl<T, W: WakerSlot> Clone for SharedCoroutine<T, W> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<T, W: WakerSlot> fmt::Debug for SharedCoroutine<T, W> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SharedCoroutine")
+            .field("finished", &self.is_finished())
+            .field("handles", &self.handles())
+            .finish_non_exhaustive()
+    }
+}
@@ -0,0 +1,293 @@
+//! Spawning background futures from inside a coroutine.
+//!
+//! A coroutine body can start a future without blocking on it through
+//! [`CoroutineAwaiter::spawn`], which hands it to the [`Spawner`] registered
+//! with [`CoroutineBuilder::spawner`](crate::CoroutineBuilder::spawner). The
+//! returned [`JoinHandle`] is a future, so the body can later block on it with
+//! [`r#await`](awaiter_trait::Awaiter::r#await). [`CoroutineAwaiter::await_on`]
+//! does both at once for futures that should be polled on another executor
+//! than the one driving the coroutine.
+//!
+//! [`TokioSpawner`] (with the `tokio` feature) and [`SmolSpawner`] (with the
+//! `smol` feature) cover the common runtimes. Executors that only spawn
+//! statically declared tasks, such as embassy, can implement [`Spawner`] on
+//! top of a task pool of their own.
+//!
+//! Thread-per-core runtimes such as glommio and monoio spawn `!Send`
+//! futures onto the current thread only. Their spawn functions fit the
+//! [`LocalSpawner`] trait as closures, and [`spawn_local`] starts a future
+//! through one from any awaiter, handing back the same [`JoinHandle`].
+
+use core::cell::UnsafeCell;
+use core::fmt;
+use core::future::Future;
+use core::pin::Pin;
+use core::task::Context;
+use core::task::Poll;
+
+use alloc::boxed::Box;
+
+use crate::CoroutineAwaiter;
+use crate::sync::Arc;
+use crate::sync::AtomicBool;
+use crate::sync::AtomicWaker;
+use crate::sync::Ordering;
+use crate::waker::WakerSlot;
+
+/// A future handed to a [`Spawner`].
+pub type BoxFuture = Pin<Box<dyn Future<Output = ()> + Send + 'static>>;
+
+/// Runs futures spawned from inside coroutines in the background.
+pub trait Spawner: Send + Sync {
+    /// Starts running `future` to completion without waiting for it.
+    fn spawn(&self, future: BoxFuture);
+}
+
+/// A future handed to a [`LocalSpawner`].
+pub type LocalBoxFuture = Pin<Box<dyn Future<Output = ()> + 'static>>;
+
+/// Runs `!Send` futures in the background on the current thread.
+///
+/// Implemented for closures taking a [`LocalBoxFuture`], so a runtime's own
+/// spawn function can be passed directly.
+pub trait LocalSpawner {
+    /// Starts running `future` to completion without waiting for it.
+    fn spawn_local(&self, future: LocalBoxFuture);
+}
+
+impl<F: Fn(LocalBoxFuture)> LocalSpawner for F {
+    fn spawn_local(&self, future: LocalBoxFuture) {
+        self(future)
+    }
+}
+
+/// Error returned by a [`JoinHandle`] whose task was dropped before completing,
+/// for example because its runtime shut down.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug, Hash, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct JoinError;
+
+impl fmt::Display for JoinError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("the spawned task was dropped before completing")
+    }
+}
+
+impl core::error::Error for JoinError {}
+
+/// The output handoff between a spawned task and its [`JoinHandle`].
+struct Join<T> {
+    value: UnsafeCell<Option<T>>,
+    /// Set once the task has stored its value or been dropped.
+    finished: AtomicBool,
+    waker: AtomicWaker,
+}
+
+// SAFETY: `value` is written by the task before `finished` is released and
+// only read by the handle after acquiring it.
+unsafe impl<T: Send> Send for Join<T> {}
+unsafe impl<T: Send> Sync for Join<T> {}
+
+/// Completes the [`Join`] when the spawned task finishes or is dropped.
+pub(crate) struct Completer<T>(Arc<Join<T>>);
+
+/// Creates the two ends of a task's output handoff.
+pub(crate) fn handoff<T>() -> (Completer<T>, JoinHandle<T>) {
+    let join = Arc::new(Join {
+        value: UnsafeCell::new(None),
+        finished: AtomicBool::new(false),
+        waker: AtomicWaker::new(),
+    });
+    (Completer(join.clone()), JoinHandle { join })
+}
+
+impl<T> Completer<T> {
+    pub(crate) fn complete(self, value: T) {
+        // SAFETY: only the task writes, and only before `finished` is set.
+        unsafe { *self.0.value.get() = Some(value) };
+    }
+}
+
+impl<T> Drop for Completer<T> {
+    fn drop(&mut self) {
+        self.0.finished.store(true, Ordering::Release);
+        self.0.waker.wake();
+    }
+}
+
+/// A handle to a future spawned with [`CoroutineAwaiter::spawn`] or
+/// [`spawn_local`].
+///
+/// Awaiting it yields the future's output. Dropping it detaches the task.
+pub struct JoinHandle<T> {
+    join: Arc<Join<T>>,
+}
+
+impl<T> JoinHandle<T> {
+    /// Returns `true` once the task has finished or been dropped.
+    pub fn is_finished(&self) -> bool {
+        self.join.finished.load(Ordering::Acquire)
+    }
+}
+
+impl<T> Future for JoinHandle<T> {
+    type Output = Result<T, JoinError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.join.waker.register(cx.waker());
+        if !self.is_finished() {
+            return Poll::Pending;
+        }
+        // SAFETY: the task finished writing before releasing `finished`.
+        match unsafe { (*self.join.value.get()).take() } {
+            Some(value) => Poll::Ready(Ok(value)),
+            None => Poll::Ready(Err(JoinError)),
+        }
+    }
+}
+
+impl<T> fmt::Debug for JoinHandle<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("JoinHandle")
+            .field("finished", &self.is_finished())
+            .finish_non_exhaustive()
+    }
+}
+
+impl<W: WakerSlot> CoroutineAwaiter<W> {
+    /// Spawns `future` onto the coroutine's [`Spawner`] and returns a handle to its output.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the coroutine was built without a spawner.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// use minicoro_awaiters::{CoroutineFuture, spawn::TokioSpawner};
+    ///
+    /// let coro = CoroutineFuture::builder()
+    ///     .spawner(TokioSpawner::current())
+    ///     .build(|awaiter| {
+    ///         let handle = awaiter.spawn(async { 1 + 1 });
+    ///         // ... do other work ...
+    ///         awaiter.r#await(Box::pin(handle)).unwrap()
+    ///     })?;
+    /// ```
+    pub fn spawn<F>(&self, future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        let shared = self.coro.user_data();
+        let spawner = shared
+            .spawner()
+            .unwrap_or_else(|| panic!("{} has no spawner", shared.describe()));
+        let (completer, handle) = handoff();
+        let task = Box::pin(async move { completer.complete(future.await) });
+        crate::events::allocated(shared, size_of_val(&*task));
+        spawner.spawn(task);
+        handle
+    }
+
+    /// Polls `future` on `spawner` instead of the thread driving the
+    /// coroutine, blocking the coroutine until its output arrives.
+    ///
+    /// Useful for heavy futures that should stay off a latency-sensitive
+    /// executor. If the coroutine is aborted while it waits, the future keeps
+    /// running in the background and its output is discarded.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`JoinError`] if the spawned task was dropped before
+    /// completing, for example because its runtime shut down.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// use minicoro_awaiters::{CoroutineFuture, spawn::TokioSpawner};
+    ///
+    /// let compute = TokioSpawner(compute_runtime.handle().clone());
+    /// let coro = CoroutineFuture::new(move |awaiter| {
+    ///     let index = awaiter.await_on(&compute, build_index(documents)).unwrap();
+    ///     serve(&awaiter, index)
+    /// });
+    /// ```
+    #[track_caller]
+    pub fn await_on<S, F>(&self, spawner: &S, future: F) -> Result<F::Output, JoinError>
+    where
+        S: Spawner + ?Sized,
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        let (completer, handle) = handoff();
+        let task = Box::pin(async move { completer.complete(future.await) });
+        crate::events::allocated(self.coro.user_data(), size_of_val(&*task));
+        spawner.spawn(task);
+        self.wait(handle)
+    }
+}
+
+/// Spawns `future` onto `spawner` and returns a handle to its output.
+///
+/// Unlike [`CoroutineAwaiter::spawn`] this needs neither a `Send` future nor
+/// a spawner registered on the builder, so it also works from the body of a
+/// [`LocalCoroutineFuture`](crate::local::LocalCoroutineFuture).
+///
+/// # Example
+///
+/// ```ignore
+/// use minicoro_awaiters::local::LocalCoroutineFuture;
+/// use minicoro_awaiters::spawn::{LocalBoxFuture, spawn_local};
+///
+/// let spawner = |task: LocalBoxFuture| glommio::spawn_local(task).detach();
+/// let coro = LocalCoroutineFuture::new(move |awaiter| {
+///     let handle = spawn_local(&spawner, async { fetch(Rc::clone(&cache)).await });
+///     awaiter.r#await(Box::pin(handle)).unwrap()
+/// });
+/// ```
+pub fn spawn_local<S, F>(spawner: &S, future: F) -> JoinHandle<F::Output>
+where
+    S: LocalSpawner + ?Sized,
+    F: Future + 'static,
+{
+    let (completer, handle) = handoff();
+    spawner.spawn_local(Box::pin(async move { completer.complete(future.await) }));
+    handle
+}
+
+/// Spawns onto a tokio runtime.
+#[cfg(feature = "tokio")]
+#[derive(Clone, Debug)]
+pub struct TokioSpawner(pub tokio::runtime::Handle);
+
+#[cfg(feature = "tokio")]
+impl TokioSpawner {
+    /// Spawns onto the runtime the caller is running on.
+    ///
+    /// # Panics
+    ///
+    /// Panics when called outside of a tokio runtime.
+    pub fn current() -> Self {
+        Self(tokio::runtime::Handle::current())
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl Spawner for TokioSpawner {
+    fn spawn(&self, future: BoxFuture) {
+        drop(self.0.spawn(future));
+    }
+}
+
+/// Spawns onto a smol (`async-executor`) executor.
+#[cfg(feature = "smol")]
+#[derive(Clone, Debug)]
+pub struct SmolSpawner(pub alloc::sync::Arc<async_executor::Executor<'static>>);
+
+#[cfg(feature = "smol")]
+impl Spawner for SmolSpawner {
+    fn spawn(&self, future: BoxFuture) {
+        self.0.spawn(future).detach();
+    }
+}
@@ -0,0 +1,452 @@
+//! Detecting coroutine stack overflows.
+//!
+//! A coroutine that runs past the end of its stack crashes the process at
+//! best and silently corrupts memory at worst. The crate cannot add guard
+//! pages to stacks allocated by minicoro, so it checks instead: every
+//! coroutine records where its stack starts, and each suspension point (plus
+//! every explicit [`CoroutineAwaiter::check_stack`]) measures how much of it
+//! is left. Once less than [`RED_ZONE`] bytes remain, the coroutine is marked
+//! as overflowed and, with the `std` feature, its stack is unwound.
+//! [`CoroutineFuture::catch_overflow`] turns that into an
+//! `Err(StackOverflow)`; the plain future panics. Without `std` the check
+//! panics on the coroutine stack, so such builds should use
+//! `panic = "abort"`.
+//!
+//! For coroutines that need a deep stack only occasionally, reserve a large
+//! one with [`CoroutineBuilder::reserve_stack`](crate::CoroutineBuilder::reserve_stack):
+//! where [`LAZY_COMMIT`] holds, it costs address space up front and physical
+//! memory only for the pages the coroutine actually reaches.
+//!
+//! Where it is lazily committed, a fresh stack page faults on first use.
+//! [`CoroutineBuilder::prefault_stack`](crate::CoroutineBuilder::prefault_stack)
+//! touches every page when the coroutine is built, so that cost is paid up
+//! front rather than on some request's hot path.
+//!
+//! With the `secure-stack` feature,
+//! [`CoroutineBuilder::secure_stack`](crate::CoroutineBuilder::secure_stack)
+//! keeps secrets a body leaves on its stack from outliving it. The stack is
+//! locked into memory with `mlock` when the body starts, on Unix, and zeroed
+//! and unlocked once the coroutine completes, is aborted or is dropped,
+//! before minicoro frees it. Only whole pages are locked, so the partial
+//! pages at either end may still be swapped out, and a failure to lock,
+//! typically from `RLIMIT_MEMLOCK`, is logged rather than reported. Both
+//! keep to the part of the stack that certainly lies within minicoro's
+//! allocation, leaving its lowest few KiB alone; heap allocations made by
+//! the body, and the body's closure and output, are not covered. A pooled
+//! worker runs many jobs on one stack, which is wiped when the worker is
+//! dropped rather than between jobs.
+//!
+//! Every check also records how deep the stack went, and
+//! [`CoroutineAwaiter::stack_high_water`] and
+//! [`CoroutineFuture::stack_high_water`] report the deepest point seen so far,
+//! a lower bound on the stack the coroutine needs. The
+//! [pool](crate::pool::CoroutinePool::learn_stack_sizes) can size stacks from
+//! these measurements.
+//!
+//! [`FixedStackFuture`] makes the stack size part of the type, so memory
+//! budgets can be audited from signatures and checked at compile time.
+//!
+//! Stacks always come from the heap. minicoroutine allocates a coroutine's
+//! context and stack together and offers no way to hand it a caller-owned
+//! buffer, such as a `&'static mut [u8]` on targets without an allocator, and
+//! the shared state and output slot of every coroutine are heap-allocated as
+//! well. Fully static coroutines would need that support upstream first.
+//!
+//! The check is a software canary: it catches deep recursion that reaches a
+//! checkpoint, not a single frame that jumps over the red zone. Stacks are
+//! assumed to grow downwards, as they do on every target minicoro supports.
+
+use core::fmt;
+use core::future::Future;
+use core::pin::Pin;
+use core::task::Context;
+use core::task::Poll;
+
+use crate::BuildError;
+use crate::CoroutineAwaiter;
+use crate::CoroutineBuilder;
+use crate::CoroutineFuture;
+use crate::Resumed;
+use crate::sync::AtomicWaker;
+use crate::waker::CoroutineShared;
+use crate::waker::WakerSlot;
+
+/// The stack size minicoro uses when none is given to the builder.
+pub const DEFAULT_STACK_SIZE: usize = 56 * 1024;
+
+/// Whether large stacks only consume physical memory for the pages a
+/// coroutine actually touches on this target.
+///
+/// minicoro allocates stacks with `calloc`. On these platforms allocations
+/// beyond [`LAZY_COMMIT_THRESHOLD`] are served by fresh anonymous mappings,
+/// whose pages the kernel commits on first use.
+pub const LAZY_COMMIT: bool = cfg!(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd",
+    target_os = "netbsd",
+    target_os = "openbsd",
+));
+
+/// The allocation size above which stacks are lazily committed where
+/// [`LAZY_COMMIT`] holds.
+pub const LAZY_COMMIT_THRESHOLD: usize = 256 * 1024;
+
+/// Bytes of stack that must remain at every checkpoint.
+pub const RED_ZONE: usize = 4 * 1024;
+
+/// Error returned when a coroutine ran out of stack.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug, Hash, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct StackOverflow;
+
+impl fmt::Display for StackOverflow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("the coroutine overflowed its stack")
+    }
+}
+
+impl core::error::Error for StackOverflow {}
+
+/// The payload of the unwind started by a failed stack check.
+#[cfg(feature = "std")]
+struct Overflowed;
+
+/// Approximates the current stack pointer.
+#[inline(always)]
+pub(crate) fn stack_pointer() -> usize {
+    let marker = 0u8;
+    core::hint::black_box(&marker) as *const u8 as usize
+}
+
+/// The granularity at which [`prefault`] touches the stack.
+const PAGE: usize = 4 * 1024;
+
+/// A generous bound on the stack minicoro and minicoroutine use above the
+/// base the body records, before any of the body's own data.
+///
+/// The base is only an estimate of the top of the stack, so its bottom is
+/// only known to lie at most this far above the base less the stack size.
+const ENTRY_SLACK: usize = 2 * PAGE;
+
+/// The part of the coroutine's stack that certainly lies within the
+/// allocation: from [`ENTRY_SLACK`] above its estimated bottom up to the
+/// base recorded when the body started.
+fn owned_range<W: WakerSlot>(shared: &CoroutineShared<W>) -> Option<(usize, usize)> {
+    let (bottom, size) = shared.stack_bottom()?;
+    let start = bottom.checked_add(ENTRY_SLACK)?;
+    let end = bottom + size;
+    (start < end).then_some((start, end))
+}
+
+/// Touches every page of the running coroutine's stack that certainly lies
+/// within it.
+pub(crate) fn prefault<W: WakerSlot>(shared: &CoroutineShared<W>) {
+    #[inline(never)]
+    fn touch(low: usize) {
+        let mut page = [0u8; PAGE];
+        core::hint::black_box(&mut page);
+        // The next frame reaches a page further down.
+        if stack_pointer() > low + 2 * PAGE {
+            touch(low);
+        } else {
+            return;
+        }
+        // Used after the call, so the recursion is not turned into a loop.
+        core::hint::black_box(&mut page);
+    }
+    if let Some((low, _)) = owned_range(shared) {
+        touch(low);
+    }
+}
+
+/// The size of a page of virtual memory.
+#[cfg(all(unix, any(feature = "decommit", feature = "secure-stack")))]
+fn page_size() -> usize {
+    // SAFETY: `sysconf` has no preconditions.
+    let page = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+    usize::try_from(page).unwrap_or(PAGE)
+}
+
+/// The part of the stack [`lock`], [`wipe`] and [`decommit`] may touch.
+///
+/// Coroutines emulated by the `thread-backend` run on thread stacks, which
+/// may be gone by the time a coroutine is wiped, so they are left alone.
+#[cfg(any(feature = "decommit", feature = "secure-stack"))]
+fn managed_range<W: WakerSlot>(shared: &CoroutineShared<W>) -> Option<(usize, usize)> {
+    if cfg!(feature = "thread-backend") {
+        return None;
+    }
+    owned_range(shared)
+}
+
+/// The whole pages within `(start, end)`.
+#[cfg(all(unix, feature = "secure-stack"))]
+fn whole_pages((start, end): (usize, usize)) -> Option<(*const libc::c_void, usize)> {
+    let page = page_size();
+    let start = start.next_multiple_of(page);
+    let end = end & !(page - 1);
+    (start < end).then(|| (start as *const libc::c_void, end - start))
+}
+
+/// Locks the running coroutine's stack into memory, called by the body when
+/// it starts.
+#[cfg(feature = "secure-stack")]
+pub(crate) fn lock<W: WakerSlot>(shared: &CoroutineShared<W>) {
+    shared.mark_stack_dirty();
+    #[cfg(unix)]
+    if let Some((start, len)) = managed_range(shared).and_then(whole_pages) {
+        // SAFETY: `mlock` only pins the pages, which lie within the stack.
+        if unsafe { libc::mlock(start, len) } != 0 {
+            crate::events::stack_lock_failed(shared);
+        }
+    }
+}
+
+/// Zeroes and unlocks the stack of a coroutine that will not run again, if
+/// the body ran on it since the last wipe.
+#[cfg(feature = "secure-stack")]
+pub(crate) fn wipe<W: WakerSlot>(shared: &CoroutineShared<W>) {
+    if !shared.take_stack_dirty() {
+        return;
+    }
+    let Some((start, end)) = managed_range(shared) else {
+        return;
+    };
+    let word = size_of::<usize>();
+    let mut addr = start.next_multiple_of(word);
+    while addr + word <= end {
+        // SAFETY: the range lies within the stack, and no frame on it is
+        // used again. The writes are volatile so they are not elided before
+        // the stack is freed.
+        unsafe { core::ptr::write_volatile(addr as *mut usize, 0) };
+        addr += word;
+    }
+    #[cfg(unix)]
+    if let Some((start, len)) = whole_pages((start, end)) {
+        // SAFETY: `munlock` only unpins the pages, which lie within the stack.
+        unsafe { libc::munlock(start, len) };
+    }
+}
+
+/// Releases the physical memory behind the unused part of a suspended
+/// coroutine's stack, returning the number of bytes released.
+///
+/// The whole pages from the part of the stack that certainly lies within
+/// the allocation up to one page below the stack pointer recorded at the
+/// last suspension are handed back to the kernel. The pages read as
+/// zeroes, and are committed again, once the coroutine reaches them.
+#[cfg(feature = "decommit")]
+pub(crate) fn decommit<W: WakerSlot>(shared: &CoroutineShared<W>) -> usize {
+    let (Some((low, _)), Some(sp)) = (managed_range(shared), shared.suspended_sp()) else {
+        return 0;
+    };
+    #[cfg(unix)]
+    {
+        let page = page_size();
+        let start = low.next_multiple_of(page);
+        let end = sp.saturating_sub(page) & !(page - 1);
+        if end <= start {
+            return 0;
+        }
+        // SAFETY: the whole pages lie within the coroutine's stack, below
+        // every live frame of the suspended coroutine, so nothing refers to
+        // them and no other allocation shares them.
+        let result =
+            unsafe { libc::madvise(start as *mut libc::c_void, end - start, libc::MADV_DONTNEED) };
+        if result == 0 { end - start } else { 0 }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = (low, sp);
+        0
+    }
+}
+
+impl<W: WakerSlot> CoroutineAwaiter<W> {
+    /// Returns the approximate number of stack bytes left to the running
+    /// coroutine, or `None` if its stack size is unknown.
+    pub fn remaining_stack(&self) -> Option<usize> {
+        self.coro.user_data().remaining_stack(stack_pointer())
+    }
+
+    /// Returns the most stack, in bytes, the running coroutine has used at any
+    /// stack check so far, or `None` before the first.
+    ///
+    /// Checks happen at every suspension point and every
+    /// [`check_stack`](Self::check_stack), so deeper frames between them go
+    /// unnoticed; treat the result as a lower bound.
+    pub fn stack_high_water(&self) -> Option<usize> {
+        self.coro.user_data().stack_high_water()
+    }
+
+    /// Panics if the caller is not running on this coroutine's stack, as when
+    /// a nested coroutine's body suspends through an outer awaiter.
+    #[track_caller]
+    pub(crate) fn assert_on_own_stack(&self) {
+        let shared = self.coro.user_data();
+        if !shared.contains_stack_pointer(stack_pointer()) {
+            panic!(
+                "{} suspended from another stack; await through the awaiter of the running coroutine",
+                shared.describe()
+            );
+        }
+    }
+
+    /// Fails the coroutine with [`StackOverflow`] if fewer than [`RED_ZONE`]
+    /// bytes of stack remain.
+    ///
+    /// Suspension points check automatically; call this from deeply
+    /// recursive code that may not suspend for a while.
+    #[track_caller]
+    pub fn check_stack(&self) {
+        let shared = self.coro.user_data();
+        let sp = stack_pointer();
+        shared.record_stack_pointer(sp);
+        match shared.remaining_stack(sp) {
+            Some(remaining) if remaining < RED_ZONE => {}
+            _ => return,
+        }
+        #[cfg(feature = "std")]
+        if std::thread::panicking() {
+            // Already unwinding; a second unwind would abort the process.
+            return;
+        }
+        shared.set_overflowed();
+        #[cfg(feature = "std")]
+        std::panic::resume_unwind(alloc::boxed::Box::new(Overflowed));
+        #[cfg(not(feature = "std"))]
+        panic!("{} overflowed its stack", shared.describe());
+    }
+}
+
+impl<T, W: WakerSlot> CoroutineFuture<T, W> {
+    /// Returns the most stack, in bytes, the coroutine has used at any stack
+    /// check so far, or `None` before the first.
+    ///
+    /// See [`CoroutineAwaiter::stack_high_water`].
+    pub fn stack_high_water(&self) -> Option<usize> {
+        self.coro.user_data().stack_high_water()
+    }
+
+    /// Resolves to `Err(StackOverflow)` instead of panicking if the coroutine
+    /// runs out of stack.
+    pub fn catch_overflow(self) -> CatchOverflow<T, W> {
+        CatchOverflow { inner: self }
+    }
+}
+
+/// A coroutine reporting stack overflows as errors, returned by
+/// [`CoroutineFuture::catch_overflow`].
+pub struct CatchOverflow<T, W: WakerSlot = AtomicWaker> {
+    inner: CoroutineFuture<T, W>,
+}
+
+impl<T, W: WakerSlot> CatchOverflow<T, W> {
+    /// The wrapped coroutine.
+    pub fn get_ref(&self) -> &CoroutineFuture<T, W> {
+        &self.inner
+    }
+}
+
+impl<T, W: WakerSlot> fmt::Debug for CatchOverflow<T, W> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CatchOverflow")
+            .field("coroutine", &self.inner)
+            .finish()
+    }
+}
+
+impl<T, W: WakerSlot> Future for CatchOverflow<T, W> {
+    type Output = Result<T, StackOverflow>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let inner = &mut self.get_mut().inner;
+        inner.register(cx.waker());
+        if !inner.coro.user_data().should_resume() {
+            return Poll::Pending;
+        }
+        match inner.resume_checked() {
+            Resumed::Yielded => Poll::Pending,
+            Resumed::Completed(output) => Poll::Ready(output),
+        }
+    }
+}
+
+/// A coroutine whose stack size, `STACK` bytes, is part of its type.
+///
+/// Sizes that leave no room beyond the [`RED_ZONE`] are rejected at compile
+/// time. The stack is still allocated from the heap when the coroutine is
+/// built, so it cannot be placed in a particular linker section.
+///
+/// # Example
+///
+/// ```ignore
+/// use minicoro_awaiters::stack::FixedStackFuture;
+///
+/// let coro = FixedStackFuture::<{ 16 << 10 }, _>::new(|awaiter| {
+///     awaiter.r#await(Box::pin(async { 42 }))
+/// });
+/// ```
+pub struct FixedStackFuture<const STACK: usize, T> {
+    inner: CoroutineFuture<T>,
+}
+
+impl<const STACK: usize, T> FixedStackFuture<STACK, T> {
+    /// The stack size of coroutines of this type, in bytes.
+    pub const STACK_SIZE: usize = {
+        assert!(STACK > RED_ZONE, "the stack size must exceed `RED_ZONE`");
+        STACK
+    };
+
+    /// Creates a coroutine with a `STACK`-byte stack.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the coroutine cannot be created; use
+    /// [`with_builder`](Self::with_builder) to handle that case.
+    #[track_caller]
+    pub fn new<F: FnOnce(CoroutineAwaiter) -> T>(f: F) -> Self {
+        Self::with_builder(CoroutineBuilder::new(), f).unwrap()
+    }
+
+    /// Creates a coroutine with the settings of `builder` and a `STACK`-byte
+    /// stack, overriding any stack size set on the builder.
+    #[track_caller]
+    pub fn with_builder<F: FnOnce(CoroutineAwaiter) -> T>(
+        builder: CoroutineBuilder,
+        f: F,
+    ) -> Result<Self, BuildError> {
+        let inner = builder.stack_size(Self::STACK_SIZE).build(f)?;
+        Ok(Self { inner })
+    }
+
+    /// The wrapped coroutine.
+    pub fn get_ref(&self) -> &CoroutineFuture<T> {
+        &self.inner
+    }
+
+    /// Unwraps the coroutine, forgetting its stack size at the type level.
+    pub fn into_inner(self) -> CoroutineFuture<T> {
+        self.inner
+    }
+}
+
+impl<const STACK: usize, T> fmt::Debug for FixedStackFuture<STACK, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FixedStackFuture")
+            .field("stack_size", &STACK)
+            .field("coroutine", &self.inner)
+            .finish()
+    }
+}
+
+impl<const STACK: usize, T> Future for FixedStackFuture<STACK, T> {
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T> {
+        Pin::new(&mut self.get_mut().inner).poll(cx)
+    }
+}
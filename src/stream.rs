@@ -0,0 +1,90 @@
+//! Consuming async streams from inside a coroutine.
+//!
+//! [`BlockingStream`] turns a `futures_core::Stream` into a plain
+//! [`Iterator`] whose `next` blocks the coroutine until the stream yields,
+//! and [`CoroutineAwaiter::collect_stream`] drains a stream into any
+//! [`FromIterator`] collection with it, and [`CoroutineAwaiter::recv`] takes
+//! a single item, as from a channel receiver. Every item is a separate await, so
+//! long streams of ready items still yield to the executor once the
+//! coroutine's [budget](crate::coop) runs out.
+
+use core::fmt;
+use core::future::poll_fn;
+use core::pin::Pin;
+use core::pin::pin;
+
+use awaiter_trait::Awaiter;
+use futures_core::Stream;
+
+use crate::CoroutineAwaiter;
+use crate::waker::WakerSlot;
+
+/// A blocking iterator over the items of a stream.
+pub struct BlockingStream<'a, A: ?Sized, S> {
+    awaiter: &'a A,
+    stream: Pin<&'a mut S>,
+}
+
+impl<'a, A: Awaiter + ?Sized, S: Stream> BlockingStream<'a, A, S> {
+    /// Iterates over `stream`, blocking on each item through `awaiter`.
+    pub fn new(awaiter: &'a A, stream: Pin<&'a mut S>) -> Self {
+        Self { awaiter, stream }
+    }
+}
+
+impl<A: Awaiter + ?Sized, S: Stream> Iterator for BlockingStream<'_, A, S> {
+    type Item = S::Item;
+
+    fn next(&mut self) -> Option<S::Item> {
+        let stream = &mut self.stream;
+        self.awaiter
+            .r#await(pin!(poll_fn(|cx| stream.as_mut().poll_next(cx))))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.stream.size_hint()
+    }
+}
+
+impl<A: ?Sized, S> fmt::Debug for BlockingStream<'_, A, S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BlockingStream").finish_non_exhaustive()
+    }
+}
+
+impl<W: WakerSlot> CoroutineAwaiter<W> {
+    /// Blocks the coroutine until `stream` yields its next item, returning
+    /// `None` once it has ended.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// use minicoro_awaiters::CoroutineFuture;
+    ///
+    /// let coro = CoroutineFuture::new(move |awaiter| {
+    ///     while let Some(job) = awaiter.recv(&mut jobs_rx) {
+    ///         run(job);
+    ///     }
+    /// });
+    /// ```
+    #[track_caller]
+    pub fn recv<S: Stream + Unpin + ?Sized>(&self, stream: &mut S) -> Option<S::Item> {
+        self.r#await(pin!(poll_fn(|cx| Pin::new(&mut *stream).poll_next(cx))))
+    }
+
+    /// Drains `stream` into a collection, blocking the coroutine between items.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// use minicoro_awaiters::CoroutineFuture;
+    ///
+    /// let coro = CoroutineFuture::new(|awaiter| {
+    ///     let lines: Vec<String> = awaiter.collect_stream(reader.lines());
+    ///     lines.len()
+    /// });
+    /// ```
+    pub fn collect_stream<C: FromIterator<S::Item>, S: Stream>(&self, stream: S) -> C {
+        BlockingStream::new(self, pin!(stream)).collect()
+    }
+}
@@ -0,0 +1,104 @@
+//! Synchronization primitives used by the waker handoff.
+//!
+//! Under `cfg(loom)` these are swapped for loom's instrumented versions, so the
+//! handoff between wakers and the outer future can be model checked with
+//! `RUSTFLAGS="--cfg loom" cargo test --release --lib`.
+//!
+//! With the `portable-atomic` feature the atomics and [`Arc`] come from
+//! `portable-atomic` and `portable-atomic-util` instead, for targets without
+//! native compare-and-swap. Public signatures taking an `Arc`, such as
+//! [`CoroutineBuilder::shared_hooks`](crate::CoroutineBuilder::shared_hooks),
+//! then take `portable_atomic_util::Arc`.
+
+#[cfg(not(loom))]
+pub use atomic_waker::AtomicWaker;
+#[cfg(all(not(loom), not(feature = "portable-atomic")))]
+pub(crate) use core::sync::atomic::AtomicBool;
+#[cfg(all(not(loom), not(feature = "portable-atomic")))]
+pub(crate) use core::sync::atomic::AtomicPtr;
+#[cfg(all(not(loom), not(feature = "portable-atomic")))]
+pub(crate) use core::sync::atomic::AtomicU32;
+#[cfg(all(not(loom), not(feature = "portable-atomic")))]
+pub(crate) use core::sync::atomic::AtomicUsize;
+#[cfg(all(not(loom), not(feature = "portable-atomic")))]
+pub(crate) use core::sync::atomic::Ordering;
+#[cfg(all(not(loom), not(feature = "portable-atomic")))]
+pub(crate) use core::sync::atomic::fence;
+
+#[cfg(all(not(loom), feature = "portable-atomic"))]
+pub(crate) use portable_atomic::AtomicBool;
+#[cfg(all(not(loom), feature = "portable-atomic"))]
+pub(crate) use portable_atomic::AtomicPtr;
+#[cfg(all(not(loom), feature = "portable-atomic"))]
+pub(crate) use portable_atomic::AtomicU32;
+#[cfg(all(not(loom), feature = "portable-atomic"))]
+pub(crate) use portable_atomic::AtomicUsize;
+#[cfg(all(not(loom), feature = "portable-atomic"))]
+pub(crate) use portable_atomic::Ordering;
+#[cfg(all(not(loom), feature = "portable-atomic"))]
+pub(crate) use portable_atomic::fence;
+
+#[cfg(not(feature = "portable-atomic"))]
+pub(crate) use alloc::sync::Arc;
+#[cfg(feature = "portable-atomic")]
+pub(crate) use portable_atomic_util::Arc;
+
+/// Creates an `Arc<$ty>` from `$value`.
+///
+/// `portable_atomic_util::Arc` cannot unsize on stable, so with
+/// `portable-atomic` the value goes through a `Box` first.
+macro_rules! dyn_arc {
+    ($value:expr => $ty:ty) => {{
+        #[cfg(not(feature = "portable-atomic"))]
+        let arc: $crate::sync::Arc<$ty> = $crate::sync::Arc::new($value);
+        #[cfg(feature = "portable-atomic")]
+        let arc = $crate::sync::Arc::<$ty>::from(alloc::boxed::Box::new($value) as alloc::boxed::Box<$ty>);
+        arc
+    }};
+}
+pub(crate) use dyn_arc;
+
+/// Creates an `Arc<T>`, failing instead of aborting if the allocation does.
+///
+/// Only `nightly` builds on the standard `Arc` can detect the failure; the
+/// others allocate infallibly.
+pub(crate) fn try_arc<T>(value: T) -> Result<Arc<T>, ()> {
+    #[cfg(all(feature = "nightly", not(feature = "portable-atomic")))]
+    return Arc::try_new(value).map_err(drop);
+    #[cfg(not(all(feature = "nightly", not(feature = "portable-atomic"))))]
+    Ok(Arc::new(value))
+}
+
+#[cfg(loom)]
+pub(crate) use loom::sync::atomic::AtomicBool;
+#[cfg(loom)]
+pub(crate) use loom::sync::atomic::AtomicPtr;
+#[cfg(loom)]
+pub(crate) use loom::sync::atomic::AtomicU32;
+#[cfg(loom)]
+pub(crate) use loom::sync::atomic::AtomicUsize;
+#[cfg(loom)]
+pub(crate) use loom::sync::atomic::Ordering;
+#[cfg(loom)]
+pub(crate) use loom::sync::atomic::fence;
+
+/// loom's `AtomicWaker`, with the registration signature of `atomic_waker`'s.
+///
+/// Public because it is the default [`WakerSlot`](crate::waker::WakerSlot).
+#[cfg(loom)]
+pub struct AtomicWaker(loom::future::AtomicWaker);
+
+#[cfg(loom)]
+impl AtomicWaker {
+    pub(crate) fn new() -> Self {
+        Self(loom::future::AtomicWaker::new())
+    }
+
+    pub(crate) fn register(&self, waker: &core::task::Waker) {
+        self.0.register_by_ref(waker);
+    }
+
+    pub(crate) fn wake(&self) {
+        self.0.wake();
+    }
+}
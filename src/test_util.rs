@@ -0,0 +1,322 @@
+//! Testing blocking-style code without coroutines or a runtime.
+//!
+//! Code written against [`awaiter_trait::Awaiter`] can be unit-tested with a
+//! [`MockAwaiter`] instead of a real coroutine: it polls each awaited future
+//! inline, on the test's own stack, until it completes. Time is virtual. A
+//! [`MockClock`] only moves when told to, or when every awaited future is
+//! pending on one of its timers, in which case the awaiter jumps straight to
+//! the earliest deadline, so tests of timeouts and retries run instantly and
+//! always the same way. A future that is pending with neither a wakeup nor a
+//! timer left can never complete, and the awaiter panics instead of hanging.
+//!
+//! [`Scripted`] futures play back a fixed sequence of `Poll::Pending` and
+//! `Poll::Ready` results, to exercise how code under test reacts to futures
+//! that take a given number of polls.
+//!
+//! # Example
+//!
+//! ```ignore
+//! use core::task::Poll;
+//! use minicoro_awaiters::test_util::{MockAwaiter, Scripted};
+//!
+//! let awaiter = MockAwaiter::new();
+//! let reply = retry(&awaiter, || Scripted::new([Poll::Pending, Poll::Ready(Ok(7))]));
+//! assert_eq!(reply, Ok(7));
+//! assert_eq!(awaiter.clock().now(), Duration::ZERO);
+//! ```
+
+use core::cell::Cell;
+use core::fmt;
+use core::future::Future;
+use core::pin::Pin;
+use core::task::Context;
+use core::task::Poll;
+use core::task::Waker;
+use core::time::Duration;
+
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::task::Wake;
+
+use awaiter_trait::Awaiter;
+
+use crate::time::Sleep;
+use crate::time::Sleeper;
+
+/// The state behind every handle to a [`MockClock`].
+#[derive(Default)]
+struct ClockState {
+    now: Duration,
+    /// Pending timers: their deadlines and the wakers of their last polls.
+    timers: Vec<(Duration, Waker)>,
+}
+
+/// A virtual clock for [`MockAwaiter`] and [`MockSleep`] timers.
+///
+/// Clones share the same time. The clock is also a [`Sleeper`], so
+/// coroutines built with
+/// [`CoroutineBuilder::sleeper`](crate::CoroutineBuilder::sleeper) can use
+/// virtual time under a real executor, advanced by the test.
+#[derive(Clone, Default)]
+pub struct MockClock {
+    state: Arc<Mutex<ClockState>>,
+}
+
+impl MockClock {
+    /// Creates a clock at time zero.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, ClockState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// The virtual time elapsed since the clock was created.
+    pub fn now(&self) -> Duration {
+        self.lock().now
+    }
+
+    /// Moves the clock forward by `duration`, waking the timers that expire.
+    pub fn advance(&self, duration: Duration) {
+        let now = self.lock().now + duration;
+        self.advance_to(now);
+    }
+
+    /// Moves the clock to the earliest pending deadline, returning `false`
+    /// if no timer is pending.
+    fn advance_to_next(&self) -> bool {
+        let next = self.lock().timers.iter().map(|(deadline, _)| *deadline).min();
+        match next {
+            Some(deadline) => {
+                self.advance_to(deadline);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn advance_to(&self, now: Duration) {
+        let expired: Vec<Waker> = {
+            let mut state = self.lock();
+            state.now = state.now.max(now);
+            let now = state.now;
+            let (expired, pending) = core::mem::take(&mut state.timers)
+                .into_iter()
+                .partition(|(deadline, _)| *deadline <= now);
+            state.timers = pending;
+            expired.into_iter().map(|(_, waker)| waker).collect()
+        };
+        // Woken outside the lock, which the timers take when polled again.
+        for waker in expired {
+            waker.wake();
+        }
+    }
+
+    /// Returns a timer that completes once the clock has advanced by `duration`.
+    pub fn sleep(&self, duration: Duration) -> MockSleep {
+        MockSleep {
+            clock: self.clone(),
+            deadline: self.now() + duration,
+        }
+    }
+}
+
+impl Sleeper for MockClock {
+    fn sleep(&self, duration: Duration) -> Sleep {
+        Box::pin(MockClock::sleep(self, duration))
+    }
+}
+
+impl fmt::Debug for MockClock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = self.lock();
+        f.debug_struct("MockClock")
+            .field("now", &state.now)
+            .field("timers", &state.timers.len())
+            .finish()
+    }
+}
+
+/// A timer on a [`MockClock`], returned by [`MockClock::sleep`].
+#[derive(Debug)]
+pub struct MockSleep {
+    clock: MockClock,
+    deadline: Duration,
+}
+
+impl MockSleep {
+    /// The virtual time at which the timer completes.
+    pub fn deadline(&self) -> Duration {
+        self.deadline
+    }
+}
+
+impl Future for MockSleep {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let mut state = self.clock.lock();
+        if state.now >= self.deadline {
+            return Poll::Ready(());
+        }
+        state.timers.push((self.deadline, cx.waker().clone()));
+        Poll::Pending
+    }
+}
+
+/// Records that a waker was woken.
+struct Flag(AtomicBool);
+
+impl Wake for Flag {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.0.store(true, Ordering::Release);
+    }
+}
+
+/// An [`Awaiter`] that polls awaited futures inline against a [`MockClock`].
+///
+/// # Panics
+///
+/// Awaiting panics if the future is pending without having been woken and
+/// no timer of the clock is pending either. Wakeups from other threads are
+/// not waited for.
+#[derive(Default)]
+pub struct MockAwaiter {
+    clock: MockClock,
+    polls: Cell<usize>,
+}
+
+impl MockAwaiter {
+    /// Creates an awaiter with a clock of its own.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates an awaiter using `clock`, for tests that share it.
+    pub fn with_clock(clock: MockClock) -> Self {
+        Self {
+            clock,
+            polls: Cell::new(0),
+        }
+    }
+
+    /// The awaiter's clock.
+    pub fn clock(&self) -> &MockClock {
+        &self.clock
+    }
+
+    /// Returns a timer on the awaiter's clock; see [`MockClock::sleep`].
+    pub fn sleep(&self, duration: Duration) -> MockSleep {
+        self.clock.sleep(duration)
+    }
+
+    /// Number of times awaited futures have been polled.
+    pub fn polls(&self) -> usize {
+        self.polls.get()
+    }
+}
+
+impl Awaiter for MockAwaiter {
+    fn r#await<T>(&self, mut f: Pin<&mut (dyn Future<Output = T> + '_)>) -> T {
+        let flag = Arc::new(Flag(AtomicBool::new(false)));
+        let waker = Waker::from(flag.clone());
+        let mut cx = Context::from_waker(&waker);
+        loop {
+            self.polls.set(self.polls.get() + 1);
+            if let Poll::Ready(output) = f.as_mut().poll(&mut cx) {
+                return output;
+            }
+            if flag.0.swap(false, Ordering::Acquire) {
+                continue;
+            }
+            if !self.clock.advance_to_next() {
+                panic!(
+                    "MockAwaiter: the awaited future is pending with no wakeup or timer left, so it can never complete"
+                );
+            }
+        }
+    }
+}
+
+impl fmt::Debug for MockAwaiter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MockAwaiter")
+            .field("clock", &self.clock)
+            .field("polls", &self.polls())
+            .finish()
+    }
+}
+
+awaiter_trait::autoimpl!(<> MockAwaiter as Awaiter);
+
+/// A future playing back a fixed sequence of poll results.
+///
+/// Each poll takes the next step. `Poll::Pending` steps wake the task right
+/// away, so any executor, or a [`MockAwaiter`], polls again.
+///
+/// # Panics
+///
+/// Polling panics once the steps run out without a `Poll::Ready`.
+pub struct Scripted<T> {
+    steps: VecDeque<Poll<T>>,
+    polls: usize,
+}
+
+impl<T> Scripted<T> {
+    /// Creates a future taking its poll results from `steps`.
+    pub fn new(steps: impl IntoIterator<Item = Poll<T>>) -> Self {
+        Self {
+            steps: steps.into_iter().collect(),
+            polls: 0,
+        }
+    }
+
+    /// A future that is pending `n` times before resolving to `value`.
+    pub fn ready_after(n: usize, value: T) -> Self {
+        Self::new((0..n).map(|_| Poll::Pending).chain([Poll::Ready(value)]))
+    }
+
+    /// Number of times the future has been polled.
+    pub fn polls(&self) -> usize {
+        self.polls
+    }
+}
+
+impl<T> Future for Scripted<T> {
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T> {
+        let this = self.get_mut();
+        this.polls += 1;
+        match this.steps.pop_front() {
+            Some(Poll::Ready(value)) => Poll::Ready(value),
+            Some(Poll::Pending) => {
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            }
+            None => panic!("`Scripted` future polled after its last step"),
+        }
+    }
+}
+
+// Scripted futures never pin their steps; they are only ever moved out.
+impl<T> Unpin for Scripted<T> {}
+
+impl<T> fmt::Debug for Scripted<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Scripted")
+            .field("remaining", &self.steps.len())
+            .field("polls", &self.polls)
+            .finish()
+    }
+}
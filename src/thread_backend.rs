@@ -0,0 +1,219 @@
+//! An OS-thread stand-in for minicoro, for running under Miri.
+//!
+//! Miri cannot execute minicoro's stack switches, so code using coroutines
+//! cannot be tested under it. With the `thread-backend` feature the crate
+//! runs every coroutine on a dedicated OS thread instead: resuming hands
+//! control to that thread and blocks until the body yields or returns, and
+//! yielding does the reverse, so exactly one side runs at any time and the
+//! body observes the same sequence of resumes and suspensions as on a
+//! minicoro stack. [`Coroutine`] and [`CoroutineRef`] mirror the parts of
+//! minicoroutine's API the crate uses, and take the place of minicoroutine's
+//! types in public signatures such as
+//! [`CoroutineFuture::coro`](crate::CoroutineFuture::coro).
+//!
+//! ```toml
+//! [dev-dependencies]
+//! minicoro-awaiters = { version = "0.2.0-alpha.1", features = ["thread-backend"] }
+//! ```
+//!
+//! The emulation is for tests only. The body runs on another thread than
+//! the future polling it, so thread-locals set by the poller are not
+//! visible to it, and futures that require the polling thread's runtime
+//! context, such as tokio's timers and sockets, fail when polled from the
+//! body. A coroutine dropped while suspended, which minicoro would simply
+//! free, leaves its thread parked forever; the crate's own futures abort
+//! suspended bodies when dropped, so this only happens to coroutines used
+//! directly, and Miri then needs `-Zmiri-ignore-leaks`.
+
+use core::marker::PhantomData;
+
+use std::io;
+use std::sync::Arc;
+use std::sync::Condvar;
+use std::sync::Mutex;
+
+/// The allocator parameter of [`Coroutine`], standing in for minicoroutine's.
+#[allow(non_camel_case_types)]
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug, Hash, Default)]
+pub struct GLOBAL;
+
+/// The smallest thread stack the emulation asks for.
+const MIN_STACK_SIZE: usize = 64 * 1024;
+
+/// Which side of a coroutine runs.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Turn {
+    Caller,
+    Body,
+    Finished,
+}
+
+/// State shared between a [`Coroutine`] and the thread running its body.
+struct Inner<U> {
+    user_data: U,
+    turn: Mutex<Turn>,
+    changed: Condvar,
+}
+
+impl<U> Inner<U> {
+    fn pass(&self, to: Turn) {
+        *self.turn.lock().unwrap_or_else(|e| e.into_inner()) = to;
+        self.changed.notify_all();
+    }
+
+    /// Blocks until the turn is no longer `from`, returning the new turn.
+    fn wait_while(&self, from: Turn) -> Turn {
+        let turn = self.turn.lock().unwrap_or_else(|e| e.into_inner());
+        *self
+            .changed
+            .wait_while(turn, |turn| *turn == from)
+            .unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Moves a value to the body's thread.
+struct AssertSend<T>(T);
+
+// SAFETY: the handoff lets only one thread run at a time, and every switch
+// synchronizes through the mutex, so the captured values are never accessed
+// concurrently.
+unsafe impl<T> Send for AssertSend<T> {}
+
+/// Hands the turn back to the caller even if the body unwinds.
+struct FinishGuard<U>(AssertSend<Arc<Inner<U>>>);
+
+impl<U> Drop for FinishGuard<U> {
+    fn drop(&mut self) {
+        self.0.0.pass(Turn::Finished);
+    }
+}
+
+/// A coroutine emulated on an OS thread.
+///
+/// Only unit resume, yield and return values are passed through, which is
+/// all the crate uses.
+pub struct Coroutine<I, O, R, U, A> {
+    inner: Arc<Inner<U>>,
+    /// The body's thread has been given its first turn.
+    started: bool,
+    _marker: PhantomData<(*const (), fn(I) -> (O, R), A)>,
+}
+
+impl<I, O, R, U, A> Coroutine<I, O, R, U, A> {
+    /// Creates a coroutine running `f`, with a thread stack of the default size.
+    pub fn new<F>(f: F, user_data: U) -> io::Result<Self>
+    where
+        F: FnOnce(CoroutineRef<I, O, R, U, A>) -> R,
+    {
+        Self::with_stack_size(f, user_data, crate::stack::DEFAULT_STACK_SIZE)
+    }
+
+    /// Creates a coroutine running `f` on a thread with a `size`-byte stack.
+    pub fn with_stack_size<F>(f: F, user_data: U, size: usize) -> io::Result<Self>
+    where
+        F: FnOnce(CoroutineRef<I, O, R, U, A>) -> R,
+    {
+        let inner = Arc::new(Inner {
+            user_data,
+            turn: Mutex::new(Turn::Caller),
+            changed: Condvar::new(),
+        });
+        let body = AssertSend((f, inner.clone()));
+        let thread = std::thread::Builder::new()
+            .name("minicoro-awaiters coroutine".into())
+            .stack_size(size.max(MIN_STACK_SIZE));
+        // SAFETY: the thread only runs while the caller is blocked in
+        // `resume`, so borrows captured by `f` are never used concurrently,
+        // and the crate never lets them dangle while the body can run.
+        let handle = unsafe {
+            thread.spawn_unchecked(move || {
+                let AssertSend((f, inner)) = body;
+                if inner.wait_while(Turn::Caller) == Turn::Finished {
+                    // Dropped before the first resume.
+                    return;
+                }
+                let coro = CoroutineRef {
+                    inner: Arc::as_ptr(&inner),
+                    _marker: PhantomData,
+                };
+                let _finish = FinishGuard(AssertSend(inner));
+                drop(f(coro));
+            })
+        }?;
+        drop(handle);
+        Ok(Self {
+            inner,
+            started: false,
+            _marker: PhantomData,
+        })
+    }
+
+    /// Runs the body until it yields, returning `Some`, or returns, returning
+    /// `None`.
+    pub fn resume(&mut self, _input: I) -> Option<O>
+    where
+        O: Default,
+    {
+        if *self.inner.turn.lock().unwrap_or_else(|e| e.into_inner()) == Turn::Finished {
+            return None;
+        }
+        self.started = true;
+        self.inner.pass(Turn::Body);
+        match self.inner.wait_while(Turn::Body) {
+            Turn::Finished => None,
+            _ => Some(O::default()),
+        }
+    }
+
+    /// The user data the coroutine was created with.
+    pub fn user_data(&self) -> &U {
+        &self.inner.user_data
+    }
+}
+
+impl<I, O, R, U, A> Drop for Coroutine<I, O, R, U, A> {
+    fn drop(&mut self) {
+        // Lets a thread that was never resumed exit without running the
+        // body. A suspended body has no way out and stays parked.
+        if !self.started {
+            self.inner.pass(Turn::Finished);
+        }
+    }
+}
+
+/// The handle a coroutine body uses to yield, emulated on an OS thread.
+pub struct CoroutineRef<I, O, R, U, A> {
+    inner: *const Inner<U>,
+    _marker: PhantomData<(fn(I) -> (O, R), A)>,
+}
+
+impl<I, O, R, U, A> CoroutineRef<I, O, R, U, A> {
+    /// Suspends the body until the next resume.
+    pub fn yield_(&self, _output: O) -> I
+    where
+        I: Default,
+    {
+        let inner = self.inner();
+        inner.pass(Turn::Caller);
+        inner.wait_while(Turn::Caller);
+        I::default()
+    }
+
+    /// The user data the coroutine was created with.
+    pub fn user_data(&self) -> &U {
+        &self.inner().user_data
+    }
+
+    fn inner(&self) -> &Inner<U> {
+        // SAFETY: the body's thread keeps the state alive while the body runs.
+        unsafe { &*self.inner }
+    }
+}
+
+impl<I, O, R, U, A> Clone for CoroutineRef<I, O, R, U, A> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<I, O, R, U, A> Copy for CoroutineRef<I, O, R, U, A> {}
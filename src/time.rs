@@ -0,0 +1,166 @@
+//! Timeouts for awaits inside coroutines.
+//!
+//! The crate has no timer of its own. Register a [`Sleeper`] for the runtime
+//! you use with [`CoroutineBuilder::sleeper`](crate::CoroutineBuilder::sleeper),
+//! and blocking-style code can bound any await with
+//! [`CoroutineAwaiter::await_timeout`], while async code can bound a whole
+//! coroutine with [`CoroutineFuture::timeout`]. [`TokioSleeper`] is provided
+//! with the `tokio` feature.
+
+use core::fmt;
+use core::future::Future;
+use core::future::IntoFuture;
+use core::future::poll_fn;
+use core::pin::Pin;
+use core::pin::pin;
+use core::task::Context;
+use core::task::Poll;
+use core::time::Duration;
+
+use alloc::boxed::Box;
+
+use awaiter_trait::Awaiter;
+
+use crate::CoroutineAwaiter;
+use crate::CoroutineFuture;
+use crate::sync::AtomicWaker;
+use crate::waker::WakerSlot;
+
+/// A timer future returned by a [`Sleeper`].
+pub type Sleep = Pin<Box<dyn Future<Output = ()> + 'static>>;
+
+/// Creates timers for the runtime driving a coroutine.
+pub trait Sleeper: Send + Sync {
+    /// Returns a future that completes once `duration` has elapsed.
+    fn sleep(&self, duration: Duration) -> Sleep;
+}
+
+/// Error returned when an await does not complete within its timeout.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug, Hash, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Elapsed;
+
+impl fmt::Display for Elapsed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("deadline has elapsed")
+    }
+}
+
+impl core::error::Error for Elapsed {}
+
+impl<W: WakerSlot> CoroutineAwaiter<W> {
+    /// Blocks on `future` for at most `duration`.
+    ///
+    /// `future` is polled before the timer, so one that is ready in time is
+    /// never reported as elapsed. On timeout it is dropped unfinished.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the coroutine was built without a sleeper.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// use core::time::Duration;
+    /// use minicoro_awaiters::{CoroutineFuture, time::TokioSleeper};
+    ///
+    /// let coro = CoroutineFuture::builder()
+    ///     .sleeper(TokioSleeper)
+    ///     .build(|awaiter| {
+    ///         awaiter.await_timeout(fetch(), Duration::from_secs(5))
+    ///     })?;
+    /// ```
+    #[track_caller]
+    pub fn await_timeout<F: IntoFuture>(
+        &self,
+        future: F,
+        duration: Duration,
+    ) -> Result<F::Output, Elapsed> {
+        let shared = self.coro.user_data();
+        let sleeper = shared
+            .sleeper()
+            .unwrap_or_else(|| panic!("{} has no sleeper", shared.describe()));
+        let mut sleep = sleeper.sleep(duration);
+        crate::events::allocated(shared, size_of_val(&*sleep));
+        let mut future = pin!(future.into_future());
+        self.r#await(pin!(poll_fn(|cx| {
+            if let Poll::Ready(output) = future.as_mut().poll(cx) {
+                return Poll::Ready(Ok(output));
+            }
+            sleep.as_mut().poll(cx).map(|()| Err(Elapsed))
+        })))
+    }
+}
+
+impl<T, W: WakerSlot> CoroutineFuture<T, W> {
+    /// Bounds the coroutine to `duration`, [aborting](Self::abort) it if it
+    /// has not completed by then.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the coroutine was built without a sleeper.
+    pub fn timeout(self, duration: Duration) -> Timeout<T, W> {
+        let shared = self.coro.user_data();
+        let sleeper = shared
+            .sleeper()
+            .unwrap_or_else(|| panic!("{} has no sleeper", shared.describe()));
+        Timeout {
+            sleep: sleeper.sleep(duration),
+            inner: self,
+        }
+    }
+}
+
+/// A coroutine bounded by a timeout, returned by [`CoroutineFuture::timeout`].
+pub struct Timeout<T, W: WakerSlot = AtomicWaker> {
+    inner: CoroutineFuture<T, W>,
+    sleep: Sleep,
+}
+
+impl<T, W: WakerSlot> Timeout<T, W> {
+    /// The bounded coroutine.
+    pub fn get_ref(&self) -> &CoroutineFuture<T, W> {
+        &self.inner
+    }
+}
+
+impl<T, W: WakerSlot> Future for Timeout<T, W> {
+    type Output = Result<T, Elapsed>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        if let Poll::Ready(output) = Pin::new(&mut this.inner).poll(cx) {
+            return Poll::Ready(Ok(output));
+        }
+        match this.sleep.as_mut().poll(cx) {
+            Poll::Ready(()) => {
+                this.inner.abort();
+                Poll::Ready(Err(Elapsed))
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl<T, W: WakerSlot> fmt::Debug for Timeout<T, W> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Timeout")
+            .field("coroutine", &self.inner)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Sleeps with `tokio::time::sleep`.
+///
+/// The coroutine must be polled from within a tokio runtime with the time
+/// driver enabled.
+#[cfg(feature = "tokio")]
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug, Hash, Default)]
+pub struct TokioSleeper;
+
+#[cfg(feature = "tokio")]
+impl Sleeper for TokioSleeper {
+    fn sleep(&self, duration: Duration) -> Sleep {
+        Box::pin(tokio::time::sleep(duration))
+    }
+}
@@ -0,0 +1,214 @@
+//! Waiting for a coroutine from threads without an executor.
+//!
+//! [`CoroutineFuture::sync_join_handle`] splits a coroutine into a
+//! [`Joinable`] future, which the async side drives as usual, and a
+//! [`SyncJoinHandle`] that can be sent to any thread. [`SyncJoinHandle::wait`]
+//! parks that thread until the coroutine has finished and returns its output,
+//! so code behind a legacy thread boundary can use the results of coroutines
+//! without running an executor of its own.
+//!
+//! A panic of the body is resumed on the waiting thread, with its original
+//! payload; every other failure, including the [`Joinable`] being dropped
+//! before the coroutine finished, panics there with its message.
+//! [`SyncJoinHandle::try_wait`] reports them as a [`CoroutineError`] instead.
+
+use core::fmt;
+use core::future::Future;
+use core::pin::Pin;
+use core::task::Context;
+use core::task::Poll;
+use core::time::Duration;
+
+use std::sync::Condvar;
+use std::sync::Mutex;
+use std::sync::MutexGuard;
+use std::time::Instant;
+
+use crate::CoroutineFuture;
+use crate::error::CatchErrors;
+use crate::error::CoroutineError;
+use crate::sync::Arc;
+use crate::sync::AtomicWaker;
+use crate::waker::WakerSlot;
+
+/// The result handoff between a [`Joinable`] and its [`SyncJoinHandle`].
+struct Slot<T> {
+    result: Mutex<Option<Result<T, CoroutineError>>>,
+    finished: Condvar,
+}
+
+impl<T> Slot<T> {
+    fn lock(&self) -> MutexGuard<'_, Option<Result<T, CoroutineError>>> {
+        self.result.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Stores the result, unless one was stored already, and wakes the waiter.
+    fn complete(&self, result: Result<T, CoroutineError>) {
+        let mut slot = self.lock();
+        if slot.is_none() {
+            *slot = Some(result);
+        }
+        drop(slot);
+        self.finished.notify_all();
+    }
+}
+
+impl<T, W: WakerSlot> CoroutineFuture<T, W> {
+    /// Splits the coroutine into a future to drive it and a handle plain
+    /// threads can wait on for its output.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// use minicoro_awaiters::CoroutineFuture;
+    ///
+    /// let coro = CoroutineFuture::new(|awaiter| fetch(&awaiter));
+    /// let (joinable, handle) = coro.sync_join_handle();
+    /// std::thread::spawn(move || legacy::consume(handle.wait()));
+    /// joinable.await;
+    /// ```
+    pub fn sync_join_handle(self) -> (Joinable<T, W>, SyncJoinHandle<T>) {
+        let slot = Arc::new(Slot {
+            result: Mutex::new(None),
+            finished: Condvar::new(),
+        });
+        let joinable = Joinable {
+            inner: self.catch_errors(),
+            slot: slot.clone(),
+        };
+        (joinable, SyncJoinHandle { slot })
+    }
+}
+
+/// A coroutine publishing its result to a [`SyncJoinHandle`], returned by
+/// [`CoroutineFuture::sync_join_handle`].
+///
+/// It resolves once the coroutine has finished, however it finished; the
+/// result itself goes to the handle.
+pub struct Joinable<T, W: WakerSlot = AtomicWaker> {
+    inner: CatchErrors<T, W>,
+    slot: Arc<Slot<T>>,
+}
+
+impl<T, W: WakerSlot> Joinable<T, W> {
+    /// The wrapped coroutine.
+    pub fn get_ref(&self) -> &CoroutineFuture<T, W> {
+        self.inner.get_ref()
+    }
+}
+
+impl<T, W: WakerSlot> Future for Joinable<T, W> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let this = self.get_mut();
+        match Pin::new(&mut this.inner).poll(cx) {
+            Poll::Ready(result) => {
+                this.slot.complete(result);
+                Poll::Ready(())
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl<T, W: WakerSlot> fmt::Debug for Joinable<T, W> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Joinable")
+            .field("coroutine", self.inner.get_ref())
+            .finish_non_exhaustive()
+    }
+}
+
+// The future never pins its fields.
+impl<T, W: WakerSlot> Unpin for Joinable<T, W> {}
+
+impl<T, W: WakerSlot> Drop for Joinable<T, W> {
+    fn drop(&mut self) {
+        // Unwind the body first, so the waiter is released only once it has stopped.
+        self.inner.get_mut().abort_for_drop();
+        self.slot.complete(Err(CoroutineError::Aborted));
+    }
+}
+
+/// A handle a thread can block on until a coroutine driven elsewhere
+/// finishes, returned by [`CoroutineFuture::sync_join_handle`].
+///
+/// It is `Send` whenever the output is, and never needs an executor.
+pub struct SyncJoinHandle<T> {
+    slot: Arc<Slot<T>>,
+}
+
+impl<T> SyncJoinHandle<T> {
+    /// Returns `true` once the coroutine has finished or its [`Joinable`]
+    /// was dropped.
+    pub fn is_finished(&self) -> bool {
+        self.slot.lock().is_some()
+    }
+
+    /// Parks the current thread until the coroutine has finished and
+    /// returns its output.
+    ///
+    /// This must not be called from the thread driving the [`Joinable`],
+    /// which would never finish.
+    ///
+    /// # Panics
+    ///
+    /// Resumes the panic of the body, and panics if the coroutine failed
+    /// any other way; use [`try_wait`](Self::try_wait) to handle that case.
+    pub fn wait(self) -> T {
+        match self.try_wait() {
+            Ok(output) => output,
+            Err(error) => {
+                let error = error.resume_panic();
+                panic!("{error}")
+            }
+        }
+    }
+
+    /// Like [`wait`](Self::wait), but reports failures as a
+    /// [`CoroutineError`].
+    pub fn try_wait(self) -> Result<T, CoroutineError> {
+        let mut slot = self.slot.lock();
+        loop {
+            if let Some(result) = slot.take() {
+                return result;
+            }
+            slot = self
+                .slot
+                .finished
+                .wait(slot)
+                .unwrap_or_else(|e| e.into_inner());
+        }
+    }
+
+    /// Like [`try_wait`](Self::try_wait), but gives up after `timeout`,
+    /// handing the handle back.
+    pub fn wait_timeout(self, timeout: Duration) -> Result<Result<T, CoroutineError>, Self> {
+        let deadline = Instant::now() + timeout;
+        let mut slot = self.slot.lock();
+        loop {
+            if let Some(result) = slot.take() {
+                return Ok(result);
+            }
+            let Some(left) = deadline.checked_duration_since(Instant::now()) else {
+                drop(slot);
+                return Err(self);
+            };
+            slot = self
+                .slot
+                .finished
+                .wait_timeout(slot, left)
+                .unwrap_or_else(|e| e.into_inner())
+                .0;
+        }
+    }
+}
+
+impl<T> fmt::Debug for SyncJoinHandle<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SyncJoinHandle")
+            .field("finished", &self.is_finished())
+            .finish_non_exhaustive()
+    }
+}
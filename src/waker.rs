@@ -0,0 +1,897 @@
+//! The waker handed to futures awaited inside a coroutine.
+//!
+//! Every coroutine owns a reference-counted [`CoroutineShared`] holding the
+//! executor's waker. Futures awaited inside the coroutine are polled with a
+//! waker that points straight at that allocation, so awaiting never clones
+//! the executor's waker and waking is a single [`WakerSlot::wake`].
+//! The awaiter never takes the executor's waker out of the slot either: the
+//! waker it polls with only borrows the coroutine's reference, so it costs
+//! nothing to build, stays valid for every await of a resume and never
+//! forces a yield just to obtain a waker.
+//!
+//! The slot holding the executor's waker is pluggable: [`CoroutineShared`] and
+//! the types built on it take a [`WakerSlot`] parameter that defaults to
+//! [`AtomicWaker`](atomic_waker::AtomicWaker). Build coroutines with a
+//! different slot through
+//! [`CoroutineBuilder::build_with_slot`](crate::CoroutineBuilder::build_with_slot).
+//!
+//! ## Waking from interrupts
+//!
+//! Waking a coroutine is bounded and never allocates: a few atomic stores,
+//! then [`WakerSlot::wake`], which with the default slot is a lock-free take
+//! followed by the executor's own wake. Interrupt handlers should wake by
+//! reference ([`Waker::wake_by_ref`]), since waking by value may drop the
+//! last reference to the coroutine and free its state inside the handler.
+//! Whether the executor's waker is itself safe to call from an interrupt is
+//! up to the executor. With the `critical-section` feature,
+//! [`CriticalSectionWaker`] guards the slot with a critical section instead,
+//! so a registration can never be interleaved with a wake from an interrupt
+//! on the same core.
+//!
+//! The slot also records whether an awaited future has actually woken since
+//! the coroutine parked on it, so the outer future can skip resuming the
+//! coroutine when its task is polled for unrelated reasons.
+
+use core::fmt;
+use core::marker::PhantomData;
+use core::mem::ManuallyDrop;
+use core::panic::Location;
+use core::task::RawWaker;
+use core::task::RawWakerVTable;
+use core::task::Waker;
+
+use alloc::boxed::Box;
+
+use crate::blocking::BlockingPool;
+use crate::cancel::Teardown;
+use crate::context::ThreadPolicy;
+use crate::spawn::Spawner;
+use crate::sync::Arc;
+use crate::sync::AtomicBool;
+use crate::sync::AtomicPtr;
+use crate::sync::AtomicU32;
+use crate::sync::AtomicUsize;
+use crate::sync::AtomicWaker;
+use crate::sync::Ordering;
+use crate::sync::fence;
+use crate::time::Sleeper;
+
+/// Storage for the executor's waker, written by the outer future and woken
+/// from any thread.
+pub trait WakerSlot: Send + Sync + 'static {
+    /// Creates an empty slot.
+    fn new() -> Self;
+
+    /// Stores `waker`, replacing any previously registered one.
+    ///
+    /// Must not lose a [`wake`](Self::wake) that races with it: either the
+    /// wake observes the new waker, or `register` wakes it itself.
+    fn register(&self, waker: &Waker);
+
+    /// Takes and wakes the registered waker, if any.
+    fn wake(&self);
+}
+
+impl WakerSlot for AtomicWaker {
+    fn new() -> Self {
+        AtomicWaker::new()
+    }
+
+    fn register(&self, waker: &Waker) {
+        AtomicWaker::register(self, waker);
+    }
+
+    fn wake(&self) {
+        AtomicWaker::wake(self);
+    }
+}
+
+/// A [`WakerSlot`] guarded by a critical section, for waking from interrupt
+/// handlers on bare-metal targets.
+///
+/// The critical section covers only the swap of the stored waker; the
+/// executor's waker is woken and dropped outside of it.
+#[cfg(feature = "critical-section")]
+pub struct CriticalSectionWaker(critical_section::Mutex<core::cell::RefCell<Option<Waker>>>);
+
+#[cfg(feature = "critical-section")]
+impl WakerSlot for CriticalSectionWaker {
+    fn new() -> Self {
+        Self(critical_section::Mutex::new(core::cell::RefCell::new(None)))
+    }
+
+    fn register(&self, waker: &Waker) {
+        let old = critical_section::with(|cs| {
+            let mut slot = self.0.borrow_ref_mut(cs);
+            match &*slot {
+                Some(current) if current.will_wake(waker) => None,
+                _ => slot.replace(waker.clone()),
+            }
+        });
+        drop(old);
+    }
+
+    fn wake(&self) {
+        if let Some(waker) = critical_section::with(|cs| self.0.borrow_ref_mut(cs).take()) {
+            waker.wake();
+        }
+    }
+}
+
+#[cfg(feature = "critical-section")]
+impl fmt::Debug for CriticalSectionWaker {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CriticalSectionWaker").finish_non_exhaustive()
+    }
+}
+
+/// State shared between a coroutine, its outer future and the wakers of the
+/// futures it awaits.
+pub struct CoroutineShared<W = AtomicWaker> {
+    waker: W,
+    /// Set by every wake, cleared right before an awaited future is polled.
+    woken: AtomicBool,
+    /// The coroutine is suspended on a pending awaited future.
+    parked: AtomicBool,
+    /// Ready awaits allowed per resume; `u32::MAX` disables the budget.
+    budget: AtomicU32,
+    /// Ready awaits left in the current resume.
+    remaining: AtomicU32,
+    /// Wall time each resume may run before ready awaits yield.
+    #[cfg(feature = "std")]
+    time_slice: Option<core::time::Duration>,
+    /// When the time slice of the current resume runs out.
+    #[cfg(feature = "std")]
+    slice_deadline: std::sync::Mutex<Option<std::time::Instant>>,
+    /// Resumes a single poll of the outer future may perform.
+    resumes_per_poll: u32,
+    /// Number of times the coroutine has been resumed.
+    resumes: AtomicUsize,
+    /// The outer future is unwinding the coroutine.
+    aborted: AtomicBool,
+    /// Cancellation has been requested.
+    cancelled: AtomicBool,
+    /// Approximate top of the coroutine's stack; zero until the body starts.
+    stack_base: AtomicUsize,
+    /// Size of the coroutine's stack; zero if unknown.
+    stack_size: usize,
+    /// Lowest stack pointer seen at a stack check; `usize::MAX` before the first.
+    lowest_sp: AtomicUsize,
+    /// Approximate stack pointer at the coroutine's last suspension.
+    #[cfg(feature = "decommit")]
+    suspended_sp: AtomicUsize,
+    /// Lock the stack while the body runs and wipe it afterwards.
+    #[cfg(feature = "secure-stack")]
+    secure_stack: bool,
+    /// The body has run on the stack since it was last wiped.
+    #[cfg(feature = "secure-stack")]
+    stack_dirty: AtomicBool,
+    /// A stack check failed.
+    overflowed: AtomicBool,
+    /// The coroutine is being resumed.
+    running: AtomicBool,
+    /// Which threads may resume the coroutine.
+    thread_policy: ThreadPolicy,
+    /// The thread of the first resume, recorded for [`ThreadPolicy::Pinned`].
+    #[cfg(feature = "std")]
+    owner: std::sync::OnceLock<std::thread::ThreadId>,
+    /// The name given through the builder, if any.
+    name: Option<Box<str>>,
+    /// Where the coroutine was created.
+    location: &'static Location<'static>,
+    /// Unique among the coroutines of the process.
+    id: usize,
+    /// The `r#await` call the coroutine is inside of; null outside awaits.
+    await_site: AtomicPtr<Location<'static>>,
+    /// The last lifecycle event, one of the `observe::PHASE_*` constants, in
+    /// the low two bits; the number of events so far in the rest.
+    phase: AtomicUsize,
+    /// Woken on every lifecycle event once `observed` is set.
+    observer: AtomicWaker,
+    /// A `CoroutineObserver` has been created.
+    observed: AtomicBool,
+    /// The innermost live `NoYieldGuard`; null outside them.
+    no_yield: AtomicPtr<Location<'static>>,
+    /// Runs futures spawned from inside the coroutine.
+    spawner: Option<Arc<dyn Spawner>>,
+    /// The awaiter made current by `CoroutineAwaiter::enter`; null outside it.
+    #[cfg(feature = "std")]
+    entered: AtomicPtr<()>,
+    /// The extension data of the outer future's `Context`, as a
+    /// `*mut &mut dyn Any`; null outside resumes.
+    #[cfg(feature = "nightly")]
+    ext: AtomicPtr<()>,
+    /// Reports resumes that run too long.
+    #[cfg(feature = "std")]
+    watchdog: Option<crate::watchdog::Watchdog>,
+    /// Decides what happens to a panic of the body.
+    #[cfg(feature = "std")]
+    panic_handler: Option<Arc<dyn crate::panic::PanicHandler>>,
+    /// Runs closures passed to `block_in_place`.
+    blocking_pool: Option<Arc<dyn BlockingPool>>,
+    /// Creates timers for timeouts.
+    sleeper: Option<Arc<dyn Sleeper>>,
+    /// What sanitizers are told about the coroutine's stack switches.
+    #[cfg(feature = "sanitize")]
+    fiber: crate::sanitize::Fiber,
+    /// Panic when the coroutine parks on a future that cannot be woken.
+    detect_deadlocks: bool,
+    /// `CAwaiter::block_on` calls running on the coroutine's stack, under
+    /// which a detected deadlock could not unwind and is not reported.
+    c_awaits: AtomicU32,
+    /// How an abort tears down the suspended body, already made effective.
+    teardown: Teardown,
+    /// Allocations made by the crate on behalf of the coroutine.
+    #[cfg(feature = "alloc-stats")]
+    allocations: AtomicUsize,
+    /// Bytes of those allocations.
+    #[cfg(feature = "alloc-stats")]
+    allocated_bytes: AtomicUsize,
+}
+
+/// Hands out coroutine ids, starting at 1.
+fn next_id() -> usize {
+    // loom atomics cannot live in statics.
+    #[cfg(not(loom))]
+    static NEXT: AtomicUsize = AtomicUsize::new(1);
+    #[cfg(loom)]
+    static NEXT: core::sync::atomic::AtomicUsize = core::sync::atomic::AtomicUsize::new(1);
+    NEXT.fetch_add(1, Ordering::Relaxed)
+}
+
+#[cfg(feature = "diagnostics")]
+impl<W> Drop for CoroutineShared<W> {
+    fn drop(&mut self) {
+        crate::diagnostics::unregister(self.id);
+    }
+}
+
+impl<W: WakerSlot> Default for CoroutineShared<W> {
+    #[track_caller]
+    fn default() -> Self {
+        Self::new(None, Location::caller())
+    }
+}
+
+impl<W: WakerSlot> CoroutineShared<W> {
+    pub(crate) fn new(name: Option<Box<str>>, location: &'static Location<'static>) -> Self {
+        Self {
+            waker: W::new(),
+            woken: AtomicBool::new(true),
+            parked: AtomicBool::new(false),
+            budget: AtomicU32::new(crate::coop::DEFAULT_BUDGET),
+            remaining: AtomicU32::new(crate::coop::DEFAULT_BUDGET),
+            #[cfg(feature = "std")]
+            time_slice: None,
+            #[cfg(feature = "std")]
+            slice_deadline: std::sync::Mutex::new(None),
+            resumes_per_poll: 1,
+            resumes: AtomicUsize::new(0),
+            aborted: AtomicBool::new(false),
+            cancelled: AtomicBool::new(false),
+            stack_base: AtomicUsize::new(0),
+            stack_size: 0,
+            lowest_sp: AtomicUsize::new(usize::MAX),
+            #[cfg(feature = "decommit")]
+            suspended_sp: AtomicUsize::new(0),
+            #[cfg(feature = "secure-stack")]
+            secure_stack: false,
+            #[cfg(feature = "secure-stack")]
+            stack_dirty: AtomicBool::new(false),
+            overflowed: AtomicBool::new(false),
+            running: AtomicBool::new(false),
+            thread_policy: ThreadPolicy::Migrate,
+            #[cfg(feature = "std")]
+            owner: std::sync::OnceLock::new(),
+            name,
+            location,
+            id: next_id(),
+            await_site: AtomicPtr::new(core::ptr::null_mut()),
+            phase: AtomicUsize::new(crate::observe::PHASE_CREATED),
+            observer: AtomicWaker::new(),
+            observed: AtomicBool::new(false),
+            no_yield: AtomicPtr::new(core::ptr::null_mut()),
+            spawner: None,
+            blocking_pool: None,
+            #[cfg(feature = "std")]
+            entered: AtomicPtr::new(core::ptr::null_mut()),
+            #[cfg(feature = "nightly")]
+            ext: AtomicPtr::new(core::ptr::null_mut()),
+            #[cfg(feature = "std")]
+            watchdog: None,
+            #[cfg(feature = "std")]
+            panic_handler: None,
+            sleeper: None,
+            #[cfg(feature = "sanitize")]
+            fiber: crate::sanitize::Fiber::new(),
+            detect_deadlocks: cfg!(debug_assertions),
+            c_awaits: AtomicU32::new(0),
+            teardown: Teardown::default(),
+            #[cfg(feature = "alloc-stats")]
+            allocations: AtomicUsize::new(0),
+            #[cfg(feature = "alloc-stats")]
+            allocated_bytes: AtomicUsize::new(0),
+        }
+    }
+
+    pub(crate) fn running(&self) -> &AtomicBool {
+        &self.running
+    }
+
+    pub(crate) fn set_resumes_per_poll(&mut self, resumes: u32) {
+        self.resumes_per_poll = resumes.max(1);
+    }
+
+    pub(crate) fn resumes_per_poll(&self) -> u32 {
+        self.resumes_per_poll
+    }
+
+    pub(crate) fn set_thread_policy(&mut self, policy: ThreadPolicy) {
+        self.thread_policy = policy;
+    }
+
+    pub(crate) fn thread_policy(&self) -> ThreadPolicy {
+        self.thread_policy
+    }
+
+    #[cfg(feature = "std")]
+    pub(crate) fn owner(&self) -> &std::sync::OnceLock<std::thread::ThreadId> {
+        &self.owner
+    }
+
+    pub(crate) fn set_stack_size(&mut self, size: usize) {
+        self.stack_size = size;
+    }
+
+    /// Called by the body when it starts, with the stack pointer at that point.
+    pub(crate) fn set_stack_base(&self, base: usize) {
+        self.stack_base.store(base, Ordering::Relaxed);
+    }
+
+    /// The estimated lowest address and the size of the coroutine's stack,
+    /// once known.
+    pub(crate) fn stack_bottom(&self) -> Option<(usize, usize)> {
+        let base = self.stack_base.load(Ordering::Relaxed);
+        if base == 0 || self.stack_size == 0 {
+            return None;
+        }
+        Some((base.saturating_sub(self.stack_size), self.stack_size))
+    }
+
+    #[cfg(feature = "secure-stack")]
+    pub(crate) fn set_secure_stack(&mut self, secure: bool) {
+        self.secure_stack = secure;
+    }
+
+    #[cfg(feature = "secure-stack")]
+    pub(crate) fn secure_stack(&self) -> bool {
+        self.secure_stack
+    }
+
+    /// Records that the body is running on the stack.
+    #[cfg(feature = "secure-stack")]
+    pub(crate) fn mark_stack_dirty(&self) {
+        self.stack_dirty.store(true, Ordering::Relaxed);
+    }
+
+    /// Returns whether the stack needs wiping, clearing the flag.
+    #[cfg(feature = "secure-stack")]
+    pub(crate) fn take_stack_dirty(&self) -> bool {
+        self.stack_dirty.swap(false, Ordering::Relaxed)
+    }
+
+    /// Called by the coroutine right before it suspends.
+    #[cfg(feature = "decommit")]
+    pub(crate) fn set_suspended_sp(&self, sp: usize) {
+        self.suspended_sp.store(sp, Ordering::Relaxed);
+    }
+
+    /// The stack pointer recorded at the last suspension, if any.
+    #[cfg(feature = "decommit")]
+    pub(crate) fn suspended_sp(&self) -> Option<usize> {
+        Some(self.suspended_sp.load(Ordering::Relaxed)).filter(|&sp| sp != 0)
+    }
+
+    #[cfg(feature = "sanitize")]
+    pub(crate) fn fiber(&self) -> &crate::sanitize::Fiber {
+        &self.fiber
+    }
+
+    /// The size of the coroutine's stack, if known.
+    pub(crate) fn stack_size(&self) -> Option<usize> {
+        Some(self.stack_size).filter(|&size| size != 0)
+    }
+
+    /// Records `sp`, seen at a stack check, for [`stack_high_water`](Self::stack_high_water).
+    pub(crate) fn record_stack_pointer(&self, sp: usize) {
+        self.lowest_sp.fetch_min(sp, Ordering::Relaxed);
+    }
+
+    /// The most stack, in bytes, in use at any stack check since the body
+    /// started or since the last [`reset_stack_high_water`](Self::reset_stack_high_water).
+    pub(crate) fn stack_high_water(&self) -> Option<usize> {
+        let base = self.stack_base.load(Ordering::Relaxed);
+        let lowest = self.lowest_sp.load(Ordering::Relaxed);
+        if base == 0 || lowest == usize::MAX {
+            return None;
+        }
+        Some(base.saturating_sub(lowest))
+    }
+
+    pub(crate) fn reset_stack_high_water(&self) {
+        self.lowest_sp.store(usize::MAX, Ordering::Relaxed);
+    }
+
+    /// Stack bytes left below `sp`, if the stack's extent is known.
+    pub(crate) fn remaining_stack(&self, sp: usize) -> Option<usize> {
+        let base = self.stack_base.load(Ordering::Relaxed);
+        if base == 0 || self.stack_size == 0 {
+            return None;
+        }
+        Some(self.stack_size.saturating_sub(base.saturating_sub(sp)))
+    }
+
+    /// Returns `false` if `sp` is known to lie outside the coroutine's stack.
+    pub(crate) fn contains_stack_pointer(&self, sp: usize) -> bool {
+        let base = self.stack_base.load(Ordering::Relaxed);
+        if base == 0 || self.stack_size == 0 {
+            return true;
+        }
+        sp <= base && base - sp <= self.stack_size
+    }
+
+    pub(crate) fn set_overflowed(&self) {
+        self.overflowed.store(true, Ordering::Relaxed);
+    }
+
+    pub(crate) fn has_overflowed(&self) -> bool {
+        self.overflowed.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn set_spawner(&mut self, spawner: Option<Arc<dyn Spawner>>) {
+        self.spawner = spawner;
+    }
+
+    pub(crate) fn spawner(&self) -> Option<&Arc<dyn Spawner>> {
+        self.spawner.as_ref()
+    }
+
+    #[cfg(feature = "alloc-stats")]
+    pub(crate) fn record_alloc(&self, bytes: usize) {
+        self.allocations.fetch_add(1, Ordering::Relaxed);
+        self.allocated_bytes.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    #[cfg(feature = "alloc-stats")]
+    pub(crate) fn alloc_stats(&self) -> crate::memory::AllocStats {
+        crate::memory::AllocStats::new(
+            self.allocations.load(Ordering::Relaxed),
+            self.allocated_bytes.load(Ordering::Relaxed),
+        )
+    }
+
+    pub(crate) fn set_blocking_pool(&mut self, pool: Option<Arc<dyn BlockingPool>>) {
+        self.blocking_pool = pool;
+    }
+
+    pub(crate) fn blocking_pool(&self) -> Option<&Arc<dyn BlockingPool>> {
+        self.blocking_pool.as_ref()
+    }
+
+    #[cfg(feature = "std")]
+    pub(crate) fn entered(&self) -> *const () {
+        self.entered.load(Ordering::Relaxed)
+    }
+
+    #[cfg(feature = "std")]
+    pub(crate) fn swap_entered(&self, awaiter: *const ()) -> *const () {
+        self.entered.swap(awaiter as *mut (), Ordering::Relaxed)
+    }
+
+    #[cfg(feature = "nightly")]
+    pub(crate) fn ext(&self) -> &AtomicPtr<()> {
+        &self.ext
+    }
+
+    #[cfg(feature = "std")]
+    pub(crate) fn set_watchdog(&mut self, watchdog: Option<crate::watchdog::Watchdog>) {
+        self.watchdog = watchdog;
+    }
+
+    #[cfg(feature = "std")]
+    pub(crate) fn watchdog(&self) -> Option<&crate::watchdog::Watchdog> {
+        self.watchdog.as_ref()
+    }
+
+    #[cfg(feature = "std")]
+    pub(crate) fn set_panic_handler(
+        &mut self,
+        handler: Option<Arc<dyn crate::panic::PanicHandler>>,
+    ) {
+        self.panic_handler = handler;
+    }
+
+    #[cfg(feature = "std")]
+    pub(crate) fn panic_handler(&self) -> Option<&dyn crate::panic::PanicHandler> {
+        self.panic_handler.as_deref()
+    }
+
+    pub(crate) fn set_sleeper(&mut self, sleeper: Option<Arc<dyn Sleeper>>) {
+        self.sleeper = sleeper;
+    }
+
+    pub(crate) fn sleeper(&self) -> Option<&Arc<dyn Sleeper>> {
+        self.sleeper.as_ref()
+    }
+
+    pub(crate) fn set_detect_deadlocks(&mut self, detect: bool) {
+        self.detect_deadlocks = detect;
+    }
+
+    /// Called by the body around an await issued from C code.
+    pub(crate) fn enter_c_await(&self) {
+        self.c_awaits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn exit_c_await(&self) {
+        self.c_awaits.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn set_teardown(&mut self, teardown: Teardown) {
+        self.teardown = teardown.effective();
+    }
+
+    pub(crate) fn teardown(&self) -> Teardown {
+        self.teardown
+    }
+
+    /// The coroutine's name, if one was given through the builder.
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// Where the coroutine was created.
+    pub fn location(&self) -> &'static Location<'static> {
+        self.location
+    }
+
+    /// A number identifying the coroutine among all coroutines of the process.
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    /// The `r#await` call the coroutine is currently inside of, if any.
+    pub fn await_site(&self) -> Option<&'static Location<'static>> {
+        // SAFETY: the pointer is null or was stored from a `&'static Location`.
+        unsafe { self.await_site.load(Ordering::Relaxed).as_ref() }
+    }
+
+    pub(crate) fn set_await_site(&self, site: Option<&'static Location<'static>>) {
+        let site = site.map_or(core::ptr::null_mut(), |site| site as *const _ as *mut _);
+        self.await_site.store(site, Ordering::Relaxed);
+    }
+
+    pub(crate) fn no_yield(&self) -> Option<&'static Location<'static>> {
+        // SAFETY: the pointer is null or was stored from a `&'static Location`.
+        unsafe { self.no_yield.load(Ordering::Relaxed).as_ref() }
+    }
+
+    pub(crate) fn swap_no_yield(
+        &self,
+        site: Option<&'static Location<'static>>,
+    ) -> Option<&'static Location<'static>> {
+        let site = site.map_or(core::ptr::null_mut(), |site| site as *const _ as *mut _);
+        // SAFETY: as in `no_yield`.
+        unsafe { self.no_yield.swap(site, Ordering::Relaxed).as_ref() }
+    }
+
+    /// The last lifecycle event and the number of events before it, packed
+    /// so that every event changes the value.
+    pub(crate) fn phase(&self) -> usize {
+        self.phase.load(Ordering::Acquire)
+    }
+
+    /// Records a lifecycle event and wakes the observer, if there is one.
+    pub(crate) fn set_phase(&self, phase: usize) {
+        // Only the thread resuming the coroutine records events.
+        let sequence = (self.phase.load(Ordering::Relaxed) | 3) + 1;
+        self.phase.store(sequence | phase, Ordering::Release);
+        if self.observed.load(Ordering::Acquire) {
+            self.observer.wake();
+        }
+    }
+
+    /// Registers `waker` to be woken by the next lifecycle event.
+    pub(crate) fn observe(&self, waker: &Waker) {
+        self.observer.register(waker);
+        self.observed.store(true, Ordering::Release);
+    }
+
+    /// Identifies the coroutine in propagated panics.
+    pub(crate) fn origin(&self) -> crate::panic::Origin<'_> {
+        (self.name(), self.location, self.await_site())
+    }
+
+    /// Number of times the coroutine has been resumed.
+    pub fn resume_count(&self) -> usize {
+        self.resumes.load(Ordering::Relaxed)
+    }
+
+    /// Returns `true` while the coroutine is suspended on a pending awaited future.
+    pub fn is_parked(&self) -> bool {
+        self.parked.load(Ordering::Relaxed)
+    }
+
+    /// Called by the outer future before it unwinds the coroutine.
+    pub(crate) fn abort(&self) {
+        self.aborted.store(true, Ordering::Relaxed);
+    }
+
+    pub(crate) fn is_aborted(&self) -> bool {
+        self.aborted.load(Ordering::Relaxed)
+    }
+
+    /// Requests cancellation and wakes the coroutine so it can observe it.
+    pub(crate) fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Release);
+        self.wake();
+    }
+
+    pub(crate) fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Acquire)
+    }
+
+    /// Called by the outer future before every resume.
+    pub(crate) fn count_resume(&self) {
+        self.resumes.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Formats the coroutine's name and creation site for diagnostics.
+    pub(crate) fn describe(&self) -> Describe<'_, W> {
+        Describe(self)
+    }
+
+    /// Stores the executor's waker to be notified when an awaited future wakes.
+    pub fn register(&self, waker: &Waker) {
+        self.waker.register(waker);
+    }
+
+    /// Wakes the executor's waker, if one is registered.
+    pub fn wake(&self) {
+        self.woken.store(true, Ordering::Release);
+        self.waker.wake();
+    }
+
+    /// Returns `true` if resuming the coroutine can make progress.
+    ///
+    /// This is `false` only while the coroutine is parked on an awaited future
+    /// that has not woken since it was last polled. Call it after
+    /// [`register`](Self::register) so a concurrent wake is never missed.
+    pub fn should_resume(&self) -> bool {
+        !self.parked.load(Ordering::Relaxed) || self.woken.load(Ordering::Acquire)
+    }
+
+    /// Returns `true` if the coroutine was woken since it last polled an
+    /// awaited future.
+    pub(crate) fn is_woken(&self) -> bool {
+        self.woken.load(Ordering::Acquire)
+    }
+
+    /// Called by the awaiter right before polling an awaited future.
+    pub(crate) fn clear_woken(&self) {
+        self.woken.store(false, Ordering::Relaxed);
+    }
+
+    /// Called by the awaiter around the yield on a pending awaited future.
+    pub(crate) fn set_parked(&self, parked: bool) {
+        self.parked.store(parked, Ordering::Relaxed);
+    }
+
+    pub(crate) fn set_budget(&self, budget: Option<u32>) {
+        self.budget.store(budget.unwrap_or(u32::MAX), Ordering::Relaxed);
+    }
+
+    #[cfg(feature = "std")]
+    pub(crate) fn set_time_slice(&mut self, slice: Option<core::time::Duration>) {
+        self.time_slice = slice;
+    }
+
+    /// Called by the outer future before every resume.
+    pub(crate) fn reset_budget(&self) {
+        self.remaining
+            .store(self.budget.load(Ordering::Relaxed), Ordering::Relaxed);
+        #[cfg(feature = "std")]
+        if let Some(slice) = self.time_slice {
+            let deadline = std::time::Instant::now() + slice;
+            *self.slice_deadline.lock().unwrap_or_else(|e| e.into_inner()) = Some(deadline);
+        }
+    }
+
+    /// Returns `true` once the current resume has run past its time slice.
+    pub(crate) fn slice_expired(&self) -> bool {
+        #[cfg(feature = "std")]
+        if self.time_slice.is_some() {
+            let deadline = *self.slice_deadline.lock().unwrap_or_else(|e| e.into_inner());
+            return deadline.is_some_and(|deadline| std::time::Instant::now() >= deadline);
+        }
+        false
+    }
+
+    /// Consumes one unit of budget, returning `false` once it is exhausted.
+    pub(crate) fn consume_budget(&self) -> bool {
+        match self.remaining.load(Ordering::Relaxed) {
+            u32::MAX => true,
+            0 => false,
+            n => {
+                self.remaining.store(n - 1, Ordering::Relaxed);
+                true
+            }
+        }
+    }
+}
+
+impl<W> fmt::Debug for CoroutineShared<W> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CoroutineShared")
+            .field("name", &self.name())
+            .field("location", &self.location)
+            .field("await_site", &self.await_site())
+            .field("resumes", &self.resume_count())
+            .field("parked", &self.is_parked())
+            .finish_non_exhaustive()
+    }
+}
+
+/// Displays a coroutine's name, creation site and current await site, as in
+/// ``coroutine `parser` created at src/main.rs:10:5, awaiting at src/parse.rs:42:9``.
+pub(crate) struct Describe<'a, W>(&'a CoroutineShared<W>);
+
+impl<W> fmt::Display for Describe<'_, W> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0.name() {
+            Some(name) => write!(f, "coroutine `{name}`")?,
+            None => f.write_str("coroutine")?,
+        }
+        write!(f, " created at {}", self.0.location)?;
+        if let Some(site) = self.0.await_site() {
+            write!(f, ", awaiting at {site}")?;
+        }
+        Ok(())
+    }
+}
+
+/// The waker vtable for coroutines using the slot `W`.
+struct VTable<W>(PhantomData<W>);
+
+impl<W: WakerSlot> VTable<W> {
+    const VTABLE: RawWakerVTable =
+        RawWakerVTable::new(clone_waker::<W>, wake::<W>, wake_by_ref::<W>, drop_waker::<W>);
+}
+
+unsafe fn clone_waker<W: WakerSlot>(data: *const ()) -> RawWaker {
+    // SAFETY: `data` came from `Arc::as_ptr` on a live `Arc<CoroutineShared<W>>`.
+    unsafe { Arc::increment_strong_count(data as *const CoroutineShared<W>) };
+    RawWaker::new(data, &VTable::<W>::VTABLE)
+}
+
+unsafe fn wake<W: WakerSlot>(data: *const ()) {
+    // SAFETY: the waker owns one strong count, which is released here.
+    let shared = unsafe { Arc::from_raw(data as *const CoroutineShared<W>) };
+    shared.wake();
+}
+
+unsafe fn wake_by_ref<W: WakerSlot>(data: *const ()) {
+    // SAFETY: the waker keeps the allocation alive.
+    unsafe { (*(data as *const CoroutineShared<W>)).wake() };
+}
+
+unsafe fn drop_waker<W: WakerSlot>(data: *const ()) {
+    // SAFETY: the waker owns one strong count, which is released here.
+    unsafe { Arc::decrement_strong_count(data as *const CoroutineShared<W>) };
+}
+
+/// Called after an awaited future returned `Pending`: panics if nothing can
+/// ever wake the coroutine again.
+///
+/// Every waker clone holds a strong reference, and the coroutine itself
+/// holds the only other one; with no clone left and no wake recorded, the
+/// future dropped its waker without waking it and the coroutine would hang
+/// silently. Handles such as a [`CancelHandle`](crate::cancel::CancelHandle)
+/// also hold references, so the check errs on the side of staying quiet.
+///
+/// Awaits issued from C through [`CAwaiter`](crate::ffi::CAwaiter) are not
+/// checked: the panic could not unwind out of the C frames and would abort
+/// the process, and C futures may arrange their wakeup without the waker.
+pub(crate) fn assert_wakeable<W: WakerSlot>(shared: &Arc<CoroutineShared<W>>) {
+    if !shared.detect_deadlocks
+        || shared.c_awaits.load(Ordering::Relaxed) != 0
+        || Arc::strong_count(shared) > 1
+    {
+        return;
+    }
+    // Pairs with the release decrement of the last waker clone, which comes
+    // after its wake.
+    fence(Ordering::Acquire);
+    if !shared.woken.load(Ordering::Relaxed) {
+        panic!(
+            "{} is awaiting a future that dropped its waker without waking it, so nothing can resume it",
+            shared.describe()
+        );
+    }
+}
+
+/// Borrows a waker for `shared` without touching its reference count.
+///
+/// The returned waker must not outlive `shared`; clones taken from it own
+/// their own reference and may.
+pub(crate) fn borrow<W: WakerSlot>(shared: &Arc<CoroutineShared<W>>) -> ManuallyDrop<Waker> {
+    let raw = RawWaker::new(Arc::as_ptr(shared) as *const (), &VTable::<W>::VTABLE);
+    // SAFETY: the vtable upholds the `RawWaker` contract for `CoroutineShared`.
+    ManuallyDrop::new(unsafe { Waker::from_raw(raw) })
+}
+
+#[cfg(all(test, loom))]
+mod tests {
+    use super::CoroutineShared;
+    use loom::sync::atomic::AtomicUsize;
+    use loom::sync::atomic::Ordering;
+    use std::sync::Arc;
+    use std::task::Wake;
+    use std::task::Waker;
+
+    #[derive(Default)]
+    struct Counter(AtomicUsize);
+
+    impl Wake for Counter {
+        fn wake(self: Arc<Self>) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    fn counting_waker() -> (Arc<Counter>, Waker) {
+        let counter = Arc::new(Counter::default());
+        (counter.clone(), Waker::from(counter))
+    }
+
+    /// A poll registering its waker while a wake consumes the previous one
+    /// must leave the new waker where that wake or a later one reaches it.
+    #[test]
+    fn wake_racing_register_is_not_lost() {
+        loom::model(|| {
+            let shared = loom::sync::Arc::new(<CoroutineShared>::default());
+            let (_, first) = counting_waker();
+            let (counter, waker) = counting_waker();
+            // The first poll registered its waker and the coroutine parked.
+            shared.register(&first);
+            let waker_thread = {
+                let shared = shared.clone();
+                loom::thread::spawn(move || shared.wake())
+            };
+            // The next poll runs while the wake is taking the first waker.
+            shared.register(&waker);
+            shared.wake();
+            waker_thread.join().unwrap();
+            assert!(counter.0.load(Ordering::SeqCst) > 0);
+        });
+    }
+
+    /// Skipping a resume is only allowed if the wake that would justify it reaches the executor.
+    #[test]
+    fn skipped_resume_still_observes_wake() {
+        loom::model(|| {
+            let shared = loom::sync::Arc::new(<CoroutineShared>::default());
+            let (counter, waker) = counting_waker();
+            // The coroutine polled its awaited future and parked on it.
+            shared.clear_woken();
+            shared.set_parked(true);
+            let waker_thread = {
+                let shared = shared.clone();
+                loom::thread::spawn(move || shared.wake())
+            };
+            shared.register(&waker);
+            let resumed = shared.should_resume();
+            waker_thread.join().unwrap();
+            assert!(resumed || counter.0.load(Ordering::SeqCst) > 0);
+        });
+    }
+}
@@ -0,0 +1,80 @@
+//! Running coroutines in the browser with `wasm-bindgen`.
+//!
+//! Blocking-style code ported to the web can keep its shape: inside a
+//! coroutine, [`CoroutineAwaiter::await_promise`] blocks on a JavaScript
+//! promise, such as the one returned by `fetch`, and
+//! [`CoroutineAwaiter::await_js`] on any [`JsFuture`]. The coroutine itself
+//! is driven by the page's microtask queue through
+//! [`CoroutineFuture::spawn_local`], and [`WasmSpawner`] lets its body spawn
+//! background futures the same way.
+//!
+//! minicoro switches stacks on WebAssembly only through Binaryen's asyncify
+//! transform or Emscripten fibers, so the module has to be built with one of
+//! them.
+
+use core::pin::pin;
+
+use awaiter_trait::Awaiter;
+use js_sys::Promise;
+use wasm_bindgen::JsValue;
+use wasm_bindgen_futures::JsFuture;
+
+use crate::CoroutineAwaiter;
+use crate::CoroutineFuture;
+use crate::spawn::BoxFuture;
+use crate::spawn::JoinHandle;
+use crate::spawn::Spawner;
+use crate::waker::WakerSlot;
+
+impl<W: WakerSlot> CoroutineAwaiter<W> {
+    /// Blocks the coroutine until `future` settles, returning its value or
+    /// the rejection.
+    #[track_caller]
+    pub fn await_js(&self, future: JsFuture) -> Result<JsValue, JsValue> {
+        self.r#await(pin!(future))
+    }
+
+    /// Blocks the coroutine until `promise` settles, returning its value or
+    /// the rejection.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// use minicoro_awaiters::CoroutineFuture;
+    ///
+    /// CoroutineFuture::new(|awaiter| {
+    ///     let window = web_sys::window().unwrap();
+    ///     let response = awaiter.await_promise(window.fetch_with_str("/config.json"))?;
+    ///     render(response);
+    ///     Ok::<_, JsValue>(())
+    /// })
+    /// .spawn_local();
+    /// ```
+    #[track_caller]
+    pub fn await_promise(&self, promise: Promise) -> Result<JsValue, JsValue> {
+        self.await_js(JsFuture::from(promise))
+    }
+}
+
+impl<T: 'static, W: WakerSlot> CoroutineFuture<T, W> {
+    /// Runs the coroutine on the current thread's `wasm-bindgen` executor
+    /// and returns a handle to its output.
+    ///
+    /// The coroutine runs to completion whether or not the handle is
+    /// awaited.
+    pub fn spawn_local(self) -> JoinHandle<T> {
+        let (completer, handle) = crate::spawn::handoff();
+        wasm_bindgen_futures::spawn_local(async move { completer.complete(self.await) });
+        handle
+    }
+}
+
+/// Spawns onto the current thread's `wasm-bindgen` executor.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug, Hash, Default)]
+pub struct WasmSpawner;
+
+impl Spawner for WasmSpawner {
+    fn spawn(&self, future: BoxFuture) {
+        wasm_bindgen_futures::spawn_local(future);
+    }
+}
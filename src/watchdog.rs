@@ -0,0 +1,130 @@
+//! Catching coroutines that run too long without yielding.
+//!
+//! A coroutine only gives its thread back to the executor at an await that
+//! is pending or out of budget. A synchronous blocking call, or a long
+//! computation, inside the body stalls every other task on the thread, and
+//! nothing in the async stack points at it. A [`Watchdog`] registered with
+//! [`CoroutineBuilder::watchdog`](crate::CoroutineBuilder::watchdog) measures
+//! the wall time of every resume and reports the ones that exceed its
+//! threshold as a [`Stall`].
+//!
+//! The report is made after the resume returns, so a coroutine that never
+//! yields again is not caught. To make long resumes yield rather than just
+//! report them, give the coroutine a
+//! [`time_slice`](crate::CoroutineBuilder::time_slice) as well. Requires the
+//! `std` feature.
+
+use core::fmt;
+use core::panic::Location;
+use core::time::Duration;
+
+use crate::sync::Arc;
+use crate::sync::dyn_arc;
+use crate::waker::CoroutineShared;
+
+/// A resume that exceeded a [`Watchdog`]'s threshold.
+#[derive(Clone, Copy, Debug)]
+pub struct Stall<'a> {
+    name: Option<&'a str>,
+    location: &'static Location<'static>,
+    await_site: Option<&'static Location<'static>>,
+    elapsed: Duration,
+}
+
+impl Stall<'_> {
+    /// The name of the stalling coroutine, if it was given one.
+    pub fn name(&self) -> Option<&str> {
+        self.name
+    }
+
+    /// Where the stalling coroutine was created.
+    pub fn location(&self) -> &'static Location<'static> {
+        self.location
+    }
+
+    /// The `r#await` the coroutine finally suspended in, if it did not
+    /// complete.
+    pub fn await_site(&self) -> Option<&'static Location<'static>> {
+        self.await_site
+    }
+
+    /// How long the resume ran.
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+}
+
+impl fmt::Display for Stall<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.name {
+            Some(name) => write!(f, "coroutine `{name}`")?,
+            None => f.write_str("coroutine")?,
+        }
+        write!(f, " created at {} ran for {:?} without yielding", self.location, self.elapsed)?;
+        if let Some(site) = self.await_site {
+            write!(f, " before awaiting at {site}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Reports resumes that run longer than a threshold.
+#[derive(Clone)]
+pub struct Watchdog {
+    threshold: Duration,
+    on_stall: Arc<dyn Fn(&Stall<'_>) + Send + Sync>,
+}
+
+impl Watchdog {
+    /// Calls `on_stall` after every resume that ran longer than `threshold`.
+    ///
+    /// # Example
+    ///
+    /// ```ignore
+    /// use core::time::Duration;
+    /// use minicoro_awaiters::{CoroutineFuture, watchdog::Watchdog};
+    ///
+    /// let watchdog = Watchdog::new(Duration::from_millis(50), |stall| {
+    ///     metrics::counter!("coroutine_stalls").increment(1);
+    ///     eprintln!("{stall}");
+    /// });
+    /// let coro = CoroutineFuture::builder().watchdog(watchdog).build(body)?;
+    /// ```
+    pub fn new(threshold: Duration, on_stall: impl Fn(&Stall<'_>) + Send + Sync + 'static) -> Self {
+        Self {
+            threshold,
+            on_stall: dyn_arc!(on_stall => dyn Fn(&Stall<'_>) + Send + Sync),
+        }
+    }
+
+    /// Prints every resume that ran longer than `threshold` to standard error.
+    pub fn eprint(threshold: Duration) -> Self {
+        Self::new(threshold, |stall| std::eprintln!("{stall}"))
+    }
+
+    /// The resume duration above which stalls are reported.
+    pub fn threshold(&self) -> Duration {
+        self.threshold
+    }
+
+    /// Reports the resume of `shared` that just took `elapsed`, if too long.
+    pub(crate) fn check<W>(&self, shared: &CoroutineShared<W>, elapsed: Duration) {
+        if elapsed <= self.threshold {
+            return;
+        }
+        (self.on_stall)(&Stall {
+            name: shared.name(),
+            location: shared.location(),
+            await_site: shared.await_site(),
+            elapsed,
+        });
+    }
+}
+
+impl fmt::Debug for Watchdog {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Watchdog")
+            .field("threshold", &self.threshold)
+            .finish_non_exhaustive()
+    }
+}
@@ -0,0 +1,114 @@
+//! A long-lived coroutine running jobs submitted from async code.
+//!
+//! A [`CoroutineWorker`] keeps one coroutine, and one stack, alive across
+//! any number of jobs. Each job is a blocking-style closure that can await
+//! futures and gets mutable access to state owned by the worker, so caches,
+//! arenas or an interpreter instance survive from one job to the next
+//! without an OS thread to hold them.
+//!
+//! Jobs run one at a time, in submission order. A job that panics poisons
+//! the worker: the panic propagates out of [`CoroutineWorker::run`], and
+//! every later call panics as well.
+
+use core::cell::Cell;
+use core::fmt;
+
+use alloc::boxed::Box;
+use alloc::rc::Rc;
+
+use crate::BuildError;
+use crate::CoroutineAwaiter;
+use crate::CoroutineBuilder;
+use crate::consumer::CoroutineConsumer;
+use crate::consumer::Receiver;
+
+/// A job queued on a worker, writing its result into a slot shared with `run`.
+type Job<S> = Box<dyn FnOnce(&CoroutineAwaiter, &mut S)>;
+
+/// A coroutine running jobs against long-lived state of type `S`.
+///
+/// # Example
+///
+/// ```ignore
+/// use minicoro_awaiters::worker::CoroutineWorker;
+///
+/// let mut worker = CoroutineWorker::new(Interpreter::new());
+/// let a = worker.run(|awaiter, vm| vm.eval(awaiter, "let x = fetch()")).await;
+/// let b = worker.run(|awaiter, vm| vm.eval(awaiter, "x + 1")).await;
+/// ```
+pub struct CoroutineWorker<S: 'static = ()> {
+    inner: CoroutineConsumer<Job<S>, S>,
+}
+
+impl<S: 'static> CoroutineWorker<S> {
+    /// Starts a worker owning `state`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the coroutine cannot be created; use
+    /// [`with_builder`](Self::with_builder) to handle that case.
+    #[track_caller]
+    pub fn new(state: S) -> Self {
+        Self::with_builder(CoroutineBuilder::new(), state).unwrap()
+    }
+
+    /// Starts a worker owning `state` on a coroutine with the settings of
+    /// `builder`.
+    #[track_caller]
+    pub fn with_builder(builder: CoroutineBuilder, state: S) -> Result<Self, BuildError> {
+        let inner = CoroutineConsumer::with_builder(builder, move |jobs: &Receiver<Job<S>>| {
+            let awaiter: &CoroutineAwaiter = jobs;
+            let mut state = state;
+            while let Some(job) = jobs.recv() {
+                job(awaiter, &mut state);
+            }
+            state
+        })?;
+        Ok(Self { inner })
+    }
+
+    /// Runs `job` on the worker's coroutine and returns its result.
+    ///
+    /// If this future is dropped while the job is suspended, the job still
+    /// finishes, during the next call, and its result is discarded.
+    ///
+    /// # Panics
+    ///
+    /// Resumes the panic of `job`, and panics if an earlier job panicked.
+    pub async fn run<T: 'static>(
+        &mut self,
+        job: impl FnOnce(&CoroutineAwaiter, &mut S) -> T + 'static,
+    ) -> T {
+        let output = Rc::new(Cell::new(None));
+        let slot = output.clone();
+        let job: Job<S> = Box::new(move |awaiter, state| slot.set(Some(job(awaiter, state))));
+        self.inner
+            .send(job)
+            .await
+            .expect("the worker coroutine exited");
+        output
+            .take()
+            .expect("the worker coroutine finished a job without its result")
+    }
+
+    /// Waits for an unfinished job, stops the worker and returns its state.
+    pub async fn finish(mut self) -> S {
+        self.inner
+            .close()
+            .await
+            .expect("the worker coroutine exited")
+    }
+
+    /// The worker's coroutine.
+    pub(crate) fn coroutine(&self) -> &crate::CoroutineFuture<S> {
+        self.inner.coroutine()
+    }
+}
+
+impl<S: 'static> fmt::Debug for CoroutineWorker<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CoroutineWorker")
+            .field("coroutine", &self.inner)
+            .finish()
+    }
+}